@@ -1,9 +1,9 @@
 use nix_interop::flake_output::FlakeOutput;
 use nix_interop::nixos_options::NixosOptions;
 use salsa::Durability;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use std::sync::Arc;
 use syntax::{TextRange, TextSize};
 
@@ -131,6 +131,44 @@ impl FileSet {
     pub fn iter(&self) -> impl Iterator<Item = (FileId, &'_ VfsPath)> + ExactSizeIterator + '_ {
         self.paths.iter().map(|(&file, path)| (file, path))
     }
+
+    /// List direct children of directory `dir`, sorted by name.
+    ///
+    /// Directories are derived from paths of files in the set, so only those containing some
+    /// files are listed.
+    pub fn list_dir(&self, dir: &VfsPath) -> Vec<DirEntry> {
+        let Some(dir) = dir.as_path() else {
+            return Vec::new();
+        };
+        let mut entries = BTreeMap::<&str, bool>::new();
+        for path in self.files.keys().filter_map(VfsPath::as_path) {
+            let Ok(rest) = path.strip_prefix(dir) else {
+                continue;
+            };
+            let mut comps = rest.components();
+            let Some(Component::Normal(name)) = comps.next() else {
+                continue;
+            };
+            let Some(name) = name.to_str() else {
+                continue;
+            };
+            *entries.entry(name).or_default() |= comps.next().is_some();
+        }
+        entries
+            .into_iter()
+            .map(|(name, is_dir)| DirEntry {
+                name: name.into(),
+                is_dir,
+            })
+            .collect()
+    }
+}
+
+/// A child of a directory in a [`FileSet`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub is_dir: bool,
 }
 
 impl fmt::Debug for FileSet {
@@ -163,6 +201,10 @@ impl SourceRoot {
         self.file_set.iter()
     }
 
+    pub fn list_dir(&self, dir: &VfsPath) -> Vec<DirEntry> {
+        self.file_set.list_dir(dir)
+    }
+
     pub fn entry(&self) -> Option<FileId> {
         self.entry
     }
@@ -191,6 +233,30 @@ impl fmt::Debug for FlakeInfo {
     }
 }
 
+/// The search path for `<name>` lookups, in the same order as `NIX_PATH`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SearchPath {
+    pub entries: Vec<SearchPathEntry>,
+}
+
+/// An entry of `NIX_PATH`, eg. `nixpkgs=/path/to/nixpkgs`, or just `/path` with an empty prefix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchPathEntry {
+    pub prefix: String,
+    pub path: VfsPath,
+}
+
+impl SearchPath {
+    /// Root names available for `<name>`, in order and deduplicated.
+    pub fn root_names(&self) -> impl Iterator<Item = &str> + '_ {
+        let mut seen = HashSet::new();
+        self.entries
+            .iter()
+            .filter_map(|entry| entry.prefix.split('/').next())
+            .filter(move |name| !name.is_empty() && seen.insert(*name))
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
 pub struct InFile<T> {
     pub file_id: FileId,
@@ -246,6 +312,10 @@ pub trait SourceDatabase {
     #[salsa::input]
     fn source_root(&self, sid: SourceRootId) -> Arc<SourceRoot>;
 
+    /// All valid `SourceRootId`s.
+    #[salsa::input]
+    fn source_root_ids(&self) -> Arc<[SourceRootId]>;
+
     fn source_root_flake_info(&self, sid: SourceRootId) -> Option<Arc<FlakeInfo>>;
 
     #[salsa::input]
@@ -256,8 +326,26 @@ pub trait SourceDatabase {
 
     #[salsa::input]
     fn nixos_options(&self) -> Arc<NixosOptions>;
+
+    #[salsa::input]
+    fn search_path(&self) -> Arc<SearchPath>;
+
+    /// The maximum allowed depth of nested attrsets.
+    #[salsa::input]
+    fn max_nesting(&self) -> usize;
+
+    /// The number of distinct `lib.<name>` selects in a file above which to suggest
+    /// `inherit (lib) ...`.
+    #[salsa::input]
+    fn lib_inherit_threshold(&self) -> usize;
 }
 
+/// The default of [`SourceDatabase::max_nesting`].
+pub const DEFAULT_MAX_NESTING: usize = 4;
+
+/// The default of [`SourceDatabase::lib_inherit_threshold`].
+pub const DEFAULT_LIB_INHERIT_THRESHOLD: usize = 5;
+
 fn source_root_flake_info(db: &dyn SourceDatabase, sid: SourceRootId) -> Option<Arc<FlakeInfo>> {
     db.flake_graph().nodes.get(&sid).cloned().map(Arc::new)
 }
@@ -268,6 +356,9 @@ pub struct Change {
     pub roots: Option<Vec<SourceRoot>>,
     pub file_changes: Vec<(FileId, Arc<str>)>,
     pub nixos_options: Option<NixosOptions>,
+    pub search_path: Option<SearchPath>,
+    pub max_nesting: Option<usize>,
+    pub lib_inherit_threshold: Option<usize>,
 }
 
 impl Change {
@@ -283,6 +374,18 @@ impl Change {
         self.nixos_options = Some(opts);
     }
 
+    pub fn set_search_path(&mut self, search_path: SearchPath) {
+        self.search_path = Some(search_path);
+    }
+
+    pub fn set_max_nesting(&mut self, max_nesting: usize) {
+        self.max_nesting = Some(max_nesting);
+    }
+
+    pub fn set_lib_inherit_threshold(&mut self, threshold: usize) {
+        self.lib_inherit_threshold = Some(threshold);
+    }
+
     pub fn set_roots(&mut self, roots: Vec<SourceRoot>) {
         self.roots = Some(roots);
     }
@@ -298,14 +401,25 @@ impl Change {
         if let Some(opts) = self.nixos_options {
             db.set_nixos_options_with_durability(Arc::new(opts), Durability::MEDIUM);
         }
+        if let Some(search_path) = self.search_path {
+            db.set_search_path_with_durability(Arc::new(search_path), Durability::MEDIUM);
+        }
+        if let Some(max_nesting) = self.max_nesting {
+            db.set_max_nesting_with_durability(max_nesting, Durability::MEDIUM);
+        }
+        if let Some(threshold) = self.lib_inherit_threshold {
+            db.set_lib_inherit_threshold_with_durability(threshold, Durability::MEDIUM);
+        }
         if let Some(roots) = self.roots {
-            u32::try_from(roots.len()).expect("Length overflow");
+            let cnt = u32::try_from(roots.len()).expect("Length overflow");
             for (sid, root) in (0u32..).map(SourceRootId).zip(roots) {
                 for (fid, _) in root.files() {
                     db.set_file_source_root_with_durability(fid, sid, Durability::HIGH);
                 }
                 db.set_source_root_with_durability(sid, Arc::new(root), Durability::HIGH);
             }
+            let ids = (0..cnt).map(SourceRootId).collect();
+            db.set_source_root_ids_with_durability(ids, Durability::HIGH);
         }
         for (file_id, content) in self.file_changes {
             db.set_file_content_with_durability(file_id, content, Durability::LOW);
@@ -0,0 +1,154 @@
+use super::{
+    resolve_import_file, BindingValue, DefDatabase, Expr, ExprId, Literal, NameId, ResolveResult,
+};
+use crate::FileId;
+use smol_str::SmolStr;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// References to names defined in other files through `import`, eg. `foo` in
+/// `(import ./lib.nix).foo`, `lib.foo` with `lib = import ./lib.nix`,
+/// or `inherit (import ./lib.nix) foo`.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct ImportReference {
+    refs: HashMap<(FileId, NameId), Vec<ImportRef>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportRef {
+    /// An attribute in the attrpath of `Select` or `HasAttr`.
+    Attr(ExprId),
+    /// A name bound by `inherit (...) name`.
+    Inherit(NameId),
+}
+
+impl ImportReference {
+    pub(crate) fn import_reference_query(db: &dyn DefDatabase, file_id: FileId) -> Arc<Self> {
+        let module = db.module(file_id);
+        let name_res = db.name_resolution(file_id);
+
+        // Names bound to imported files. Resolved once to avoid repeated lookups on heavy uses
+        // like `lib.foo`.
+        let mut imported_names = HashMap::new();
+        for (_, expr) in module.exprs() {
+            let (Expr::LetIn(bindings, _)
+            | Expr::Attrset(bindings)
+            | Expr::RecAttrset(bindings)
+            | Expr::LetAttrset(bindings)) = expr
+            else {
+                continue;
+            };
+            for &(name, value) in bindings.statics.iter() {
+                let (BindingValue::Expr(value) | BindingValue::Inherit(value)) = value else {
+                    continue;
+                };
+                if let Some(target) = resolve_import_file(db, file_id, value) {
+                    imported_names.insert(name, target);
+                }
+            }
+        }
+        let imported_file = |expr: ExprId| match &module[expr] {
+            Expr::Reference(_) => match name_res.get(expr)? {
+                ResolveResult::Definition(name) => imported_names.get(name).copied(),
+                _ => None,
+            },
+            _ => resolve_import_file(db, file_id, expr),
+        };
+
+        let mut this = Self::default();
+        for (_, expr) in module.exprs() {
+            match expr {
+                Expr::Select(set, attrpath, _) | Expr::HasAttr(set, attrpath) => {
+                    let Some(target) = imported_file(*set) else {
+                        continue;
+                    };
+                    let keys = attrpath
+                        .iter()
+                        .map_while(|&attr| match &module[attr] {
+                            Expr::Literal(Literal::String(key)) => Some((attr, key.clone())),
+                            _ => None,
+                        })
+                        .collect::<Vec<_>>();
+                    let names = imported_attr_names(db, target, keys.iter().map(|(_, key)| key));
+                    for ((attr, _), name) in keys.into_iter().zip(names) {
+                        this.refs
+                            .entry((target, name))
+                            .or_default()
+                            .push(ImportRef::Attr(attr));
+                    }
+                }
+                Expr::LetIn(bindings, _)
+                | Expr::Attrset(bindings)
+                | Expr::RecAttrset(bindings)
+                | Expr::LetAttrset(bindings) => {
+                    for &(name, value) in bindings.statics.iter() {
+                        let BindingValue::InheritFrom(idx) = value else {
+                            continue;
+                        };
+                        let Some(target) = imported_file(bindings.inherit_froms[idx]) else {
+                            continue;
+                        };
+                        let key = &module[name].text;
+                        if let Some(&target_name) = imported_attr_names(db, target, [key]).first() {
+                            this.refs
+                                .entry((target, target_name))
+                                .or_default()
+                                .push(ImportRef::Inherit(name));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        this.refs.shrink_to_fit();
+        Arc::new(this)
+    }
+
+    /// References to `name` of `file` in this file.
+    pub fn references(&self, file: FileId, name: NameId) -> &[ImportRef] {
+        self.refs.get(&(file, name)).map_or(&[], |refs| &**refs)
+    }
+
+    /// The name in another file which `attr_expr` refers to.
+    pub fn definition_for_attr(&self, attr_expr: ExprId) -> Option<(FileId, NameId)> {
+        self.refs
+            .iter()
+            .find_map(|(&def, refs)| refs.contains(&ImportRef::Attr(attr_expr)).then_some(def))
+    }
+}
+
+/// Follow static `keys` from the entry expression of `file` through nested attrsets,
+/// returning the names of each key found, until the first missing one.
+/// `let ... in` wrapping attrsets are also looked into.
+pub(crate) fn imported_attr_names<'a>(
+    db: &dyn DefDatabase,
+    file: FileId,
+    keys: impl IntoIterator<Item = &'a SmolStr>,
+) -> Vec<NameId> {
+    let module = db.module(file);
+    let mut set = Some(module.entry_expr());
+    let mut names = Vec::new();
+    for key in keys {
+        let Some(mut cur) = set.take() else {
+            break;
+        };
+        while let Expr::LetIn(_, body) = &module[cur] {
+            cur = *body;
+        }
+        let (Expr::Attrset(bindings) | Expr::RecAttrset(bindings)) = &module[cur] else {
+            break;
+        };
+        let Some(&(name, value)) = bindings
+            .statics
+            .iter()
+            .find(|(name, _)| module[*name].text == *key)
+        else {
+            break;
+        };
+        names.push(name);
+        if let BindingValue::Expr(value) = value {
+            set = Some(value);
+        }
+    }
+    names
+}
@@ -4,7 +4,7 @@ use std::sync::Arc;
 use if_chain::if_chain;
 use smol_str::SmolStr;
 
-use crate::{DefDatabase, FileId, Module};
+use crate::{DefDatabase, Diagnostic, DiagnosticKind, FileId, FileRange, Module};
 
 use super::{BindingValue, Expr, ExprId, NameId};
 
@@ -52,6 +52,58 @@ impl ModuleKind {
 
         Arc::new(guess(&module))
     }
+
+    /// Check shapes of values of reserved keys in NixOS modules.
+    /// Only values which are obviously of a wrong type are reported.
+    pub fn to_diagnostics(&self, db: &dyn DefDatabase, file: FileId) -> Vec<Diagnostic> {
+        let (Self::ConfigModule { lambda_expr } | Self::Config { lambda_expr }) = *self else {
+            return Vec::new();
+        };
+        let module = db.module(file);
+        let source_map = db.source_map(file);
+        let Expr::Lambda(_, _, body) = module[lambda_expr] else {
+            return Vec::new();
+        };
+        let (Expr::Attrset(bindings) | Expr::RecAttrset(bindings)) =
+            &module[peel_expr(&module, body)]
+        else {
+            return Vec::new();
+        };
+
+        let mut diags = Vec::new();
+        for &(name, value) in bindings.statics.iter() {
+            let (expect_list, expect_desc) = match &*module[name].text {
+                "imports" => (true, "a list"),
+                "config" | "options" => (false, "an attrset"),
+                _ => continue,
+            };
+            let value = match value {
+                BindingValue::Expr(e) => peel_expr(&module, e),
+                // `inherit config;` or `inherit (x) config;`.
+                BindingValue::Inherit(..) | BindingValue::InheritFrom(..) => continue,
+            };
+            let is_wrong = match &module[value] {
+                Expr::List(_) => !expect_list,
+                Expr::Attrset(_) | Expr::RecAttrset(_) => expect_list,
+                Expr::Literal(_)
+                | Expr::StringInterpolation(_)
+                | Expr::PathInterpolation(_)
+                | Expr::Lambda(..) => true,
+                _ => false,
+            };
+            let Some(ptr) = source_map.node_for_expr(value).filter(|_| is_wrong) else {
+                continue;
+            };
+            let range = ptr.text_range();
+            diags.push(
+                Diagnostic::new(range, DiagnosticKind::InvalidModuleKey).with_note(
+                    FileRange::new(file, range),
+                    format!("`{}` should be {expect_desc}", module[name].text),
+                ),
+            );
+        }
+        diags
+    }
 }
 
 fn parse_flake_nix(module: &Module) -> ModuleKind {
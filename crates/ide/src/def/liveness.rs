@@ -17,6 +17,7 @@
 //! - Unused `with` expressions.
 //! - Unnecessary `rec` attrsets.
 //! - Unused parameters of a package.
+//! - Unused parameters of other lambdas, as hints since removing them changes the signature.
 //!
 //! Notes:
 //! - All identifiers starting with `_` are skipped from warnings. This also includes Nix internals
@@ -38,9 +39,20 @@ pub struct LivenessCheckResult {
     names: Box<[NameId]>,
     withs: Box<[ExprId]>,
     rec_attrsets: Box<[ExprId]>,
+    params: Box<[NameId]>,
 }
 
 impl LivenessCheckResult {
+    /// Whether `name` is an unused binding which can be removed.
+    pub fn is_unused_binding(&self, name: NameId) -> bool {
+        self.names.contains(&name)
+    }
+
+    /// Whether `name` is an unused lambda parameter, whose removal changes the signature.
+    pub fn is_unused_param(&self, name: NameId) -> bool {
+        self.params.contains(&name)
+    }
+
     pub fn to_diagnostics<'a>(
         &'a self,
         db: &dyn DefDatabase,
@@ -55,6 +67,12 @@ impl LivenessCheckResult {
                 .flat_map(|&def| source_map.nodes_for_name(def))
                 .map(|ptr| Diagnostic::new(ptr.text_range(), DiagnosticKind::UnusedBinding)),
         );
+        diags.extend(
+            self.params
+                .iter()
+                .flat_map(|&param| source_map.nodes_for_name(param))
+                .map(|ptr| Diagnostic::new(ptr.text_range(), DiagnosticKind::UnusedParameter)),
+        );
         diags.extend(self.withs.iter().map(|&expr| {
             let ptr = source_map.node_for_expr(expr).unwrap();
             let node = ast::With::cast(ptr.to_node(&root)).unwrap();
@@ -164,6 +182,7 @@ pub(crate) fn liveness_check_query(
     // situation may be changed when user tries to fixing them.
     let mut unused_withs = Vec::new();
     let mut unused_recs = Vec::new();
+    let mut unused_params = Vec::new();
     for (expr, kind) in module.exprs() {
         match kind {
             Expr::Lambda(param, pat, _) => {
@@ -199,6 +218,15 @@ pub(crate) fn liveness_check_query(
                         }));
                     }
                 }
+                // `foo: ...` or `{ foo [, ...] }[@bar]: ...` of other lambdas.
+                //  ^                ^ Unused, but removing it changes the signature.
+                // An unused `@bar` is already reported above.
+                if must_use_params_expr != Some(expr) {
+                    match pat {
+                        None => unused_params.extend(*param),
+                        Some(pat) => unused_params.extend(pat.fields.iter().filter_map(|f| f.0)),
+                    }
+                }
             }
             &Expr::With(..) if visited_withs.get(expr).is_none() => {
                 unused_withs.push(expr);
@@ -215,12 +243,15 @@ pub(crate) fn liveness_check_query(
         }
     }
 
+    unused_params.retain(|&name| visited_defs.get(name).is_none());
     unused_defs.retain(|name| !should_ignore(&module[*name].text));
+    unused_params.retain(|name| !should_ignore(&module[*name].text));
 
     Arc::new(LivenessCheckResult {
         names: unused_defs.into(),
         withs: unused_withs.into(),
         rec_attrsets: unused_recs.into(),
+        params: unused_params.into(),
     })
 }
 
@@ -260,21 +291,57 @@ mod tests {
         // Shadowing.
         check("let $0a = 1; in let a = 2; in a");
         check("let $0a = 1; in let a = a/*self*/; in a");
+        check("let $0a = 1; in a: a");
+        check("let $0a = 1; in { a }: a");
         // Not shadowing.
         check("let   a = 1; in let inherit a; in a");
         // Mutual references.
         check("let $0a = b; $1b = a; in 1");
     }
 
+    #[test]
+    fn let_in_body() {
+        // Bindings referenced only by the body are alive.
+        check("let   a = 1; $0b = 2; in a");
+        check("let   a = 1; $0b = 2; in { c = a; }");
+        check(r#"let   a = 1; $0b = 2; in "${toString a}""#);
+        // References from dead bindings do not count.
+        check("let   a = 1; $0b = a; $1c = b; in a");
+    }
+
     #[test]
     fn lambda() {
-        check("a: { b }: $0c@{}: 0");
+        check("$0a: { $1b }: $2c@{}: 0");
+    }
+
+    #[test]
+    fn unused_param() {
+        check("{ $0a }@args: args");
+        check("{ $0a, ... }@args: args.b");
+        check("{ a, ... }@$0args: a");
+        check("{ _a, b }: b");
+        check("x: _: x");
+
+        let (db, file) = TestDB::single_file("{ a, b ? 1 }@args: x: args").unwrap();
+        let got = db
+            .liveness_check(file)
+            .to_diagnostics(&db, file)
+            .map(|diag| format!("{:?}: {:?} {:?}", diag.range, diag.kind, diag.severity()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            got,
+            [
+                "19..20: UnusedParameter Hint",
+                "2..3: UnusedParameter Hint",
+                "5..6: UnusedParameter Hint",
+            ]
+        );
     }
 
     #[test]
     fn with() {
         check("a: $0with 1; a");
-        check("a: with 1; with 2; b");
+        check("$0a: with 1; with 2; b");
     }
 
     #[test]
@@ -375,6 +442,23 @@ mod tests {
     fn underscore_names() {
         check("x: _y: x");
         check("let __findFile = 42; in <nixpkgs>");
+        check("let _a = 1; $0b = 2; in 1");
+    }
+
+    #[test]
+    fn used_in_interpolation() {
+        check(r#"let   a = 1; in "${a}""#);
+        check("{ a, $0b }: ''${toString a}''");
+        check(r#"{ a, ... }@$0args: "${a}/bin""#);
+        check(r#"let   a = "x"; in { ${a} = 1; }"#);
+    }
+
+    #[test]
+    fn not_captured_by_with() {
+        // Lexical bindings take precedence over `with`.
+        check("let   a = 1; in $0with { a = 2; }; a");
+        check("{ a }: $0with { }; a");
+        check("let $0a = 1; in with { b = 2; }; b");
     }
 
     // Issue #114
@@ -78,9 +78,17 @@ impl LowerCtx<'_> {
                 self.alloc_expr(lit.map_or(Expr::Missing, Expr::Literal), ptr)
             }
             ast::Expr::Ref(e) => {
-                let name = e
+                let name: SmolStr = e
                     .token()
                     .map_or_else(Default::default, |tok| tok.text().into());
+                // The keyword `or` after a non-select expression is parsed as an argument.
+                // Eg. `(a + b) or c` => `((a + b) or) c`
+                if name == "or" {
+                    self.diagnostic(Diagnostic::new(
+                        e.syntax().text_range(),
+                        DiagnosticKind::MisplacedOr,
+                    ));
+                }
                 self.alloc_expr(Expr::Reference(name), ptr)
             }
             ast::Expr::Apply(e) => {
@@ -1209,6 +1217,24 @@ mod tests {
         );
     }
 
+    #[test]
+    fn misplaced_or_error() {
+        check_error(
+            "(1 + 2) or 3",
+            expect![[r#"
+                8..10: MisplacedOr
+            "#]],
+        );
+        check_error(
+            "f x or 3",
+            expect![[r#"
+                4..6: MisplacedOr
+            "#]],
+        );
+        check_error("a.b or 3", expect![""]);
+        check_error("a.b.or or 3", expect![""]);
+    }
+
     #[test]
     fn invalid_dynamic_error() {
         check_error(
@@ -1276,6 +1302,23 @@ mod tests {
         );
     }
 
+    #[test]
+    fn attrset_nested_duplicated_error() {
+        check_error(
+            "{ a.b = 1; a.b = 2; }",
+            expect![[r#"
+                13..14: DuplicatedKey
+                    4..5: Previously defined here
+            "#]],
+        );
+        check_error("{ a.b = 1; a.c = 2; }", expect![""]);
+        // Dynamic keys cannot be compared statically.
+        check_error(
+            "{ ${x} = 1; ${x} = 2; a.${x} = 1; a.${x} = 2; }",
+            expect![""],
+        );
+    }
+
     #[test]
     fn attrset_no_duplicated_duplicated_error() {
         check_error(
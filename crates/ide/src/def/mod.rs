@@ -1,3 +1,4 @@
+mod imports;
 mod kind;
 mod liveness;
 mod lower;
@@ -14,14 +15,16 @@ use nix_interop::DEFAULT_IMPORT_FILE;
 use ordered_float::OrderedFloat;
 use smallvec::SmallVec;
 use smol_str::SmolStr;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::ops;
 use std::sync::Arc;
 use syntax::Parse;
 
+pub(crate) use self::imports::imported_attr_names;
+pub use self::imports::{ImportRef, ImportReference};
 pub use self::kind::ModuleKind;
 pub use self::liveness::LivenessCheckResult;
-pub use self::nameres::{ModuleScopes, NameReference, NameResolution, ResolveResult};
+pub use self::nameres::{ModuleScopes, NameReference, NameResolution, ResolveResult, ScopeId};
 pub use self::path::{Path, PathAnchor, PathData};
 pub use syntax::ast::{BinaryOpKind as BinaryOp, UnaryOpKind as UnaryOp};
 
@@ -44,6 +47,9 @@ pub trait DefDatabase: SourceDatabase {
     #[salsa::invoke(Module::module_references_query)]
     fn module_references(&self, file_id: FileId) -> Arc<HashSet<FileId>>;
 
+    #[salsa::invoke(Module::module_exports_query)]
+    fn module_exports(&self, file_id: FileId) -> Arc<[SmolStr]>;
+
     fn source_root_referrer_graph(
         &self,
         sid: SourceRootId,
@@ -51,6 +57,9 @@ pub trait DefDatabase: SourceDatabase {
 
     fn source_root_closure(&self, id: SourceRootId) -> Arc<HashSet<FileId>>;
 
+    #[salsa::invoke(ReachableFiles::reachable_files_query)]
+    fn reachable_files(&self, file_id: FileId) -> Arc<ReachableFiles>;
+
     // The result is not wrapped in Arc. Typically, the number of referrers is just 1 or 0.
     // And also this method is not call so often.
     fn module_referrers(&self, file_id: FileId) -> ModuleReferrers;
@@ -67,6 +76,9 @@ pub trait DefDatabase: SourceDatabase {
     #[salsa::invoke(NameReference::name_reference_query)]
     fn name_reference(&self, file_id: FileId) -> Arc<NameReference>;
 
+    #[salsa::invoke(ImportReference::import_reference_query)]
+    fn import_reference(&self, file_id: FileId) -> Arc<ImportReference>;
+
     #[salsa::invoke(liveness::liveness_check_query)]
     fn liveness_check(&self, file_id: FileId) -> Arc<LivenessCheckResult>;
 }
@@ -189,11 +201,32 @@ impl Module {
         self.names.iter()
     }
 
+    /// The value expression bound to a `let` or attrset name, if it is a plain binding or
+    /// an `inherit` without a source.
+    pub fn binding_value(&self, name: NameId) -> Option<ExprId> {
+        self.exprs().find_map(|(_, expr)| {
+            let bindings = match expr {
+                Expr::LetIn(bindings, _)
+                | Expr::Attrset(bindings)
+                | Expr::RecAttrset(bindings)
+                | Expr::LetAttrset(bindings) => bindings,
+                _ => return None,
+            };
+            bindings
+                .statics
+                .iter()
+                .find(|&&(n, _)| n == name)
+                .and_then(|&(_, value)| match value {
+                    BindingValue::Expr(e) | BindingValue::Inherit(e) => Some(e),
+                    BindingValue::InheritFrom(_) => None,
+                })
+        })
+    }
+
     pub(crate) fn module_references_query(
         db: &dyn DefDatabase,
         file_id: FileId,
     ) -> Arc<HashSet<FileId>> {
-        let source_root = db.source_root(db.file_source_root(file_id));
         let mut refs = db
             .module(file_id)
             .exprs()
@@ -201,16 +234,138 @@ impl Module {
                 let &Expr::Literal(Literal::Path(path)) = kind else {
                     return None;
                 };
-                let mut vpath = path.resolve(db)?;
-                source_root.file_for_path(&vpath).or_else(|| {
-                    vpath.push(DEFAULT_IMPORT_FILE)?;
-                    source_root.file_for_path(&vpath)
-                })
+                resolve_path_file(db, file_id, path)
             })
             .collect::<HashSet<_>>();
         refs.shrink_to_fit();
         Arc::new(refs)
     }
+
+    /// Static keys of the attrset a file evaluates to, looking through `let`-in, like `foo` in
+    /// `let ... in { foo = 1; }`. Empty if the file is not such an attrset.
+    pub(crate) fn module_exports_query(db: &dyn DefDatabase, file_id: FileId) -> Arc<[SmolStr]> {
+        let module = db.module(file_id);
+        let mut entry = module.entry_expr();
+        while let Expr::LetIn(_, body) = &module[entry] {
+            entry = *body;
+        }
+        let (Expr::Attrset(bindings) | Expr::RecAttrset(bindings)) = &module[entry] else {
+            return Arc::new([]);
+        };
+        bindings
+            .statics
+            .iter()
+            .map(|&(name, _)| module[name].text.clone())
+            .collect()
+    }
+}
+
+/// Resolve the file a path literal in `file_id` refers to when imported, falling back to
+/// `default.nix` for directories.
+/// Files in the source root of `file_id` are preferred, then files in other roots.
+pub(crate) fn resolve_path_file(
+    db: &dyn DefDatabase,
+    file_id: FileId,
+    path: Path,
+) -> Option<FileId> {
+    let sid = db.file_source_root(file_id);
+    let root_ids = db.source_root_ids();
+    let roots = std::iter::once(sid)
+        .chain(root_ids.iter().copied().filter(|&id| id != sid))
+        .map(|id| db.source_root(id))
+        .collect::<Vec<_>>();
+    let file_for_path = |vpath: &VfsPath| {
+        roots
+            .iter()
+            .find_map(|source_root| source_root.file_for_path(vpath))
+    };
+    let mut vpath = path.resolve(db)?;
+    file_for_path(&vpath).or_else(|| {
+        vpath.push(DEFAULT_IMPORT_FILE)?;
+        file_for_path(&vpath)
+    })
+}
+
+/// Check if `to` can be reached from `from` through file references.
+pub(crate) fn is_reachable(db: &dyn DefDatabase, from: FileId, to: FileId) -> bool {
+    db.reachable_files(from).set.contains(&to)
+}
+
+/// Files reachable from `file` by path references, including itself, in a stable order.
+pub(crate) fn reachable_files(db: &dyn DefDatabase, file: FileId) -> Vec<FileId> {
+    db.reachable_files(file).ordered.clone()
+}
+
+/// Files reachable from a file by path references, including itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReachableFiles {
+    /// In breadth-first order, with references of each file sorted.
+    ordered: Vec<FileId>,
+    set: HashSet<FileId>,
+}
+
+impl ReachableFiles {
+    pub(crate) fn reachable_files_query(db: &dyn DefDatabase, file: FileId) -> Arc<Self> {
+        let mut set = HashSet::from([file]);
+        let mut ordered = vec![file];
+        let mut i = 0;
+        while let Some(&cur) = ordered.get(i) {
+            i += 1;
+            let refs = db.module_references(cur);
+            let new_refs = refs
+                .iter()
+                .copied()
+                .filter(|f| set.insert(*f))
+                .collect::<BTreeSet<_>>();
+            ordered.extend(new_refs);
+        }
+        ordered.shrink_to_fit();
+        set.shrink_to_fit();
+        Arc::new(Self { ordered, set })
+    }
+}
+
+/// Resolve the file imported by `expr` in `file_id`, which is either `import <path>` or a
+/// reference to a name bound to one, like `pkgs` in `let pkgs = import ./pkgs.nix; in pkgs`.
+pub(crate) fn resolve_import_file(
+    db: &dyn DefDatabase,
+    file_id: FileId,
+    expr: ExprId,
+) -> Option<FileId> {
+    resolve_path_file(db, file_id, resolve_import_path(db, file_id, expr)?)
+}
+
+/// Like `resolve_import_file`, but return the imported path, which may not be loaded.
+pub(crate) fn resolve_import_path(
+    db: &dyn DefDatabase,
+    file_id: FileId,
+    mut expr: ExprId,
+) -> Option<Path> {
+    let module = db.module(file_id);
+    let name_res = db.name_resolution(file_id);
+    // Avoid infinite loops on self-references like `let a = a; in a`.
+    let mut visited = HashSet::new();
+    while visited.insert(expr) {
+        match &module[expr] {
+            &Expr::Apply(lam, arg) => {
+                if name_res.check_builtin(lam, &module) != Some("import") {
+                    return None;
+                }
+                let &Expr::Literal(Literal::Path(path)) = &module[arg] else {
+                    return None;
+                };
+                return Some(path);
+            }
+            Expr::Reference(_) => {
+                let &ResolveResult::Definition(name) = name_res.get(expr)? else {
+                    return None;
+                };
+                expr = module.binding_value(name)?;
+            }
+            _ => return None,
+        }
+    }
+    None
 }
 
 pub type AstPtr = syntax::SyntaxNodePtr;
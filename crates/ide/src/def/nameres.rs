@@ -1,5 +1,5 @@
 use super::{BindingValue, Bindings, DefDatabase, Expr, ExprId, Module, NameId};
-use crate::{Diagnostic, DiagnosticKind, FileId};
+use crate::{Diagnostic, DiagnosticKind, FileId, FileRange};
 use builtin::ALL_BUILTINS;
 use if_chain::if_chain;
 use la_arena::{Arena, ArenaMap, Idx};
@@ -7,6 +7,7 @@ use smol_str::SmolStr;
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use std::{iter, ops};
+use syntax::ast::{self, AstNode};
 
 #[derive(Default, Debug, Clone, PartialEq, Eq)]
 pub struct ModuleScopes {
@@ -318,15 +319,44 @@ impl NameResolution {
         db: &dyn DefDatabase,
         file_id: FileId,
     ) -> impl Iterator<Item = Diagnostic> + '_ {
+        let module = db.module(file_id);
         let source_map = db.source_map(file_id);
-        self.resolve_map
-            .iter()
-            .filter(|(_, res)| res.is_none())
-            .filter_map(move |(&e, _)| {
-                let ptr = source_map.node_for_expr(e)?;
-                let range = ptr.text_range();
-                Some(Diagnostic::new(range, DiagnosticKind::UndefinedName))
-            })
+        let root = db.parse(file_id).syntax_node();
+        let src = db.file_content(file_id);
+        self.resolve_map.iter().filter_map(move |(&e, res)| {
+            let ptr = source_map.node_for_expr(e)?;
+            let range = ptr.text_range();
+            let withs = match res {
+                None => return Some(Diagnostic::new(range, DiagnosticKind::UndefinedName)),
+                Some(ResolveResult::WithExprs(withs)) => withs,
+                Some(_) => return None,
+            };
+            // Names from `with builtins;` are still statically known.
+            if self.check_builtin(e, &module).is_some() {
+                return None;
+            }
+            let diag = withs.iter().fold(
+                Diagnostic::new(range, DiagnosticKind::NameFromWith),
+                |diag, &with_expr| {
+                    let Some(with_node) = source_map
+                        .node_for_expr(with_expr)
+                        .and_then(|ptr| ast::With::cast(ptr.to_node(&root)))
+                    else {
+                        return diag;
+                    };
+                    let header = match (with_node.with_token(), with_node.semicolon_token()) {
+                        (Some(start), Some(end)) => start.text_range().cover(end.text_range()),
+                        _ => with_node.syntax().text_range(),
+                    };
+                    let text = &src[header];
+                    diag.with_note(
+                        FileRange::new(file_id, header),
+                        format!("It may come from `{text}`"),
+                    )
+                },
+            );
+            Some(diag)
+        })
     }
 }
 
@@ -1,5 +1,6 @@
 use super::DefDatabase;
 use crate::{FileId, VfsPath};
+use nix_interop::DEFAULT_IMPORT_FILE;
 use smol_str::SmolStr;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -19,8 +20,14 @@ impl Path {
         let data = path.data(db);
         let file = match &data.anchor {
             &PathAnchor::Relative(file) => file,
+            PathAnchor::Absolute => {
+                let mut vpath = VfsPath::new("/");
+                vpath.push(&data.relative_path)?;
+                return Some(vpath);
+            }
+            PathAnchor::Search(name) => return resolve_search_path(db, name, &data),
             // TODO
-            PathAnchor::Absolute | PathAnchor::Home | PathAnchor::Search(_) => return None,
+            PathAnchor::Home => return None,
         };
 
         let sid = db.file_source_root(file);
@@ -49,6 +56,57 @@ impl Path {
     }
 }
 
+/// Resolve `<name/relative_path>` like Nix does with `NIX_PATH`.
+///
+/// Nix picks the first entry where the target exists, but we only know about loaded files. So
+/// the first candidate with a loaded file (or `default.nix`) is preferred, then the first
+/// candidate from an entry with a matching prefix.
+fn resolve_search_path(db: &dyn DefDatabase, name: &str, data: &PathData) -> Option<VfsPath> {
+    // `<name/..>` escapes the search path entry.
+    if data.supers != 0 {
+        return None;
+    }
+    let full_path = if data.relative_path.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{name}/{}", data.relative_path)
+    };
+
+    let search_path = db.search_path();
+    let candidates = search_path
+        .entries
+        .iter()
+        .filter_map(|entry| {
+            if entry.prefix.is_empty() {
+                return Some((false, entry.path.join(&full_path)?));
+            }
+            let rest = full_path.strip_prefix(&*entry.prefix)?;
+            if rest.is_empty() {
+                return Some((true, entry.path.clone()));
+            }
+            Some((true, entry.path.join(rest.strip_prefix('/')?)?))
+        })
+        .collect::<Vec<_>>();
+
+    let root_ids = db.source_root_ids();
+    let is_loaded = |vpath: &VfsPath| {
+        let file_for_path = |vpath: &VfsPath| {
+            root_ids
+                .iter()
+                .any(|&sid| db.source_root(sid).file_for_path(vpath).is_some())
+        };
+        file_for_path(vpath)
+            || vpath
+                .join(DEFAULT_IMPORT_FILE)
+                .is_some_and(|vpath| file_for_path(&vpath))
+    };
+    candidates
+        .iter()
+        .find(|(_, vpath)| is_loaded(vpath))
+        .or_else(|| candidates.iter().find(|(prefixed, _)| *prefixed))
+        .map(|(_, vpath)| vpath.clone())
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct PathData {
     anchor: PathAnchor,
@@ -58,6 +116,10 @@ pub struct PathData {
 }
 
 impl PathData {
+    pub fn anchor(&self) -> &PathAnchor {
+        &self.anchor
+    }
+
     pub(crate) fn normalize(anchor: PathAnchor, segments: &str) -> Self {
         let mut relative_path = String::with_capacity(segments.len());
         let mut supers = 0u8;
@@ -1,10 +1,13 @@
 use super::DefDatabase;
 use crate::tests::TestDB;
-use crate::{FlakeInfo, ModuleKind, SourceDatabase, VfsPath};
+use crate::{
+    Change, FileId, FileSet, FlakeGraph, FlakeInfo, ModuleKind, SourceDatabase, SourceRoot, VfsPath,
+};
 use expect_test::expect;
 use itertools::Itertools;
 use std::collections::{HashMap, HashSet};
 use std::fmt::Write;
+use std::sync::Arc;
 
 #[test]
 fn change_barrier() {
@@ -90,6 +93,45 @@ baz/../../bar.nix + ../default.nix
     }
 }
 
+#[test]
+fn cross_root_module_references() {
+    let mut db = TestDB::default();
+    let mut change = Change::default();
+    let files = [
+        ("/flake/default.nix", "import ../nixpkgs + ./foo.nix"),
+        ("/flake/foo.nix", "42"),
+        ("/nixpkgs/default.nix", "../flake/foo.nix"),
+    ];
+    let mut file_sets = [FileSet::default(), FileSet::default()];
+    for (i, (path, text)) in (0u32..).zip(files) {
+        let root = usize::from(!path.starts_with("/flake/"));
+        file_sets[root].insert(FileId(i), VfsPath::new(path));
+        change.change_file(FileId(i), text.into());
+    }
+    change.set_roots(
+        file_sets
+            .into_iter()
+            .map(|file_set| SourceRoot::new_local(file_set, None))
+            .collect(),
+    );
+    change.set_flake_graph(FlakeGraph::default());
+    db.set_nixos_options(Arc::default());
+    change.apply(&mut db);
+
+    let refs = |file: u32| {
+        let mut refs = db
+            .module_references(FileId(file))
+            .iter()
+            .map(|f| f.0)
+            .collect::<Vec<_>>();
+        refs.sort();
+        refs
+    };
+    assert_eq!(refs(0), [1, 2]);
+    assert_eq!(refs(1), [0u32; 0]);
+    assert_eq!(refs(2), [1]);
+}
+
 #[test]
 fn source_root_referrer_graph() {
     let (db, f) = TestDB::from_fixture(
@@ -231,3 +273,34 @@ fn module_kind() {
         HashSet::from_iter(["nixpkgs".into(), "nix".into()]),
     );
 }
+
+#[test]
+fn reachable_files_computed_once() {
+    use crate::ty::TyDatabase;
+
+    let (db, f) = TestDB::from_fixture(
+        "
+#- /default.nix
+[ (import ./a.nix) (import ./b.nix) (import ./c.nix) ]
+
+#- /a.nix
+import ./c.nix
+
+#- /b.nix
+[ (import ./c.nix) (import ./a.nix) ]
+
+#- /c.nix
+42
+    ",
+    )
+    .unwrap();
+    let executed = db.log_executed(|| {
+        db.infer(f["/default.nix"]);
+    });
+    let reachable = executed
+        .iter()
+        .filter(|q| q.starts_with("reachable_files("))
+        .collect::<Vec<_>>();
+    assert_eq!(reachable.len(), 3, "{executed:#?}");
+    assert!(reachable.iter().all_unique(), "{executed:#?}");
+}
@@ -24,26 +24,91 @@ pub enum DiagnosticKind {
     UriLiteral,
     MergePlainRecAttrset,
     MergeRecAttrset,
+    MisplacedOr,
 
     // Name resolution.
     UndefinedName,
+    NameFromWith,
+    WithMaskedBuiltin,
 
     // Liveness.
     UnusedBinding,
     UnusedWith,
     UnusedRec,
+    UnusedParameter,
+
+    // Flake checks.
+    UnknownFlakeOutput,
+    MissingFlakeSystem,
+    UnlockedFlakeInput,
+    UnusedLockedInput,
+    MissingModuleFile,
+
+    // Module checks.
+    InvalidModuleKey,
+
+    // Builtin calls.
+    ReplaceStringsLengthMismatch,
+
+    // Attrset updates. Opt-in.
+    DuplicatedUpdateKey,
+
+    // Style. Opt-in.
+    DeepNesting,
+    ManyLibSelects,
+    RedundantLetIn,
+    RedundantIf,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Severity {
+    Hint,
+    Info,
     Warning,
     Error,
     IncompleteSyntax,
 }
 
+impl DiagnosticKind {
+    pub fn code(&self) -> &'static str {
+        match self {
+            DiagnosticKind::SyntaxError(_) => "syntax_error",
+            DiagnosticKind::InvalidDynamic => "invalid_dynamic",
+            DiagnosticKind::DuplicatedKey => "duplicated_key",
+            DiagnosticKind::DuplicatedParam => "duplicated_param",
+            DiagnosticKind::EmptyInherit => "empty_inherit",
+            DiagnosticKind::EmptyLetIn => "empty_let_in",
+            DiagnosticKind::LetAttrset => "let_attrset",
+            DiagnosticKind::UriLiteral => "uri_literal",
+            DiagnosticKind::MergePlainRecAttrset => "merge_plain_rec_attrset",
+            DiagnosticKind::MergeRecAttrset => "merge_rec_attrset",
+            DiagnosticKind::MisplacedOr => "misplaced_or",
+            DiagnosticKind::UndefinedName => "undefined_name",
+            DiagnosticKind::NameFromWith => "name_from_with",
+            DiagnosticKind::WithMaskedBuiltin => "with_masked_builtin",
+            DiagnosticKind::UnusedBinding => "unused_binding",
+            DiagnosticKind::UnusedWith => "unused_with",
+            DiagnosticKind::UnusedRec => "unused_rec",
+            DiagnosticKind::UnusedParameter => "unused_parameter",
+            DiagnosticKind::UnknownFlakeOutput => "unknown_flake_output",
+            DiagnosticKind::MissingFlakeSystem => "missing_flake_system",
+            DiagnosticKind::UnlockedFlakeInput => "unlocked_flake_input",
+            DiagnosticKind::UnusedLockedInput => "unused_locked_input",
+            DiagnosticKind::MissingModuleFile => "missing_module_file",
+            DiagnosticKind::InvalidModuleKey => "invalid_module_key",
+            DiagnosticKind::ReplaceStringsLengthMismatch => "replace_strings_length_mismatch",
+            DiagnosticKind::DuplicatedUpdateKey => "duplicated_update_key",
+            DiagnosticKind::DeepNesting => "deep_nesting",
+            DiagnosticKind::ManyLibSelects => "many_lib_selects",
+            DiagnosticKind::RedundantLetIn => "redundant_let_in",
+            DiagnosticKind::RedundantIf => "redundant_if",
+        }
+    }
+}
+
 impl Severity {
     pub fn is_fatal(self) -> bool {
-        self != Self::Warning
+        self >= Self::Error
     }
 }
 
@@ -62,22 +127,7 @@ impl Diagnostic {
     }
 
     pub fn code(&self) -> &'static str {
-        match self.kind {
-            DiagnosticKind::SyntaxError(_) => "syntax_error",
-            DiagnosticKind::InvalidDynamic => "invalid_dynamic",
-            DiagnosticKind::DuplicatedKey => "duplicated_key",
-            DiagnosticKind::DuplicatedParam => "duplicated_param",
-            DiagnosticKind::EmptyInherit => "empty_inherit",
-            DiagnosticKind::EmptyLetIn => "empty_let_in",
-            DiagnosticKind::LetAttrset => "let_attrset",
-            DiagnosticKind::UriLiteral => "uri_literal",
-            DiagnosticKind::MergePlainRecAttrset => "merge_plain_rec_attrset",
-            DiagnosticKind::MergeRecAttrset => "merge_rec_attrset",
-            DiagnosticKind::UndefinedName => "undefined_name",
-            DiagnosticKind::UnusedBinding => "unused_binding",
-            DiagnosticKind::UnusedWith => "unused_with",
-            DiagnosticKind::UnusedRec => "unused_rec",
-        }
+        self.kind.code()
     }
 
     pub fn severity(&self) -> Severity {
@@ -86,16 +136,32 @@ impl Diagnostic {
             | DiagnosticKind::InvalidDynamic
             | DiagnosticKind::DuplicatedKey
             | DiagnosticKind::DuplicatedParam
-            | DiagnosticKind::UndefinedName => Severity::Error,
+            | DiagnosticKind::UndefinedName
+            | DiagnosticKind::MissingFlakeSystem
+            | DiagnosticKind::UnlockedFlakeInput
+            | DiagnosticKind::MissingModuleFile => Severity::Error,
             DiagnosticKind::EmptyInherit
             | DiagnosticKind::EmptyLetIn
             | DiagnosticKind::LetAttrset
             | DiagnosticKind::UriLiteral
             | DiagnosticKind::MergePlainRecAttrset
             | DiagnosticKind::MergeRecAttrset
+            | DiagnosticKind::MisplacedOr
             | DiagnosticKind::UnusedBinding
             | DiagnosticKind::UnusedWith
-            | DiagnosticKind::UnusedRec => Severity::Warning,
+            | DiagnosticKind::UnusedRec
+            | DiagnosticKind::UnknownFlakeOutput
+            | DiagnosticKind::UnusedLockedInput
+            | DiagnosticKind::InvalidModuleKey
+            | DiagnosticKind::ReplaceStringsLengthMismatch
+            | DiagnosticKind::DeepNesting => Severity::Warning,
+            DiagnosticKind::DuplicatedUpdateKey => Severity::Info,
+            DiagnosticKind::NameFromWith
+            | DiagnosticKind::WithMaskedBuiltin
+            | DiagnosticKind::UnusedParameter
+            | DiagnosticKind::ManyLibSelects
+            | DiagnosticKind::RedundantLetIn
+            | DiagnosticKind::RedundantIf => Severity::Hint,
         }
     }
 
@@ -120,12 +186,47 @@ impl Diagnostic {
             DiagnosticKind::MergeRecAttrset => {
                 "Merging rec-attrset with other attrsets or attrpath. Merged values can unexpectedly reference each other remotely as in a single `rec { ... }`"
             }
+            DiagnosticKind::MisplacedOr => {
+                "`or` only provides a default for attribute selections like `x.y or default`. Here it is parsed as an argument named `or`"
+            }
 
             DiagnosticKind::UndefinedName => "Undefined name",
+            DiagnosticKind::NameFromWith => "Name is not statically known and may come from `with`",
+            DiagnosticKind::WithMaskedBuiltin => {
+                "`with` provides attributes named after builtins, which are never used since builtins take precedence"
+            }
 
             DiagnosticKind::UnusedBinding => "Unused binding",
             DiagnosticKind::UnusedWith => "Unused `with`",
             DiagnosticKind::UnusedRec => "Unused `rec`",
+            DiagnosticKind::UnusedParameter => "Unused parameter",
+
+            DiagnosticKind::UnknownFlakeOutput => "Unknown flake output",
+            DiagnosticKind::MissingFlakeSystem => "Missing the system level of flake outputs",
+            DiagnosticKind::UnlockedFlakeInput => "Input is not locked in flake.lock",
+            DiagnosticKind::UnusedLockedInput => "Locked input is not declared in flake.nix",
+            DiagnosticKind::MissingModuleFile => "Module file not found",
+
+            DiagnosticKind::InvalidModuleKey => "Invalid value type for a reserved module key",
+
+            DiagnosticKind::ReplaceStringsLengthMismatch => {
+                "`from` and `to` lists of `replaceStrings` have different lengths"
+            }
+
+            DiagnosticKind::DuplicatedUpdateKey => {
+                "Attribute is defined in multiple operands of `//`, only the rightmost one is kept"
+            }
+
+            DiagnosticKind::DeepNesting => "Attrset is nested too deeply",
+            DiagnosticKind::ManyLibSelects => {
+                "Many attributes of `lib` are selected. Consider `inherit (lib) ...` instead"
+            }
+            DiagnosticKind::RedundantLetIn => {
+                "`let` of a single binding which is only returned. Use the value directly instead"
+            }
+            DiagnosticKind::RedundantIf => {
+                "`if` returning boolean literals. Use the condition or its negation instead"
+            }
         }
         .into()
     }
@@ -137,6 +238,22 @@ impl Diagnostic {
                 | DiagnosticKind::UnusedBinding
                 | DiagnosticKind::UnusedWith
                 | DiagnosticKind::UnusedRec
+                | DiagnosticKind::UnusedParameter
+        )
+    }
+
+    /// Opt-in diagnostics are only reported when enabled explicitly, since they are often
+    /// intended.
+    pub fn is_opt_in(&self) -> bool {
+        matches!(
+            self.kind,
+            DiagnosticKind::DuplicatedUpdateKey
+                | DiagnosticKind::NameFromWith
+                | DiagnosticKind::WithMaskedBuiltin
+                | DiagnosticKind::DeepNesting
+                | DiagnosticKind::ManyLibSelects
+                | DiagnosticKind::RedundantLetIn
+                | DiagnosticKind::RedundantIf
         )
     }
 
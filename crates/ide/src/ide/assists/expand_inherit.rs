@@ -0,0 +1,188 @@
+//! Expand `inherit key;` into `key = key;`, the reverse of `convert_to_inherit`.
+//!
+//! ```nix
+//! { inherit foo; inherit (bar) baz; }
+//! ```
+//! =>
+//! ```nix
+//! { foo = foo; baz = bar.baz; }
+//! ```
+//!
+//! If the cursor is on one of multiple attributes, only that one is split out.
+//! Plain `inherit` in `rec` attrsets and `let` are not expanded, since `foo = foo;` there would
+//! be an infinite recursion.
+use super::{AssistKind, AssistsCtx};
+use crate::TextEdit;
+use itertools::Itertools;
+use syntax::ast::{self, AstNode};
+use syntax::semantic::{escape_literal_attr, is_valid_ident, AttrKind};
+use syntax::{SyntaxKind, TextRange};
+
+pub(super) fn expand_inherit(ctx: &mut AssistsCtx<'_>) -> Option<()> {
+    let inherit = ctx.covering_node::<ast::Inherit>()?;
+    let all_attrs = inherit.attrs().collect::<Vec<_>>();
+    let selected = ctx
+        .covering_node::<ast::Attr>()
+        .filter(|attr| attr.syntax().parent().as_ref() == Some(inherit.syntax()));
+
+    let from_frag = match inherit.from_expr() {
+        Some(paren) => {
+            let need_paren = !matches!(
+                paren.expr()?,
+                ast::Expr::Ref(_) | ast::Expr::AttrSet(_) | ast::Expr::Paren(_)
+            ) && !matches!(paren.expr()?, ast::Expr::Select(e) if e.or_token().is_none());
+            let from = if need_paren {
+                paren.syntax().to_string()
+            } else {
+                paren.expr()?.syntax().to_string()
+            };
+            Some(from)
+        }
+        None => {
+            let is_rec = inherit.syntax().parent().is_some_and(|p| match p.kind() {
+                SyntaxKind::LET_IN => true,
+                SyntaxKind::ATTR_SET => ast::AttrSet::cast(p)
+                    .and_then(|set| set.rec_token())
+                    .is_some(),
+                _ => false,
+            });
+            if is_rec {
+                return None;
+            }
+            None
+        }
+    };
+
+    let binding_for = |attr: &ast::Attr| -> Option<String> {
+        let AttrKind::Static(Some(name)) = AttrKind::of(attr.clone()) else {
+            return None;
+        };
+        let key = escape_literal_attr(&name);
+        Some(match &from_frag {
+            Some(from) => format!("{key} = {from}.{key};"),
+            // The value must be referable by name.
+            None if is_valid_ident(&name) => format!("{name} = {name};"),
+            None => return None,
+        })
+    };
+
+    let (label, edits) = match selected {
+        Some(attr) if all_attrs.len() > 1 => {
+            let insert = binding_for(&attr)?;
+            // Remove the attribute with the whitespace before it.
+            let mut range = attr.syntax().text_range();
+            if let Some(ws) = attr
+                .syntax()
+                .first_token()?
+                .prev_token()
+                .filter(|tok| tok.kind() == SyntaxKind::SPACE)
+            {
+                range = range.cover(ws.text_range());
+            }
+            let end = inherit.syntax().text_range().end();
+            (
+                format!("Expand `{}` out of `inherit`", attr.syntax()),
+                vec![
+                    TextEdit {
+                        delete: range,
+                        insert: "".into(),
+                    },
+                    TextEdit {
+                        delete: TextRange::empty(end),
+                        insert: format!(" {insert}").into(),
+                    },
+                ],
+            )
+        }
+        _ => {
+            let insert = all_attrs
+                .iter()
+                .map(binding_for)
+                .collect::<Option<Vec<_>>>()?;
+            if insert.is_empty() {
+                return None;
+            }
+            (
+                "Expand `inherit` into bindings".into(),
+                vec![TextEdit {
+                    delete: inherit.syntax().text_range(),
+                    insert: insert.into_iter().join(" ").into(),
+                }],
+            )
+        }
+    };
+
+    ctx.add("expand_inherit", label, AssistKind::RefactorRewrite, edits);
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::expect;
+
+    define_check_assist!(super::expand_inherit);
+
+    #[test]
+    fn simple() {
+        check("{ $0inherit foo; }", expect!["{ foo = foo; }"]);
+        check("{ inherit f$0oo; }", expect!["{ foo = foo; }"]);
+        check(
+            "{ inherit foo bar;$0 }",
+            expect!["{ foo = foo; bar = bar; }"],
+        );
+        check(
+            r#"{ $0inherit "foo" ${"bar"}; }"#,
+            expect!["{ foo = foo; bar = bar; }"],
+        );
+    }
+
+    #[test]
+    fn single_attr() {
+        check(
+            "{ inherit foo b$0ar baz; }",
+            expect!["{ inherit foo baz; bar = bar; }"],
+        );
+        check(
+            "{ inherit (x) foo $0bar; }",
+            expect!["{ inherit (x) foo; bar = x.bar; }"],
+        );
+    }
+
+    #[test]
+    fn from() {
+        check(
+            "{ inherit (x)$0 foo bar; }",
+            expect!["{ foo = x.foo; bar = x.bar; }"],
+        );
+        check("{ inherit (x.y) $0foo; }", expect!["{ foo = x.y.foo; }"]);
+        check(
+            "{ inherit (import ./a.nix) $0foo; }",
+            expect!["{ foo = (import ./a.nix).foo; }"],
+        );
+        check(
+            r#"{ inherit (x) $0"a b"; }"#,
+            expect![[r#"{ "a b" = x."a b"; }"#]],
+        );
+        check(
+            "let inherit (x) $0foo; in foo",
+            expect!["let foo = x.foo; in foo"],
+        );
+        check(
+            "rec { inherit (x) $0foo; }",
+            expect!["rec { foo = x.foo; }"],
+        );
+    }
+
+    #[test]
+    fn no_recursion() {
+        check_no("rec { inherit $0foo; }");
+        check_no("let inherit $0foo; in foo");
+    }
+
+    #[test]
+    fn not_applicable() {
+        check_no("{ $0inherit; }");
+        check_no(r#"{ inherit $0"a b"; }"#);
+        check_no("{ foo = $0foo; }");
+    }
+}
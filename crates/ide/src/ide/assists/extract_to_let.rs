@@ -0,0 +1,444 @@
+//! Extract the selected expression, or the expression under the cursor, into a `let` binding.
+//!
+//! ```nix
+//! { pkgs }:
+//! pkgs.stdenv.mkDerivation { }
+//! ```
+//! =>
+//! ```nix
+//! { pkgs }:
+//! let
+//!   stdenv = pkgs.stdenv;
+//! in
+//! stdenv.mkDerivation { }
+//! ```
+//!
+//! The binding is added to the nearest enclosing `let`, or wraps the nearest enclosing body of
+//! a lambda, a `with` or the file, whichever still sees all variables the expression uses.
+//! The name is the last attribute of a select expression if possible, otherwise `value`,
+//! with primes appended if it is already used in this file.
+use super::{
+    insert_let_binding, line_indent, outer_references, reindent, resolves_to, AssistKind,
+    AssistsCtx,
+};
+use crate::def::{AstPtr, Expr, ExprId, Literal, Module, ModuleScopes, ScopeId};
+use crate::TextEdit;
+use std::collections::HashSet;
+use syntax::ast::{self, AstNode, HasBindings};
+use syntax::semantic::is_valid_ident;
+use syntax::{
+    best_token_at_offset, NodeOrToken, SyntaxKind, SyntaxNode, SyntaxToken, TextRange, TextSize,
+};
+
+const DEFAULT_NAME: &str = "value";
+
+pub(super) fn extract_to_let(ctx: &mut AssistsCtx<'_>) -> Option<()> {
+    let target = target_node(ctx)?;
+    let file_id = ctx.frange.file_id;
+    let module = ctx.db.module(file_id);
+    let source_map = ctx.db.source_map(file_id);
+    let nameres = ctx.db.name_resolution(file_id);
+    let scopes = ctx.db.scopes(file_id);
+
+    let inner = target.clone().flatten_paren()?;
+    let target_expr = source_map.expr_for_node(AstPtr::new(inner.syntax()))?;
+    if matches!(module[target_expr], Expr::Reference(_) | Expr::Missing) {
+        return None;
+    }
+    let (first, last) = trimmed_bounds(target.syntax())?;
+    let target_range = first.text_range().cover(last.text_range());
+
+    let outer_refs = outer_references(&module, &source_map, &nameres, target_expr, target_range);
+    let sees_all = |scope: ScopeId| {
+        outer_refs
+            .iter()
+            .all(|(text, resolved)| resolves_to(&scopes, scope, text, resolved))
+    };
+
+    // Find the nearest enclosing place for the binding.
+    let mut anchor = None;
+    for node in target.syntax().ancestors().skip(1) {
+        let Some(expr) = ast::Expr::cast(node) else {
+            continue;
+        };
+        let candidate = match expr {
+            ast::Expr::LetIn(let_in) => Anchor::LetIn(let_in),
+            ast::Expr::Lambda(lam) => Anchor::Body(lam.body()?),
+            ast::Expr::With(with) => Anchor::Body(with.body()?),
+            _ => continue,
+        };
+        if let Anchor::Body(body) = &candidate {
+            if !body.syntax().text_range().contains_range(target_range) {
+                continue;
+            }
+        }
+        if sees_all(candidate.scope(&module, &source_map, &scopes)?) {
+            anchor = Some(candidate);
+            break;
+        }
+    }
+    let anchor = match anchor {
+        Some(anchor) => anchor,
+        None => {
+            let root = Anchor::Body(ctx.ast.expr()?);
+            if !sees_all(root.scope(&module, &source_map, &scopes)?) {
+                return None;
+            }
+            root
+        }
+    };
+
+    let name = unique_name(&module, &suggest_name(&module, target_expr));
+    let (first, last) = trimmed_bounds(inner.syntax())?;
+    let value_indent = line_indent(&first);
+    let reindented_value = |to: &str| Some(reindent(&first, &last, &value_indent, to));
+
+    let let_edit = match &anchor {
+        Anchor::LetIn(let_in) => match let_in.bindings().last() {
+            Some(last) => {
+                let first_tok = last.syntax().first_token()?;
+                let sep = first_tok
+                    .prev_token()
+                    .filter(|tok| tok.kind() == SyntaxKind::SPACE)
+                    .map_or_else(|| " ".to_owned(), |tok| tok.text().to_owned());
+                let value = reindented_value(&line_indent(&first_tok))?;
+                TextEdit {
+                    delete: TextRange::empty(last.syntax().text_range().end()),
+                    insert: format!("{sep}{name} = {value};").into(),
+                }
+            }
+            None => {
+                let let_token = let_in.let_token()?;
+                let value = reindented_value(&line_indent(&let_token))?;
+                TextEdit {
+                    delete: TextRange::empty(let_token.text_range().end()),
+                    insert: format!(" {name} = {value};").into(),
+                }
+            }
+        },
+        Anchor::Body(body) => {
+            let body_expr = source_map.expr_for_node(AstPtr::new(body.syntax()))?;
+            let body_node = source_map
+                .node_for_expr(body_expr)?
+                .to_node(ctx.ast.syntax());
+            let body_indent = line_indent(&body_node.first_token()?);
+            // A new `let` on its own lines indents the binding further.
+            let binding_indent = match body_node.first_token()?.prev_token() {
+                Some(tok) if tok.kind() == SyntaxKind::SPACE && tok.text().contains('\n') => {
+                    format!("{body_indent}  ")
+                }
+                _ => body_indent,
+            };
+            let value = reindented_value(&binding_indent)?;
+            let binding = format!("{name} = {value};");
+            insert_let_binding(ctx, None, body_expr, &binding)?
+        }
+    };
+
+    // Parentheses around the expression are no longer needed.
+    let replaced = match target.syntax().parent() {
+        Some(parent) if parent.kind() == SyntaxKind::PAREN => {
+            let (first, last) = trimmed_bounds(&parent)?;
+            first.text_range().cover(last.text_range())
+        }
+        _ => target_range,
+    };
+    let edits = if let_edit.delete.start() == replaced.start() {
+        vec![TextEdit {
+            delete: replaced,
+            insert: format!("{}{name}", let_edit.insert).into(),
+        }]
+    } else {
+        vec![
+            let_edit,
+            TextEdit {
+                delete: replaced,
+                insert: name.as_str().into(),
+            },
+        ]
+    };
+
+    ctx.add(
+        "extract_to_let",
+        format!("Extract into let binding `{name}`"),
+        AssistKind::RefactorRewrite,
+        edits,
+    );
+    if let Some(assist) = ctx.assists.last_mut() {
+        assist.rename = true;
+    }
+    Some(())
+}
+
+enum Anchor {
+    /// Append to the bindings of this `let`.
+    LetIn(ast::LetIn),
+    /// Wrap this expression in a new `let`.
+    Body(ast::Expr),
+}
+
+impl Anchor {
+    /// The scope the new binding is defined in.
+    fn scope(
+        &self,
+        module: &Module,
+        source_map: &crate::def::ModuleSourceMap,
+        scopes: &ModuleScopes,
+    ) -> Option<ScopeId> {
+        let expr = match self {
+            Self::LetIn(let_in) => {
+                let e = source_map.expr_for_node(AstPtr::new(let_in.syntax()))?;
+                let Expr::LetIn(_, body) = module[e] else {
+                    return None;
+                };
+                body
+            }
+            Self::Body(body) => {
+                let body = body.clone().flatten_paren()?;
+                source_map.expr_for_node(AstPtr::new(body.syntax()))?
+            }
+        };
+        scopes.scope_for_expr(expr)
+    }
+}
+
+/// The selected expression, or the innermost one under the cursor.
+/// Names of bindings, `inherit`s and patterns are not expressions to extract.
+fn target_node(ctx: &AssistsCtx<'_>) -> Option<ast::Expr> {
+    let root = ctx.ast.syntax();
+    let range = ctx.frange.range;
+    let node = if range.is_empty() {
+        best_token_at_offset(root, range.start())?.parent()?
+    } else {
+        // Selections must cover exactly one expression, ignoring surrounding spaces.
+        let src = ctx.db.file_content(ctx.frange.file_id);
+        let text = &src[range];
+        let start = range.start() + TextSize::of(&text[..text.len() - text.trim_start().len()]);
+        let end = range.end() - TextSize::of(&text[text.trim_end().len()..]);
+        let range = TextRange::new(start, end.max(start));
+        let node = match root.covering_element(range) {
+            NodeOrToken::Node(node) => node,
+            NodeOrToken::Token(tok) => tok.parent()?,
+        };
+        let (first, last) = trimmed_bounds(&node)?;
+        if first.text_range().cover(last.text_range()) != range {
+            return None;
+        }
+        node
+    };
+    for node in node.ancestors() {
+        let parent_kind = node.parent().map(|p| p.kind());
+        match node.kind() {
+            SyntaxKind::INHERIT | SyntaxKind::PAT | SyntaxKind::PAT_FIELD | SyntaxKind::PARAM => {
+                return None
+            }
+            // Attrpaths of selections are part of the selection.
+            SyntaxKind::ATTR_PATH if parent_kind == Some(SyntaxKind::ATTR_PATH_VALUE) => {
+                return None
+            }
+            _ => {}
+        }
+        // Attrs like `"a"` are not expressions by their own.
+        if matches!(
+            parent_kind,
+            Some(SyntaxKind::ATTR_PATH | SyntaxKind::INHERIT)
+        ) {
+            continue;
+        }
+        match ast::Expr::cast(node) {
+            // The cursor on `pkgs` of `pkgs.stdenv` means the whole selection.
+            Some(ast::Expr::Ref(_))
+                if range.is_empty() && parent_kind == Some(SyntaxKind::SELECT) =>
+            {
+                continue
+            }
+            Some(expr) => return Some(expr),
+            None => {}
+        }
+    }
+    None
+}
+
+/// The first and last tokens of `node`, excluding trailing spaces and comments.
+fn trimmed_bounds(node: &SyntaxNode) -> Option<(SyntaxToken, SyntaxToken)> {
+    let first = std::iter::successors(node.first_token(), |tok| tok.next_token())
+        .find(|tok| !tok.kind().is_trivia())?;
+    let last = std::iter::successors(node.last_token(), |tok| tok.prev_token())
+        .find(|tok| !tok.kind().is_trivia())?;
+    Some((first, last))
+}
+
+/// The last attribute of `a.b.c`, or the default name.
+fn suggest_name(module: &Module, expr: ExprId) -> String {
+    if let Expr::Select(_, path, None) = &module[expr] {
+        if let Some(&last) = path.last() {
+            if let Expr::Literal(Literal::String(s)) = &module[last] {
+                if is_valid_ident(s) {
+                    return s.to_string();
+                }
+            }
+        }
+    }
+    DEFAULT_NAME.into()
+}
+
+/// Append primes to `base` until it is neither defined nor referenced anywhere in the file,
+/// so the new binding never shadows or gets shadowed.
+fn unique_name(module: &Module, base: &str) -> String {
+    let used = module
+        .names()
+        .filter(|(_, name)| name.kind.is_definition())
+        .map(|(_, name)| &*name.text)
+        .chain(module.exprs().filter_map(|(_, e)| match e {
+            Expr::Reference(text) => Some(&**text),
+            _ => None,
+        }))
+        .collect::<HashSet<_>>();
+    let mut name = base.to_owned();
+    while used.contains(&*name) {
+        name.push('\'');
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::expect;
+
+    define_check_assist!(super::extract_to_let);
+
+    #[test]
+    fn append_to_let() {
+        check(
+            "let a = 1; in a $0+ 2",
+            expect!["let a = 1; value = a + 2; in value"],
+        );
+        check(
+            "let a = 1; in f (a + $02)",
+            expect!["let a = 1; value = 2; in f (a + value)"],
+        );
+        check(
+            "let a = 1; in f $0(a + 2)",
+            expect!["let a = 1; value = a + 2; in f value"],
+        );
+        check("let in f $0[ ]", expect!["let value = [ ]; in f value"]);
+    }
+
+    #[test]
+    fn select_name() {
+        check(
+            "{ pkgs }: pkgs.$0stdenv",
+            expect!["{ pkgs }: let stdenv = pkgs.stdenv; in stdenv"],
+        );
+        check(
+            "{ pkgs }: f pkgs.$0stdenv.cc",
+            expect!["{ pkgs }: let cc = pkgs.stdenv.cc; in f cc"],
+        );
+        // Already used.
+        check(
+            "{ pkgs, stdenv }: f $0pkgs.stdenv stdenv",
+            expect!["{ pkgs, stdenv }: let stdenv' = pkgs.stdenv; in f stdenv' stdenv"],
+        );
+        check(
+            r#"{ pkgs }: f $0pkgs."a b""#,
+            expect![[r#"{ pkgs }: let value = pkgs."a b"; in f value"#]],
+        );
+    }
+
+    #[test]
+    fn selection() {
+        check(
+            "x: f $0(g x)$1 1",
+            expect!["x: let value = g x; in f value 1"],
+        );
+        check("x: $0f g x $1", expect!["x: let value = f g x; in value"]);
+        check_no("x: f $0(g x$1) 1");
+        check_no("x: f$0 (g$1 x)");
+    }
+
+    #[test]
+    fn nearest_scope() {
+        // The inner lambda parameter is needed.
+        check(
+            "let a = 1; in x: f $0(x + a)",
+            expect!["let a = 1; in x: let value = x + a; in f value"],
+        );
+        check(
+            "let a = 1; in x: f $0(a + 1)",
+            expect!["let a = 1; in x: let value = a + 1; in f value"],
+        );
+        check(
+            "with lib; x: f ($0g 1$1)",
+            expect!["with lib; x: let value = g 1; in f value"],
+        );
+        check(
+            "f (with lib; $0g 1$1)",
+            expect!["f (with lib; let value = g 1; in value)"],
+        );
+        check("f $0(g 1)", expect!["let value = g 1; in f value"]);
+        // Names defined inside the expression.
+        check(
+            "f $0(let x = 1; in x)",
+            expect!["let value = let x = 1; in x; in f value"],
+        );
+    }
+
+    #[test]
+    fn multiline() {
+        check(
+            "
+{ pkgs }:
+{
+  foo = f $0[
+    1
+  ];
+}",
+            expect![[r#"
+                { pkgs }:
+                let
+                  value = [
+                    1
+                  ];
+                in
+                {
+                  foo = f value;
+                }
+            "#]],
+        );
+        check(
+            "
+let
+  a = 1;
+in
+{
+  foo = f $0[
+    a
+  ];
+}",
+            expect![[r#"
+                let
+                  a = 1;
+                  value = [
+                    a
+                  ];
+                in
+                {
+                  foo = f value;
+                }
+            "#]],
+        );
+    }
+
+    #[test]
+    fn no_extract() {
+        check_no("let a = 1; in $0a");
+        check_no("let $0a = 1; in a");
+        check_no("let inherit ($0b) a; in a");
+        check_no("let inherit $0a; in a");
+        check_no("{ $0a ? 1 }: a");
+        check_no(r#"{ "$0a" = 1; }"#);
+        check_no(r#"{ a.$0"b" = 1; }"#);
+        // Names of a `rec` Attrset are not visible outside.
+        check_no("rec { a = 1; b = $0a + 1; }");
+    }
+}
@@ -16,11 +16,20 @@
 //!   foo.baz = 2;
 //! }
 //! ```
-use super::{AssistKind, AssistsCtx};
+//!
+//! Nested Attrsets of single bindings are flattened into one attrpath.
+//! ```nix
+//! { foo = { bar = { baz = 1; }; }; }
+//! ```
+//! =>
+//! ```nix
+//! { foo.bar.baz = 1; }
+//! ```
+use super::{line_indent, reindent, AssistKind, AssistsCtx};
 use crate::TextEdit;
 use itertools::Itertools;
 use syntax::ast::{self, AstNode, HasBindings};
-use syntax::TextRange;
+use syntax::{SyntaxKind, TextRange};
 
 pub(super) fn flatten_attrset(ctx: &mut AssistsCtx<'_>) -> Option<()> {
     // Matches `attr.path = { ... };`.
@@ -35,6 +44,18 @@ pub(super) fn flatten_attrset(ctx: &mut AssistsCtx<'_>) -> Option<()> {
     set.bindings().next()?;
 
     let src = ctx.db.file_content(ctx.frange.file_id);
+
+    // A chain of single bindings becomes one binding.
+    if let Some(edit) = flatten_single_chain(&src, &path_value, "") {
+        ctx.add(
+            "flatten_attrset",
+            "Flatten Attrset RHS into outer level bindings",
+            AssistKind::RefactorRewrite,
+            vec![edit],
+        );
+        return Some(());
+    }
+
     let prefix_path = &src[path_value.attrpath()?.syntax().text_range()].trim();
 
     let open_range = TextRange::new(
@@ -59,10 +80,15 @@ pub(super) fn flatten_attrset(ctx: &mut AssistsCtx<'_>) -> Option<()> {
     for b in set.bindings() {
         match b {
             ast::Binding::AttrpathValue(path_value) => {
+                let prefix = format!("{prefix_path}.");
+                if let Some(edit) = flatten_single_chain(&src, &path_value, &prefix) {
+                    edits.push(edit);
+                    continue;
+                }
                 let start_pos = path_value.syntax().text_range().start();
                 edits.push(TextEdit {
                     delete: TextRange::empty(start_pos),
-                    insert: format!("{prefix_path}.").into(),
+                    insert: prefix.into(),
                 });
             }
             ast::Binding::Inherit(i) => {
@@ -94,6 +120,56 @@ pub(super) fn flatten_attrset(ctx: &mut AssistsCtx<'_>) -> Option<()> {
     Some(())
 }
 
+/// Rewrite `attr = { path = { ... = value; }; };`, where each Attrset has only one binding, into
+/// `{prefix}attr.path.... = value;`.
+fn flatten_single_chain(
+    src: &str,
+    path_value: &ast::AttrpathValue,
+    prefix: &str,
+) -> Option<TextEdit> {
+    let mut path = format!(
+        "{prefix}{}",
+        src[path_value.attrpath()?.syntax().text_range()].trim(),
+    );
+    let mut inner = path_value.clone();
+    while let Some(ast::Expr::AttrSet(set)) = inner.value()?.flatten_paren() {
+        if set.rec_token().is_some() || set.let_token().is_some() {
+            break;
+        }
+        let mut bindings = set.bindings();
+        let (Some(ast::Binding::AttrpathValue(binding)), None) = (bindings.next(), bindings.next())
+        else {
+            break;
+        };
+        path += ".";
+        path += src[binding.attrpath()?.syntax().text_range()].trim();
+        inner = binding;
+    }
+    if inner == *path_value {
+        return None;
+    }
+
+    // Comments outside the innermost value would be lost.
+    let value = inner.value()?;
+    let value_range = value.syntax().text_range();
+    if path_value.syntax().descendants_with_tokens().any(|elem| {
+        elem.kind() == SyntaxKind::COMMENT && !value_range.contains_range(elem.text_range())
+    }) {
+        return None;
+    }
+
+    let value = reindent(
+        &value.syntax().first_token()?,
+        &value.syntax().last_token()?,
+        &line_indent(&inner.syntax().first_token()?),
+        &line_indent(&path_value.syntax().first_token()?),
+    );
+    Some(TextEdit {
+        delete: path_value.syntax().text_range(),
+        insert: format!("{path} = {value};").into(),
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use expect_test::expect;
@@ -110,9 +186,20 @@ mod tests {
         check_no("{ $0foo = rec { bar = 1; }; }");
     }
 
+    #[test]
+    fn no_comment_in_chain() {
+        check(
+            "{ $0a = { # comment\n b = 1; }; }",
+            expect![[r#"
+                {  # comment
+                 a.b = 1;  }
+            "#]],
+        );
+    }
+
     #[test]
     fn single() {
-        check("{ $0foo = { a = 1; }; }", expect!["{  foo.a = 1;  }"]);
+        check("{ $0foo = { a = 1; }; }", expect!["{ foo.a = 1; }"]);
         check(
             "
 {
@@ -122,9 +209,7 @@ mod tests {
 }",
             expect![[r#"
                 {
-
-                    foo.${bar}.baz.a = 1;
-
+                  foo.${bar}.baz.a = 1;
                 }
             "#]],
         );
@@ -161,4 +246,64 @@ mod tests {
             "#]],
         );
     }
+
+    #[test]
+    fn nested_single() {
+        check("{ $0a = { b = { c = 1; }; }; }", expect!["{ a.b.c = 1; }"]);
+        check(
+            "let $0a.b = ({ c.d = 1; }); in a",
+            expect!["let a.b.c.d = 1; in a"],
+        );
+        check(
+            "{ a = { $0b = { c = 1; }; }; }",
+            expect!["{ a = { b.c = 1; }; }"],
+        );
+        check(
+            "{ $0a = { b = { c = 1; }; d = { e = 1; f = 2; }; }; }",
+            expect!["{  a.b.c = 1; a.d = { e = 1; f = 2; };  }"],
+        );
+    }
+
+    #[test]
+    fn nested_multiline() {
+        check(
+            r#"
+{
+  $0a = {
+    b = {
+      c = [
+        1
+        "x
+  y"
+      ];
+    };
+  };
+}"#,
+            expect![[r#"
+                {
+                  a.b.c = [
+                    1
+                    "x
+                  y"
+                  ];
+                }
+            "#]],
+        );
+    }
+
+    #[test]
+    fn nested_stop_at_non_single() {
+        check(
+            "{ $0a = { b = { c = 1; d = 2; }; }; }",
+            expect!["{ a.b = { c = 1; d = 2; }; }"],
+        );
+        check(
+            "{ $0a = { b = rec { c = 1; }; }; }",
+            expect!["{ a.b = rec { c = 1; }; }"],
+        );
+        check(
+            "{ $0a = { b = { inherit c; }; }; }",
+            expect!["{ a.b = { inherit c; }; }"],
+        );
+    }
 }
@@ -0,0 +1,317 @@
+//! Replace selects of `lib` attributes with names inherited by `inherit (lib) ...`.
+//!
+//! ```nix
+//! { lib, ... }:
+//! {
+//!   options.foo = lib.mkOption { type = lib.types.str; };
+//! }
+//! ```
+//! =>
+//! ```nix
+//! { lib, ... }:
+//! let
+//!   inherit (lib) mkOption types;
+//! in
+//! {
+//!   options.foo = mkOption { type = types.str; };
+//! }
+//! ```
+//!
+//! The names are added to the `let` binding `lib`, or the top-level `let` of the lambda taking
+//! `lib`, extending an existing `inherit (lib)` there if any. A new `let` is created if needed.
+//! Names which would collide with other bindings or capture other references are left alone,
+//! so are selects where the name is shadowed by an inner binding.
+use super::{body_let, insert_let_binding, is_shadowed, resolve_in, AssistKind, AssistsCtx};
+use crate::def::{AstPtr, Expr, ResolveResult};
+use crate::ide::{lib_select, LIB_NAME};
+use crate::{NameKind, TextEdit};
+use std::collections::{BTreeSet, HashSet};
+use syntax::ast::{self, AstNode};
+use syntax::semantic::AttrKind;
+use syntax::TextRange;
+
+pub(super) fn inherit_from_lib(ctx: &mut AssistsCtx<'_>) -> Option<()> {
+    let file_id = ctx.frange.file_id;
+    let cursor_node = ctx.covering_node::<ast::Select>()?;
+
+    let module = ctx.db.module(file_id);
+    let source_map = ctx.db.source_map(file_id);
+    let nameres = ctx.db.name_resolution(file_id);
+    let scopes = ctx.db.scopes(file_id);
+    let root = ctx.ast.syntax().clone();
+
+    // The definition of `lib` and the first attribute name of a select on it.
+    let lib_select = |e| lib_select(&module, &nameres, e);
+
+    let cursor_expr = source_map.expr_for_node(AstPtr::new(cursor_node.syntax()))?;
+    let (lib_name, cursor_attr) = lib_select(cursor_expr)?;
+
+    // The `let` to add names to, and the expression it is or will be wrapping.
+    let lib_node = source_map.nodes_for_name(lib_name).next()?.to_node(&root);
+    let (let_expr, body) = match module[lib_name].kind {
+        NameKind::LetIn => {
+            let let_node = lib_node.ancestors().find_map(ast::LetIn::cast)?;
+            let e = source_map.expr_for_node(AstPtr::new(let_node.syntax()))?;
+            let Expr::LetIn(_, let_body) = module[e] else {
+                return None;
+            };
+            (Some(e), let_body)
+        }
+        NameKind::Param | NameKind::PatField => {
+            let lambda_node = lib_node.ancestors().find_map(ast::Lambda::cast)?;
+            let lambda = source_map.expr_for_node(AstPtr::new(lambda_node.syntax()))?;
+            let Expr::Lambda(_, _, body) = module[lambda] else {
+                return None;
+            };
+            (body_let(&module, body), body)
+        }
+        NameKind::PlainAttrset | NameKind::RecAttrset => return None,
+    };
+    let (scope_expr, scope_range) = match let_expr {
+        Some(e) => {
+            let Expr::LetIn(_, let_body) = module[e] else {
+                unreachable!()
+            };
+            (let_body, source_map.node_for_expr(e)?.text_range())
+        }
+        None => (body, source_map.node_for_expr(body)?.text_range()),
+    };
+    let boundary = scopes.scope_for_expr(scope_expr)?;
+    if resolve_in(&scopes, boundary, LIB_NAME) != Some(lib_name) {
+        return None;
+    }
+
+    // An existing `inherit (lib) ...;` in the `let`, and names it already inherits.
+    let existing = let_expr.and_then(|e| {
+        let let_node = source_map.node_for_expr(e)?.to_node(&root);
+        let_node
+            .children()
+            .filter_map(ast::Inherit::cast)
+            .find(|inherit| {
+                let from = inherit
+                    .from_expr()
+                    .and_then(|paren| paren.expr())
+                    .and_then(|e| source_map.expr_for_node(AstPtr::new(e.syntax())));
+                from.and_then(|e| nameres.get(e)) == Some(&ResolveResult::Definition(lib_name))
+            })
+    });
+    let inherited = existing
+        .iter()
+        .flat_map(|inherit| inherit.attrs())
+        .filter_map(|attr| match AttrKind::of(attr) {
+            AttrKind::Static(Some(name)) => Some(name),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+
+    // All selects on the same `lib` which would be in scope of the inherited names.
+    let selects = module
+        .exprs()
+        .filter_map(|(e, _)| {
+            let (lib, attr) = lib_select(e)?;
+            let range = source_map.node_for_expr(e)?.text_range();
+            (lib == lib_name
+                && scope_range.contains_range(range)
+                && !is_shadowed(&scopes, boundary, e, attr))
+            .then_some((e, attr))
+        })
+        .collect::<Vec<_>>();
+
+    let can_inherit = |name: &str| {
+        if inherited.contains(name) {
+            return true;
+        }
+        // Should not be already defined.
+        resolve_in(&scopes, boundary, name).is_none()
+            // Existing references should not be captured.
+            && module.exprs().all(|(e, kind)| {
+                let Expr::Reference(text) = kind else {
+                    return true;
+                };
+                let in_scope = source_map
+                    .node_for_expr(e)
+                    .is_some_and(|ptr| scope_range.contains_range(ptr.text_range()));
+                *text != name || !in_scope || is_shadowed(&scopes, boundary, e, name)
+            })
+    };
+    let names = selects
+        .iter()
+        .map(|&(_, attr)| attr)
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .filter(|name| can_inherit(name))
+        .collect::<BTreeSet<_>>();
+    if !names.contains(cursor_attr) || !selects.iter().any(|&(e, _)| e == cursor_expr) {
+        return None;
+    }
+
+    let mut edits = selects
+        .iter()
+        .filter(|(_, attr)| names.contains(attr))
+        .filter_map(|&(e, attr)| {
+            let Expr::Select(_, path, _) = &module[e] else {
+                return None;
+            };
+            let start = source_map.node_for_expr(e)?.text_range().start();
+            let end = source_map.node_for_expr(path[0])?.text_range().end();
+            Some(TextEdit {
+                delete: TextRange::new(start, end),
+                insert: attr.into(),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let new_names = names
+        .iter()
+        .filter(|name| !inherited.contains(**name))
+        .copied()
+        .collect::<Vec<_>>();
+    if !new_names.is_empty() {
+        let edit = match &existing {
+            Some(inherit) => {
+                let pos = match inherit.attrs().last() {
+                    Some(attr) => attr.syntax().text_range().end(),
+                    None => inherit.from_expr()?.syntax().text_range().end(),
+                };
+                TextEdit {
+                    delete: TextRange::empty(pos),
+                    insert: format!(" {}", new_names.join(" ")).into(),
+                }
+            }
+            None => {
+                let binding = format!("inherit ({LIB_NAME}) {};", new_names.join(" "));
+                insert_let_binding(ctx, let_expr, body, &binding)?
+            }
+        };
+        edits.push(edit);
+    }
+
+    let label = names
+        .iter()
+        .map(|name| format!("`{name}`"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    ctx.add(
+        "inherit_from_lib",
+        format!("Inherit {label} from `{LIB_NAME}`"),
+        AssistKind::RefactorRewrite,
+        edits,
+    );
+
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::expect;
+
+    define_check_assist!(super::inherit_from_lib);
+
+    #[test]
+    fn new_let() {
+        check(
+            "
+{ lib, ... }:
+{
+  options.foo = lib.mkOption$0 { type = lib.types.str; };
+  config = lib.mkIf true { };
+}
+",
+            expect![[r#"
+                { lib, ... }:
+                let
+                  inherit (lib) mkIf mkOption types;
+                in
+                {
+                  options.foo = mkOption { type = types.str; };
+                  config = mkIf true { };
+                }
+            "#]],
+        );
+        check(
+            "lib: [ lib.a$0 lib.b.c lib.a ]",
+            expect!["lib: let inherit (lib) a b; in [ a b.c a ]"],
+        );
+    }
+
+    #[test]
+    fn existing_let() {
+        check(
+            "
+{ config, lib, ... }:
+let
+  cfg = config.foo;
+in
+{
+  a = lib.mkIf$0 cfg.enable { };
+}
+",
+            expect![[r#"
+                { config, lib, ... }:
+                let
+                  inherit (lib) mkIf;
+                  cfg = config.foo;
+                in
+                {
+                  a = mkIf cfg.enable { };
+                }
+            "#]],
+        );
+        // `lib` bound by the `let` itself.
+        check(
+            "let lib = import ./lib.nix; a = lib.b$0; in lib.c",
+            expect!["let inherit (lib) b c; lib = import ./lib.nix; a = b; in c"],
+        );
+    }
+
+    #[test]
+    fn extend_inherit() {
+        check(
+            "{ lib }: let inherit (lib) a; in [ lib.a lib.b$0 a ]",
+            expect!["{ lib }: let inherit (lib) a b; in [ a b a ]"],
+        );
+        check(
+            "{ lib }: let inherit (lib) a; in [ lib.a$0 ]",
+            expect!["{ lib }: let inherit (lib) a; in [ a ]"],
+        );
+    }
+
+    #[test]
+    fn shadowed() {
+        // Selects where the name is shadowed are kept.
+        check(
+            "{ lib }: [ lib.a$0 (let a = 1; in lib.a + a) ({ a }: lib.a) ]",
+            expect![
+                "{ lib }: let inherit (lib) a; in [ a (let a = 1; in lib.a + a) ({ a }: lib.a) ]"
+            ],
+        );
+        // Names colliding with existing bindings are kept.
+        check(
+            "{ lib, b }: [ lib.a$0 lib.b ]",
+            expect!["{ lib, b }: let inherit (lib) a; in [ a lib.b ]"],
+        );
+        check(
+            "{ lib }: let b = 1; in [ lib.a$0 lib.b lib.lib ]",
+            expect!["{ lib }: let inherit (lib) a; b = 1; in [ a lib.b lib.lib ]"],
+        );
+        // Names which would capture existing references are kept.
+        check(
+            "{ lib }: with lib; [ lib.a$0 lib.b b ]",
+            expect!["{ lib }: let inherit (lib) a; in with lib; [ a lib.b b ]"],
+        );
+        check_no("{ lib, a }: [ lib.a$0 ]");
+        check_no("{ lib }: x: let a = 1; in lib.a$0");
+    }
+
+    #[test]
+    fn not_applicable() {
+        // Not `lib`.
+        check_no("{ pkgs }: pkgs.a$0");
+        check_no("lib.a$0");
+        check_no("{ lib = { a = 1; }; b = lib.a$0; }");
+        // Not a valid identifier.
+        check_no(r#"{ lib }: lib."a b"$0"#);
+        // `or` cannot be kept.
+        check_no("{ lib }: lib.a$0 or 1");
+    }
+}
@@ -0,0 +1,193 @@
+//! Inline a `let` binding into all its references, the reverse of `extract_to_let`.
+//!
+//! ```nix
+//! let x = f a; in g x x
+//! ```
+//! =>
+//! ```nix
+//! g (f a) (f a)
+//! ```
+//!
+//! Parentheses are added only if required by precedence.
+//! Recursive bindings, names inherited elsewhere, and values whose variables are shadowed at
+//! some references are not inlined.
+use super::{entry_removal_range, outer_references, resolves_to, AssistKind, AssistsCtx};
+use crate::def::{AstPtr, NameKind, ResolveResult};
+use crate::TextEdit;
+use syntax::ast::{self, AstNode, HasBindings};
+use syntax::SyntaxKind;
+
+pub(super) fn inline_binding(ctx: &mut AssistsCtx<'_>) -> Option<()> {
+    let file_id = ctx.frange.file_id;
+    let module = ctx.db.module(file_id);
+    let source_map = ctx.db.source_map(file_id);
+    let nameres = ctx.db.name_resolution(file_id);
+    let scopes = ctx.db.scopes(file_id);
+    let root = ctx.ast.syntax();
+
+    // Either the definition or a reference under the cursor.
+    let name = if let Some(attr) = ctx.covering_node::<ast::Attr>() {
+        source_map.name_for_node(AstPtr::new(attr.syntax()))?
+    } else {
+        let ref_node = ctx.covering_node::<ast::Ref>()?;
+        let e = source_map.expr_for_node(AstPtr::new(ref_node.syntax()))?;
+        match nameres.get(e)? {
+            &ResolveResult::Definition(name) => name,
+            _ => return None,
+        }
+    };
+    if module[name].kind != NameKind::LetIn {
+        return None;
+    }
+
+    // Only `x = value;`, not `x.y = value;` or `inherit x;`.
+    let mut defs = source_map.nodes_for_name(name);
+    let (Some(def_ptr), None) = (defs.next(), defs.next()) else {
+        return None;
+    };
+    let path = ast::Attrpath::cast(def_ptr.to_node(root).parent()?)?;
+    if path.attrs().count() != 1 {
+        return None;
+    }
+    let binding = ast::AttrpathValue::cast(path.syntax().parent()?)?;
+    let let_in = ast::LetIn::cast(binding.syntax().parent()?)?;
+    let value = binding.value()?;
+    let value_expr =
+        source_map.expr_for_node(AstPtr::new(value.clone().flatten_paren()?.syntax()))?;
+    let value_range = value.syntax().text_range();
+
+    let name_refs = ctx.db.name_reference(file_id);
+    let refs = name_refs.name_references(name).unwrap_or_default();
+    let outer_refs = outer_references(&module, &source_map, &nameres, value_expr, value_range);
+    let src = ctx.db.file_content(file_id);
+    let value_text = &src[value_range];
+
+    let mut edits = Vec::new();
+    for &e in refs {
+        let ref_node = source_map.node_for_expr(e)?.to_node(root);
+        // Recursive.
+        if value_range.contains_range(ref_node.text_range()) {
+            return None;
+        }
+        // Synthesized references of `inherit x;`.
+        if ref_node.parent()?.kind() == SyntaxKind::INHERIT {
+            return None;
+        }
+        let scope = scopes.scope_for_expr(e)?;
+        if !outer_refs
+            .iter()
+            .all(|(text, resolved)| resolves_to(&scopes, scope, text, resolved))
+        {
+            return None;
+        }
+
+        let need_paren = ref_node
+            .parent()
+            .and_then(ast::Expr::cast)
+            .is_some_and(|outer| !outer.contains_without_paren(&value));
+        edits.push(TextEdit {
+            delete: ref_node.text_range(),
+            insert: if need_paren {
+                format!("({value_text})").into()
+            } else {
+                value_text.into()
+            },
+        });
+    }
+
+    // Drop the whole `let` if nothing is left.
+    if let_in.bindings().count() == 1 {
+        let in_token = let_in.in_token()?;
+        let last = in_token
+            .next_token()
+            .filter(|tok| tok.kind() == SyntaxKind::SPACE)
+            .unwrap_or(in_token);
+        edits.push(TextEdit {
+            delete: let_in.let_token()?.text_range().cover(last.text_range()),
+            insert: "".into(),
+        });
+    } else {
+        edits.push(TextEdit {
+            delete: entry_removal_range(
+                &binding.syntax().first_token()?,
+                &binding.syntax().last_token()?,
+            ),
+            insert: "".into(),
+        });
+    }
+
+    ctx.add(
+        "inline_binding",
+        format!("Inline binding `{}`", module[name].text),
+        AssistKind::RefactorRewrite,
+        edits,
+    );
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::expect;
+
+    define_check_assist!(super::inline_binding);
+
+    #[test]
+    fn simple() {
+        check("let $0x = f a; in g x x", expect!["g (f a) (f a)"]);
+        check("let x = f a; in g $0x x", expect!["g (f a) (f a)"]);
+        check(
+            "let $0x = 1; y = x; in x + y",
+            expect!["let y = 1; in 1 + y"],
+        );
+        check("let $0x = 1; in 42", expect!["42"]);
+        check(
+            "let\n  $0x = [ 1 ];\n  y = 2;\nin\nx ++ [ y ]",
+            expect![[r#"
+                let
+                  y = 2;
+                in
+                [ 1 ] ++ [ y ]
+            "#]],
+        );
+    }
+
+    #[test]
+    fn parentheses() {
+        check("let $0x = a + b; in x * 2", expect!["(a + b) * 2"]);
+        check("let $0x = a * b; in x + 2", expect!["a * b + 2"]);
+        check("let $0x = a + b; in 1 - x", expect!["1 - (a + b)"]);
+        check("let $0x = f a; in x.b", expect!["(f a).b"]);
+        check("let $0x = a.b; in f x", expect!["f a.b"]);
+        check("let $0x = y: y; in x 1", expect!["(y: y) 1"]);
+        check("let $0x = y: y; in x", expect!["y: y"]);
+        check("let $0x = f a; in [ x ]", expect!["[ (f a) ]"]);
+        check(
+            r#"let $0x = f a; in { b = x; c = "${x}"; }"#,
+            expect![[r#"{ b = f a; c = "${f a}"; }"#]],
+        );
+        check("let $0x = (f a); in g x", expect!["g (f a)"]);
+    }
+
+    #[test]
+    fn shadowed() {
+        check("a: let $0x = a; in x + (b: x)", expect!["a: a + (b: a)"]);
+        check("a: let $0x = a; in with b; x", expect!["a: with b; a"]);
+        check(
+            "let $0x = a; a = 1; in { a = 2; b = x; }",
+            expect!["let a = 1; in { a = 2; b = a; }"],
+        );
+        check_no("a: let $0x = a; in a: x");
+        check_no("let $0x = a; a = 1; in rec { a = 2; b = x; }");
+        check_no("with c; let $0x = a; in with b; x");
+    }
+
+    #[test]
+    fn no_inline() {
+        check_no("let $0x = [ x ]; in x");
+        check_no("let $0x = 1; in { inherit x; }");
+        check_no("let $0x.y = 1; in x");
+        check_no("let inherit (a) $0x; in x");
+        check_no("{ $0x = 1; }");
+        check_no("$0x: x");
+    }
+}
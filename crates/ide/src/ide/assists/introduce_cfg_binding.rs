@@ -0,0 +1,296 @@
+//! Introduce a `cfg` binding for select chains in a NixOS module sharing a common prefix.
+//!
+//! ```nix
+//! { config, lib, ... }:
+//! {
+//!   config = lib.mkIf config.services.foo.enable {
+//!     environment.etc.foo.text = config.services.foo.text;
+//!     users.users.${config.services.foo.user} = { };
+//!   };
+//! }
+//! ```
+//! =>
+//! ```nix
+//! { config, lib, ... }:
+//! let
+//!   cfg = config.services.foo;
+//! in
+//! {
+//!   config = lib.mkIf cfg.enable {
+//!     environment.etc.foo.text = cfg.text;
+//!     users.users.${cfg.user} = { };
+//!   };
+//! }
+//! ```
+//!
+//! If the module body is already a `let`, the binding is added to it instead.
+//! Uses where the name is shadowed by an inner binding are left alone.
+use super::{body_let, insert_let_binding, is_shadowed, resolve_in, AssistKind, AssistsCtx};
+use crate::def::{AstPtr, Expr, ExprId, Literal, NameId, ResolveResult};
+use crate::{ModuleKind, NameKind, TextEdit};
+use syntax::ast::{self, AstNode};
+use syntax::TextRange;
+
+/// The minimal number of matching select chains for the assist to be offered.
+const MIN_OCCURRENCES: usize = 3;
+/// The minimal number of attributes in the common prefix, excluding `config`.
+const MIN_PREFIX_LEN: usize = 2;
+
+const CONFIG_NAME: &str = "config";
+const NAME_CANDIDATES: &[&str] = &["cfg", "cfg'", "cfg''"];
+
+pub(super) fn introduce_cfg_binding(ctx: &mut AssistsCtx<'_>) -> Option<()> {
+    let file_id = ctx.frange.file_id;
+    let cursor_node = ctx.covering_node::<ast::Select>()?;
+
+    let module = ctx.db.module(file_id);
+    let source_map = ctx.db.source_map(file_id);
+    let nameres = ctx.db.name_resolution(file_id);
+    let scopes = ctx.db.scopes(file_id);
+
+    let (ModuleKind::ConfigModule { lambda_expr } | ModuleKind::Config { lambda_expr }) =
+        *ctx.db.module_kind(file_id)
+    else {
+        return None;
+    };
+    let Expr::Lambda(_, _, body) = module[lambda_expr] else {
+        return None;
+    };
+
+    // The binding is added to the top-level `let` if there is one, or a new `let` around the body.
+    let let_expr = body_let(&module, body);
+    let (scope_expr, scope_range) = match let_expr {
+        Some(e) => {
+            let Expr::LetIn(_, let_body) = module[e] else {
+                unreachable!()
+            };
+            (let_body, source_map.node_for_expr(e)?.text_range())
+        }
+        None => (body, source_map.node_for_expr(body)?.text_range()),
+    };
+    let boundary = scopes.scope_for_expr(scope_expr)?;
+
+    // Static attribute names of a select chain on `config`.
+    let config_chain = |e: ExprId| -> Option<(NameId, Vec<&str>, bool)> {
+        let Expr::Select(set, path, default) = &module[e] else {
+            return None;
+        };
+        let Some(&ResolveResult::Definition(name)) = nameres.get(*set) else {
+            return None;
+        };
+        if module[name].text != CONFIG_NAME || module[name].kind != NameKind::PatField {
+            return None;
+        }
+        let attrs = path
+            .iter()
+            .map_while(|&attr| match &module[attr] {
+                Expr::Literal(Literal::String(s)) => Some(&**s),
+                _ => None,
+            })
+            .collect();
+        Some((name, attrs, default.is_some()))
+    };
+
+    let cursor_expr = source_map.expr_for_node(AstPtr::new(cursor_node.syntax()))?;
+    let (config_name, cursor_attrs, _) = config_chain(cursor_expr)?;
+    if cursor_attrs.len() < MIN_PREFIX_LEN
+        || resolve_in(&scopes, boundary, CONFIG_NAME) != Some(config_name)
+    {
+        return None;
+    }
+
+    // All select chains on the same `config` which would be in scope of the new binding.
+    let chains = module
+        .exprs()
+        .filter_map(|(e, _)| {
+            let (name, attrs, has_default) = config_chain(e)?;
+            let range = source_map.node_for_expr(e)?.text_range();
+            (name == config_name && scope_range.contains_range(range)).then_some((
+                e,
+                attrs,
+                has_default,
+            ))
+        })
+        .collect::<Vec<_>>();
+
+    // Choose the longest prefix shared by enough chains, keeping at least one attribute of the
+    // chain under the cursor if possible.
+    let max_len = cursor_attrs.len().saturating_sub(1).max(MIN_PREFIX_LEN);
+    let (prefix, matched) = (MIN_PREFIX_LEN..=max_len).rev().find_map(|len| {
+        let prefix = &cursor_attrs[..len];
+        let matched = chains
+            .iter()
+            // `config.a.b or x` cannot be rewritten to `cfg or x`.
+            .filter(|(_, attrs, has_default)| {
+                attrs.starts_with(prefix) && !(*has_default && attrs.len() == len)
+            })
+            .map(|&(e, ..)| e)
+            .collect::<Vec<_>>();
+        (matched.len() >= MIN_OCCURRENCES).then_some((prefix, matched))
+    })?;
+
+    let name = NAME_CANDIDATES.iter().copied().find(|&candidate| {
+        // Should not be already defined.
+        resolve_in(&scopes, boundary, candidate).is_none()
+            // Existing references should not be captured.
+            && module.exprs().all(|(e, kind)| {
+                let Expr::Reference(text) = kind else {
+                    return true;
+                };
+                let in_scope = source_map
+                    .node_for_expr(e)
+                    .is_some_and(|ptr| scope_range.contains_range(ptr.text_range()));
+                *text != candidate || !in_scope || is_shadowed(&scopes, boundary, e, candidate)
+            })
+    })?;
+
+    let src = ctx.db.file_content(file_id);
+    let prefix_range = |e: ExprId| -> Option<TextRange> {
+        let Expr::Select(_, path, _) = &module[e] else {
+            return None;
+        };
+        let start = source_map.node_for_expr(e)?.text_range().start();
+        let end = source_map
+            .node_for_expr(path[prefix.len() - 1])?
+            .text_range()
+            .end();
+        Some(TextRange::new(start, end))
+    };
+    let prefix_text = &src[prefix_range(cursor_expr)?];
+
+    let mut edits = matched
+        .into_iter()
+        .filter(|&e| !is_shadowed(&scopes, boundary, e, name))
+        .filter_map(|e| {
+            Some(TextEdit {
+                delete: prefix_range(e)?,
+                insert: name.into(),
+            })
+        })
+        .collect::<Vec<_>>();
+    if edits.len() < MIN_OCCURRENCES {
+        return None;
+    }
+
+    let binding = format!("{name} = {prefix_text};");
+    edits.push(insert_let_binding(ctx, let_expr, body, &binding)?);
+
+    ctx.add(
+        "introduce_cfg_binding",
+        format!("Introduce `{name}` binding for `{prefix_text}`"),
+        AssistKind::RefactorRewrite,
+        edits,
+    );
+
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::expect;
+
+    define_check_assist!(super::introduce_cfg_binding);
+
+    #[test]
+    fn new_let() {
+        check(
+            "
+{ config, lib, ... }:
+{
+  config = lib.mkIf config.services.foo.$0enable {
+    environment.etc.foo.text = config.services.foo.text;
+    users.users.${config.services.foo.user} = { };
+    networking.hostName = config.networking.hostName;
+  };
+}
+",
+            expect![[r#"
+                { config, lib, ... }:
+                let
+                  cfg = config.services.foo;
+                in
+                {
+                  config = lib.mkIf cfg.enable {
+                    environment.etc.foo.text = cfg.text;
+                    users.users.${cfg.user} = { };
+                    networking.hostName = config.networking.hostName;
+                  };
+                }
+            "#]],
+        );
+        check(
+            "{ config, ... }: { a = config.a.b.c$0; b = config.a.b.d; c = config.a.b; }",
+            expect!["{ config, ... }: let cfg = config.a.b; in { a = cfg.c; b = cfg.d; c = cfg; }"],
+        );
+    }
+
+    #[test]
+    fn existing_let() {
+        check(
+            "
+{ config, lib, ... }:
+let
+  inherit (lib) mkIf;
+in
+{
+  config = mkIf config.services.foo.$0enable {
+    a = config.services.foo.a;
+    b = config.services.foo.b;
+  };
+}
+",
+            expect![[r#"
+                { config, lib, ... }:
+                let
+                  cfg = config.services.foo;
+                  inherit (lib) mkIf;
+                in
+                {
+                  config = mkIf cfg.enable {
+                    a = cfg.a;
+                    b = cfg.b;
+                  };
+                }
+            "#]],
+        );
+    }
+
+    #[test]
+    fn longest_prefix() {
+        check(
+            "{ config, ... }: { a = config.a.b.c.d$0; b = config.a.b.c.e; c = config.a.b.c.f; d = config.a.b.x; }",
+            expect!["{ config, ... }: let cfg = config.a.b.c; in { a = cfg.d; b = cfg.e; c = cfg.f; d = config.a.b.x; }"],
+        );
+    }
+
+    #[test]
+    fn shadowed() {
+        check(
+            "{ config, ... }: { a = config.a.b.c$0; b = config.a.b.d; c = config.a.b.e; d = let cfg = 1; in config.a.b.f + cfg; }",
+            expect!["{ config, ... }: let cfg = config.a.b; in { a = cfg.c; b = cfg.d; c = cfg.e; d = let cfg = 1; in config.a.b.f + cfg; }"],
+        );
+        // Collision with existing names.
+        check(
+            "{ config, cfg, ... }: { a = config.a.b.c$0; b = config.a.b.d; c = config.a.b.e; }",
+            expect!["{ config, cfg, ... }: let cfg' = config.a.b; in { a = cfg'.c; b = cfg'.d; c = cfg'.e; }"],
+        );
+        check(
+            "{ config, ... }: with config; { a = config.a.b.c$0; b = config.a.b.d; c = config.a.b.e; d = cfg; }",
+            expect!["{ config, ... }: let cfg' = config.a.b; in with config; { a = cfg'.c; b = cfg'.d; c = cfg'.e; d = cfg; }"],
+        );
+    }
+
+    #[test]
+    fn not_applicable() {
+        // Not enough occurrences.
+        check_no("{ config, ... }: { a = config.a.b.c$0; b = config.a.b.d; }");
+        // Prefix too short.
+        check_no("{ config, ... }: { a = config.a$0; b = config.a; c = config.a; }");
+        // Not a module.
+        check_no("config: { a = config.a.b.c$0; b = config.a.b.d; c = config.a.b.e; }");
+        // Not the module `config`.
+        check_no("{ ... }: let config = { }; in { a = config.a.b.c$0; b = config.a.b.d; c = config.a.b.e; }");
+        // A prefix with `or` cannot be replaced.
+        check_no("{ config, ... }: { a = config.a.b.c$0; b = config.a.b.d; c = config.a.b or 1; }");
+    }
+}
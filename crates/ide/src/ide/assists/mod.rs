@@ -15,15 +15,28 @@ macro_rules! define_check_assist {
 
 mod add_to_top_level_lambda_param;
 mod convert_to_inherit;
+mod expand_inherit;
+mod extract_to_let;
 mod flatten_attrset;
+mod inherit_from_lib;
+mod inline_binding;
+mod introduce_cfg_binding;
 mod pack_bindings;
 mod remove_empty_inherit;
 mod remove_empty_let_in;
+mod remove_redundant_let_in;
+mod remove_unused_binding;
 mod rewrite_string;
+mod simplify_redundant_if;
 
-use crate::{DefDatabase, FileRange, TextEdit, WorkspaceEdit};
+use crate::def::{
+    AstPtr, Expr, ExprId, Module, ModuleScopes, ModuleSourceMap, NameId, NameResolution,
+    ResolveResult, ScopeId,
+};
+use crate::{DefDatabase, DiagnosticKind, FileId, FileRange, TextEdit, WorkspaceEdit};
+use smol_str::SmolStr;
 use syntax::ast::{self, AstNode};
-use syntax::{best_token_at_offset, NixLanguage};
+use syntax::{best_token_at_offset, NixLanguage, SyntaxKind, SyntaxToken, TextRange, TextSize};
 
 #[derive(Debug, Clone)]
 pub struct Assist {
@@ -33,6 +46,11 @@ pub struct Assist {
     pub label: String,
     pub kind: AssistKind,
     pub edits: WorkspaceEdit,
+    /// The kind of diagnostics on the edited ranges this assist fixes, if any.
+    pub fixes: Option<DiagnosticKind>,
+    /// Whether the edits replace the cursor range with a new name, which should be renamed
+    /// right after applying.
+    pub rename: bool,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -45,15 +63,23 @@ pub(crate) fn assists(db: &dyn DefDatabase, frange: FileRange) -> Vec<Assist> {
     let handlers = [
         add_to_top_level_lambda_param::add_to_top_level_lambda_param,
         convert_to_inherit::convert_to_inherit,
+        expand_inherit::expand_inherit,
+        extract_to_let::extract_to_let,
         flatten_attrset::flatten_attrset,
+        inherit_from_lib::inherit_from_lib,
+        inline_binding::inline_binding,
+        introduce_cfg_binding::introduce_cfg_binding,
         pack_bindings::pack_bindings,
         remove_empty_inherit::remove_empty_inherit,
         remove_empty_let_in::remove_empty_let_in,
+        remove_redundant_let_in::remove_redundant_let_in,
+        remove_unused_binding::remove_unused_binding,
         rewrite_string::quote_attr,
         rewrite_string::rewrite_indented_to_string,
         rewrite_string::rewrite_string_to_indented,
         rewrite_string::rewrite_uri_to_string,
         rewrite_string::unquote_attr,
+        simplify_redundant_if::simplify_redundant_if,
     ];
 
     let mut ctx = AssistsCtx::new(db, frange);
@@ -63,6 +89,33 @@ pub(crate) fn assists(db: &dyn DefDatabase, frange: FileRange) -> Vec<Assist> {
     ctx.assists
 }
 
+/// Quick fixes which never change semantics and can be applied to a whole file at once without
+/// user interaction, eg. on save.
+pub(crate) fn batch_fixes(db: &dyn DefDatabase, file: FileId) -> Vec<TextEdit> {
+    let handlers = [
+        remove_empty_inherit::remove_empty_inherit,
+        remove_empty_let_in::remove_empty_let_in,
+    ];
+
+    let root = db.parse(file).syntax_node();
+    let mut edits = root
+        .descendants()
+        .filter(|node| ast::Inherit::can_cast(node.kind()) || ast::LetIn::can_cast(node.kind()))
+        .flat_map(|node| {
+            let mut ctx = AssistsCtx::new(db, FileRange::new(file, node.text_range()));
+            for h in handlers {
+                h(&mut ctx);
+            }
+            ctx.assists
+        })
+        .flat_map(|assist| assist.edits.content_edits.into_values().flatten())
+        .collect::<Vec<_>>();
+    // Each node is checked by all handlers, so the same fix may be found more than once.
+    edits.sort_by_key(|edit| (edit.delete.start(), edit.delete.end()));
+    edits.dedup();
+    edits
+}
+
 pub(crate) struct AssistsCtx<'a> {
     db: &'a dyn DefDatabase,
     frange: FileRange,
@@ -96,9 +149,25 @@ impl<'a> AssistsCtx<'a> {
             label: label.into(),
             kind,
             edits,
+            fixes: None,
+            rename: false,
         });
     }
 
+    /// Add a quick fix resolving the diagnostic of `diagnostic` kind on the edited ranges.
+    fn add_fix(
+        &mut self,
+        id: impl Into<String>,
+        label: impl Into<String>,
+        diagnostic: DiagnosticKind,
+        text_edits: Vec<TextEdit>,
+    ) {
+        self.add(id, label, AssistKind::QuickFix, text_edits);
+        if let Some(assist) = self.assists.last_mut() {
+            assist.fixes = Some(diagnostic);
+        }
+    }
+
     fn covering_node<N: AstNode<Language = NixLanguage>>(&self) -> Option<N> {
         let range = self.frange.range;
         if range.is_empty() {
@@ -111,12 +180,226 @@ impl<'a> AssistsCtx<'a> {
     }
 }
 
+/// The range to remove an entry spanning from `first` to `last` tokens, like a binding.
+/// The whole line is removed if the entry is on its own line, otherwise trailing spaces are.
+fn entry_removal_range(first: &SyntaxToken, last: &SyntaxToken) -> TextRange {
+    line_removal_range(first, last).unwrap_or_else(|| {
+        let range = first.text_range().cover(last.text_range());
+        match last
+            .next_token()
+            .filter(|tok| tok.kind() == SyntaxKind::SPACE)
+        {
+            Some(next) => range.cover(next.text_range()),
+            None => range,
+        }
+    })
+}
+
+/// The range to remove the line of an entry spanning from `first` to `last` tokens,
+/// with its leading newline, if the entry is on its own line.
+fn line_removal_range(first: &SyntaxToken, last: &SyntaxToken) -> Option<TextRange> {
+    let space = |tok: Option<SyntaxToken>| {
+        tok.filter(|tok| tok.kind() == SyntaxKind::SPACE && tok.text().contains('\n'))
+    };
+    let prev = space(first.prev_token())?;
+    space(last.next_token())?;
+    let line_start = prev.text().rfind('\n')?;
+    let start = prev.text_range().start() + TextSize::of(&prev.text()[..line_start]);
+    Some(TextRange::new(start, last.text_range().end()))
+}
+
+/// The indentation of the line containing `tok`.
+fn line_indent(tok: &SyntaxToken) -> String {
+    std::iter::successors(tok.prev_token(), |tok| tok.prev_token())
+        .find_map(|tok| Some(tok.text().rsplit_once('\n')?.1.to_owned()))
+        .unwrap_or_default()
+        .chars()
+        .take_while(|c| c.is_ascii_whitespace())
+        .collect()
+}
+
+/// The source text from `first` to `last` tokens, with lines indented by `from` moved to `to`.
+/// Only whitespaces are changed, so string contents are kept as is.
+fn reindent(first: &SyntaxToken, last: &SyntaxToken, from: &str, to: &str) -> String {
+    let mut text = String::new();
+    let end = last.text_range().end();
+    for tok in std::iter::successors(Some(first.clone()), |tok| tok.next_token())
+        .take_while(|tok| tok.text_range().end() <= end)
+    {
+        if tok.kind() != SyntaxKind::SPACE {
+            text += tok.text();
+            continue;
+        }
+        let mut lines = tok.text().split('\n');
+        text += lines.next().unwrap_or_default();
+        for line in lines {
+            text.push('\n');
+            match line.strip_prefix(from) {
+                Some(rest) => {
+                    text += to;
+                    text += rest;
+                }
+                None => text += line,
+            }
+        }
+    }
+    text
+}
+
+/// The `let` directly under `body`, looking through `with` and `assert`.
+fn body_let(module: &Module, body: ExprId) -> Option<ExprId> {
+    std::iter::successors(Some(body), |&e| match module[e] {
+        Expr::With(_, inner) | Expr::Assert(_, inner) => Some(inner),
+        _ => None,
+    })
+    .last()
+    .filter(|&e| matches!(module[e], Expr::LetIn(..)))
+}
+
+/// The edit adding `binding` before the first binding of `let_expr`,
+/// or wrapping `body` in a new `let` if `let_expr` is `None`.
+fn insert_let_binding(
+    ctx: &AssistsCtx<'_>,
+    let_expr: Option<ExprId>,
+    body: ExprId,
+    binding: &str,
+) -> Option<TextEdit> {
+    let source_map = ctx.db.source_map(ctx.frange.file_id);
+    let root = ctx.ast.syntax();
+    let (pos, insert) = match let_expr {
+        Some(e) => {
+            let let_node = ast::LetIn::cast(source_map.node_for_expr(e)?.to_node(root))?;
+            match let_node.syntax().children().find_map(ast::Binding::cast) {
+                Some(first) => {
+                    let pos = first.syntax().text_range().start();
+                    let sep = first
+                        .syntax()
+                        .first_token()
+                        .and_then(|tok| tok.prev_token())
+                        .filter(|tok| tok.kind() == SyntaxKind::SPACE)
+                        .map_or_else(|| " ".to_owned(), |tok| tok.text().to_owned());
+                    (pos, format!("{binding}{sep}"))
+                }
+                None => (
+                    let_node.let_token()?.text_range().end(),
+                    format!(" {binding}"),
+                ),
+            }
+        }
+        None => {
+            let body_node = source_map.node_for_expr(body)?.to_node(root);
+            let pos = body_node.text_range().start();
+            let indent = body_node
+                .first_token()
+                .and_then(|tok| tok.prev_token())
+                .filter(|tok| tok.kind() == SyntaxKind::SPACE)
+                .and_then(|tok| Some(tok.text().rsplit_once('\n')?.1.to_owned()));
+            let text = match indent {
+                Some(indent) => format!("let\n{indent}  {binding}\n{indent}in\n{indent}"),
+                None => format!("let {binding} in "),
+            };
+            (pos, text)
+        }
+    };
+    Some(TextEdit {
+        delete: TextRange::empty(pos),
+        insert: insert.into(),
+    })
+}
+
+/// Resolve a name to a definition at a scope.
+fn resolve_in(scopes: &ModuleScopes, scope: ScopeId, name: &str) -> Option<NameId> {
+    scopes
+        .ancestors(scope)
+        .find_map(|data| data.as_definitions()?.get(name))
+        .copied()
+}
+
+/// References inside `expr` to definitions and `with`s outside of `range`, the source range of
+/// `expr`, with how they are resolved.
+fn outer_references(
+    module: &Module,
+    source_map: &ModuleSourceMap,
+    nameres: &NameResolution,
+    expr: ExprId,
+    range: TextRange,
+) -> Vec<(SmolStr, ResolveResult)> {
+    let is_outside = |ptr: AstPtr| !range.contains_range(ptr.text_range());
+    let mut refs = Vec::new();
+    let mut stack = vec![expr];
+    while let Some(e) = stack.pop() {
+        module[e].walk_child_exprs(|e| stack.push(e));
+        let (Expr::Reference(text), Some(resolved)) = (&module[e], nameres.get(e)) else {
+            continue;
+        };
+        let resolved = match resolved {
+            &ResolveResult::Definition(name) => {
+                if !source_map.nodes_for_name(name).all(is_outside) {
+                    continue;
+                }
+                ResolveResult::Definition(name)
+            }
+            ResolveResult::Builtin(name) => ResolveResult::Builtin(name),
+            ResolveResult::WithExprs(withs) => {
+                let withs = withs
+                    .iter()
+                    .copied()
+                    .filter(|&with| source_map.node_for_expr(with).map_or(true, is_outside))
+                    .collect::<Vec<_>>();
+                if withs.is_empty() {
+                    continue;
+                }
+                ResolveResult::WithExprs(withs)
+            }
+        };
+        refs.push((text.clone(), resolved));
+    }
+    refs
+}
+
+/// Whether a reference to `text` at `scope` would be resolved to `resolved`.
+fn resolves_to(
+    scopes: &ModuleScopes,
+    scope: ScopeId,
+    text: &str,
+    resolved: &ResolveResult,
+) -> bool {
+    let local = resolve_in(scopes, scope, text);
+    match resolved {
+        ResolveResult::Definition(name) => local == Some(*name),
+        ResolveResult::Builtin(_) => local.is_none(),
+        ResolveResult::WithExprs(withs) => {
+            local.is_none()
+                && scopes
+                    .ancestors(scope)
+                    .filter_map(|data| data.as_with())
+                    .eq(withs.iter().copied())
+        }
+    }
+}
+
+/// Whether `name` is defined by scopes between `expr` and the `boundary` scope containing it.
+fn is_shadowed(scopes: &ModuleScopes, boundary: ScopeId, expr: ExprId, name: &str) -> bool {
+    let Some(scope) = scopes.scope_for_expr(expr) else {
+        return false;
+    };
+    // Ancestors of `boundary` is a suffix of ancestors of `scope`.
+    let inner_cnt = scopes
+        .ancestors(scope)
+        .count()
+        .saturating_sub(scopes.ancestors(boundary).count());
+    scopes.ancestors(scope).take(inner_cnt).any(|data| {
+        data.as_definitions()
+            .is_some_and(|defs| defs.contains_key(name))
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::tests::TestDB;
     use crate::SourceDatabase;
-    use expect_test::Expect;
+    use expect_test::{expect, Expect};
 
     #[track_caller]
     fn try_apply_assist(
@@ -162,4 +445,30 @@ mod tests {
             panic!("Unexpected applicable:\n{got}");
         }
     }
+
+    #[track_caller]
+    fn check_batch_fixes(fixture: &str, expect: Expect) {
+        let (db, file_id) = TestDB::single_file(fixture).unwrap();
+        let mut src = db.file_content(file_id).to_string();
+        for edit in batch_fixes(&db, file_id).iter().rev() {
+            edit.apply(&mut src);
+        }
+        expect.assert_eq(&src);
+    }
+
+    #[test]
+    fn batch_fixes_whole_file() {
+        check_batch_fixes(
+            "let in { a = let in 1; inherit; b = { inherit (a); c = 2; }; }",
+            expect!["{ a = 1; b = { c = 2; }; }"],
+        );
+    }
+
+    #[test]
+    fn batch_fixes_nothing() {
+        check_batch_fixes(
+            r#"let a = 1; in { inherit a; b = "${a}"; }"#,
+            expect![[r#"let a = 1; in { inherit a; b = "${a}"; }"#]],
+        );
+    }
 }
@@ -16,11 +16,21 @@
 //!   };
 //! }
 //! ```
+//!
+//! Inner bindings sharing a prefix are packed recursively, and a binding without other
+//! definitions is expanded all the way, the reverse of `flatten_attrset`.
+//! ```nix
+//! { foo.bar.baz = 1; }
+//! ```
+//! =>
+//! ```nix
+//! { foo = { bar = { baz = 1; }; }; }
+//! ```
 use super::{AssistKind, AssistsCtx};
-use crate::def::AstPtr;
+use crate::def::{AstPtr, ModuleSourceMap};
 use crate::TextEdit;
 use syntax::ast::{self, AstNode};
-use syntax::TextRange;
+use syntax::{TextRange, TextSize};
 
 pub(super) fn pack_bindings(ctx: &mut AssistsCtx<'_>) -> Option<()> {
     // Only match Attr in `attr.path = value;`.
@@ -34,9 +44,41 @@ pub(super) fn pack_bindings(ctx: &mut AssistsCtx<'_>) -> Option<()> {
     let source_map = ctx.db.source_map(file);
     let name = source_map.name_for_node(AstPtr::new(cursor_attr.syntax()))?;
 
-    // Ignore unique binding.
+    // Expand a unique binding `foo.bar.baz = value;` into nested Attrsets.
     if source_map.nodes_for_name(name).count() <= 1 {
-        return None;
+        // Names under dynamic Attrs are unique even if they are merged at runtime.
+        let mut prefix = cursor_path
+            .attrs()
+            .take_while(|x| x.syntax() != cursor_attr.syntax());
+        if !prefix.all(|x| source_map.name_for_node(AstPtr::new(x.syntax())).is_some()) {
+            return None;
+        }
+        let rest = cursor_path
+            .attrs()
+            .skip_while(|x| x.syntax() != cursor_attr.syntax())
+            .skip(1)
+            .collect::<Vec<_>>();
+        if rest.is_empty() {
+            return None;
+        }
+        let mut text = src[cursor_path_value.value()?.syntax().text_range()].to_owned();
+        for attr in rest.iter().rev() {
+            text = format!("{{ {} = {text}; }}", &src[attr.syntax().text_range()]);
+        }
+        let prefix_path_range = TextRange::new(
+            cursor_path_value.syntax().text_range().start(),
+            cursor_attr.syntax().text_range().end(),
+        );
+        ctx.add(
+            "pack_bindings",
+            "Pack all bindings of this Attr into nested Attrset",
+            AssistKind::RefactorRewrite,
+            vec![TextEdit {
+                delete: cursor_path_value.syntax().text_range(),
+                insert: format!("{} = {text};", &src[prefix_path_range]).into(),
+            }],
+        );
+        return Some(());
     }
 
     // FIXME: Should save RHS in Name.
@@ -48,7 +90,7 @@ pub(super) fn pack_bindings(ctx: &mut AssistsCtx<'_>) -> Option<()> {
     };
 
     // Collect all inner bindings of this Attr.
-    let mut inner_bindings = String::new();
+    let mut inner_bindings = Vec::new();
     let mut edits = Vec::new();
     let mut cursor_edit_idx = None;
     for ptr in source_map.nodes_for_name(name) {
@@ -56,31 +98,34 @@ pub(super) fn pack_bindings(ctx: &mut AssistsCtx<'_>) -> Option<()> {
         let path = ast::Attrpath::cast(attr.syntax().parent()?)?;
         let path_value = ast::AttrpathValue::cast(path.syntax().parent()?)?;
 
-        let mut iter = path.attrs().skip_while(|x| x.syntax() != attr.syntax());
-        iter.next()?; // `attr`.
-        let binding_range = match iter.next() {
-            //          /-------------\ bindings
-            // `foo.bar.baz.qux = expr;`
-            //      ^^^ ^^^ inner_attr
-            //      \ cursor_attr
-            Some(inner_attr) => {
-                let start = inner_attr.syntax().text_range().start();
-                let end = path_value.semicolon_token()?.text_range().end();
-                TextRange::new(start, end)
-            }
+        let inner_attrs = path
+            .attrs()
+            .skip_while(|x| x.syntax() != attr.syntax())
+            .skip(1)
+            .collect::<Vec<_>>();
+        let binding = if inner_attrs.is_empty() {
             //             /---\ bindings
             // `foo.bar = { ... };`
             //      ^^^ cursor_attr
-            None => match path_value.value()?.flatten_paren()? {
+            match path_value.value()?.flatten_paren()? {
                 ast::Expr::AttrSet(set) if set.let_token().is_none() => {
                     // Remove the delimiters while keep all comments inside.
                     let start = set.l_curly_token()?.text_range().end();
                     let end = set.r_curly_token()?.text_range().start();
-                    TextRange::new(start, end)
+                    InnerBinding::Set(TextRange::new(start, end))
                 }
                 // The inner value must be an Attrset. Or there should be merge failures.
                 _ => return None,
-            },
+            }
+        } else {
+            //          /-------------\ bindings
+            // `foo.bar.baz.qux = expr;`
+            //      ^^^ ^^^ inner_attrs
+            //      \ cursor_attr
+            InnerBinding::Path {
+                attrs: inner_attrs,
+                end: path_value.semicolon_token()?.text_range().end(),
+            }
         };
 
         // Collect comments and whitespaces before each bindings.
@@ -95,8 +140,7 @@ pub(super) fn pack_bindings(ctx: &mut AssistsCtx<'_>) -> Option<()> {
                 );
         let trivia_range = TextRange::new(trivia_start, path_value.syntax().text_range().start());
 
-        inner_bindings += &src[trivia_range];
-        inner_bindings += &src[binding_range];
+        inner_bindings.push((&src[trivia_range], binding));
 
         // Delete the binding.
         // We will inject the result back to the cursor bindings later.
@@ -119,7 +163,7 @@ pub(super) fn pack_bindings(ctx: &mut AssistsCtx<'_>) -> Option<()> {
         "\n{} = {}{{{}\n}};",
         &src[prefix_path_range],
         if is_rec { "rec " } else { "" },
-        inner_bindings
+        render_bindings(&src, &source_map, &inner_bindings),
     )
     .into();
 
@@ -132,6 +176,91 @@ pub(super) fn pack_bindings(ctx: &mut AssistsCtx<'_>) -> Option<()> {
     Some(())
 }
 
+enum InnerBinding {
+    /// `attr.path = value;` ending at `end`.
+    Path {
+        attrs: Vec<ast::Attr>,
+        end: TextSize,
+    },
+    /// Bindings inside `{ ... }`.
+    Set(TextRange),
+}
+
+/// Render bindings with their leading trivia, packing paths with the same first Attr into an
+/// Attrset.
+fn render_bindings(
+    src: &str,
+    source_map: &ModuleSourceMap,
+    bindings: &[(&str, InnerBinding)],
+) -> String {
+    // The first Attr of a path to be packed, and whether it is the first one of its group.
+    let group_of = |i: usize| {
+        let (_, InnerBinding::Path { attrs, .. }) = &bindings[i] else {
+            return None;
+        };
+        if attrs.len() < 2 {
+            return None;
+        }
+        let name = source_map.name_for_node(AstPtr::new(attrs[0].syntax()))?;
+        let members = bindings
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, b))| match b {
+                InnerBinding::Path { attrs, .. } => {
+                    attrs.len() >= 2
+                        && source_map.name_for_node(AstPtr::new(attrs[0].syntax())) == Some(name)
+                }
+                InnerBinding::Set(_) => false,
+            })
+            .map(|(j, _)| j)
+            .collect::<Vec<_>>();
+        (members.len() >= 2).then_some(members)
+    };
+
+    let mut out = String::new();
+    for (i, (trivia, binding)) in bindings.iter().enumerate() {
+        match (binding, group_of(i)) {
+            (InnerBinding::Path { attrs, .. }, Some(members)) => {
+                if members[0] != i {
+                    continue;
+                }
+                let inner = members
+                    .iter()
+                    .map(|&j| match &bindings[j] {
+                        (trivia, InnerBinding::Path { attrs, end }) => (
+                            *trivia,
+                            InnerBinding::Path {
+                                attrs: attrs[1..].to_vec(),
+                                end: *end,
+                            },
+                        ),
+                        (_, InnerBinding::Set(_)) => unreachable!(),
+                    })
+                    .collect::<Vec<_>>();
+                let close = match trivia.rsplit_once('\n') {
+                    Some((_, indent)) => format!("\n{indent}"),
+                    None => " ".into(),
+                };
+                out += trivia;
+                out += &src[attrs[0].syntax().text_range()];
+                out += " = {";
+                out += &render_bindings(src, source_map, &inner);
+                out += &close;
+                out += "};";
+            }
+            (InnerBinding::Path { attrs, end }, None) => {
+                out += trivia;
+                out += &src[TextRange::new(attrs[0].syntax().text_range().start(), *end)];
+            }
+            (InnerBinding::Set(range), _) => {
+                out += trivia;
+                out += &src[*range];
+            }
+        }
+    }
+    out
+}
+
 #[cfg(test)]
 mod tests {
     use expect_test::expect;
@@ -141,11 +270,47 @@ mod tests {
     #[test]
     fn no_single() {
         check_no("{ $0foo = 42; }");
-        check_no("{ $0foo.bar = 42; }");
         check_no("{ foo.$0bar = 42; }");
         check_no("{ $0foo = { bar = 1; baz = 1; } }");
     }
 
+    #[test]
+    fn expand_single() {
+        check("{ $0a.b.c = 1; }", expect!["{ a = { b = { c = 1; }; }; }"]);
+        check(
+            "{ a.$0b.c.d = 1; }",
+            expect!["{ a.b = { c = { d = 1; }; }; }"],
+        );
+        check("let $0a.b = 1; in a", expect!["let a = { b = 1; }; in a"]);
+    }
+
+    #[test]
+    fn pack_recursively() {
+        check(
+            "
+{
+  $0a.b.c = 1;
+  a.d = 2;
+  a.b.e = 3;
+  a.${f}.g = 4;
+  a.${f}.h = 5;
+}",
+            expect![[r#"
+                {
+                a = {
+                  b = {
+                  c = 1;
+                  e = 3;
+                  };
+                  d = 2;
+                  ${f}.g = 4;
+                  ${f}.h = 5;
+                };
+                }
+            "#]],
+        );
+    }
+
     #[test]
     fn no_duplicated_values() {
         check_no("{ $0foo = 1; foo = 2; }");
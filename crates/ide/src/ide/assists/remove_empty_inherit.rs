@@ -7,10 +7,9 @@
 //! ```nix
 //! { foo = "bar"; }
 //! ```
-use super::{AssistKind, AssistsCtx};
-use crate::TextEdit;
+use super::{entry_removal_range, AssistsCtx};
+use crate::{DiagnosticKind, TextEdit};
 use syntax::ast::{self, AstNode};
-use syntax::SyntaxKind;
 
 pub(super) fn remove_empty_inherit(ctx: &mut AssistsCtx<'_>) -> Option<()> {
     let node = ctx.covering_node::<ast::Inherit>()?;
@@ -21,20 +20,12 @@ pub(super) fn remove_empty_inherit(ctx: &mut AssistsCtx<'_>) -> Option<()> {
     }
 
     let syntax = node.syntax();
-    let mut range = syntax.text_range();
-    // Also remove trailing SPACEs.
-    if let Some(ws) = syntax
-        .last_token()?
-        .next_token()
-        .filter(|tok| tok.kind() == SyntaxKind::SPACE)
-    {
-        range = range.cover(ws.text_range());
-    }
+    let range = entry_removal_range(&syntax.first_token()?, &syntax.last_token()?);
 
-    ctx.add(
+    ctx.add_fix(
         "remove_empty_inherit",
         "Remove the empty `inherit`",
-        AssistKind::QuickFix,
+        DiagnosticKind::EmptyInherit,
         vec![TextEdit {
             delete: range,
             insert: Default::default(),
@@ -63,6 +54,23 @@ mod tests {
 
         check("{inhe$0rit;}", expect!["{}"]);
         check("{inherit$0;\n}", expect!["{}"]);
+        check(
+            "{\n  foo = 42;\n  inherit$0;\n}",
+            expect![[r#"
+                {
+                  foo = 42;
+                }
+            "#]],
+        );
+        check(
+            "{\n  inherit ({ })$0;\n  foo = 42;\n}",
+            expect![[r#"
+                {
+                  foo = 42;
+                }
+            "#]],
+        );
+        check("{ foo = 42; inherit$0;\n}", expect!["{ foo = 42; }"]);
 
         check_no("{ in$0herit foo; }");
         check_no("{ inher$0it ({ foo = 42; }) foo; }");
@@ -0,0 +1,90 @@
+//! Replace `let` of a single binding which is only returned by the value itself.
+//!
+//! ```nix
+//! let x = f a; in x
+//! ```
+//! =>
+//! ```nix
+//! f a
+//! ```
+//!
+//! Parentheses are added only if required by precedence.
+use super::AssistsCtx;
+use crate::def::{AstPtr, ResolveResult};
+use crate::{DiagnosticKind, TextEdit};
+use syntax::ast::{self, AstNode, HasBindings};
+
+pub(super) fn remove_redundant_let_in(ctx: &mut AssistsCtx<'_>) -> Option<()> {
+    let let_in = ctx.covering_node::<ast::LetIn>()?;
+    let mut bindings = let_in.bindings();
+    let (Some(ast::Binding::AttrpathValue(binding)), None) = (bindings.next(), bindings.next())
+    else {
+        return None;
+    };
+    let mut attrs = binding.attrpath()?.attrs();
+    let (Some(attr), None) = (attrs.next(), attrs.next()) else {
+        return None;
+    };
+    let value = binding.value()?;
+
+    // The body must be the only reference to the binding.
+    let file_id = ctx.frange.file_id;
+    let source_map = ctx.db.source_map(file_id);
+    let nameres = ctx.db.name_resolution(file_id);
+    let name = source_map.name_for_node(AstPtr::new(attr.syntax()))?;
+    let body = source_map.expr_for_node(AstPtr::new(let_in.body()?.syntax()))?;
+    if nameres.get(body) != Some(&ResolveResult::Definition(name))
+        || ctx.db.name_reference(file_id).name_references(name) != Some(&[body])
+    {
+        return None;
+    }
+
+    let src = ctx.db.file_content(file_id);
+    let value_text = &src[value.syntax().text_range()];
+    let need_paren = let_in
+        .syntax()
+        .parent()
+        .and_then(ast::Expr::cast)
+        .is_some_and(|outer| !outer.contains_without_paren(&value));
+    ctx.add_fix(
+        "remove_redundant_let_in",
+        "Replace the `let-in` by the value",
+        DiagnosticKind::RedundantLetIn,
+        vec![TextEdit {
+            delete: let_in.syntax().text_range(),
+            insert: if need_paren {
+                format!("({value_text})").into()
+            } else {
+                value_text.into()
+            },
+        }],
+    );
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::expect;
+
+    define_check_assist!(super::remove_redundant_let_in);
+
+    #[test]
+    fn simple() {
+        check("$0let x = 1; in x", expect!["1"]);
+        check("let x = f a; in $0x", expect!["f a"]);
+        check("{ a = let $0x = 1; in x; }", expect!["{ a = 1; }"]);
+        check("[ (let $0x = f a; in x) ]", expect!["[ (f a) ]"]);
+        check("g (let $0x = a + b; in x)", expect!["g (a + b)"]);
+    }
+
+    #[test]
+    fn no_fix() {
+        check_no("$0let x = 1; y = 2; in x");
+        check_no("$0let x = 1; in x + x");
+        check_no("$0let x = [ x ]; in x");
+        check_no("$0let x = 1; in y");
+        check_no("$0let x.y = 1; in x");
+        check_no("$0let inherit (a) x; in x");
+        check_no("$0let x = 1; in { inherit x; }");
+    }
+}
@@ -0,0 +1,252 @@
+//! Remove an unused `let` binding, pattern field or `@` binding.
+//!
+//! ```nix
+//! let foo = 1; bar = 2; in foo
+//! ```
+//! =>
+//! ```nix
+//! let foo = 1; in foo
+//! ```
+//!
+//! If all bindings of a `let` are removed, it is collapsed to its body.
+//! Unused fields of patterns are also removed from lambdas other than packages and modules,
+//! while plain parameters like `x: ...` are kept.
+use super::{entry_removal_range, line_removal_range, AssistsCtx};
+use crate::def::AstPtr;
+use crate::{DiagnosticKind, TextEdit};
+use syntax::ast::{self, AstNode, HasBindings};
+use syntax::rowan::Direction;
+use syntax::{SyntaxKind, SyntaxNode, SyntaxToken, TextRange, T};
+
+pub(super) fn remove_unused_binding(ctx: &mut AssistsCtx<'_>) -> Option<()> {
+    let file_id = ctx.frange.file_id;
+    let attr = ctx.covering_node::<ast::Attr>()?;
+    let source_map = ctx.db.source_map(file_id);
+    let name = source_map.name_for_node(AstPtr::new(attr.syntax()))?;
+    let liveness = ctx.db.liveness_check(file_id);
+    let kind = if liveness.is_unused_binding(name) {
+        DiagnosticKind::UnusedBinding
+    } else if liveness.is_unused_param(name)
+        && attr.syntax().parent()?.kind() == SyntaxKind::PAT_FIELD
+    {
+        DiagnosticKind::UnusedParameter
+    } else {
+        return None;
+    };
+
+    let root = ctx.ast.syntax().clone();
+    let mut ranges = Vec::new();
+    // Bindings of `let` removed as a whole.
+    let mut removed_bindings = Vec::new();
+    for ptr in source_map.nodes_for_name(name) {
+        let node = ptr.to_node(&root);
+        let parent = node.parent()?;
+        match parent.kind() {
+            // `foo = 1;` or `foo.bar = 1;`
+            SyntaxKind::ATTR_PATH => {
+                let binding = parent.parent()?;
+                ranges.push(entry_removal_range(
+                    &binding.first_token()?,
+                    &binding.last_token()?,
+                ));
+                removed_bindings.push(binding);
+            }
+            // `inherit foo;` or `inherit (bar) foo;`
+            SyntaxKind::INHERIT if ast::Inherit::cast(parent.clone())?.attrs().count() == 1 => {
+                ranges.push(entry_removal_range(
+                    &parent.first_token()?,
+                    &parent.last_token()?,
+                ));
+                removed_bindings.push(parent);
+            }
+            // `inherit foo bar;`, along with the space before it.
+            SyntaxKind::INHERIT => {
+                let prev_space = node
+                    .first_token()?
+                    .prev_token()
+                    .filter(|tok| tok.kind() == SyntaxKind::SPACE)?;
+                ranges.push(node.text_range().cover(prev_space.text_range()));
+            }
+            // `{ foo, bar }:`
+            SyntaxKind::PAT_FIELD => ranges.push(pat_field_range(&parent)?),
+            // `{ ... }@args:` or `args@{ ... }:`
+            SyntaxKind::PARAM => ranges.push(at_binding_range(&node)?),
+            _ => return None,
+        }
+    }
+
+    // Collapse the `let` if nothing is left.
+    if let Some(let_in) = removed_bindings
+        .first()
+        .and_then(|binding| ast::LetIn::cast(binding.parent()?))
+    {
+        if let_in.bindings().count() == removed_bindings.len() {
+            let in_token = let_in.in_token()?;
+            let last = in_token
+                .next_token()
+                .filter(|tok| tok.kind() == SyntaxKind::SPACE)
+                .unwrap_or(in_token);
+            ranges = vec![let_in.let_token()?.text_range().cover(last.text_range())];
+        }
+    }
+
+    ctx.add_fix(
+        "remove_unused_binding",
+        format!("Remove unused binding `{}`", attr.syntax()),
+        kind,
+        ranges
+            .into_iter()
+            .map(|range| TextEdit {
+                delete: range,
+                insert: Default::default(),
+            })
+            .collect(),
+    );
+
+    Some(())
+}
+
+/// Remove a pattern field with the comma separating it from its neighbor.
+fn pat_field_range(field: &SyntaxNode) -> Option<TextRange> {
+    let adjacent_comma = |dir| {
+        field
+            .siblings_with_tokens(dir)
+            .skip(1)
+            .find(|elem| elem.kind() != SyntaxKind::SPACE)?
+            .into_token()
+            .filter(|tok| tok.kind() == T![,])
+    };
+    // Trailing spaces are inside the field.
+    let first = field.first_token()?;
+    let last = std::iter::successors(field.last_token(), |tok| tok.prev_token())
+        .find(|tok| !tok.kind().is_trivia())?;
+    let next_on_newline = |next: &SyntaxToken| {
+        next.prev_token()
+            .is_some_and(|tok| tok.kind() == SyntaxKind::SPACE && tok.text().contains('\n'))
+    };
+    let range = match (
+        adjacent_comma(Direction::Prev),
+        adjacent_comma(Direction::Next),
+    ) {
+        // `foo, ` with the comma following it in the same line.
+        (_, Some(next)) if !next_on_newline(&next) => entry_removal_range(&first, &next),
+        // `, foo` for the last field, or with commas leading lines.
+        (Some(prev), _) => line_removal_range(&prev, &last)
+            .unwrap_or_else(|| prev.text_range().cover(last.text_range())),
+        // The first field with commas leading lines.
+        (None, Some(next)) => entry_removal_range(&first, &next),
+        (None, None) => entry_removal_range(&first, &last),
+    };
+    Some(range)
+}
+
+/// Remove `@args` or `args@` with spaces around `@`.
+fn at_binding_range(name: &SyntaxNode) -> Option<TextRange> {
+    let at = name
+        .parent()?
+        .children_with_tokens()
+        .filter_map(|elem| elem.into_token())
+        .find(|tok| tok.kind() == T![@])?;
+    let mut range = name.text_range().cover(at.text_range());
+    let outer = if at.text_range().start() < name.text_range().start() {
+        at.prev_token()
+    } else {
+        at.next_token()
+    };
+    if let Some(space) = outer.filter(|tok| tok.kind() == SyntaxKind::SPACE) {
+        range = range.cover(space.text_range());
+    }
+    Some(range)
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::expect;
+
+    define_check_assist!(super::remove_unused_binding);
+
+    #[test]
+    fn let_binding() {
+        check("let a = 1; $0b = 2; in a", expect!["let a = 1; in a"]);
+        check("let $0b = 2; a = 1; in a", expect!["let a = 1; in a"]);
+        check(
+            "let\n  a = 1;\n  $0b = {\n    c = 2;\n  };\nin\na",
+            expect![[r#"
+                let
+                  a = 1;
+                in
+                a
+            "#]],
+        );
+        // Merged attrpaths.
+        check(
+            "let a = 1; $0b.c = 2; b.d = 3; in a",
+            expect!["let a = 1; in a"],
+        );
+        check_no("let $0a = 1; in a");
+        check_no("let a = 1; $0_b = 2; in a");
+        check_no("let a = 1; b.$0c = 2; in a");
+    }
+
+    #[test]
+    fn collapse_let() {
+        check("let $0a = 1; in 42", expect!["42"]);
+        check(
+            "{ foo = let $0a = 1; b = a; in 42; }",
+            expect!["{ foo = let b = a; in 42; }"],
+        );
+        check("let\n  $0a = 1;\nin\n{ }", expect!["{ }"]);
+        check("let inherit ({ a = 1; }) $0a; in 42", expect!["42"]);
+    }
+
+    #[test]
+    fn inherit() {
+        check("let inherit a $0b; in a", expect!["let inherit a; in a"]);
+        check("let inherit $0a b; in b", expect!["let inherit b; in b"]);
+        check("let c = 1; inherit $0a; in c", expect!["let c = 1; in c"]);
+    }
+
+    #[test]
+    fn pat_field() {
+        check("{ a, $0b }: a", expect!["{ a }: a"]);
+        check("{ $0a, b }: b", expect!["{ b }: b"]);
+        check("{ a, $0b, c }: a + c", expect!["{ a, c }: a + c"]);
+        check("{ $0a }: 42", expect!["{ }: 42"]);
+        check("{ a, $0b ? 1, ... }: a", expect!["{ a, ... }: a"]);
+        check(
+            "{\n  a,\n  $0b,\n}:\na",
+            expect![[r#"
+                {
+                  a,
+                }:
+                a
+            "#]],
+        );
+        check(
+            "{ a\n, $0b\n, c\n}:\na + c",
+            expect![[r#"
+                { a
+                , c
+                }:
+                a + c
+            "#]],
+        );
+        check(
+            "{ $0a\n, b\n}:\nb",
+            expect![[r#"
+                { b
+                }:
+                b
+            "#]],
+        );
+        check("x: { $0a, b }: b", expect!["x: { b }: b"]);
+        check_no("$0x: { a }: a");
+    }
+
+    #[test]
+    fn at_binding() {
+        check("{ a, ... }@$0args: a", expect!["{ a, ... }: a"]);
+        check("$0args @ { a }: a", expect!["{ a }: a"]);
+        check_no("{ a, ... }@$0args: args");
+    }
+}
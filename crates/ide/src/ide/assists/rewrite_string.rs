@@ -167,6 +167,8 @@ pub(super) fn rewrite_string_to_indented(ctx: &mut AssistsCtx<'_>) -> Option<()>
                             }
                             None => text.push('\''),
                         },
+                        // A raw carriage return would be read as a line break by editors.
+                        '\r' => text.push_str("''\\r"),
                         '\n' => {
                             if line_start {
                                 text.insert(text.len() - indent.len(), '\n');
@@ -320,6 +322,10 @@ mod tests {
         check(r#""''"$0"#, expect!["''\n  '''''\n"]);
         check(r#"$0"${foo}""#, expect!["''\n  ${foo}''\n"]);
         check(r#"$0"\${foo}""#, expect!["''\n  ''${foo}''\n"]);
+        check(
+            r#"$0"a\r\n\"b\"\tc""#,
+            expect!["''\n  a''\\r\n  \"b\"\tc''\n"],
+        );
         check("foo\n  bar \"$0\"", expect!["foo\n  bar ''\n  ''\n"]);
         check(
             "foo\n  $0\"bar\\n\\n  baz\\n\"",
@@ -0,0 +1,129 @@
+//! Replace `if` returning boolean literals by the condition or its negation.
+//!
+//! ```nix
+//! if a == b then true else false
+//! ```
+//! =>
+//! ```nix
+//! a == b
+//! ```
+//!
+//! ```nix
+//! if a == b then false else true
+//! ```
+//! =>
+//! ```nix
+//! !(a == b)
+//! ```
+//!
+//! Parentheses are added only if required by precedence.
+use super::AssistsCtx;
+use crate::def::AstPtr;
+use crate::{DiagnosticKind, TextEdit};
+use syntax::ast::{self, AstNode, BinaryOpKind};
+
+pub(super) fn simplify_redundant_if(ctx: &mut AssistsCtx<'_>) -> Option<()> {
+    let if_expr = ctx.covering_node::<ast::IfThenElse>()?;
+    let cond = if_expr.condition()?;
+
+    let file_id = ctx.frange.file_id;
+    let module = ctx.db.module(file_id);
+    let source_map = ctx.db.source_map(file_id);
+    let nameres = ctx.db.name_resolution(file_id);
+    let literal = |e: Option<ast::Expr>| {
+        let e = source_map.expr_for_node(AstPtr::new(e?.syntax()))?;
+        nameres.check_builtin(e, &module)
+    };
+    let negate = match (literal(if_expr.then_body()), literal(if_expr.else_body())) {
+        (Some("true"), Some("false")) => false,
+        (Some("false"), Some("true")) => true,
+        _ => return None,
+    };
+
+    let src = ctx.db.file_content(file_id);
+    let cond_text = src[cond.syntax().text_range()].trim_end();
+    let replacement = if negate {
+        // `!` binds looser than arithmetic but tighter than comparisons and logical operators.
+        let cond_need_paren = match &cond {
+            ast::Expr::BinaryOp(e) => !matches!(
+                e.op_kind(),
+                Some(
+                    BinaryOpKind::Add
+                        | BinaryOpKind::Sub
+                        | BinaryOpKind::Mul
+                        | BinaryOpKind::Div
+                        | BinaryOpKind::Concat
+                )
+            ),
+            ast::Expr::With(_)
+            | ast::Expr::Lambda(_)
+            | ast::Expr::LetIn(_)
+            | ast::Expr::IfThenElse(_)
+            | ast::Expr::Assert(_) => true,
+            _ => false,
+        };
+        if cond_need_paren {
+            format!("!({cond_text})")
+        } else {
+            format!("!{cond_text}")
+        }
+    } else {
+        // `if` is never an operand without parentheses, so the condition fits anywhere.
+        cond_text.to_owned()
+    };
+
+    ctx.add_fix(
+        "simplify_redundant_if",
+        if negate {
+            "Replace the `if` by the negated condition"
+        } else {
+            "Replace the `if` by the condition"
+        },
+        DiagnosticKind::RedundantIf,
+        vec![TextEdit {
+            delete: if_expr.syntax().text_range(),
+            insert: replacement.into(),
+        }],
+    );
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::expect;
+
+    define_check_assist!(super::simplify_redundant_if);
+
+    #[test]
+    fn condition() {
+        check("$0if a then true else false", expect!["a"]);
+        check(
+            "{ b = if $0a == 1 then true else false; }",
+            expect!["{ b = a == 1; }"],
+        );
+        check(
+            "x && ($0if a || b then true else false)",
+            expect!["x && (a || b)"],
+        );
+    }
+
+    #[test]
+    fn negation() {
+        check("$0if a then false else true", expect!["!a"]);
+        check("$0if f a then false else true", expect!["!f a"]);
+        check("$0if a ? b then false else true", expect!["!a ? b"]);
+        check("$0if a + 1 then false else true", expect!["!a + 1"]);
+        check("$0if a == 1 then false else true", expect!["!(a == 1)"]);
+        check(
+            "x && ($0if a || b then false else true)",
+            expect!["x && (!(a || b))"],
+        );
+    }
+
+    #[test]
+    fn no_fix() {
+        check_no("$0if a then true else true");
+        check_no("$0if a then 1 else 0");
+        check_no("let false = 1; in $0if a then true else false");
+    }
+}
@@ -0,0 +1,165 @@
+//! The attribute path of the binding chain enclosing a position, like
+//! `programs.neovim.plugins.[3].config`, for showing breadcrumbs on cursor moves.
+//!
+//! It is syntax-only and never resolves names, so it is cheap enough to call frequently.
+//! Incomplete code is recovered by the parser, thus results inside broken syntax are prefixes of
+//! the intended path.
+use crate::{DefDatabase, FilePos};
+use syntax::ast::{self, AstNode};
+use syntax::{best_token_at_offset, match_ast, SyntaxNode, TextRange};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttrpathSegment {
+    /// The attribute name as written, or `[N]` for the N-th list element counting from 0.
+    pub name: String,
+    /// The range of the attribute, or the whole list element.
+    pub range: TextRange,
+}
+
+/// Segments of the attribute path enclosing the position, from the outermost one.
+pub(crate) fn attrpath_at(
+    db: &dyn DefDatabase,
+    FilePos { file_id, pos }: FilePos,
+) -> Vec<AttrpathSegment> {
+    let parse = db.parse(file_id);
+    let Some(tok) = best_token_at_offset(&parse.syntax_node(), pos) else {
+        return Vec::new();
+    };
+
+    // Collected from the innermost one.
+    let mut segments = Vec::new();
+    let mut child: Option<SyntaxNode> = None;
+    for node in tok.parent_ancestors() {
+        match_ast! {
+            match (node.clone()) {
+                ast::AttrpathValue(binding) => {
+                    if let Some(path) = binding.attrpath() {
+                        // On the key itself, only attrs up to the cursor are enclosing.
+                        let in_key = child.as_ref() == Some(path.syntax());
+                        let attrs = path
+                            .attrs()
+                            .filter(|attr| !in_key || attr.syntax().text_range().start() <= pos)
+                            .collect::<Vec<_>>();
+                        segments.extend(attrs.into_iter().rev().map(|attr| AttrpathSegment {
+                            name: attr.syntax().to_string(),
+                            range: attr.syntax().text_range(),
+                        }));
+                    }
+                },
+                ast::List(list) => {
+                    if let Some((idx, elem)) = child.as_ref().and_then(|child| {
+                        list.elements().enumerate().find(|(_, e)| e.syntax() == child)
+                    }) {
+                        // Incomplete elements may contain trailing spaces.
+                        let mut last = elem.syntax().last_token();
+                        while let Some(tok) = last.clone().filter(|tok| tok.kind().is_trivia()) {
+                            last = tok.prev_token();
+                        }
+                        let start = elem.syntax().text_range().start();
+                        let end = last.map_or(start, |tok| tok.text_range().end());
+                        segments.push(AttrpathSegment {
+                            name: format!("[{idx}]"),
+                            range: TextRange::new(start, end),
+                        });
+                    }
+                },
+                _ => {}
+            }
+        }
+        child = Some(node);
+    }
+    segments.reverse();
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::TestDB;
+    use crate::SourceDatabase;
+    use expect_test::{expect, Expect};
+
+    #[track_caller]
+    fn check(fixture: &str, expect: Expect) {
+        let (db, f) = TestDB::from_fixture(fixture).unwrap();
+        let src = db.file_content(f[0].file_id);
+        let segments = super::attrpath_at(&db, f[0]);
+        let path = segments
+            .iter()
+            .map(|seg| &*seg.name)
+            .collect::<Vec<_>>()
+            .join(".");
+        if segments.is_empty() {
+            expect.assert_eq("");
+            return;
+        }
+        let mut got = format!("{path}\n");
+        for seg in &segments {
+            got += &format!("{}: {}\n", seg.name, &src[seg.range]);
+        }
+        expect.assert_eq(&got);
+    }
+
+    #[test]
+    fn nested() {
+        check(
+            "{ programs.neovim = { plugins = [ a { config = $0''x''; } ]; }; }",
+            expect![[r#"
+                programs.neovim.plugins.[1].config
+                programs: programs
+                neovim: neovim
+                plugins: plugins
+                [1]: { config = ''x''; }
+                config: config
+            "#]],
+        );
+        check(
+            "let a = f { b.${c} = x: { \"d e\" = $01; }; }; in a",
+            expect![[r#"
+                a.b.${c}."d e"
+                a: a
+                b: b
+                ${c}: ${c}
+                "d e": "d e"
+            "#]],
+        );
+    }
+
+    #[test]
+    fn on_key() {
+        check(
+            "{ a.b$0.c = 1; }",
+            expect![[r#"
+                a.b
+                a: a
+                b: b
+            "#]],
+        );
+    }
+
+    #[test]
+    fn outside() {
+        check("$0{ a = 1; }", expect![""]);
+        check("[ $0 ]", expect![""]);
+    }
+
+    #[test]
+    fn broken() {
+        check(
+            "{ a = { b = [ { c = $0",
+            expect![[r#"
+                a.b.[0].c
+                a: a
+                b: b
+                [0]: { c =
+                c: c
+            "#]],
+        );
+        check(
+            "{ a = { b = [ { c = 1; } ] }; d$0",
+            expect![[r#"
+                d
+                d: d
+            "#]],
+        );
+    }
+}
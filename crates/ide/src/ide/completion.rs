@@ -1,14 +1,26 @@
-use crate::def::{AstPtr, BindingValue, Expr, ExprId, ModuleScopes, NameKind};
+use crate::def::{
+    is_reachable, AstPtr, BindingValue, Expr, ExprId, ModuleScopes, NameKind, ScopeId,
+};
 use crate::ty::{self, AttrSource, DisplayConfig, Ty};
-use crate::{FilePos, InferenceResult, Module, ModuleSourceMap, TyDatabase};
+use crate::{
+    DefDatabase, DirEntry, FileId, FilePos, IdeDatabase, InferenceResult, Module, ModuleSourceMap,
+    SearchPath, TextEdit, VfsPath,
+};
 use builtin::{BuiltinKind, ALL_BUILTINS};
+use nix_interop::flake_output::FlakeOutput;
+use nix_interop::{DEFAULT_IMPORT_FILE, FLAKE_FILE};
 use smol_str::SmolStr;
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Arc;
 use syntax::ast::{self, AstNode};
 use syntax::rowan::TokenAtOffset;
 use syntax::semantic::{escape_literal_attr, is_valid_ident, AttrKind};
-use syntax::{match_ast, SyntaxKind, SyntaxNode, SyntaxToken, TextRange, T};
+use syntax::{match_ast, SyntaxKind, SyntaxNode, SyntaxToken, TextRange, TextSize, T};
 
+use super::file_references::relative_import_path;
 use super::hover::TY_DETAILED_DISPLAY;
+use super::workspace_symbols::sibling_keys;
 
 pub const TY_SIGNATURE_DISPLAY: DisplayConfig = DisplayConfig {
     max_lambda_lhs_depth: 2,
@@ -18,6 +30,12 @@ pub const TY_SIGNATURE_DISPLAY: DisplayConfig = DisplayConfig {
     lambda_need_parentheses: false,
 };
 
+/// The flake input providing packages for `#! nix-shell -p`.
+const NIXPKGS_INPUT: &str = "nixpkgs";
+
+/// The maximum number of attributes of other files offered for auto-import.
+const MAX_AUTO_IMPORT_ITEMS: usize = 50;
+
 #[rustfmt::skip]
 const EXPR_POS_KEYWORDS: &[&str] = &[
     "assert",
@@ -41,6 +59,8 @@ pub struct CompletionItem {
     pub replace_range: TextRange,
     /// What content replaces the source range when user selects this item.
     pub replace: SmolStr,
+    /// Whether `replace` is a snippet with tab stops like `$0`.
+    pub is_snippet: bool,
     /// What item (struct, function, etc) are we completing.
     pub kind: CompletionItemKind,
     /// Type signature.
@@ -49,6 +69,9 @@ pub struct CompletionItem {
     pub description: Option<String>,
     /// The detailed documentation.
     pub documentation: Option<String>,
+    /// Edits elsewhere in the file to apply together with `replace`, like adding an `import`.
+    /// They never overlap with `replace_range`.
+    pub additional_edits: Vec<TextEdit>,
 }
 
 /// The type of the completion item.
@@ -61,6 +84,9 @@ pub enum CompletionItemKind {
     BuiltinConst,
     BuiltinFunction,
     BuiltinAttrset,
+    SearchPath,
+    File,
+    Folder,
 }
 
 impl From<BuiltinKind> for CompletionItemKind {
@@ -85,11 +111,14 @@ impl From<NameKind> for CompletionItemKind {
 }
 
 struct Context<'a> {
+    db: &'a dyn IdeDatabase,
     module: &'a Module,
     source_map: &'a ModuleSourceMap,
     scopes: &'a ModuleScopes,
     infer: &'a InferenceResult,
+    search_path: Arc<SearchPath>,
     fpos: FilePos,
+    trigger_char: Option<char>,
     // The token at cursor (left biased) to complete.
     token: SyntaxToken,
     // The replace range for the result.
@@ -100,9 +129,9 @@ struct Context<'a> {
 }
 
 pub(crate) fn completions(
-    db: &dyn TyDatabase,
+    db: &dyn IdeDatabase,
     fpos @ FilePos { file_id, pos }: FilePos,
-    _trigger_char: Option<char>,
+    trigger_char: Option<char>,
 ) -> Vec<CompletionItem> {
     let parse = db.parse(file_id);
 
@@ -137,11 +166,14 @@ pub(crate) fn completions(
     let infer = db.infer(file_id);
 
     let mut ctx = Context {
+        db,
         module: &module,
         source_map: &source_map,
         scopes: &scopes,
         infer: &infer,
+        search_path: db.search_path(),
         fpos,
+        trigger_char,
         token: token.clone(),
         replace_range,
         prefix,
@@ -158,6 +190,31 @@ pub(crate) fn completions(
 
 impl Context<'_> {
     fn complete(&mut self) -> Option<()> {
+        if self.token.kind() == SyntaxKind::COMMENT {
+            self.complete_nix_shell_package();
+            return Some(());
+        }
+
+        if self.is_search_path_start() {
+            self.complete_search_path();
+            return Some(());
+        }
+
+        if let Some(path_ctx) =
+            path_context_at(self.db, self.fpos.file_id, &self.token, self.fpos.pos)
+        {
+            let source_root = self
+                .db
+                .source_root(self.db.file_source_root(self.fpos.file_id));
+            let entries = source_root.list_dir(&path_ctx.dir);
+            self.completions.extend(path_ctx.items(entries));
+            return Some(());
+        }
+        // `/` is a trigger only for paths, not divisions.
+        if self.trigger_char == Some('/') {
+            return None;
+        }
+
         // Do not complete inside strings.
         // TODO: Escapes and `${}` snippets?
         if let T!["''"] | T!['"'] | SyntaxKind::STRING_FRAGMENT = self.token.kind() {
@@ -173,7 +230,19 @@ impl Context<'_> {
             match parent {
                 // A definition, or a child of `HasAttr` or `Select`.
                 ast::Attrpath(path) => {
-                    self.complete_attrpath(path);
+                    self.complete_attrpath(path.clone());
+                    if let Some(container) = path
+                        .syntax()
+                        .parent()
+                        .filter(|n| ast::AttrpathValue::can_cast(n.kind()))
+                        .and_then(|n| n.parent())
+                    {
+                        let pos = self.fpos.pos;
+                        let prefix_attrs = path
+                            .attrs()
+                            .take_while(|attr| attr.syntax().text_range().end() < pos);
+                        self.complete_defined_keys(&container, prefix_attrs);
+                    }
                 },
                 ast::Inherit(inherit_node) => {
                     self.complete_inherit_attr(inherit_node);
@@ -196,6 +265,7 @@ impl Context<'_> {
                     self.complete_binding(ast::Expr::LetIn(e));
                 },
                 ast::AttrSet(e) => {
+                    self.complete_defined_keys(e.syntax(), std::iter::empty());
                     self.complete_binding(ast::Expr::AttrSet(e));
                 },
 
@@ -226,6 +296,14 @@ impl Context<'_> {
                 ast::Expr(expr) => {
                     self.complete_expr(expr);
                 },
+                // Inside an empty or unterminated interpolation, eg. `"${|` or `"${ | }"`.
+                // It shares the scope of the enclosing expression.
+                ast::Dynamic(dynamic) => {
+                    if self.token.kind() != T!['}'] {
+                        let expr = dynamic.syntax().ancestors().find_map(ast::Expr::cast)?;
+                        self.complete_expr(expr);
+                    }
+                },
                 _ => {}
             }
         }
@@ -233,21 +311,98 @@ impl Context<'_> {
         Some(())
     }
 
-    /// Subsequence matching check.
-    fn can_complete(&self, replace: &str) -> bool {
-        let mut prefix = self.prefix.as_bytes();
-        if prefix.is_empty() {
+    /// Check if we are right after the `<` of an incomplete search path, eg. `import <nix|`.
+    /// A complete `<nixpkgs>` is lexed as a single token, but an incomplete one is parsed as a
+    /// less-than operator. It is only considered as a search path if there is no space after
+    /// `<`, and it starts an expression, or is the argument of `import`. So `a <b` is still a
+    /// comparison.
+    fn is_search_path_start(&self) -> bool {
+        let lt = match self.token.kind() {
+            T![<] => self.token.clone(),
+            SyntaxKind::IDENT => match self.token.prev_token() {
+                Some(tok) if tok.kind() == T![<] => tok,
+                _ => return false,
+            },
+            _ => return false,
+        };
+        let prev = std::iter::successors(lt.prev_token(), |tok| tok.prev_token())
+            .find(|tok| !tok.kind().is_trivia());
+        let Some(prev) = prev else {
+            // The start of the file.
             return true;
+        };
+        match prev.kind() {
+            T!['('] | T!['['] | T![=] | T![:] | T![;] => true,
+            T![in] | T![if] | T![then] | T![else] | T![assert] | T![with] => true,
+            SyntaxKind::IDENT => prev.text() == "import",
+            _ => false,
         }
-        for b in replace.bytes() {
-            if prefix.first().unwrap() == &b {
-                prefix = &prefix[1..];
-                if prefix.is_empty() {
-                    return true;
-                }
-            }
+    }
+
+    /// Complete root names of the search path, and close the bracket.
+    fn complete_search_path(&mut self) {
+        let search_path = self.search_path.clone();
+        for name in search_path.root_names() {
+            self.record_item(CompletionItem {
+                label: name.into(),
+                replace_range: self.replace_range,
+                replace: format!("{name}>$0").into(),
+                is_snippet: true,
+                kind: CompletionItemKind::SearchPath,
+                signature: None,
+                description: None,
+                documentation: None,
+                additional_edits: Vec::new(),
+            });
         }
-        false
+    }
+
+    /// Complete package names after `-p` in `#! nix-shell` directives of scripts, from the
+    /// packages of the flake input `nixpkgs`.
+    fn complete_nix_shell_package(&mut self) -> Option<()> {
+        let start = self.token.text_range().start();
+        let text = &self.token.text()[..usize::from(self.fpos.pos - start)];
+        let prefix = nix_shell_package_prefix(text)?;
+        let replace_range = TextRange::new(self.fpos.pos - TextSize::of(prefix), self.fpos.pos);
+
+        let sid = self.db.file_source_root(self.fpos.file_id);
+        let flake_info = self.db.source_root_flake_info(sid)?;
+        let FlakeOutput::Attrset(outputs) = flake_info.input_flake_outputs.get(NIXPKGS_INPUT)?
+        else {
+            return None;
+        };
+        // Packages of all systems. Usually they are the same.
+        let names = ["legacyPackages", "packages"]
+            .into_iter()
+            .filter_map(|key| match outputs.get(key)? {
+                FlakeOutput::Attrset(systems) => Some(systems.values()),
+                FlakeOutput::Leaf(_) => None,
+            })
+            .flatten()
+            .filter_map(|pkgs| match pkgs {
+                FlakeOutput::Attrset(pkgs) => Some(pkgs.keys()),
+                FlakeOutput::Leaf(_) => None,
+            })
+            .flatten()
+            .filter(|name| is_subsequence(prefix, name))
+            .collect::<HashSet<_>>();
+        self.completions
+            .extend(names.into_iter().map(|name| CompletionItem {
+                label: name.into(),
+                replace_range,
+                replace: name.into(),
+                is_snippet: false,
+                kind: CompletionItemKind::Field,
+                signature: None,
+                description: Some("package".into()),
+                documentation: None,
+                additional_edits: Vec::new(),
+            }));
+        Some(())
+    }
+
+    fn can_complete(&self, replace: &str) -> bool {
+        is_subsequence(self.prefix, replace)
     }
 
     fn record_item(&mut self, compe: CompletionItem) {
@@ -261,10 +416,12 @@ impl Context<'_> {
             label: kw.into(),
             replace_range: self.replace_range,
             replace: kw.into(),
+            is_snippet: false,
             kind: CompletionItemKind::Keyword,
             signature: None,
             description: None,
             documentation: None,
+            additional_edits: Vec::new(),
         });
     }
 
@@ -278,20 +435,41 @@ impl Context<'_> {
             .get(name)
             .cloned()
             .unwrap_or(Ty::Unknown);
+        // The number of curried parameters, if known.
+        let arity = std::iter::successors(Some(&ty), |ty| match ty {
+            Ty::Lambda(_, ret) => Some(ret),
+            _ => None,
+        })
+        .count()
+            - 1;
         self.record_item(CompletionItem {
             label: name.into(),
             replace_range: self.replace_range,
             replace: name.into(),
+            is_snippet: false,
             kind: builtin.kind.into(),
             signature: ty
                 .is_known()
                 .then(|| ty.display_with(TY_SIGNATURE_DISPLAY).to_string()),
-            description: Some(format!(
-                "{}\n{}",
-                ty.display_with(TY_DETAILED_DISPLAY),
-                builtin.summary,
-            )),
+            description: Some(match arity {
+                0 => format!(
+                    "{}\n{}",
+                    ty.display_with(TY_DETAILED_DISPLAY),
+                    builtin.summary
+                ),
+                1 => format!(
+                    "{}\nTakes 1 argument\n{}",
+                    ty.display_with(TY_DETAILED_DISPLAY),
+                    builtin.summary,
+                ),
+                n => format!(
+                    "{}\nTakes {n} arguments\n{}",
+                    ty.display_with(TY_DETAILED_DISPLAY),
+                    builtin.summary,
+                ),
+            }),
             documentation: builtin.doc.map(|s| s.to_owned()),
+            additional_edits: Vec::new(),
         });
     }
 
@@ -346,6 +524,7 @@ impl Context<'_> {
                     label: text.clone(),
                     replace_range: self.replace_range,
                     replace: text.clone(),
+                    is_snippet: false,
                     kind: self.module[name].kind.into(),
                     signature: {
                         let ty = self.infer.ty_for_name(name);
@@ -354,13 +533,176 @@ impl Context<'_> {
                     },
                     description: None,
                     documentation: None,
+                    additional_edits: Vec::new(),
                 });
             });
 
+        // Attributes of `with` environments whose types are known, innermost first.
+        // Names above take precedence, since `with` never shadows lexical names.
+        let with_envs = self
+            .scopes
+            .ancestors(scope_id)
+            .filter_map(|scope| scope.as_with())
+            .filter_map(|with_expr| match &self.module[with_expr] {
+                Expr::With(env, _) => Some(*env),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        for env in with_envs {
+            let env_ty = self.infer.ty_for_expr(env);
+            let Some(attrset) = env_ty.as_attrset() else {
+                continue;
+            };
+            for (name, ty, _) in attrset.iter() {
+                if !is_valid_ident(name) {
+                    continue;
+                }
+                self.record_item(CompletionItem {
+                    label: name.clone(),
+                    replace_range: self.replace_range,
+                    replace: name.clone(),
+                    is_snippet: false,
+                    kind: CompletionItemKind::Field,
+                    signature: ty
+                        .is_known()
+                        .then(|| ty.display_with(TY_SIGNATURE_DISPLAY).to_string()),
+                    description: None,
+                    documentation: None,
+                    additional_edits: Vec::new(),
+                });
+            }
+        }
+
+        // Attributes exported by other files, only when some prefix is typed to avoid noise.
+        if !self.prefix.is_empty() {
+            self.complete_auto_import(&expr_node, scope_id);
+        }
+
         Some(())
     }
 
-    /// Complete in binding position.
+    /// Complete top-level attributes of other files in the same source root, which are
+    /// attrsets with static keys, like `{ foo = 1; }` or `let ... in { foo = 1; }`.
+    ///
+    /// Selecting an item completes to `file.foo`, and binds `file = import ./file.nix;`
+    /// in the outermost enclosing `let`, or in a new `let` around the body of top-level
+    /// lambdas if there is none.
+    fn complete_auto_import(&mut self, expr_node: &ast::Expr, scope_id: ScopeId) -> Option<()> {
+        let file_id = self.fpos.file_id;
+        let source_root = self.db.source_root(self.db.file_source_root(file_id));
+        let cur_path = source_root.path_for_file(file_id).as_path()?;
+        let cur_dir = cur_path.parent()?;
+        let is_in_scope = |name: &str| {
+            self.scopes
+                .ancestors(scope_id)
+                .filter_map(|scope| scope.as_definitions())
+                .any(|defs| defs.contains_key(name))
+                || ALL_BUILTINS.get(name).is_some_and(|b| b.is_global)
+        };
+
+        let mut files = source_root
+            .files()
+            .filter(|&(target, _)| target != file_id)
+            .filter_map(|(target, vpath)| Some((target, vpath.as_path()?)))
+            .collect::<Vec<_>>();
+        files.sort_by_key(|&(target, _)| target);
+
+        let mut items = Vec::new();
+        for (target, target_path) in files {
+            if items.len() >= MAX_AUTO_IMPORT_ITEMS {
+                break;
+            }
+            if target_path.file_name()? == FLAKE_FILE {
+                continue;
+            }
+            let Some(alias) = import_alias(target_path).filter(|alias| !is_in_scope(alias)) else {
+                continue;
+            };
+            let exports = self.db.module_exports(target);
+            let matched = exports
+                .iter()
+                .filter(|text| {
+                    is_valid_ident(text) && !is_in_scope(text) && self.can_complete(text)
+                })
+                .take(MAX_AUTO_IMPORT_ITEMS - items.len())
+                .collect::<Vec<_>>();
+            if matched.is_empty() {
+                continue;
+            }
+            let Some(import_path) = relative_import_path(cur_dir, target_path) else {
+                continue;
+            };
+            // Importing files referring back to the current one would cause infinite recursion.
+            if is_reachable(self.db, target, file_id) {
+                continue;
+            }
+            for text in matched {
+                items.push((text.clone(), alias.clone(), import_path.clone()));
+            }
+        }
+
+        let insert_pos = self.import_insert_position(expr_node)?;
+        for (text, alias, import_path) in items {
+            let replace = format!("{alias}.{text}");
+            let binding = format!("{alias} = import {import_path};");
+            let (replace, additional_edits) = match insert_pos {
+                ImportInsert::Let(pos) => (
+                    replace,
+                    vec![TextEdit {
+                        delete: TextRange::empty(pos),
+                        insert: format!(" {binding}").into(),
+                    }],
+                ),
+                // Insert in the replacement directly, to avoid touching edits.
+                ImportInsert::NewLet(pos) if pos >= self.replace_range.start() => {
+                    (format!("let {binding} in {replace}"), Vec::new())
+                }
+                ImportInsert::NewLet(pos) => (
+                    replace,
+                    vec![TextEdit {
+                        delete: TextRange::empty(pos),
+                        insert: format!("let {binding} in ").into(),
+                    }],
+                ),
+            };
+            self.completions.push(CompletionItem {
+                label: text,
+                replace_range: self.replace_range,
+                replace: replace.into(),
+                is_snippet: false,
+                kind: CompletionItemKind::Field,
+                signature: None,
+                description: Some(format!("import {import_path}")),
+                documentation: None,
+                additional_edits,
+            });
+        }
+
+        Some(())
+    }
+
+    /// Where to bind an auto-imported file for the expression at cursor.
+    fn import_insert_position(&self, expr_node: &ast::Expr) -> Option<ImportInsert> {
+        if let Some(let_in) = expr_node
+            .syntax()
+            .ancestors()
+            .filter_map(ast::LetIn::cast)
+            .last()
+        {
+            return Some(ImportInsert::Let(let_in.let_token()?.text_range().end()));
+        }
+        let mut expr = expr_node
+            .syntax()
+            .ancestors()
+            .last()
+            .and_then(ast::SourceFile::cast)?
+            .expr()?;
+        while let ast::Expr::Lambda(lam) = &expr {
+            expr = lam.body()?;
+        }
+        Some(ImportInsert::NewLet(expr.syntax().text_range().start()))
+    }
+
     /// Eg. `{ a = 1; | }` or `let |`.
     fn complete_binding(&mut self, container: ast::Expr) -> Option<()> {
         self.record_keyword("inherit");
@@ -413,10 +755,12 @@ impl Context<'_> {
                     label: escaped_name.as_ref().into(),
                     replace_range: self.replace_range,
                     replace: escaped_name.into(),
+                    is_snippet: false,
                     kind: CompletionItemKind::LetBinding,
                     signature: None,
                     description: None,
                     documentation: None,
+                    additional_edits: Vec::new(),
                 });
             });
         Some(())
@@ -441,6 +785,7 @@ impl Context<'_> {
                 label: escaped_name.as_ref().into(),
                 replace_range: self.replace_range,
                 replace: escaped_name.into(),
+                is_snippet: false,
                 kind: match src {
                     AttrSource::Unknown => CompletionItemKind::Field,
                     AttrSource::Name(name) => self.module[name].kind.into(),
@@ -450,6 +795,7 @@ impl Context<'_> {
                 signature: Some(ty.display_with(TY_SIGNATURE_DISPLAY).to_string()),
                 description: Some(ty.display_with(TY_DETAILED_DISPLAY).to_string()),
                 documentation: None,
+                additional_edits: Vec::new(),
             });
         }
         Some(())
@@ -475,8 +821,8 @@ impl Context<'_> {
 
         let prefix = match_ast! {
             match (node.syntax().parent()?){
-                ast::HasAttr(n) => Prefix::SetExpr(n.set()?.syntax().clone()),
-                ast::Select(n) => Prefix::SetExpr(n.set()?.syntax().clone()),
+                ast::HasAttr(n) => Prefix::SetExpr(n.set()?.flatten_paren()?.syntax().clone()),
+                ast::Select(n) => Prefix::SetExpr(n.set()?.flatten_paren()?.syntax().clone()),
                 ast::AttrpathValue(n) => {
                     // We are typing the first word of a binding.
                     if prefix_attrs.peek().is_none() {
@@ -519,31 +865,126 @@ impl Context<'_> {
         self.complete_attr(set_ty)
     }
 
+    /// Complete a segment of an `Attrpath` being defined in `container`, from keys defined under
+    /// the same parent path elsewhere in the workspace. `prefix_attrs` are the typed segments.
+    /// Eg. `git` for `{ users.bob.programs.| }` if `users.alice.programs.git` exists.
+    fn complete_defined_keys(
+        &mut self,
+        container: &SyntaxNode,
+        prefix_attrs: impl Iterator<Item = ast::Attr>,
+    ) -> Option<()> {
+        let static_name = |attr: ast::Attr| match AttrKind::of(attr) {
+            AttrKind::Static(Some(name)) => Some(SmolStr::from(name)),
+            _ => None,
+        };
+        let prefix_attrs = prefix_attrs.map(static_name).collect::<Option<Vec<_>>>()?;
+        // Names of `let` are variables, not keys.
+        if ast::LetIn::can_cast(container.kind()) && prefix_attrs.is_empty() {
+            return None;
+        }
+
+        // Static names of enclosing bindings, from the innermost one.
+        let mut parent = Vec::new();
+        for outer in container.ancestors().filter_map(ast::AttrpathValue::cast) {
+            // Names inside dynamic keys are not definitions.
+            let in_value = outer.value().is_some_and(|v| {
+                v.syntax()
+                    .text_range()
+                    .contains_range(container.text_range())
+            });
+            if !in_value {
+                return None;
+            }
+            let attrs = outer
+                .attrpath()?
+                .attrs()
+                .map(static_name)
+                .collect::<Option<Vec<_>>>()?;
+            parent.extend(attrs.into_iter().rev());
+        }
+        parent.reverse();
+        parent.extend(prefix_attrs);
+        // Top-level names are too common to be hints.
+        if parent.is_empty() {
+            return None;
+        }
+
+        for name in sibling_keys(self.db, self.fpos.file_id, &parent) {
+            if name == self.prefix {
+                continue;
+            }
+            let escaped_name = escape_literal_attr(&name);
+            // Never insert ` = `, since a nested path may follow.
+            self.record_item(CompletionItem {
+                label: escaped_name.as_ref().into(),
+                replace_range: self.replace_range,
+                replace: escaped_name.into(),
+                is_snippet: false,
+                kind: CompletionItemKind::Field,
+                signature: None,
+                description: None,
+                documentation: None,
+                additional_edits: Vec::new(),
+            });
+        }
+        Some(())
+    }
+
     /// Complete an `Attr` of an `inherit` binding.
     fn complete_inherit_attr(&mut self, inherit: ast::Inherit) -> Option<()> {
-        let ptr = AstPtr::new(&inherit.syntax().parent()?);
-        let container_expr = self.source_map.expr_for_node(ptr)?;
-        let scope_id = self.scopes.scope_for_expr(container_expr)?;
-        self.scopes
-            .ancestors(scope_id)
-            .filter_map(|scope| scope.as_definitions())
-            .flatten()
-            .for_each(|(text, &name)| {
-                let escaped_name = escape_literal_attr(text);
-                self.record_item(CompletionItem {
-                    label: escaped_name.as_ref().into(),
-                    replace_range: self.replace_range,
-                    replace: escaped_name.into(),
-                    kind: self.module[name].kind.into(),
-                    signature: {
-                        let ty = self.infer.ty_for_name(name);
-                        ty.is_known()
-                            .then(|| ty.display_with(TY_SIGNATURE_DISPLAY).to_string())
-                    },
-                    description: None,
-                    documentation: None,
+        // Names already inherited, except for the one being typed.
+        let pos = self.fpos.pos;
+        let listed = inherit
+            .attrs()
+            .filter(|attr| !attr.syntax().text_range().contains_inclusive(pos))
+            .filter_map(|attr| match AttrKind::of(attr) {
+                AttrKind::Static(Some(name)) => Some(SmolStr::from(escape_literal_attr(&name))),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+        let start = self.completions.len();
+
+        if let Some(from) = inherit.from_expr() {
+            // `inherit (from) |;`, attributes of `from`.
+            let from_expr = self
+                .source_map
+                .expr_for_node(AstPtr::new(from.expr()?.syntax()))?;
+            self.complete_attr(self.infer.ty_for_expr(from_expr));
+        } else {
+            // `inherit |;`, names in scope.
+            let ptr = AstPtr::new(&inherit.syntax().parent()?);
+            let container_expr = self.source_map.expr_for_node(ptr)?;
+            let scope_id = self.scopes.scope_for_expr(container_expr)?;
+            self.scopes
+                .ancestors(scope_id)
+                .filter_map(|scope| scope.as_definitions())
+                .flatten()
+                .for_each(|(text, &name)| {
+                    let escaped_name = escape_literal_attr(text);
+                    self.record_item(CompletionItem {
+                        label: escaped_name.as_ref().into(),
+                        replace_range: self.replace_range,
+                        replace: escaped_name.into(),
+                        is_snippet: false,
+                        kind: self.module[name].kind.into(),
+                        signature: {
+                            let ty = self.infer.ty_for_name(name);
+                            ty.is_known()
+                                .then(|| ty.display_with(TY_SIGNATURE_DISPLAY).to_string())
+                        },
+                        description: None,
+                        documentation: None,
+                        additional_edits: Vec::new(),
+                    });
                 });
-            });
+        }
+
+        let added = self.completions.split_off(start);
+        self.completions.extend(
+            added
+                .into_iter()
+                .filter(|item| !listed.contains(&item.label)),
+        );
         Some(())
     }
 
@@ -566,18 +1007,213 @@ impl Context<'_> {
                     label: name.clone(),
                     replace_range: self.replace_range,
                     replace: name.clone(),
+                    is_snippet: false,
                     kind: CompletionItemKind::Param,
                     signature: ty
                         .is_known()
                         .then(|| ty.display_with(TY_SIGNATURE_DISPLAY).to_string()),
                     description: Some(ty.display_with(TY_DETAILED_DISPLAY).to_string()),
                     documentation: None,
+                    additional_edits: Vec::new(),
                 });
             });
         Some(())
     }
 }
 
+#[derive(Debug, Clone, Copy)]
+enum ImportInsert {
+    /// After the `let` token of an existing `let ... in`.
+    Let(TextSize),
+    /// Before an expression, to be wrapped in a new `let ... in`.
+    NewLet(TextSize),
+}
+
+/// A relative path literal being typed, eg. `./foo/ba|`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathCompletionContext {
+    /// The directory to list, which is `./foo/` resolved against the current file.
+    pub dir: VfsPath,
+    /// The range of the last segment `ba` to replace.
+    pub replace_range: TextRange,
+    /// The typed part of the last segment, as filter.
+    pub prefix: String,
+    /// Whether it is the argument of `import`, which only accepts Nix files or directories.
+    pub is_import: bool,
+}
+
+impl PathCompletionContext {
+    /// Completion items for entries of `dir`.
+    /// Hidden entries like `.git` or `.direnv` are skipped.
+    pub fn items(&self, entries: impl IntoIterator<Item = DirEntry>) -> Vec<CompletionItem> {
+        entries
+            .into_iter()
+            .filter(|entry| {
+                !entry.name.starts_with('.')
+                    && (!self.is_import || entry.is_dir || entry.name.ends_with(".nix"))
+                    && is_subsequence(&self.prefix, &entry.name)
+            })
+            .map(|entry| {
+                let (label, kind) = if entry.is_dir {
+                    (format!("{}/", entry.name), CompletionItemKind::Folder)
+                } else {
+                    (entry.name, CompletionItemKind::File)
+                };
+                CompletionItem {
+                    label: label.clone().into(),
+                    replace_range: self.replace_range,
+                    replace: label.into(),
+                    is_snippet: false,
+                    kind,
+                    signature: None,
+                    description: None,
+                    documentation: None,
+                    additional_edits: Vec::new(),
+                }
+            })
+            .collect()
+    }
+}
+
+/// The relative path literal being typed at `pos`, if any.
+/// Returns `None` for files without real paths.
+pub(crate) fn path_completion_context(
+    db: &dyn DefDatabase,
+    FilePos { file_id, pos }: FilePos,
+) -> Option<PathCompletionContext> {
+    let token = db
+        .parse(file_id)
+        .syntax_node()
+        .token_at_offset(pos)
+        .left_biased()?;
+    path_context_at(db, file_id, &token, pos)
+}
+
+fn path_context_at(
+    db: &dyn DefDatabase,
+    file_id: FileId,
+    token: &SyntaxToken,
+    pos: TextSize,
+) -> Option<PathCompletionContext> {
+    let content = db.file_content(file_id);
+    let start = token.text_range().start();
+    // The path text before the cursor, and the end of the segment to replace.
+    let (path_start, text, end) = match token.kind() {
+        SyntaxKind::PATH => {
+            let text = token.text();
+            let offset = usize::from(pos - start);
+            let seg_len = text[offset..].find('/').unwrap_or(text.len() - offset);
+            (
+                start,
+                text[..offset].to_owned(),
+                pos + TextSize::try_from(seg_len).ok()?,
+            )
+        }
+        // Incomplete ones like `./` and `../` are not lexed as paths, but an incomplete
+        // `Select` of `.`s followed by `/`. Check the source text instead.
+        T![/] if token.text_range().end() == pos => {
+            let before = content[..usize::from(start)].trim_end_matches('.');
+            let dots = usize::from(start) - before.len();
+            // Must be separated from the previous expression, eg. not `a./`.
+            let separated = before.chars().next_back().map_or(true, |c| {
+                c.is_ascii_whitespace() || matches!(c, '(' | '[' | '=' | ':' | ';')
+            });
+            if !separated || !(1..=2).contains(&dots) {
+                return None;
+            }
+            (
+                TextSize::try_from(before.len()).ok()?,
+                format!("{}/", ".".repeat(dots)),
+                pos,
+            )
+        }
+        _ => return None,
+    };
+    if !text.starts_with("./") && !text.starts_with("../") {
+        return None;
+    }
+
+    let dir_len = text.rfind('/')? + 1;
+    let source_root = db.source_root(db.file_source_root(file_id));
+    let mut dir = source_root.path_for_file(file_id).clone();
+    if !dir.pop() {
+        return None;
+    }
+    for seg in text[..dir_len].split('/') {
+        match seg {
+            "" | "." => {}
+            ".." => {
+                if !dir.pop() {
+                    return None;
+                }
+            }
+            seg => dir.push(seg)?,
+        }
+    }
+
+    let before_path = content[..usize::from(path_start)].trim_end();
+    let is_import = before_path.strip_suffix("import").is_some_and(|rest| {
+        !rest.ends_with(|c: char| c.is_ascii_alphanumeric() || "_'-.".contains(c))
+    });
+
+    let prefix = text[dir_len..].to_owned();
+    let seg_start = pos - TextSize::try_from(prefix.len()).ok()?;
+    Some(PathCompletionContext {
+        dir,
+        replace_range: TextRange::new(seg_start, end),
+        prefix,
+        is_import,
+    })
+}
+
+/// If `text` is a `#! nix-shell` directive ending in a package argument of `-p`, like
+/// `#! nix-shell -i bash -p curl jq`, returns the partially written package name.
+fn nix_shell_package_prefix(text: &str) -> Option<&str> {
+    let (args, prefix) = text.strip_prefix("#!")?.rsplit_once(char::is_whitespace)?;
+    if prefix.starts_with('-') {
+        return None;
+    }
+    let mut args = args.split_whitespace();
+    args.find(|&arg| arg == "nix-shell" || arg.ends_with("/nix-shell"))?;
+    // Packages continue until the next option.
+    let mut in_packages = false;
+    for arg in args {
+        if arg.starts_with('-') {
+            in_packages = arg == "-p" || arg == "--packages";
+        }
+    }
+    in_packages.then_some(prefix)
+}
+
+/// Subsequence matching check.
+fn is_subsequence(prefix: &str, text: &str) -> bool {
+    let mut prefix = prefix.as_bytes();
+    if prefix.is_empty() {
+        return true;
+    }
+    for b in text.bytes() {
+        if prefix.first().unwrap() == &b {
+            prefix = &prefix[1..];
+            if prefix.is_empty() {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// The name to bind an imported file to, which is the file stem,
+/// or the directory name for `default.nix`.
+fn import_alias(path: &Path) -> Option<SmolStr> {
+    let stem = path.file_stem()?.to_str()?;
+    let alias = if path.file_name()? == DEFAULT_IMPORT_FILE {
+        path.parent()?.file_name()?.to_str()?
+    } else {
+        stem
+    };
+    is_valid_ident(alias).then(|| alias.into())
+}
+
 #[cfg(test)]
 mod tests {
     use std::ops::Range;
@@ -585,16 +1221,37 @@ mod tests {
 
     use crate::base::SourceDatabase;
     use crate::tests::TestDB;
+    use crate::{FlakeGraph, FlakeInfo};
+    use crate::{SearchPath, SearchPathEntry, TextEdit, VfsPath};
     use expect_test::{expect, Expect};
+    use nix_interop::flake_output::{FlakeOutput, Leaf, Type};
     use nix_interop::nixos_options::{self, NixosOption, NixosOptions};
+    use std::collections::HashMap;
+    use syntax::TextRange;
 
     #[track_caller]
     fn check_no(fixture: &str, label: &str) {
-        let (db, f) = TestDB::from_fixture(fixture).unwrap();
+        let (mut db, f) = TestDB::from_fixture(fixture).unwrap();
+        set_search_path(&mut db);
         let compes = super::completions(&db, f[0], None);
         assert_eq!(compes.iter().find(|item| item.label == label), None);
     }
 
+    fn set_search_path(db: &mut TestDB) {
+        let entries = [
+            ("nixpkgs", "/nixpkgs"),
+            ("nixpkgs/lib", "/lib"),
+            ("", "/channels"),
+        ]
+        .into_iter()
+        .map(|(prefix, path)| SearchPathEntry {
+            prefix: prefix.into(),
+            path: VfsPath::new(path),
+        })
+        .collect();
+        db.set_search_path(Arc::new(SearchPath { entries }));
+    }
+
     #[track_caller]
     fn check_trigger(fixture: &str, trigger_char: Option<char>, label: &str, expect: Expect) {
         let (mut db, f) = TestDB::from_fixture(fixture).expect("fixture should be valid");
@@ -615,6 +1272,7 @@ mod tests {
                 ..NixosOption::default()
             },
         )])));
+        set_search_path(&mut db);
 
         let compes = super::completions(&db, f[0], trigger_char);
         let item = compes
@@ -623,7 +1281,16 @@ mod tests {
             .expect("No expected completion");
 
         let mut completed = db.file_content(f[0].file_id).to_string();
-        completed.replace_range(<Range<usize>>::from(item.replace_range), &item.replace);
+        let mut edits = item.additional_edits.clone();
+        edits.push(TextEdit {
+            delete: item.replace_range,
+            insert: item.replace.clone(),
+        });
+        // Apply from back to front, so that offsets of preceding edits are kept.
+        edits.sort_by_key(|edit| std::cmp::Reverse(edit.delete.start()));
+        for edit in edits {
+            completed.replace_range(<Range<usize>>::from(edit.delete), &edit.insert);
+        }
         let got = format!("({:?}) {}", item.kind, completed);
         expect.assert_eq(&got);
     }
@@ -633,6 +1300,47 @@ mod tests {
         check_trigger(fixture, None, label, expect);
     }
 
+    #[test]
+    fn search_path() {
+        check("<$0", "nixpkgs", expect!["(SearchPath) <nixpkgs>$0"]);
+        check(
+            "import <$0",
+            "nixpkgs",
+            expect!["(SearchPath) import <nixpkgs>$0"],
+        );
+        check(
+            "import <ni$0",
+            "nixpkgs",
+            expect!["(SearchPath) import <nixpkgs>$0"],
+        );
+        check(
+            "[ <$0 ]",
+            "nixpkgs",
+            expect!["(SearchPath) [ <nixpkgs>$0 ]"],
+        );
+        check(
+            "{ a = <$0",
+            "nixpkgs",
+            expect!["(SearchPath) { a = <nixpkgs>$0"],
+        );
+
+        // Comparisons.
+        check_no("1 < $0", "nixpkgs");
+        check_no("a<$0", "nixpkgs");
+        check_no("a < ni$0", "nixpkgs");
+        check_no("a <$0b", "nixpkgs");
+        check_no("let a = 1; in a\n  <$0", "nixpkgs");
+        check(
+            "with <$0",
+            "nixpkgs",
+            expect!["(SearchPath) with <nixpkgs>$0"],
+        );
+        // Only search paths are completed then.
+        check_no("import <n$0", "null");
+        // Not after `<`.
+        check_no("ni$0", "nixpkgs");
+    }
+
     #[test]
     fn keyword() {
         check("l$0", "let", expect!["(Keyword) let"]);
@@ -690,6 +1398,27 @@ mod tests {
         );
     }
 
+    #[test]
+    fn builtin_detail() {
+        let (db, f) = TestDB::from_fixture("builtins.$0").unwrap();
+        let compes = super::completions(&db, f[0], Some('.'));
+        let get = |label: &str| compes.iter().find(|item| item.label == label).unwrap();
+
+        let item = get("attrNames");
+        assert_eq!(item.kind, super::CompletionItemKind::BuiltinFunction);
+        // Only the field name is inserted after `builtins.`.
+        assert_eq!(item.replace, "attrNames");
+        assert_eq!(item.replace_range, TextRange::empty(9.into()));
+        let desc = item.description.as_deref().unwrap();
+        assert!(desc.contains("\nTakes 1 argument\n"), "{desc}");
+        let desc = get("replaceStrings").description.as_deref().unwrap();
+        assert!(desc.contains("\nTakes 3 arguments\n"), "{desc}");
+        let desc = get("true").description.as_deref().unwrap();
+        assert!(!desc.contains("Takes"), "{desc}");
+        // Non-global ones are only available here.
+        assert!(compes.iter().any(|item| item.label == "getEnv"));
+    }
+
     #[test]
     fn inherit_keyword() {
         check("{ i$0 }", "inherit", expect!["(Keyword) { inherit }"]);
@@ -727,6 +1456,48 @@ mod tests {
         );
     }
 
+    #[test]
+    fn inherit_from_attr() {
+        check(
+            "let lib = { foo = 1; bar = 2; }; in { inherit (lib) $0; }",
+            "foo",
+            expect!["(Field) let lib = { foo = 1; bar = 2; }; in { inherit (lib) foo; }"],
+        );
+        check(
+            "let lib = { foo = 1; bar = 2; }; in { inherit (lib) b$0; }",
+            "bar",
+            expect!["(Field) let lib = { foo = 1; bar = 2; }; in { inherit (lib) bar; }"],
+        );
+        check(
+            "{ inherit (builtins) ma$0; }",
+            "map",
+            expect!["(BuiltinFunction) { inherit (builtins) map; }"],
+        );
+        // Only attributes of the source.
+        check_no("let lib = { }; foo = 1; in { inherit (lib) $0; }", "foo");
+        check_no("{ inherit (import ./unknown.nix) $0; }", "foo");
+    }
+
+    #[test]
+    fn inherit_listed_attr() {
+        check_no("let foo = 1; bar = 2; in { inherit foo $0; }", "foo");
+        check(
+            "let foo = 1; bar = 2; in { inherit foo $0; }",
+            "bar",
+            expect!["(LetBinding) let foo = 1; bar = 2; in { inherit foo bar; }"],
+        );
+        check_no(
+            "let lib = { foo = 1; bar = 2; }; in { inherit (lib) $0 foo; }",
+            "foo",
+        );
+        // The one being typed is not excluded.
+        check(
+            "let foo = 1; in { inherit fo$0; }",
+            "foo",
+            expect!["(LetBinding) let foo = 1; in { inherit foo; }"],
+        );
+    }
+
     #[test]
     fn select_known_field() {
         check(
@@ -779,6 +1550,51 @@ mod tests {
         );
     }
 
+    #[test]
+    fn select_imported_field() {
+        // Fields before the syntax error are still available.
+        check_trigger(
+            "
+#- /default.nix
+let lib = import ./lib.nix; in lib.$0
+#- /lib.nix
+{ foo = 1; bar = ; baz = 2;
+",
+            Some('.'),
+            "foo",
+            expect!["(Field) let lib = import ./lib.nix; in lib.foo"],
+        );
+        check_trigger(
+            "
+#- /default.nix
+(import ./lib.nix).$0
+#- /lib.nix
+{ foo = 1; bar = ; baz = 2;
+",
+            Some('.'),
+            "foo",
+            expect!["(Field) (import ./lib.nix).foo"],
+        );
+    }
+
+    #[test]
+    fn select_map_attrs_field() {
+        check_trigger(
+            "let m = builtins.mapAttrs (k: v: v + 1) { foo = 1; bar = 2; }; in m.$0",
+            Some('.'),
+            "foo",
+            expect![
+                "(Field) let m = builtins.mapAttrs (k: v: v + 1) { foo = 1; bar = 2; }; in m.foo"
+            ],
+        );
+        check_trigger(
+            "let s = { foo = 1; }; in (builtins.mapAttrs (k: v: [ v ]) s).$0",
+            Some('.'),
+            "foo",
+            expect!["(Field) let s = { foo = 1; }; in (builtins.mapAttrs (k: v: [ v ]) s).foo"],
+        );
+    }
+
     #[test]
     fn has_known_field() {
         check(
@@ -889,6 +1705,61 @@ mod tests {
         );
     }
 
+    #[test]
+    fn define_workspace_sibling() {
+        // A middle segment differs.
+        check(
+            "
+#- /home.nix
+{ users.bob.programs.g$0 }
+#- /other.nix
+{ users.alice.programs.git.enable = true; }
+",
+            "git",
+            expect!["(Field) { users.bob.programs.git }"],
+        );
+        // The last segment differs.
+        check_no(
+            "
+#- /default.nix
+{ services.nginx.e$0 }
+#- /other.nix
+{ services.openssh.enable = true; }
+",
+            "enable",
+        );
+        check(
+            "
+#- /home.nix
+{ programs = { git.enable = true; $0 }; }
+#- /other.nix
+{ programs.zsh.enable = true; }
+",
+            "zsh",
+            expect!["(Field) { programs = { git.enable = true; zsh }; }"],
+        );
+        // Top-level names.
+        check_no(
+            "
+#- /home.nix
+{ p$0 }
+#- /other.nix
+{ programs.git.enable = true; }
+",
+            "programs",
+        );
+        // Not a definition.
+        check_no(
+            "
+#- /home.nix
+{ a = x: x.programs.$0; }
+#- /other.nix
+{ programs.git.enable = true; }
+",
+            "git",
+        );
+    }
+
     #[test]
     fn no_incomplete_field() {
         check_no("a: a.f$0", "f");
@@ -958,6 +1829,64 @@ stdenv.mkDerivation {
         );
     }
 
+    #[test]
+    fn nix_shell_package() {
+        let leaf = || {
+            FlakeOutput::Leaf(Leaf {
+                type_: Type::Derivation,
+                name: None,
+                description: None,
+            })
+        };
+        let pkgs = FlakeOutput::Attrset(HashMap::from_iter([
+            ("hello".into(), leaf()),
+            ("jq".into(), leaf()),
+        ]));
+        let nixpkgs = FlakeOutput::Attrset(HashMap::from_iter([(
+            "legacyPackages".into(),
+            FlakeOutput::Attrset(HashMap::from_iter([("x86_64-linux".into(), pkgs)])),
+        )]));
+
+        let labels = |fixture: &str| {
+            let (mut db, f) = TestDB::from_fixture(fixture).unwrap();
+            let sid = db.file_source_root(f[0].file_id);
+            db.set_flake_graph(Arc::new(FlakeGraph {
+                nodes: HashMap::from_iter([(
+                    sid,
+                    FlakeInfo {
+                        flake_file: f[0].file_id,
+                        input_store_paths: HashMap::new(),
+                        input_flake_outputs: HashMap::from_iter([(
+                            "nixpkgs".into(),
+                            nixpkgs.clone(),
+                        )]),
+                    },
+                )]),
+            }));
+            let src = db.file_content(f[0].file_id);
+            super::completions(&db, f[0], None)
+                .into_iter()
+                .map(|item| format!("{} {:?}", item.label, &src[item.replace_range]))
+                .collect::<Vec<_>>()
+        };
+
+        let script = |line: &str| format!("#!/usr/bin/env nix-shell\n{line}\n1");
+        assert_eq!(
+            labels(&script("#! nix-shell -p $0")),
+            ["hello \"\"", "jq \"\""]
+        );
+        assert_eq!(
+            labels(&script("#! nix-shell -i bash -p hello h$0")),
+            ["hello \"h\""]
+        );
+        assert_eq!(labels(&script("#! nix-shell --packages j$0")), ["jq \"j\""]);
+        // Not a package argument.
+        assert_eq!(labels(&script("#! nix-shell -p hello -i $0")), [""; 0]);
+        assert_eq!(labels(&script("#! nix-shell $0")), [""; 0]);
+        assert_eq!(labels(&script("# nix-shell -p $0")), [""; 0]);
+        assert_eq!(labels(&script("#! bash -p $0")), [""; 0]);
+    }
+
     #[test]
     fn escape_attr() {
         check(
@@ -1054,4 +1983,311 @@ stdenv.mkDerivation {
             expect!["(Param) { foo, bar ? foo }: 42"],
         );
     }
+
+    #[test]
+    fn auto_import() {
+        check(
+            "
+#- /default.nix
+let a = 1; in fo$0
+
+#- /lib.nix
+let x = 1; in { foo = x; bar = 2; }
+            ",
+            "foo",
+            expect!["(Field) let lib = import ./lib.nix; a = 1; in lib.foo"],
+        );
+        check(
+            "
+#- /default.nix
+{ pkgs }: [ fo$0 ]
+
+#- /utils/default.nix
+{ foo = x: x; }
+            ",
+            "foo",
+            expect!["(Field) { pkgs }: let utils = import ./utils/default.nix; in [ utils.foo ]"],
+        );
+        check(
+            "
+#- /sub/default.nix
+fo$0
+
+#- /lib.nix
+rec { foo = 1; }
+            ",
+            "foo",
+            expect!["(Field) let lib = import ../lib.nix; in lib.foo"],
+        );
+    }
+
+    #[test]
+    fn no_auto_import() {
+        // The alias is already in scope.
+        check_no(
+            "
+#- /default.nix
+let lib = 1; in fo$0
+
+#- /lib.nix
+{ foo = 1; }
+            ",
+            "foo",
+        );
+        // Importing would be cyclic.
+        check_no(
+            "
+#- /default.nix
+fo$0
+
+#- /lib.nix
+{ foo = import ./default.nix; }
+            ",
+            "foo",
+        );
+        // Exports are not statically known.
+        check_no(
+            "
+#- /default.nix
+fo$0
+
+#- /lib.nix
+{ pkgs }: { foo = 1; }
+            ",
+            "foo",
+        );
+    }
+
+    #[test]
+    fn auto_import_limit() {
+        let fixture = (0..10)
+            .map(|i| {
+                let fields = (0..10)
+                    .map(|j| format!("foo{i}_{j} = 1;"))
+                    .collect::<String>();
+                format!("#- /lib{i}.nix\n{{ {fields} }}\n")
+            })
+            .collect::<String>();
+        let (db, f) = TestDB::from_fixture(&format!("#- /default.nix\nfo$0\n{fixture}")).unwrap();
+        let cnt = super::completions(&db, f[0], None)
+            .iter()
+            .filter(|item| {
+                item.description
+                    .as_deref()
+                    .is_some_and(|d| d.starts_with("import"))
+            })
+            .count();
+        assert_eq!(cnt, super::MAX_AUTO_IMPORT_ITEMS);
+    }
+
+    #[test]
+    fn select_field_sources() {
+        check_trigger(
+            "let a = rec { x = 1; y = x; }; in a.$0",
+            Some('.'),
+            "y",
+            expect!["(Field) let a = rec { x = 1; y = x; }; in a.y"],
+        );
+        check_trigger(
+            "let b = 1; a = { inherit b; inherit ({ c = 1; }) c; }; in a.$0",
+            Some('.'),
+            "b",
+            expect!["(Field) let b = 1; a = { inherit b; inherit ({ c = 1; }) c; }; in a.b"],
+        );
+        check_trigger(
+            "let b = 1; a = { inherit b; inherit ({ c = 1; }) c; }; in a.$0",
+            Some('.'),
+            "c",
+            expect!["(Field) let b = 1; a = { inherit b; inherit ({ c = 1; }) c; }; in a.c"],
+        );
+        check_trigger(
+            "let a = { x.y.z = 1; x.w = 2; }; in a.x.$0",
+            Some('.'),
+            "y",
+            expect!["(Field) let a = { x.y.z = 1; x.w = 2; }; in a.x.y"],
+        );
+        check_trigger(
+            "let a = { x = 1; }; b = a; in b.$0",
+            Some('.'),
+            "x",
+            expect!["(Field) let a = { x = 1; }; b = a; in b.x"],
+        );
+    }
+
+    #[test]
+    fn with_env_field() {
+        check(
+            "let s = { foo = 1; }; in with s; f$0",
+            "foo",
+            expect!["(Field) let s = { foo = 1; }; in with s; foo"],
+        );
+        check(
+            "let s = { foo = 1; }; t = { bar = 1; }; in with s; with t; b$0",
+            "bar",
+            expect!["(Field) let s = { foo = 1; }; t = { bar = 1; }; in with s; with t; bar"],
+        );
+        // Lexical names win.
+        check(
+            "let s = { foo = 1; }; in let foo = 2; in with s; f$0",
+            "foo",
+            expect!["(LetBinding) let s = { foo = 1; }; in let foo = 2; in with s; foo"],
+        );
+        check_no("with import ./unknown.nix; f$0", "foo");
+        check_no(r#"with { "a b" = 1; }; a$0"#, "a b");
+    }
+
+    #[test]
+    fn select_unknown_field_no_fallback() {
+        // Neither keywords nor bindings in scope are suggested.
+        for src in ["pkgs.$0", "{ pkgs, ... }: pkgs.$0", "let f = x: x; in f.$0"] {
+            let (db, f) = TestDB::from_fixture(src).unwrap();
+            let compes = super::completions(&db, f[0], Some('.'));
+            assert_eq!(compes, Vec::new(), "{src}");
+        }
+    }
+
+    #[track_caller]
+    fn check_labels(fixture: &str, expect: Expect) {
+        let (db, f) = TestDB::from_fixture(fixture).unwrap();
+        let got = super::completions(&db, f[0], Some('/'))
+            .into_iter()
+            .map(|item| format!("({:?}) {}\n", item.kind, item.label))
+            .collect::<String>();
+        expect.assert_eq(&got);
+    }
+
+    #[test]
+    fn relative_path() {
+        let fixture = "
+#- /default.nix
+import ./$0
+#- /foo.nix
+#- /README.md
+#- /lib/default.nix
+#- /lib/utils.nix
+#- /.direnv/env.nix
+        ";
+        check_labels(
+            fixture,
+            expect![[r#"
+                (File) default.nix
+                (File) foo.nix
+                (Folder) lib/
+            "#]],
+        );
+        check(fixture, "lib/", expect!["(Folder) import ./lib/"]);
+        check(
+            "
+#- /default.nix
+{ src = ./lib/u$0; }
+#- /lib/utils.nix
+            ",
+            "utils.nix",
+            expect!["(File) { src = ./lib/utils.nix; }"],
+        );
+        // Replace the whole segment.
+        check(
+            "
+#- /default.nix
+./li$0b/utils.nix
+#- /lib/utils.nix
+            ",
+            "lib/",
+            expect!["(Folder) ./lib//utils.nix"],
+        );
+    }
+
+    #[test]
+    fn non_import_path() {
+        check_labels(
+            "
+#- /default.nix
+{ src = ./$0; }
+#- /README.md
+#- /lib/utils.nix
+            ",
+            expect![[r#"
+                (File) README.md
+                (File) default.nix
+                (Folder) lib/
+            "#]],
+        );
+        check_labels(
+            "
+#- /default.nix
+reimport ./$0
+#- /README.md
+            ",
+            expect![[r#"
+                (File) README.md
+                (File) default.nix
+            "#]],
+        );
+    }
+
+    #[test]
+    fn parent_path() {
+        check_labels(
+            "
+#- /sub/default.nix
+import ../$0
+#- /foo.nix
+            ",
+            expect![[r#"
+                (File) foo.nix
+                (Folder) sub/
+            "#]],
+        );
+        // Beyond the root.
+        check_labels(
+            "
+#- /sub/default.nix
+import ../../$0
+#- /foo.nix
+            ",
+            expect![""],
+        );
+    }
+
+    #[test]
+    fn no_path_for_division() {
+        check_labels("let a = 1; in a /$0", expect![""]);
+        check_labels("let a = 1; in a./$0", expect![""]);
+    }
+
+    #[test]
+    fn unterminated_interpolation() {
+        check(
+            r#"let foo = 1; in "${fo$0"#,
+            "foo",
+            expect![[r#"(LetBinding) let foo = 1; in "${foo"#]],
+        );
+        check(
+            r#"let foo = 1; in "${$0"#,
+            "foo",
+            expect![[r#"(LetBinding) let foo = 1; in "${foo"#]],
+        );
+        check(
+            r#"let foo = 1; in "${ $0 }""#,
+            "foo",
+            expect![[r#"(LetBinding) let foo = 1; in "${ foo }""#]],
+        );
+        check(
+            "let foo = 1; in ''a${$0",
+            "foo",
+            expect!["(LetBinding) let foo = 1; in ''a${foo"],
+        );
+        check(
+            r#"let foo = { bar = 1; }; in "${foo.$0"#,
+            "bar",
+            expect![[r#"(Field) let foo = { bar = 1; }; in "${foo.bar"#]],
+        );
+        check(
+            r#"{ foo }: { a = "${fo$0; }"#,
+            "foo",
+            expect![[r#"(Param) { foo }: { a = "${foo; }"#]],
+        );
+        check_no(r#"let foo = 1; in "${foo}$0"#, "foo");
+        check_no(r#"let foo = 1; in "fo$0"#, "foo");
+    }
 }
@@ -1,6 +1,12 @@
-use crate::{DefDatabase, Diagnostic, FileId};
+use super::{lib_select, MAX_RESOLVE_DEPTH};
+use crate::def::{BinaryOp, BindingValue, Bindings, Expr, ExprId, Literal, NameId, ResolveResult};
+use crate::{DefDatabase, Diagnostic, DiagnosticKind, FileId, FileRange, TyDatabase};
+use std::collections::{HashMap, HashSet};
+use syntax::ast::{self, AstNode};
+use syntax::rowan::WalkEvent;
+use syntax::{SyntaxKind, TextRange};
 
-pub(crate) fn diagnostics(db: &dyn DefDatabase, file: FileId) -> Vec<Diagnostic> {
+pub(crate) fn diagnostics(db: &dyn TyDatabase, file: FileId) -> Vec<Diagnostic> {
     let mut diags = Vec::new();
 
     // Parsing.
@@ -18,12 +24,439 @@ pub(crate) fn diagnostics(db: &dyn DefDatabase, file: FileId) -> Vec<Diagnostic>
     let liveness = db.liveness_check(file);
     diags.extend(liveness.to_diagnostics(db, file));
 
+    // Module checks.
+    diags.extend(db.module_kind(file).to_diagnostics(db, file));
+
+    // Builtin calls.
+    diags.extend(builtin_call_diagnostics(db, file));
+    diags.extend(with_builtin_diagnostics(db, file));
+
+    // Attrset updates.
+    diags.extend(update_diagnostics(db, file));
+
+    // Style.
+    diags.extend(nesting_diagnostics(db, file));
+    diags.extend(lib_select_diagnostics(db, file));
+    diags.extend(redundant_let_in_diagnostics(db, file));
+    diags.extend(redundant_if_diagnostics(db, file));
+
     diags
 }
 
+/// Lint calls of builtins whose arguments are literals and are obviously wrong.
+fn builtin_call_diagnostics(db: &dyn DefDatabase, file: FileId) -> Vec<Diagnostic> {
+    let module = db.module(file);
+    let nameres = db.name_resolution(file);
+    let source_map = db.source_map(file);
+    let is_builtin = |expr, name: &str| match &module[expr] {
+        // `builtins.name`
+        Expr::Select(set, attrpath, None) => match &**attrpath {
+            [attr] => {
+                matches!(&module[*attr], Expr::Literal(Literal::String(s)) if s == name)
+                    && nameres.check_builtin(*set, &module) == Some("builtins")
+            }
+            _ => false,
+        },
+        _ => nameres.check_builtin(expr, &module) == Some(name),
+    };
+
+    let mut diags = Vec::new();
+    for (_, expr) in module.exprs() {
+        // `replaceStrings from to`
+        let &Expr::Apply(lam, to) = expr else {
+            continue;
+        };
+        let &Expr::Apply(func, from) = &module[lam] else {
+            continue;
+        };
+        let (Expr::List(from_elems), Expr::List(to_elems)) = (&module[from], &module[to]) else {
+            continue;
+        };
+        if from_elems.len() == to_elems.len() || !is_builtin(func, "replaceStrings") {
+            continue;
+        }
+        let (Some(from_ptr), Some(to_ptr)) =
+            (source_map.node_for_expr(from), source_map.node_for_expr(to))
+        else {
+            continue;
+        };
+        let elements = |n: usize| if n == 1 { "element" } else { "elements" };
+        diags.push(
+            Diagnostic::new(
+                to_ptr.text_range(),
+                DiagnosticKind::ReplaceStringsLengthMismatch,
+            )
+            .with_note(
+                FileRange::new(file, from_ptr.text_range()),
+                format!(
+                    "`from` has {} {}",
+                    from_elems.len(),
+                    elements(from_elems.len())
+                ),
+            )
+            .with_note(
+                FileRange::new(file, to_ptr.text_range()),
+                format!("`to` has {} {}", to_elems.len(), elements(to_elems.len())),
+            ),
+        );
+    }
+    diags
+}
+
+/// Report file-scope `with`s providing attributes named after builtins used in their bodies,
+/// like `with { map = f; }; map x`. Builtins always take precedence over `with`.
+fn with_builtin_diagnostics(db: &dyn TyDatabase, file: FileId) -> Vec<Diagnostic> {
+    let module = db.module(file);
+    let nameres = db.name_resolution(file);
+    let source_map = db.source_map(file);
+
+    // `with`s not nested in any other expression than lambdas, `let`, `assert` and `with`.
+    let mut withs = Vec::new();
+    let mut expr = module.entry_expr();
+    loop {
+        expr = match &module[expr] {
+            &Expr::Lambda(_, _, body) | &Expr::LetIn(_, body) | &Expr::Assert(_, body) => body,
+            &Expr::With(env, body) => {
+                withs.push((expr, env, body));
+                body
+            }
+            _ => break,
+        };
+    }
+    if withs.is_empty() {
+        return Vec::new();
+    }
+
+    let infer = db.infer(file);
+    let mut diags = Vec::new();
+    for (with_expr, env, body) in withs {
+        if nameres.check_builtin(env, &module) == Some("builtins") {
+            continue;
+        }
+        let env_ty = infer.ty_for_expr(env);
+        let Some(attrset) = env_ty.as_attrset() else {
+            continue;
+        };
+        let (Some(with_ptr), Some(body_ptr)) = (
+            source_map.node_for_expr(with_expr),
+            source_map.node_for_expr(body),
+        ) else {
+            continue;
+        };
+        let body_range = body_ptr.text_range();
+
+        let mut uses = module
+            .exprs()
+            .filter_map(|(e, _)| match nameres.get(e)? {
+                ResolveResult::Builtin(name) if attrset.get(name).is_some() => {
+                    let range = source_map.node_for_expr(e)?.text_range();
+                    body_range.contains_range(range).then_some((range, *name))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if uses.is_empty() {
+            continue;
+        }
+        uses.sort_by_key(|(range, _)| range.start());
+
+        let with_node = ast::With::cast(with_ptr.to_node(&db.parse(file).syntax_node()));
+        let header = with_node
+            .and_then(|node| {
+                let (start, end) = (node.with_token()?, node.semicolon_token()?);
+                Some(start.text_range().cover(end.text_range()))
+            })
+            .unwrap_or_else(|| with_ptr.text_range());
+        diags.push(uses.into_iter().fold(
+            Diagnostic::new(header, DiagnosticKind::WithMaskedBuiltin),
+            |diag, (range, name)| {
+                diag.with_note(
+                    FileRange::new(file, range),
+                    format!("`{name}` refers to the builtin, not the attribute from `with`"),
+                )
+            },
+        ));
+    }
+    diags
+}
+
+/// Lint keys defined in more than one operand of a `//` chain, like `{ a = 1; } // { a = 2; }`.
+/// Only chains whose operands are all attrset literals, or references to them in the same file,
+/// are checked. Nested keys are not checked since `//` replaces them as a whole.
+fn update_diagnostics(db: &dyn DefDatabase, file: FileId) -> Vec<Diagnostic> {
+    let module = db.module(file);
+    let nameres = db.name_resolution(file);
+    let source_map = db.source_map(file);
+
+    let is_update = |expr: ExprId| matches!(module[expr], Expr::Binary(Some(BinaryOp::Update), ..));
+    // Only check the outermost `//` of each chain.
+    let mut inner_updates = HashSet::new();
+    for (_, expr) in module.exprs() {
+        if let &Expr::Binary(Some(BinaryOp::Update), lhs, rhs) = expr {
+            inner_updates.extend([lhs, rhs].into_iter().filter(|&e| is_update(e)));
+        }
+    }
+
+    let resolve_attrset = |mut expr: ExprId| -> Option<&Bindings> {
+        for _ in 0..MAX_RESOLVE_DEPTH {
+            match &module[expr] {
+                Expr::Attrset(bindings) | Expr::RecAttrset(bindings) => {
+                    return bindings.dynamics.is_empty().then_some(bindings);
+                }
+                Expr::Reference(_) => {
+                    let &ResolveResult::Definition(name) = nameres.get(expr)? else {
+                        return None;
+                    };
+                    expr = module.binding_value(name)?;
+                }
+                _ => return None,
+            }
+        }
+        None
+    };
+
+    let mut diags = Vec::new();
+    for (root, _) in module.exprs() {
+        if !is_update(root) || inner_updates.contains(&root) {
+            continue;
+        }
+        // Flatten operands from left to right.
+        let mut operands = Vec::new();
+        let mut stack = vec![root];
+        while let Some(expr) = stack.pop() {
+            match module[expr] {
+                Expr::Binary(Some(BinaryOp::Update), lhs, rhs) => stack.extend([rhs, lhs]),
+                _ => operands.push(expr),
+            }
+        }
+        // Any dynamic operand may define or remove any key.
+        let Some(operands) = operands
+            .into_iter()
+            .map(resolve_attrset)
+            .collect::<Option<Vec<_>>>()
+        else {
+            continue;
+        };
+
+        let mut defs: HashMap<&str, Vec<NameId>> = HashMap::new();
+        let mut keys = Vec::new();
+        for bindings in &operands {
+            for &(name, _) in bindings.statics.iter() {
+                let text = &*module[name].text;
+                let names = defs.entry(text).or_default();
+                // Each key is reported once per chain, even if an operand is repeated.
+                if names.is_empty() {
+                    keys.push(text);
+                }
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+
+        for key in keys {
+            let names = &defs[key];
+            let ranges = names
+                .iter()
+                .filter_map(|&name| Some(source_map.nodes_for_name(name).next()?.text_range()))
+                .collect::<Vec<_>>();
+            let [overridden @ .., kept] = &ranges[..] else {
+                continue;
+            };
+            if overridden.is_empty() {
+                continue;
+            }
+            let diag = overridden.iter().fold(
+                Diagnostic::new(*kept, DiagnosticKind::DuplicatedUpdateKey),
+                |diag, &range| diag.with_note(FileRange::new(file, range), "Overridden definition"),
+            );
+            diags.push(diag);
+        }
+    }
+    diags
+}
+
+/// Lint attrsets nested deeper than the configured limit, counting only the ones written out
+/// as `{ ... }`. Attrpaths like `a.b.c = 1;` keep the nesting flat, so they are not counted.
+fn nesting_diagnostics(db: &dyn DefDatabase, file: FileId) -> Vec<Diagnostic> {
+    let max_nesting = db.max_nesting();
+    let parse = db.parse(file);
+    let mut diags = Vec::new();
+    let mut stack = Vec::new();
+    let mut iter = parse.syntax_node().preorder();
+    while let Some(event) = iter.next() {
+        match event {
+            WalkEvent::Enter(node) => {
+                let Some(set) = ast::AttrSet::cast(node) else {
+                    continue;
+                };
+                let l_curly = set
+                    .l_curly_token()
+                    .map_or(set.syntax().text_range(), |tok| tok.text_range());
+                stack.push(l_curly);
+                if stack.len() <= max_nesting {
+                    continue;
+                }
+                // Report the key of the binding if any, since the set itself may be large.
+                let range = set
+                    .syntax()
+                    .parent()
+                    .and_then(ast::AttrpathValue::cast)
+                    .and_then(|path_value| path_value.attrpath())
+                    .and_then(|path| {
+                        let first = path.attrs().next()?.syntax().text_range();
+                        let last = path.attrs().last()?.syntax().text_range();
+                        Some(first.cover(last))
+                    })
+                    .unwrap_or(l_curly);
+                diags.push(
+                    Diagnostic::new(range, DiagnosticKind::DeepNesting).with_note(
+                        FileRange::new(file, stack[0]),
+                        format!("Outermost attrset, allowing {max_nesting} levels of nesting"),
+                    ),
+                );
+                // Deeper ones are covered by this report.
+                iter.skip_subtree();
+            }
+            WalkEvent::Leave(node) => {
+                if node.kind() == SyntaxKind::ATTR_SET {
+                    stack.pop();
+                }
+            }
+        }
+    }
+    diags
+}
+
+/// Suggest `inherit (lib) ...` when more distinct attributes of the same `lib` are selected than
+/// the configured threshold. The first select is reported.
+fn lib_select_diagnostics(db: &dyn DefDatabase, file: FileId) -> Vec<Diagnostic> {
+    let threshold = db.lib_inherit_threshold();
+    let module = db.module(file);
+    let nameres = db.name_resolution(file);
+    let source_map = db.source_map(file);
+
+    // Distinct attribute names and the ranges of `lib.name` for each `lib`.
+    let mut groups: HashMap<NameId, (HashSet<&str>, Vec<TextRange>)> = HashMap::new();
+    for (expr, kind) in module.exprs() {
+        let Expr::Select(_, path, _) = kind else {
+            continue;
+        };
+        // Only ones the `inherit (lib)` assist can rewrite.
+        let Some((lib, attr)) = lib_select(&module, &nameres, expr) else {
+            continue;
+        };
+        let (Some(select_ptr), Some(attr_ptr)) = (
+            source_map.node_for_expr(expr),
+            source_map.node_for_expr(path[0]),
+        ) else {
+            continue;
+        };
+        let range = TextRange::new(select_ptr.text_range().start(), attr_ptr.text_range().end());
+        let (names, ranges) = groups.entry(lib).or_default();
+        names.insert(attr);
+        ranges.push(range);
+    }
+
+    let mut diags = groups
+        .into_values()
+        .filter(|(names, _)| names.len() > threshold)
+        .filter_map(|(_, ranges)| {
+            let first = ranges.into_iter().min_by_key(|range| range.start())?;
+            Some(Diagnostic::new(first, DiagnosticKind::ManyLibSelects))
+        })
+        .collect::<Vec<_>>();
+    diags.sort_by_key(|diag| diag.range.start());
+    diags
+}
+
+/// Report `let x = value; in x`, where `x` is only used as the body.
+fn redundant_let_in_diagnostics(db: &dyn DefDatabase, file: FileId) -> Vec<Diagnostic> {
+    let module = db.module(file);
+    let nameres = db.name_resolution(file);
+    let name_refs = db.name_reference(file);
+    let source_map = db.source_map(file);
+    let root = db.parse(file).syntax_node();
+
+    let mut diags = Vec::new();
+    for (expr, kind) in module.exprs() {
+        let Expr::LetIn(bindings, body) = kind else {
+            continue;
+        };
+        let ([(name, BindingValue::Expr(_))], [], []) = (
+            &*bindings.statics,
+            &*bindings.inherit_froms,
+            &*bindings.dynamics,
+        ) else {
+            continue;
+        };
+        if nameres.get(*body) != Some(&ResolveResult::Definition(*name))
+            || name_refs.name_references(*name) != Some(&[*body])
+        {
+            continue;
+        }
+        // Only `x = value;`, not `x.y = value;`.
+        let mut defs = source_map.nodes_for_name(*name);
+        let (Some(def), None) = (defs.next(), defs.next()) else {
+            continue;
+        };
+        let is_single_attr = def
+            .to_node(&root)
+            .parent()
+            .and_then(ast::Attrpath::cast)
+            .is_some_and(|path| path.attrs().count() == 1);
+        let Some(let_in) = source_map
+            .node_for_expr(expr)
+            .and_then(|ptr| ast::LetIn::cast(ptr.to_node(&root)))
+        else {
+            continue;
+        };
+        let (Some(let_token), Some(in_token)) = (let_in.let_token(), let_in.in_token()) else {
+            continue;
+        };
+        if is_single_attr {
+            diags.push(Diagnostic::new(
+                let_token.text_range().cover(in_token.text_range()),
+                DiagnosticKind::RedundantLetIn,
+            ));
+        }
+    }
+    diags
+}
+
+/// Report `if c then true else false` and `if c then false else true`, where `true` and `false`
+/// are builtins.
+fn redundant_if_diagnostics(db: &dyn DefDatabase, file: FileId) -> Vec<Diagnostic> {
+    let module = db.module(file);
+    let nameres = db.name_resolution(file);
+    let source_map = db.source_map(file);
+
+    module
+        .exprs()
+        .filter_map(|(expr, kind)| {
+            let &Expr::IfThenElse(_, then_expr, else_expr) = kind else {
+                return None;
+            };
+            match (
+                nameres.check_builtin(then_expr, &module)?,
+                nameres.check_builtin(else_expr, &module)?,
+            ) {
+                ("true", "false") | ("false", "true") => {}
+                _ => return None,
+            }
+            let ptr = source_map.node_for_expr(expr)?;
+            Some(Diagnostic::new(
+                ptr.text_range(),
+                DiagnosticKind::RedundantIf,
+            ))
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use crate::tests::TestDB;
+    use crate::{DiagnosticKind, SourceDatabase};
     use expect_test::{expect, Expect};
 
     fn check(fixture: &str, expect: Expect) {
@@ -41,6 +474,16 @@ mod tests {
         expect.assert_eq(&got);
     }
 
+    #[track_caller]
+    fn check_no(fixture: &str, kind: DiagnosticKind) {
+        let (db, file_id) = TestDB::single_file(fixture).unwrap();
+        let diags = super::diagnostics(&db, file_id);
+        assert!(
+            diags.iter().all(|diag| diag.kind != kind),
+            "{fixture}: {diags:?}",
+        );
+    }
+
     #[test]
     fn syntax_error() {
         check("1 == 2 == 3", expect!["7..9: SyntaxError(MultipleNoAssoc)"]);
@@ -74,6 +517,128 @@ mod tests {
         );
     }
 
+    #[test]
+    fn module_keys() {
+        check(
+            "{ ... }: { imports = { }; config = [ ]; options = \"\"; }",
+            expect![[r#"
+                21..24: InvalidModuleKey
+                    21..24: `imports` should be a list
+                35..38: InvalidModuleKey
+                    35..38: `config` should be an attrset
+                50..52: InvalidModuleKey
+                    50..52: `options` should be an attrset
+            "#]],
+        );
+
+        let (db, file) = TestDB::single_file(
+            "{ lib, ... }: { imports = [ ]; config = lib.mkIf true { }; options = { }; }",
+        )
+        .unwrap();
+        assert_eq!(super::diagnostics(&db, file), Vec::new());
+    }
+
+    #[test]
+    fn replace_strings_length_mismatch() {
+        check(
+            r#"builtins.replaceStrings [ "a" "b" ] [ "c" ] "ab""#,
+            expect![[r#"
+                36..43: ReplaceStringsLengthMismatch
+                    24..35: `from` has 2 elements
+                    36..43: `to` has 1 element
+            "#]],
+        );
+        check(
+            r#"let inherit (builtins) replaceStrings; in replaceStrings [ ] [ "a" ]"#,
+            expect![[r#"
+                61..68: ReplaceStringsLengthMismatch
+                    57..60: `from` has 0 elements
+                    61..68: `to` has 1 element
+            "#]],
+        );
+
+        for src in [
+            r#"builtins.replaceStrings [ "a" ] [ "b" ] "a""#,
+            r#"{ f }: builtins.replaceStrings f [ "b" ] "a""#,
+            r#"let replaceStrings = a: _b: a; in replaceStrings [ "a" ] [ ]"#,
+        ] {
+            let (db, file) = TestDB::single_file(src).unwrap();
+            assert_eq!(super::diagnostics(&db, file), Vec::new(), "{src}");
+        }
+    }
+
+    #[test]
+    fn name_from_with() {
+        check(
+            "{ pkgs, lib }: with pkgs; with lib;\n[ stdnev ]",
+            expect![[r#"
+                38..44: NameFromWith
+                    26..35: It may come from `with lib;`
+                    15..25: It may come from `with pkgs;`
+            "#]],
+        );
+        // Lexical bindings and builtins are statically known.
+        check_no(
+            "{ pkgs }: with pkgs; let a = 1; in [ a pkgs (with builtins; map) true ]",
+            DiagnosticKind::NameFromWith,
+        );
+    }
+
+    #[test]
+    fn duplicated_update_key() {
+        check(
+            "{ a = 1; b = 2; } // { a = 3; } // { a = 4; b = 5; c = 6; }",
+            expect![[r#"
+                37..38: DuplicatedUpdateKey
+                    2..3: Overridden definition
+                    23..24: Overridden definition
+                44..45: DuplicatedUpdateKey
+                    9..10: Overridden definition
+            "#]],
+        );
+        // References to literals and nested attrpaths.
+        check(
+            "let x = { a.b = 1; }; y = x; in y // { a.c = 2; }",
+            expect![[r#"
+                39..40: DuplicatedUpdateKey
+                    10..11: Overridden definition
+            "#]],
+        );
+
+        for src in [
+            // Dynamic operands.
+            "{ f }: { a = 1; } // f // { a = 2; }",
+            "{ x }: { a = 1; } // { ${x} = 2; } // { a = 3; }",
+            "{ a = 1; } // (if true then { } else { }) // { a = 2; }",
+            // Nested keys are merged by `recursiveUpdate`.
+            "{ lib }: lib.recursiveUpdate { a.b = 1; } { a.c = 2; }",
+            "{ lib }: lib.recursiveUpdate { a.b = 1; } { a.c = 2; } // { b = 1; }",
+            "let x = { a = 1; }; in x // x",
+        ] {
+            let (db, file) = TestDB::single_file(src).unwrap();
+            assert_eq!(super::diagnostics(&db, file), Vec::new(), "{src}");
+        }
+    }
+
+    #[test]
+    fn reuse_parse_and_lowering() {
+        let (db, f) = TestDB::from_fixture("let a = 1; b = a$0; in b").unwrap();
+        let executed = db.log_executed(|| {
+            super::diagnostics(&db, f[0].file_id);
+            crate::ide::hover::hover(&db, f[0]);
+        });
+        let cnt = |query: &str| executed.iter().filter(|q| q.starts_with(query)).count();
+        assert_eq!(cnt("parse("), 1, "{executed:#?}");
+        assert_eq!(cnt("module_with_source_map("), 1, "{executed:#?}");
+
+        // Nothing is recomputed on the same revision.
+        let executed = db.log_executed(|| {
+            super::diagnostics(&db, f[0].file_id);
+            crate::ide::hover::hover(&db, f[0]);
+        });
+        assert_eq!(executed, Vec::<String>::new());
+    }
+
     #[test]
     fn deterministic_order() {
         check(
@@ -86,4 +651,125 @@ mod tests {
             "#]],
         );
     }
+
+    #[test]
+    fn deep_nesting() {
+        let src = "{ a = { b = { c = { d = 1; }; }; }; x.y.z.w = 1; l = [ { m = { }; } ]; }";
+        let (mut db, file_id) = TestDB::single_file(src).unwrap();
+        let nesting_diags = |db: &TestDB| {
+            super::diagnostics(db, file_id)
+                .iter()
+                .map(|d| d.debug_display().to_string() + "\n")
+                .collect::<String>()
+        };
+
+        // Within the default limit.
+        assert_eq!(nesting_diags(&db), "");
+
+        db.set_max_nesting(2);
+        expect![[r#"
+            8..9: DeepNesting
+                0..1: Outermost attrset, allowing 2 levels of nesting
+            57..58: DeepNesting
+                0..1: Outermost attrset, allowing 2 levels of nesting
+        "#]]
+        .assert_eq(&nesting_diags(&db));
+
+        db.set_max_nesting(5);
+        assert_eq!(nesting_diags(&db), "");
+    }
+
+    #[test]
+    fn many_lib_selects() {
+        let src = "{ lib, ... }: { a = lib.mkIf lib.b (lib.mkIf 1 2); c = lib.types.str; d = let lib = { }; in lib.e; f = lib.\"g h\"; }";
+        let (mut db, file_id) = TestDB::single_file(src).unwrap();
+        let lib_diags = |db: &TestDB| {
+            super::diagnostics(db, file_id)
+                .iter()
+                .map(|d| d.debug_display().to_string() + "\n")
+                .collect::<String>()
+        };
+
+        // Within the default threshold.
+        assert_eq!(lib_diags(&db), "");
+
+        // `mkIf`, `b` and `types` are counted.
+        db.set_lib_inherit_threshold(2);
+        expect![[r#"
+            20..28: ManyLibSelects
+        "#]]
+        .assert_eq(&lib_diags(&db));
+
+        db.set_lib_inherit_threshold(3);
+        assert_eq!(lib_diags(&db), "");
+
+        // Selects the assist cannot rewrite are not counted.
+        for src in ["{ lib }: lib.a or 1", "rec { lib = { }; b = lib.a; }"] {
+            let (mut db, file) = TestDB::single_file(src).unwrap();
+            db.set_lib_inherit_threshold(0);
+            assert_eq!(super::diagnostics(&db, file), Vec::new(), "{src}");
+        }
+    }
+
+    #[test]
+    fn with_masked_builtin() {
+        check(
+            "with { map = f: f; foo = 1; }; map toString [ 1 ]",
+            expect![[r#"
+                0..30: UnusedWith
+                0..30: WithMaskedBuiltin
+                    31..34: `map` refers to the builtin, not the attribute from `with`
+            "#]],
+        );
+        check(
+            "{ pkgs }: let pkgs' = pkgs // { toString = 1; }; in with pkgs';\n[ toString map ]",
+            expect![[r#"
+                52..63: UnusedWith
+                52..63: WithMaskedBuiltin
+                    66..74: `toString` refers to the builtin, not the attribute from `with`
+            "#]],
+        );
+        for src in [
+            "with builtins; map toString [ ]",
+            "with { foo = 1; }; map toString [ foo ]",
+            "with { map = 1; }; [ ]",
+            "[ (with { map = 1; }; map) ]",
+        ] {
+            check_no(src, DiagnosticKind::WithMaskedBuiltin);
+        }
+    }
+
+    #[test]
+    fn redundant_let_in() {
+        check("{ a = let x = 1; in x; }", expect!["6..19: RedundantLetIn"]);
+        for src in [
+            "let x = 1; y = 2; in x + y",
+            "let x = 1; in x + x",
+            "let x = [ x ]; in x",
+            "let x.y = 1; in x",
+            "let inherit (a) x; in x",
+            "let x = 1; in let y = x; in x",
+        ] {
+            check_no(src, DiagnosticKind::RedundantLetIn);
+        }
+    }
+
+    #[test]
+    fn redundant_if() {
+        check(
+            "c: { a = if c then true else false; }",
+            expect!["9..34: RedundantIf"],
+        );
+        check(
+            "c: if c then false else true",
+            expect!["3..28: RedundantIf"],
+        );
+        for src in [
+            "if c then true else true",
+            "if c then 1 else false",
+            "let true = 1; in if c then true else false",
+        ] {
+            check_no(src, DiagnosticKind::RedundantIf);
+        }
+    }
 }
@@ -1,5 +1,5 @@
 use crate::{DefDatabase, FileRange};
-use syntax::{best_token_at_offset, NodeOrToken, SyntaxKind, SyntaxNode, TextRange, T};
+use syntax::{best_token_at_offset, NodeOrToken, SyntaxKind, SyntaxNode, TextRange, TextSize, T};
 
 /// Interesting parent ranges covering the given range.
 /// Returns all ranges from the smallest to the largest.
@@ -28,13 +28,16 @@ pub(crate) fn expand_selection(
         }
     };
 
-    ret.extend(
-        std::iter::successors(node, |node| node.parent()).filter_map(|node| {
-            is_node_kind_good(node.kind())
-                .then(|| non_space_range(&node))
-                .flatten()
-        }),
-    );
+    for node in std::iter::successors(node, |node| node.parent()) {
+        if !is_node_kind_good(node.kind()) {
+            continue;
+        }
+        // The content of strings without quotes comes first.
+        if matches!(node.kind(), SyntaxKind::STRING | SyntaxKind::INDENT_STRING) {
+            ret.extend(string_content_range(&node));
+        }
+        ret.extend(non_space_range(&node));
+    }
     ret.dedup();
 
     Some(ret)
@@ -61,6 +64,24 @@ fn non_space_range(node: &SyntaxNode) -> Option<TextRange> {
     Some(TextRange::empty(lhs).cover_offset(rhs))
 }
 
+/// The range inside quotes of a string, without surrounding whitespaces.
+/// Returns `None` if there is nothing but whitespaces.
+fn string_content_range(node: &SyntaxNode) -> Option<TextRange> {
+    let mut inner = node
+        .children_with_tokens()
+        .filter(|elem| !matches!(elem.kind(), T!['"'] | T!["''"]));
+    let first = inner.next()?;
+    let last = inner.last().unwrap_or_else(|| first.clone());
+    let range = first.text_range().cover(last.text_range());
+    let text = node
+        .text()
+        .slice(range - node.text_range().start())
+        .to_string();
+    let lhs = range.start() + TextSize::of(&text[..text.len() - text.trim_start().len()]);
+    let rhs = range.end() - TextSize::of(&text[text.trim_end().len()..]);
+    (lhs < rhs).then(|| TextRange::new(lhs, rhs))
+}
+
 /// If this node/token kind is good enough to show as interesting selection.
 fn is_node_kind_good(kind: SyntaxKind) -> bool {
     !matches!(
@@ -225,4 +246,115 @@ mod tests {
             "#]],
         );
     }
+
+    #[test]
+    fn string_interpolation() {
+        check(
+            r#"f "a${b $0c}d" e"#,
+            expect![[r#"
+                c
+                b c
+                ${b c}
+                a${b c}d
+                "a${b c}d"
+                f "a${b c}d"
+                f "a${b c}d" e
+            "#]],
+        );
+        check(
+            r#""ß${$0x}ℝ""#,
+            expect![[r#"
+                x
+                ${x}
+                ß${x}ℝ
+                "ß${x}ℝ"
+            "#]],
+        );
+        check(
+            "''\n  ${$0x}\n''",
+            expect![[r#"
+                x
+                ${x}
+                ''
+                  ${x}
+                ''
+            "#]],
+        );
+    }
+
+    #[test]
+    fn string_content() {
+        check(
+            r#"{ a = "hel$0lo"; }"#,
+            expect![[r#"
+            hello
+            "hello"
+            a = "hello";
+            { a = "hello"; }
+        "#]],
+        );
+        check(
+            "''\n  foo\n  b$0ar\n''",
+            expect![[r#"
+            foo
+              bar
+            ''
+              foo
+              bar
+            ''
+        "#]],
+        );
+        check(
+            r#"f "$0""#,
+            expect![[r#"
+            ""
+            f ""
+        "#]],
+        );
+    }
+
+    #[test]
+    fn whitespace() {
+        check(
+            "[ a $0  b ]",
+            expect![[r#"
+            [ a   b ]
+        "#]],
+        );
+        check(
+            "{\n  a = 1;\n$0\n  b = 2;\n}",
+            expect![[r#"
+            {
+              a = 1;
+
+              b = 2;
+            }
+        "#]],
+        );
+        check(
+            "  $0a  ",
+            expect![[r#"
+            a
+        "#]],
+        );
+    }
+
+    #[test]
+    fn pattern_comma() {
+        check(
+            "{ a$0, b }: a",
+            expect![[r#"
+            a
+            { a, b }
+            { a, b }: a
+        "#]],
+        );
+        check(
+            "{ a,$0 b }: a",
+            expect![[r#"
+            { a, b }
+            { a, b }: a
+        "#]],
+        );
+    }
 }
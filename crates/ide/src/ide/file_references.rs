@@ -1,4 +1,8 @@
-use crate::{DefDatabase, FileId};
+use crate::def::{self, resolve_path_file, Expr, Literal, PathAnchor};
+use crate::{DefDatabase, FileId, TextEdit, VfsPath, WorkspaceEdit};
+use nix_interop::DEFAULT_IMPORT_FILE;
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
 
 pub(crate) fn file_references(db: &dyn DefDatabase, file: FileId) -> Vec<FileId> {
     let mut refs = db
@@ -13,3 +17,258 @@ pub(crate) fn file_references(db: &dyn DefDatabase, file: FileId) -> Vec<FileId>
 pub(crate) fn file_referrers(db: &dyn DefDatabase, file: FileId) -> Vec<FileId> {
     db.module_referrers(file).into_vec()
 }
+
+/// Rewrite relative path literals so they keep referring to the same files after the file at
+/// `old_path` is moved to `new_path`.
+///
+/// Both paths importing the moved file from other files, and relative paths inside the moved
+/// file itself are updated. Paths which cannot be written as literals are left untouched.
+pub(crate) fn rename_file_imports(
+    db: &dyn DefDatabase,
+    old_path: &VfsPath,
+    new_path: &VfsPath,
+) -> WorkspaceEdit {
+    let mut content_edits = HashMap::new();
+    let moved = db
+        .source_root_ids()
+        .iter()
+        .find_map(|&sid| db.source_root(sid).file_for_path(old_path));
+    let (Some(moved), Some(new_file_path)) = (moved, new_path.as_path()) else {
+        return WorkspaceEdit { content_edits };
+    };
+    let Some(new_dir) = new_file_path.parent() else {
+        return WorkspaceEdit { content_edits };
+    };
+
+    // Importers of the moved file.
+    for referrer in db.module_referrers(moved) {
+        if referrer == moved {
+            continue;
+        }
+        let Some(dir) = file_dir(db, referrer) else {
+            continue;
+        };
+        let edits = rewrite_paths(db, referrer, |path| {
+            if resolve_path_file(db, referrer, path) != Some(moved) {
+                return None;
+            }
+            // Keep importing the directory if it is still imported as `default.nix`.
+            let target = if path.resolve(db).as_ref() != Some(old_path)
+                && new_file_path.file_name()? == DEFAULT_IMPORT_FILE
+            {
+                new_dir
+            } else {
+                new_file_path
+            };
+            relative_import_path(&dir, target)
+        });
+        if !edits.is_empty() {
+            content_edits.insert(referrer, edits);
+        }
+    }
+
+    // Relative paths in the moved file itself.
+    let edits = rewrite_paths(db, moved, |path| {
+        let target = if resolve_path_file(db, moved, path) == Some(moved) {
+            new_path.clone()
+        } else {
+            path.resolve(db)?
+        };
+        relative_import_path(new_dir, target.as_path()?)
+    });
+    if !edits.is_empty() {
+        content_edits.insert(moved, edits);
+    }
+
+    WorkspaceEdit { content_edits }
+}
+
+/// The directory containing `file` on the disk.
+fn file_dir(db: &dyn DefDatabase, file: FileId) -> Option<PathBuf> {
+    let root = db.source_root(db.file_source_root(file));
+    Some(root.path_for_file(file).as_path()?.parent()?.to_owned())
+}
+
+/// Replace relative path literals in `file` for which `f` returns a different text.
+fn rewrite_paths(
+    db: &dyn DefDatabase,
+    file: FileId,
+    mut f: impl FnMut(def::Path) -> Option<String>,
+) -> Vec<TextEdit> {
+    let module = db.module(file);
+    let source_map = db.source_map(file);
+    let root = db.parse(file).syntax_node();
+    let mut edits = module
+        .exprs()
+        .filter_map(|(expr, kind)| {
+            let &Expr::Literal(Literal::Path(path)) = kind else {
+                return None;
+            };
+            if !matches!(path.data(db).anchor(), PathAnchor::Relative(_)) {
+                return None;
+            }
+            let node = source_map.node_for_expr(expr)?.to_node(&root);
+            let new_text = f(path)?;
+            (node.text() != new_text.as_str()).then(|| TextEdit {
+                delete: node.text_range(),
+                insert: new_text.into(),
+            })
+        })
+        .collect::<Vec<_>>();
+    edits.sort_by_key(|edit| edit.delete.start());
+    edits
+}
+
+/// The relative path literal from `dir` to `target`, like `./foo.nix` or `../bar/baz.nix`.
+pub(crate) fn relative_import_path(dir: &Path, target: &Path) -> Option<String> {
+    let common = dir
+        .components()
+        .zip(target.components())
+        .take_while(|(lhs, rhs)| lhs == rhs)
+        .count();
+    let supers = dir.components().count() - common;
+    let mut ret = if supers == 0 {
+        ".".to_owned()
+    } else {
+        vec![".."; supers].join("/")
+    };
+    for comp in target.components().skip(common) {
+        let Component::Normal(seg) = comp else {
+            return None;
+        };
+        let seg = seg.to_str()?;
+        // Only characters allowed in path literals.
+        if !seg
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"._-+".contains(&b))
+        {
+            return None;
+        }
+        ret.push('/');
+        ret.push_str(seg);
+    }
+    Some(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::base::SourceDatabase;
+    use crate::tests::TestDB;
+    use crate::VfsPath;
+    use expect_test::{expect, Expect};
+
+    #[track_caller]
+    fn check_rename(fixture: &str, old_path: &str, new_path: &str, expect: Expect) {
+        let (db, f) = TestDB::from_fixture(fixture).unwrap();
+        let edit =
+            super::rename_file_imports(&db, &VfsPath::new(old_path), &VfsPath::new(new_path));
+        let mut files = edit.content_edits.into_iter().collect::<Vec<_>>();
+        files.sort_by_key(|(file, _)| f.files().iter().position(|f| f == file));
+        let mut got = String::new();
+        for (file, edits) in files {
+            let mut src = db.file_content(file).to_string();
+            for edit in edits.iter().rev() {
+                edit.apply(&mut src);
+            }
+            let path = db
+                .source_root(db.file_source_root(file))
+                .path_for_file(file)
+                .clone();
+            got += &format!("#- {}\n{}\n", path.display(), src.trim_end());
+        }
+        expect.assert_eq(&got);
+    }
+
+    #[test]
+    fn move_file() {
+        check_rename(
+            "
+#- /default.nix
+import ./lib/foo.nix
+#- /lib/foo.nix
+{ bar = import ./bar.nix; self = ./foo.nix; abs = /etc/foo.nix; nixpkgs = <nixpkgs>; }
+#- /lib/bar.nix
+import ./foo.nix
+#- /lib/baz/default.nix
+[ (import ../foo.nix) ../bar.nix ]
+            ",
+            "/lib/foo.nix",
+            "/pkgs/foo.nix",
+            expect![[r#"
+                #- /default.nix
+                import ./pkgs/foo.nix
+                #- /lib/foo.nix
+                { bar = import ../lib/bar.nix; self = ./foo.nix; abs = /etc/foo.nix; nixpkgs = <nixpkgs>; }
+                #- /lib/bar.nix
+                import ../pkgs/foo.nix
+                #- /lib/baz/default.nix
+                [ (import ../../pkgs/foo.nix) ../bar.nix ]
+            "#]],
+        );
+    }
+
+    #[test]
+    fn rename_in_same_dir() {
+        check_rename(
+            "
+#- /default.nix
+{ foo = import ./foo.nix; bar = import ./bar.nix; }
+#- /foo.nix
+import ./bar.nix
+#- /bar.nix
+42
+            ",
+            "/foo.nix",
+            "/foo-renamed.nix",
+            expect![[r#"
+                #- /default.nix
+                { foo = import ./foo-renamed.nix; bar = import ./bar.nix; }
+            "#]],
+        );
+    }
+
+    #[test]
+    fn move_directory_import() {
+        check_rename(
+            "
+#- /default.nix
+{ a = import ./lib; b = import ./lib/default.nix; }
+#- /lib/default.nix
+42
+            ",
+            "/lib/default.nix",
+            "/pkgs/default.nix",
+            expect![[r#"
+                #- /default.nix
+                { a = import ./pkgs; b = import ./pkgs/default.nix; }
+            "#]],
+        );
+        check_rename(
+            "
+#- /default.nix
+import ./lib
+#- /lib/default.nix
+42
+            ",
+            "/lib/default.nix",
+            "/lib.nix",
+            expect![[r#"
+                #- /default.nix
+                import ./lib.nix
+            "#]],
+        );
+    }
+
+    #[test]
+    fn unknown_file() {
+        check_rename(
+            "
+#- /default.nix
+import ./foo.nix
+            ",
+            "/foo.nix",
+            "/bar.nix",
+            expect![""],
+        );
+    }
+}
@@ -0,0 +1,326 @@
+//! Static structural checks of a flake, without evaluation.
+use crate::def::{
+    reachable_files, resolve_path_file, BinaryOp, BindingValue, Bindings, Expr, ExprId, Literal,
+    NameId,
+};
+use crate::ty::known::FLAKE_OUTPUT_GENERIC_SYSTEM_FIELDS;
+use crate::{DefDatabase, Diagnostic, DiagnosticKind, FileId, FileRange, Module, ModuleKind};
+use syntax::TextRange;
+
+/// Outputs known by `nix flake check`.
+const KNOWN_OUTPUTS: &[&str] = &[
+    "apps",
+    "bundlers",
+    "checks",
+    "defaultApp",
+    "defaultBundler",
+    "defaultPackage",
+    "defaultTemplate",
+    "devShell",
+    "devShells",
+    "formatter",
+    "hydraJobs",
+    "legacyPackages",
+    "nixosConfigurations",
+    "nixosModule",
+    "nixosModules",
+    "overlay",
+    "overlays",
+    "packages",
+    "templates",
+];
+
+/// Known kernel names as the last component of system doubles, eg. `x86_64-linux`.
+const KNOWN_SYSTEM_KERNELS: &[&str] = &[
+    "cygwin", "darwin", "freebsd", "genode", "illumos", "linux", "netbsd", "none", "openbsd",
+    "redox", "solaris", "wasi", "windows",
+];
+
+/// Names of list bindings containing paths to NixOS modules.
+const MODULE_LIST_NAMES: &[&str] = &["imports", "modules"];
+
+/// Check the flake whose `flake.nix` is `flake_file`, and all files statically reachable from it.
+/// If `locked_inputs` is given, declared inputs are checked against it.
+pub(crate) fn flake_check(
+    db: &dyn DefDatabase,
+    flake_file: FileId,
+    locked_inputs: Option<&[String]>,
+) -> Vec<(FileId, Diagnostic)> {
+    let mut diags = Vec::new();
+
+    if let ModuleKind::FlakeNix {
+        explicit_inputs,
+        param_inputs,
+        outputs_expr,
+    } = &*db.module_kind(flake_file)
+    {
+        let module = db.module(flake_file);
+        let source_map = db.source_map(flake_file);
+        let name_range = |name: NameId| {
+            source_map
+                .nodes_for_name(name)
+                .next()
+                .map_or_else(TextRange::default, |ptr| ptr.text_range())
+        };
+        let mut report = |range: TextRange, kind: DiagnosticKind| {
+            diags.push((flake_file, Diagnostic::new(range, kind)));
+        };
+
+        if let Some(&Expr::Lambda(_, _, body)) = outputs_expr.map(|e| &module[e]) {
+            for bindings in output_sets(&module, body) {
+                for &(name, value) in bindings.statics.iter() {
+                    let text = &*module[name].text;
+                    if !KNOWN_OUTPUTS.contains(&text) {
+                        report(name_range(name), DiagnosticKind::UnknownFlakeOutput);
+                        continue;
+                    }
+                    let Some(&(_, depth)) = FLAKE_OUTPUT_GENERIC_SYSTEM_FIELDS
+                        .iter()
+                        .find(|(field, _)| *field == text)
+                    else {
+                        continue;
+                    };
+                    let BindingValue::Expr(value) = value else {
+                        continue;
+                    };
+                    for system in static_names_at_depth(&module, value, depth) {
+                        if !is_system(&module[system].text) {
+                            report(name_range(system), DiagnosticKind::MissingFlakeSystem);
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Some(locked_inputs) = locked_inputs {
+            let mut declared = explicit_inputs
+                .iter()
+                .chain(param_inputs.iter())
+                .collect::<Vec<_>>();
+            declared.sort_by_key(|&(input, name)| (input, name_range(*name).start()));
+            declared.dedup_by_key(|(input, _)| *input);
+            for &(input, name) in &declared {
+                if !locked_inputs.iter().any(|locked| locked == input) {
+                    report(name_range(*name), DiagnosticKind::UnlockedFlakeInput);
+                }
+            }
+            for locked in locked_inputs {
+                if !declared.iter().any(|(input, _)| **input == **locked) {
+                    let range = TextRange::default();
+                    let diag = Diagnostic::new(range, DiagnosticKind::UnusedLockedInput).with_note(
+                        FileRange::new(flake_file, range),
+                        format!("Input `{locked}` is locked but not declared"),
+                    );
+                    diags.push((flake_file, diag));
+                }
+            }
+        }
+    }
+
+    let flake_dir = db
+        .source_root(db.file_source_root(flake_file))
+        .path_for_file(flake_file)
+        .as_path()
+        .and_then(|path| path.parent())
+        .map(|path| path.to_owned());
+    for file in reachable_files(db, flake_file) {
+        let parse = db.parse(file);
+        diags.extend(
+            parse
+                .errors()
+                .iter()
+                .map(|&err| (file, Diagnostic::from(err))),
+        );
+
+        let module = db.module(file);
+        let source_map = db.source_map(file);
+        for (_, kind) in module.exprs() {
+            let (Expr::Attrset(bindings) | Expr::RecAttrset(bindings) | Expr::LetIn(bindings, _)) =
+                kind
+            else {
+                continue;
+            };
+            for &(name, value) in bindings.statics.iter() {
+                if !MODULE_LIST_NAMES.contains(&&*module[name].text) {
+                    continue;
+                }
+                let BindingValue::Expr(value) = value else {
+                    continue;
+                };
+                let Expr::List(elems) = &module[value] else {
+                    continue;
+                };
+                for &elem in elems.iter() {
+                    let &Expr::Literal(Literal::Path(path)) = &module[elem] else {
+                        continue;
+                    };
+                    // Only paths inside the flake are known to us.
+                    let is_inside = path.resolve(db).is_some_and(|vpath| {
+                        matches!((vpath.as_path(), &flake_dir), (Some(p), Some(dir)) if p.starts_with(dir))
+                    });
+                    if is_inside && resolve_path_file(db, file, path).is_none() {
+                        let range = source_map
+                            .node_for_expr(elem)
+                            .map_or_else(TextRange::default, |ptr| ptr.text_range());
+                        diags.push((
+                            file,
+                            Diagnostic::new(range, DiagnosticKind::MissingModuleFile),
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    diags
+}
+
+/// Statically known attrsets of the output expression.
+/// `let`-in, `with` and `//` are looked through.
+fn output_sets(module: &Module, e: ExprId) -> Vec<&Bindings> {
+    match &module[e] {
+        Expr::Attrset(bindings) | Expr::RecAttrset(bindings) => vec![bindings],
+        &Expr::LetIn(_, body) | &Expr::With(_, body) => output_sets(module, body),
+        &Expr::Binary(Some(BinaryOp::Update), lhs, rhs) => {
+            let mut sets = output_sets(module, lhs);
+            sets.extend(output_sets(module, rhs));
+            sets
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Static attribute names under a given depth of an attrset expression.
+fn static_names_at_depth(module: &Module, e: ExprId, depth: usize) -> Vec<NameId> {
+    output_sets(module, e)
+        .into_iter()
+        .flat_map(|bindings| bindings.statics.iter())
+        .flat_map(|&(name, value)| match (depth, value) {
+            (0, _) => vec![name],
+            (_, BindingValue::Expr(value)) => static_names_at_depth(module, value, depth - 1),
+            _ => Vec::new(),
+        })
+        .collect()
+}
+
+fn is_system(name: &str) -> bool {
+    name.rsplit_once('-')
+        .is_some_and(|(arch, kernel)| !arch.is_empty() && KNOWN_SYSTEM_KERNELS.contains(&kernel))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::TestDB;
+    use crate::SourceDatabase;
+    use expect_test::{expect, Expect};
+
+    #[track_caller]
+    fn check(fixture: &str, locked_inputs: Option<&[&str]>, expect: Expect) {
+        let (db, f) = TestDB::from_fixture(fixture).unwrap();
+        let locked_inputs =
+            locked_inputs.map(|inputs| inputs.iter().map(|s| s.to_string()).collect::<Vec<_>>());
+        let diags = super::flake_check(&db, f["/flake.nix"], locked_inputs.as_deref());
+        let source_root = db.source_root(db.file_source_root(f["/flake.nix"]));
+        let mut got = String::new();
+        for (file, diag) in diags {
+            let src = db.file_content(file);
+            got += &format!(
+                "{} {:?} {}: {}\n",
+                source_root.path_for_file(file).display(),
+                diag.range,
+                &src[diag.range],
+                diag.message(),
+            );
+            for (_, note) in &diag.notes {
+                got += &format!("  note: {note}\n");
+            }
+        }
+        expect.assert_eq(&got);
+    }
+
+    #[test]
+    fn outputs() {
+        check(
+            "
+#- /flake.nix
+{
+    outputs = { self }: let x = 1; in {
+        packages.x86_64-linux.hello = x;
+        packages.hello = x;
+        lib.foo = x;
+    } // {
+        hydraJobs.foo.aarch64-darwin = x;
+        hydraJobs.bar.baz = x;
+        devShells = self.devShells;
+    };
+}
+",
+            None,
+            expect![[r#"
+                /flake.nix 100..105 hello: Missing the system level of flake outputs
+                /flake.nix 119..122 lib: Unknown flake output
+                /flake.nix 207..210 baz: Missing the system level of flake outputs
+            "#]],
+        );
+    }
+
+    #[test]
+    fn inputs() {
+        check(
+            "
+#- /flake.nix
+{
+    inputs.nixpkgs.url = \"github:NixOS/nixpkgs\";
+    inputs.flake-utils.url = \"github:numtide/flake-utils\";
+    outputs = { self, nixpkgs, home-manager, ... }: { };
+}
+",
+            Some(&["nixpkgs", "home-manager", "stale"]),
+            expect![[r#"
+                /flake.nix 62..73 flake-utils: Input is not locked in flake.lock
+                /flake.nix 0..0 : Locked input is not declared in flake.nix
+                  note: Input `stale` is locked but not declared
+            "#]],
+        );
+        // Lock is not checked without it.
+        check(
+            "
+#- /flake.nix
+{
+    inputs.nixpkgs.url = \"github:NixOS/nixpkgs\";
+    outputs = { self, nixpkgs }: { };
+}
+",
+            None,
+            expect![""],
+        );
+    }
+
+    #[test]
+    fn module_files() {
+        check(
+            "
+#- /flake.nix
+{
+    outputs = { self }: {
+        nixosConfigurations.foo = nixosSystem {
+            modules = [ ./hosts/foo.nix ./missing.nix ];
+        };
+    };
+}
+#- /hosts/foo.nix
+{ imports = [ ./common ./broken.nix ./missing ]; }
+#- /hosts/common/default.nix
+{ }
+#- /hosts/broken.nix
+{ a = ; }
+",
+            None,
+            expect![[r#"
+                /flake.nix 116..129 ./missing.nix: Module file not found
+                /hosts/foo.nix 36..45 ./missing: Module file not found
+                /hosts/broken.nix 6..7 ;: Expecting an expression
+            "#]],
+        );
+    }
+}
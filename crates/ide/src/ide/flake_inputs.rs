@@ -0,0 +1,165 @@
+//! Inputs declared by a `flake.nix`, in `inputs` or the parameter of `outputs`.
+use crate::def::{BindingValue, Expr, Literal, NameId};
+use crate::{DefDatabase, FileId, Module, ModuleKind};
+use smol_str::SmolStr;
+use syntax::TextRange;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlakeInput {
+    pub name: SmolStr,
+    /// The literal `url` declared in `inputs`, if any.
+    pub url: Option<SmolStr>,
+    /// The range of the name in `inputs`, or in the parameter of `outputs` if it is implicit.
+    pub name_range: TextRange,
+}
+
+/// All inputs of a flake, sorted by names. Empty if `file` is not a `flake.nix`.
+pub(crate) fn flake_inputs(db: &dyn DefDatabase, file: FileId) -> Vec<FlakeInput> {
+    let ModuleKind::FlakeNix {
+        explicit_inputs,
+        param_inputs,
+        ..
+    } = &*db.module_kind(file)
+    else {
+        return Vec::new();
+    };
+    let module = db.module(file);
+    let source_map = db.source_map(file);
+
+    let mut inputs = explicit_inputs
+        .iter()
+        .map(|(input, &name)| (input, name, input_url(&module, name)))
+        .chain(
+            param_inputs
+                .iter()
+                .filter(|(input, _)| !explicit_inputs.contains_key(*input))
+                .map(|(input, &name)| (input, name, None)),
+        )
+        .map(|(input, name, url)| FlakeInput {
+            name: input.clone(),
+            url,
+            name_range: source_map
+                .nodes_for_name(name)
+                .next()
+                .map_or_else(TextRange::default, |ptr| ptr.text_range()),
+        })
+        .collect::<Vec<_>>();
+    inputs.sort_by(|lhs, rhs| lhs.name.cmp(&rhs.name));
+    inputs
+}
+
+/// The literal string of `inputs.<name>.url`.
+fn input_url(module: &Module, name: NameId) -> Option<SmolStr> {
+    let (Expr::Attrset(bindings) | Expr::RecAttrset(bindings)) =
+        &module[module.binding_value(name)?]
+    else {
+        return None;
+    };
+    bindings.statics.iter().find_map(|&(key, value)| {
+        if module[key].text != "url" {
+            return None;
+        }
+        match value {
+            BindingValue::Expr(e) => match &module[e] {
+                Expr::Literal(Literal::String(url)) => Some(url.clone()),
+                _ => None,
+            },
+            _ => None,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::TestDB;
+    use crate::{
+        AnalysisHost, Change, FileId, FileSet, FlakeGraph, FlakeInfo, SourceRoot, SourceRootId,
+        VfsPath,
+    };
+    use expect_test::{expect, Expect};
+    use std::collections::HashMap;
+
+    #[track_caller]
+    fn check(fixture: &str, expect: Expect) {
+        let (db, f) = TestDB::from_fixture(fixture).unwrap();
+        let got = super::flake_inputs(&db, f.files()[0])
+            .into_iter()
+            .map(|input| {
+                format!(
+                    "{} {:?} {:?}\n",
+                    input.name,
+                    input.url.as_deref(),
+                    input.name_range,
+                )
+            })
+            .collect::<String>();
+        expect.assert_eq(&got);
+    }
+
+    #[test]
+    fn explicit_and_param() {
+        check(
+            r#"
+#- /flake.nix input:nixpkgs=/nix/store/eeee
+{
+    inputs.nixpkgs.url = "github:NixOS/nixpkgs";
+    inputs.utils = { url = "github:numtide/flake-utils"; flake = false; };
+    inputs.local.follows = "nixpkgs";
+    outputs = { self, nixpkgs, extra, ... }: { };
+}
+            "#,
+            expect![[r#"
+                extra None 195..200
+                local None 137..142
+                nixpkgs Some("github:NixOS/nixpkgs") 13..20
+                utils Some("github:numtide/flake-utils") 62..67
+            "#]],
+        );
+    }
+
+    #[test]
+    fn not_flake() {
+        check(
+            r#"
+#- /default.nix
+{
+    inputs.nixpkgs.url = "github:NixOS/nixpkgs";
+    outputs = { self, nixpkgs }: { };
+}
+            "#,
+            expect![""],
+        );
+    }
+
+    #[test]
+    fn analysis_query() {
+        let file = FileId(0);
+        let mut change = Change::default();
+        change.change_file(
+            file,
+            r#"{ inputs.nixpkgs.url = "github:NixOS/nixpkgs"; outputs = _: { }; }"#.into(),
+        );
+        let mut file_set = FileSet::default();
+        file_set.insert(file, VfsPath::new("/flake.nix"));
+        change.set_roots(vec![SourceRoot::new_local(file_set, Some(file))]);
+        change.set_flake_graph(FlakeGraph {
+            nodes: HashMap::from_iter([(
+                SourceRootId(0),
+                FlakeInfo {
+                    flake_file: file,
+                    input_store_paths: HashMap::new(),
+                    input_flake_outputs: HashMap::new(),
+                },
+            )]),
+        });
+        let mut host = AnalysisHost::new();
+        host.apply_change(change);
+
+        let inputs = host.snapshot().flake_inputs(file).unwrap();
+        let got = inputs
+            .iter()
+            .map(|input| (&*input.name, input.url.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(got, [("nixpkgs", Some("github:NixOS/nixpkgs"))]);
+    }
+}
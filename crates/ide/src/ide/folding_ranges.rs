@@ -0,0 +1,215 @@
+use crate::{DefDatabase, FileId};
+use syntax::ast::{self, AstNode};
+use syntax::{NodeOrToken, SyntaxKind, SyntaxToken, TextRange};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FoldRange {
+    pub range: TextRange,
+    pub kind: FoldKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FoldKind {
+    /// Attrsets, lists, `let ... in`, lambda bodies and strings.
+    Region,
+    /// Block comments `/* ... */`, or consecutive lines of `#` comments.
+    Comment,
+}
+
+/// Foldable multi-line constructs in a file, in the order of their start positions.
+pub(crate) fn folding_ranges(db: &dyn DefDatabase, file: FileId) -> Vec<FoldRange> {
+    let src = db.file_content(file);
+    let parse = db.parse(file);
+    parse
+        .syntax_node()
+        .descendants_with_tokens()
+        .filter_map(|elem| {
+            let (range, kind) = match elem {
+                NodeOrToken::Token(tok) => {
+                    if tok.kind() != SyntaxKind::COMMENT {
+                        return None;
+                    }
+                    if tok.text().starts_with("/*") {
+                        (tok.text_range(), FoldKind::Comment)
+                    } else {
+                        (line_comment_group(&tok)?, FoldKind::Comment)
+                    }
+                }
+                NodeOrToken::Node(node) => match node.kind() {
+                    SyntaxKind::ATTR_SET
+                    | SyntaxKind::LIST
+                    | SyntaxKind::STRING
+                    | SyntaxKind::INDENT_STRING => (node.text_range(), FoldKind::Region),
+                    // Only the bindings. The body is usually folded on its own.
+                    SyntaxKind::LET_IN => {
+                        let let_in = ast::LetIn::cast(node)?;
+                        let range = match (let_in.let_token(), let_in.in_token()) {
+                            (Some(let_tok), Some(in_tok)) => {
+                                let_tok.text_range().cover(in_tok.text_range())
+                            }
+                            _ => let_in.syntax().text_range(),
+                        };
+                        (range, FoldKind::Region)
+                    }
+                    // Bodies already folded by themselves are skipped.
+                    SyntaxKind::LAMBDA => {
+                        let body = ast::Lambda::cast(node)?.body()?;
+                        if matches!(
+                            body.syntax().kind(),
+                            SyntaxKind::ATTR_SET
+                                | SyntaxKind::LIST
+                                | SyntaxKind::STRING
+                                | SyntaxKind::INDENT_STRING
+                                | SyntaxKind::LAMBDA
+                        ) {
+                            return None;
+                        }
+                        // Trailing whitespaces at the end of file may be included.
+                        let range = body.syntax().text_range();
+                        let len = src[range].trim_end().len();
+                        (
+                            TextRange::at(range.start(), len.try_into().ok()?),
+                            FoldKind::Region,
+                        )
+                    }
+                    _ => return None,
+                },
+            };
+            src[range]
+                .contains('\n')
+                .then_some(FoldRange { range, kind })
+        })
+        .collect()
+}
+
+/// The range of `#` comments on consecutive lines starting from `tok`, or `None` if `tok` is not
+/// the first one.
+fn line_comment_group(tok: &SyntaxToken) -> Option<TextRange> {
+    let next_comment = |tok: &SyntaxToken| {
+        let space = tok.next_token()?;
+        if space.kind() != SyntaxKind::SPACE || space.text().matches('\n').count() != 1 {
+            return None;
+        }
+        space
+            .next_token()
+            .filter(|tok| tok.kind() == SyntaxKind::COMMENT && tok.text().starts_with('#'))
+    };
+    let is_continuation = tok
+        .prev_token()
+        .and_then(|space| space.prev_token())
+        .and_then(|prev| next_comment(&prev))
+        .is_some();
+    if is_continuation {
+        return None;
+    }
+    let last = std::iter::successors(Some(tok.clone()), next_comment).last()?;
+    Some(tok.text_range().cover(last.text_range()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::TestDB;
+    use expect_test::{expect, Expect};
+
+    #[track_caller]
+    fn check(fixture: &str, expect: Expect) {
+        let (db, file) = TestDB::single_file(fixture).unwrap();
+        let got = super::folding_ranges(&db, file)
+            .into_iter()
+            .map(|fold| format!("{:?} {:?}\n", fold.kind, fold.range))
+            .collect::<String>();
+        expect.assert_eq(&got);
+    }
+
+    #[test]
+    fn single_line() {
+        check("let a = { b = [ 1 ]; }; in ''c''", expect![""]);
+    }
+
+    #[test]
+    fn nested() {
+        check(
+            "
+let
+  a = {
+    b = [
+      1
+    ];
+  };
+in
+  a
+            ",
+            expect![[r#"
+                Region 0..44
+                Region 10..40
+                Region 20..35
+            "#]],
+        );
+    }
+
+    #[test]
+    fn strings() {
+        check(
+            r#"[ "a
+b" ''
+  c
+'' ]"#,
+            expect![[r#"
+                Region 0..19
+                Region 2..7
+                Region 8..17
+            "#]],
+        );
+    }
+
+    #[test]
+    fn comments() {
+        check(
+            "/* a\n b */ # c\n# d\n/* e */ 1",
+            expect![[r#"
+                Comment 0..10
+                Comment 11..18
+            "#]],
+        );
+        check(
+            "
+# a
+  # b
+
+# c
+1 # d
+# e
+",
+            expect![[r#"
+                Comment 0..9
+                Comment 17..24
+            "#]],
+        );
+    }
+
+    #[test]
+    fn lambda() {
+        check(
+            "
+a:
+b:
+  f
+    a
+    b
+            ",
+            expect![[r#"
+                Region 8..21
+            "#]],
+        );
+        check(
+            "
+a: {
+  b = a;
+}
+            ",
+            expect![[r#"
+                Region 3..15
+            "#]],
+        );
+    }
+}
@@ -0,0 +1,263 @@
+use crate::{DefDatabase, FileId, FileRange, TextEdit};
+use syntax::ast::{self, AstNode};
+use syntax::{NodeOrToken, SyntaxKind, TextRange, TextSize};
+
+/// The range to format for a range formatting request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FormattingRange {
+    pub range: TextRange,
+    /// Whether the text of `range` is an expression which can be formatted alone, and then
+    /// re-indented without changing the content of any string literal.
+    pub is_standalone: bool,
+}
+
+/// Expand `frange` to the smallest binding or list fully containing it, or the whole file.
+pub(crate) fn formatting_range(db: &dyn DefDatabase, frange: FileRange) -> FormattingRange {
+    let root = db.parse(frange.file_id).syntax_node();
+    let elem = if frange.range.is_empty() {
+        root.token_at_offset(frange.range.start())
+            .right_biased()
+            .map_or_else(|| root.clone().into(), NodeOrToken::Token)
+    } else {
+        root.covering_element(frange.range)
+    };
+    let node = match elem {
+        NodeOrToken::Node(node) => node,
+        NodeOrToken::Token(tok) => tok.parent().unwrap_or_else(|| root.clone()),
+    };
+    let Some(node) = node.ancestors().find(|node| {
+        matches!(
+            node.kind(),
+            SyntaxKind::ATTR_PATH_VALUE | SyntaxKind::INHERIT | SyntaxKind::LIST
+        )
+    }) else {
+        return FormattingRange {
+            range: root.text_range(),
+            is_standalone: false,
+        };
+    };
+
+    // Re-indenting lines inside double quoted strings changes their content.
+    let is_standalone = node.kind() == SyntaxKind::LIST
+        && !node
+            .descendants()
+            .filter_map(ast::String::cast)
+            .any(|s| s.syntax().text().contains_char('\n'));
+    FormattingRange {
+        range: node.text_range(),
+        is_standalone,
+    }
+}
+
+/// Remove trailing whitespaces of each line.
+/// Whitespaces inside string literals, especially indented strings, are kept since they are
+/// part of the string content.
+pub(crate) fn trim_trailing_whitespace(db: &dyn DefDatabase, file: FileId) -> Vec<TextEdit> {
+    let src = db.file_content(file);
+    let root = db.parse(file).syntax_node();
+
+    let mut edits = Vec::new();
+    let mut line_start = 0usize;
+    for line in src.split_inclusive('\n') {
+        let content = line.strip_suffix('\n').unwrap_or(line);
+        let trimmed_len = content.trim_end_matches([' ', '\t']).len();
+        let (start, end) = (line_start + trimmed_len, line_start + content.len());
+        line_start += line.len();
+        if start == end {
+            continue;
+        }
+
+        let start = TextSize::try_from(start).expect("Length overflow");
+        let end = TextSize::try_from(end).expect("Length overflow");
+        let is_string_content = root
+            .token_at_offset(start)
+            .right_biased()
+            .is_some_and(|tok| {
+                matches!(
+                    tok.kind(),
+                    SyntaxKind::STRING_FRAGMENT | SyntaxKind::STRING_ESCAPE
+                )
+            });
+        if is_string_content {
+            continue;
+        }
+
+        edits.push(TextEdit {
+            delete: TextRange::new(start, end),
+            insert: "".into(),
+        });
+    }
+    edits
+}
+
+/// Append a newline if the file is non-empty and does not end with one.
+/// Nothing is done if the file ends inside a string literal, where it would change the content.
+pub(crate) fn insert_final_newline(db: &dyn DefDatabase, file: FileId) -> Option<TextEdit> {
+    let src = db.file_content(file);
+    if src.is_empty() || src.ends_with('\n') {
+        return None;
+    }
+    let end = TextSize::of(&*src);
+    let is_string_content = db
+        .parse(file)
+        .syntax_node()
+        .token_at_offset(end)
+        .left_biased()
+        .is_some_and(|tok| {
+            matches!(
+                tok.kind(),
+                SyntaxKind::STRING_FRAGMENT | SyntaxKind::STRING_ESCAPE
+            )
+        });
+    if is_string_content {
+        return None;
+    }
+    Some(TextEdit {
+        delete: TextRange::empty(end),
+        insert: "\n".into(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::TestDB;
+    use crate::SourceDatabase;
+    use expect_test::{expect, Expect};
+
+    #[track_caller]
+    fn check_final_newline(fixture: &str, expect: Expect) {
+        let (db, file_id) = TestDB::single_file(fixture).unwrap();
+        let mut src = db.file_content(file_id).to_string();
+        if let Some(edit) = super::insert_final_newline(&db, file_id) {
+            edit.apply(&mut src);
+        }
+        expect.assert_debug_eq(&src);
+    }
+
+    #[track_caller]
+    fn check(fixture: &str, expect: Expect) {
+        let (db, file_id) = TestDB::single_file(fixture).unwrap();
+        let mut src = db.file_content(file_id).to_string();
+        for edit in super::trim_trailing_whitespace(&db, file_id).iter().rev() {
+            edit.apply(&mut src);
+        }
+        // Make trailing whitespaces visible.
+        expect.assert_eq(&src.replace(' ', "·"));
+    }
+
+    #[test]
+    fn trim() {
+        check(
+            "{  \n  a = 1;\t \n  # comment  \n}  ",
+            expect![[r#"
+                {
+                ··a·=·1;
+                ··#·comment
+                }"#]],
+        );
+    }
+
+    #[test]
+    fn no_trailing() {
+        check(
+            "{\n  a = 1;\n}",
+            expect![[r#"
+            {
+            ··a·=·1;
+            }"#]],
+        );
+    }
+
+    #[test]
+    fn keep_string_content() {
+        check(
+            "{  \n  a = ''  \n    foo  \n  '';  \n  b = \"bar  \n\";  \n}",
+            expect![[r#"
+                {
+                ··a·=·''··
+                ····foo··
+                ··'';
+                ··b·=·"bar··
+                ";
+                }"#]],
+        );
+    }
+
+    #[test]
+    fn trim_inside_interpolation() {
+        check(
+            "''\n  ${  \n    foo  \n  }  \n''",
+            expect![[r#"
+                ''
+                ··${
+                ····foo
+                ··}··
+                ''"#]],
+        );
+    }
+
+    #[track_caller]
+    fn check_range(fixture: &str, expect: Expect) {
+        let (db, f) = TestDB::from_fixture(fixture).unwrap();
+        let frange = f.unwrap_single_range_marker();
+        let ret = super::formatting_range(&db, frange);
+        let src = db.file_content(frange.file_id);
+        let mut got = src[ret.range].to_owned();
+        if ret.is_standalone {
+            got += " (standalone)";
+        }
+        expect.assert_eq(&got);
+    }
+
+    #[test]
+    fn range() {
+        check_range(
+            "{ a = 1; b = { c = $0[ 1 2 ]$1; }; }",
+            expect!["[ 1 2 ] (standalone)"],
+        );
+        check_range(
+            "{ a = 1; b = { c = [ 1 $02 ]; }; }",
+            expect!["[ 1 2 ] (standalone)"],
+        );
+        check_range(
+            "{ a = 1; b = { c = $01; d = 2$1; }; }",
+            expect!["b = { c = 1; d = 2; };"],
+        );
+        check_range("{ a = 1; b = { $0c = 1; }; }", expect!["c = 1;"]);
+        check_range("{ inherit $0a; }", expect!["inherit a;"]);
+        check_range("$0{ a = 1; }", expect!["{ a = 1; }"]);
+        check_range("{ a = 1$0; b = 2$1; }", expect!["{ a = 1; b = 2; }"]);
+        check_range(
+            "[ \"a\n\" $0 ]",
+            expect![[r#"
+                [ "a
+                "  ]"#]],
+        );
+    }
+
+    #[test]
+    fn final_newline() {
+        check_final_newline(
+            "{ a = 1; }",
+            expect![[r#"
+            "{ a = 1; }\n"
+        "#]],
+        );
+        check_final_newline(
+            "{ a = 1; }\n",
+            expect![[r#"
+            "{ a = 1; }\n"
+        "#]],
+        );
+    }
+
+    #[test]
+    fn final_newline_unterminated_string() {
+        check_final_newline(
+            "''\n  foo",
+            expect![[r#"
+            "''\n  foo"
+        "#]],
+        );
+    }
+}
@@ -1,14 +1,29 @@
 use super::NavigationTarget;
-use crate::def::{AstPtr, Expr, Literal, ResolveResult};
+use crate::def::{
+    imported_attr_names, resolve_import_file, resolve_import_path, AstPtr, Expr, ExprId, Literal,
+    NameId, ResolveResult,
+};
 use crate::{DefDatabase, FileId, FilePos, ModuleKind, VfsPath};
+use builtin::ALL_BUILTINS;
 use nix_interop::FLAKE_FILE;
+use smol_str::SmolStr;
 use syntax::ast::{self, AstNode};
-use syntax::{best_token_at_offset, match_ast, SyntaxKind, SyntaxToken};
+use syntax::{best_token_at_offset, match_ast, SyntaxKind, SyntaxToken, TextRange};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum GotoDefinitionResult {
     Path(VfsPath),
     Targets(Vec<NavigationTarget>),
+    /// A builtin name, which has no source but a generated document.
+    /// See `builtin_document`.
+    Builtin(&'static str),
+    /// A static attrpath selected from an imported file which is not loaded, eg. outside the
+    /// workspace. The caller may read the file from disk and look the attrpath up with
+    /// `Analysis::attr_definitions`.
+    ImportedAttr {
+        path: VfsPath,
+        attrpath: Vec<SmolStr>,
+    },
 }
 
 pub(crate) fn goto_definition(
@@ -37,35 +52,48 @@ pub(crate) fn goto_definition(
     let source_map = db.source_map(file_id);
     let expr_id = source_map.expr_for_node(ptr)?;
 
-    // Special case for goto-path.
-    if tok.kind() == SyntaxKind::PATH {
-        let module = db.module(file_id);
+    let module = db.module(file_id);
+    let name_res = db.name_resolution(file_id);
+
+    // Special case for goto-path, including `<name>` resolved by the search path.
+    if matches!(tok.kind(), SyntaxKind::PATH | SyntaxKind::SEARCH_PATH) {
         let Expr::Literal(Literal::Path(path)) = &module[expr_id] else {
             return None;
         };
+        // Imported paths jump to the beginning of the imported file in the Vfs, if any.
+        let import_expr = module.exprs().find_map(|(e, kind)| match *kind {
+            Expr::Apply(lam, arg)
+                if arg == expr_id && name_res.check_builtin(lam, &module) == Some("import") =>
+            {
+                Some(e)
+            }
+            _ => None,
+        });
+        // Files not loaded, eg. outside the workspace or from search paths, are left to the
+        // caller to be found on disk.
+        if let Some(target) = import_expr.and_then(|e| resolve_import_file(db, file_id, e)) {
+            return Some(GotoDefinitionResult::Targets(vec![NavigationTarget {
+                file_id: target,
+                focus_range: TextRange::default(),
+                full_range: TextRange::default(),
+            }]));
+        }
         let path = path.resolve(db)?;
         return Some(GotoDefinitionResult::Path(path));
     }
 
-    let name_res = db.name_resolution(file_id);
+    // Special case for `builtins.xxx`.
+    if let Some(name) = goto_builtins_attr(db, file_id, expr_id) {
+        return Some(GotoDefinitionResult::Builtin(name));
+    }
+
+    // Special case for attributes selected from an imported file, like `(import ./foo.nix).bar`.
+    if let Some(ret) = goto_imported_attr(db, file_id, expr_id) {
+        return Some(ret);
+    }
+
     let targets = match name_res.get(expr_id)? {
-        &ResolveResult::Definition(name) => source_map
-            .nodes_for_name(name)
-            .filter_map(|ptr| {
-                let name_node = ptr.to_node(&parse.syntax_node());
-                let full_node = name_node.ancestors().find(|n| {
-                    matches!(
-                        n.kind(),
-                        SyntaxKind::LAMBDA | SyntaxKind::ATTR_PATH_VALUE | SyntaxKind::INHERIT
-                    )
-                })?;
-                Some(NavigationTarget {
-                    file_id,
-                    focus_range: name_node.text_range(),
-                    full_range: full_node.text_range(),
-                })
-            })
-            .collect(),
+        &ResolveResult::Definition(name) => name_targets(db, file_id, name).collect(),
         ResolveResult::WithExprs(withs) => {
             withs
                 .iter()
@@ -91,13 +119,112 @@ pub(crate) fn goto_definition(
                 })
                 .collect()
         }
-        // Currently builtin names cannot "goto-definition".
-        ResolveResult::Builtin(_) => return None,
+        ResolveResult::Builtin(name) => return Some(GotoDefinitionResult::Builtin(name)),
     };
 
     Some(GotoDefinitionResult::Targets(targets))
 }
 
+/// Navigation targets of all definition sites of `name` in `file_id`.
+fn name_targets(
+    db: &dyn DefDatabase,
+    file_id: FileId,
+    name: NameId,
+) -> impl Iterator<Item = NavigationTarget> {
+    let parse = db.parse(file_id);
+    let source_map = db.source_map(file_id);
+    let ptrs = source_map.nodes_for_name(name).collect::<Vec<_>>();
+    ptrs.into_iter().filter_map(move |ptr| {
+        let name_node = ptr.to_node(&parse.syntax_node());
+        let full_node = name_node.ancestors().find(|n| {
+            matches!(
+                n.kind(),
+                SyntaxKind::LAMBDA | SyntaxKind::ATTR_PATH_VALUE | SyntaxKind::INHERIT
+            )
+        })?;
+        Some(NavigationTarget {
+            file_id,
+            focus_range: name_node.text_range(),
+            full_range: full_node.text_range(),
+        })
+    })
+}
+
+/// The builtin name for the first attribute selected from `builtins`, like `map` in
+/// `builtins.map`.
+fn goto_builtins_attr(
+    db: &dyn DefDatabase,
+    file_id: FileId,
+    attr_expr: ExprId,
+) -> Option<&'static str> {
+    let module = db.module(file_id);
+    let name_res = db.name_resolution(file_id);
+    let set_expr = module.exprs().find_map(|(_, kind)| match kind {
+        Expr::Select(set, attrpath, _) | Expr::HasAttr(set, attrpath)
+            if attrpath.first() == Some(&attr_expr) =>
+        {
+            Some(*set)
+        }
+        _ => None,
+    })?;
+    if name_res.get(set_expr) != Some(&ResolveResult::Builtin("builtins")) {
+        return None;
+    }
+    let Expr::Literal(Literal::String(key)) = &module[attr_expr] else {
+        return None;
+    };
+    let (&name, _) = ALL_BUILTINS.get_entry(key)?;
+    Some(name)
+}
+
+/// Goto the definition of a static attribute selected from an imported file.
+///
+/// The attrpath is followed through nested attrsets of the imported file's entry expression,
+/// up to the attribute under the cursor. Files not loaded are left to the caller.
+fn goto_imported_attr(
+    db: &dyn DefDatabase,
+    file_id: FileId,
+    attr_expr: ExprId,
+) -> Option<GotoDefinitionResult> {
+    let module = db.module(file_id);
+    let (set_expr, attrpath) = module.exprs().find_map(|(_, kind)| match kind {
+        Expr::Select(set, attrpath, _) if attrpath.contains(&attr_expr) => Some((*set, attrpath)),
+        _ => None,
+    })?;
+    let path = resolve_import_path(db, file_id, set_expr)?;
+
+    // The attrpath up to (and including) the attribute under the cursor.
+    let keys = attrpath[..=attrpath.iter().position(|&attr| attr == attr_expr)?]
+        .iter()
+        .map(|&attr| match &module[attr] {
+            Expr::Literal(Literal::String(key)) => Some(key.clone()),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    let Some(target_file) = resolve_import_file(db, file_id, set_expr) else {
+        return Some(GotoDefinitionResult::ImportedAttr {
+            path: path.resolve(db)?,
+            attrpath: keys,
+        });
+    };
+    let targets = attr_definitions(db, target_file, &keys);
+    (!targets.is_empty()).then_some(GotoDefinitionResult::Targets(targets))
+}
+
+/// Definitions of the static `attrpath` in the entry expression of `file`, if all keys are found.
+pub(crate) fn attr_definitions(
+    db: &dyn DefDatabase,
+    file: FileId,
+    attrpath: &[SmolStr],
+) -> Vec<NavigationTarget> {
+    let names = imported_attr_names(db, file, attrpath);
+    match names.last() {
+        Some(&name) if names.len() == attrpath.len() => name_targets(db, file, name).collect(),
+        _ => Vec::new(),
+    }
+}
+
 fn goto_flake_input(
     db: &dyn DefDatabase,
     file: FileId,
@@ -146,7 +273,9 @@ mod tests {
     use super::*;
     use crate::base::SourceDatabase;
     use crate::tests::TestDB;
+    use crate::{SearchPath, SearchPathEntry};
     use expect_test::{expect, Expect};
+    use std::sync::Arc;
 
     #[track_caller]
     fn check_no(fixture: &str) {
@@ -161,6 +290,14 @@ mod tests {
         assert_eq!(f.markers().len(), 1, "Missing markers");
         let mut got = match goto_definition(&db, f[0]).expect("No definition") {
             GotoDefinitionResult::Path(path) => format!("file://{}", path.display()),
+            GotoDefinitionResult::ImportedAttr { path, attrpath } => {
+                format!("file://{}: {}", path.display(), attrpath.join("."))
+            }
+            GotoDefinitionResult::Builtin(name) => {
+                let doc = crate::builtin_document(name).expect("Missing builtin document");
+                assert!(doc.starts_with(&format!("# `builtins.{name}`")));
+                format!("builtins.{name}")
+            }
             GotoDefinitionResult::Targets(targets) => {
                 assert!(!targets.is_empty());
                 targets
@@ -172,6 +309,11 @@ mod tests {
                         let relative_focus = target.focus_range - target.full_range.start();
                         full.insert(relative_focus.end().into(), '>');
                         full.insert(relative_focus.start().into(), '<');
+                        if target.file_id != f[0].file_id {
+                            let sid = db.file_source_root(target.file_id);
+                            let path = db.source_root(sid).path_for_file(target.file_id).clone();
+                            full = format!("{}: {full}", path.display());
+                        }
                         full
                     })
                     .collect::<Vec<_>>()
@@ -234,6 +376,13 @@ mod tests {
         );
     }
 
+    #[test]
+    fn forward_reference() {
+        check("rec { a = $0b; b = 1; }", expect!["<b> = 1;"]);
+        check("rec { a = { c = $0b; }; b = 1; }", expect!["<b> = 1;"]);
+        check("let a = $0b; b = 1; in a", expect!["<b> = 1;"]);
+    }
+
     #[test]
     fn left_and_right() {
         check("let a = 1; in $0a ", expect!["<a> = 1;"]);
@@ -264,7 +413,15 @@ mod tests {
     #[test]
     fn builtin() {
         check("let true = 1; in $0true && false", expect!["<true> = 1;"]);
-        check_no("let true = 1; in true && $0false");
+        check(
+            "let true = 1; in true && $0false",
+            expect!["builtins.false"],
+        );
+        check("$0builtins.map", expect!["builtins.builtins"]);
+        check("builtins.$0map", expect!["builtins.map"]);
+        check("builtins ? $0map", expect!["builtins.map"]);
+        check_no("builtins.$0foo");
+        check_no("let builtins = { }; in builtins.$0map");
     }
 
     #[test]
@@ -278,7 +435,120 @@ import $0./bar.nix
 #- /bar.nix
 hello
             ",
-            expect!["file:///bar.nix"],
+            expect!["/bar.nix: <>"],
+        );
+    }
+
+    #[test]
+    fn import_path() {
+        check(
+            "
+#- /default.nix
+import $0./dir
+
+#- /dir/default.nix
+hello
+            ",
+            expect!["/dir/default.nix: <>"],
+        );
+        check(
+            "
+#- /default.nix
+import $0./not-loaded.nix
+
+#- /bar.nix
+hello
+            ",
+            expect!["file:///not-loaded.nix"],
+        );
+        check(
+            "
+#- /default.nix
+import $0./dir
+
+#- /dir/bar.nix
+hello
+            ",
+            expect!["file:///dir"],
+        );
+    }
+
+    #[test]
+    fn search_path() {
+        let check_search = |fixture: &str, expect: Expect| {
+            let (mut db, f) = TestDB::from_fixture(fixture).unwrap();
+            let entries = vec![SearchPathEntry {
+                prefix: "nixpkgs".into(),
+                path: VfsPath::new("/nixpkgs"),
+            }];
+            db.set_search_path(Arc::new(SearchPath { entries }));
+            let got = match goto_definition(&db, f[0]) {
+                Some(GotoDefinitionResult::Path(path)) => format!("file://{}", path.display()),
+                Some(GotoDefinitionResult::Targets(targets)) => {
+                    let sid = db.file_source_root(targets[0].file_id);
+                    let path = db
+                        .source_root(sid)
+                        .path_for_file(targets[0].file_id)
+                        .clone();
+                    format!("{}: <>", path.display())
+                }
+                ret => format!("{ret:?}"),
+            };
+            expect.assert_eq(&got);
+        };
+        check_search(
+            "
+#- /default.nix
+import $0<nixpkgs> { }
+
+#- /nixpkgs/default.nix
+{ }: hello
+            ",
+            expect!["/nixpkgs/default.nix: <>"],
+        );
+        check_search("import $0<nixpkgs/lib>", expect!["file:///nixpkgs/lib"]);
+        check_search("$0<nixpkgs>", expect!["file:///nixpkgs"]);
+        check_search("import $0<unknown>", expect!["None"]);
+    }
+
+    #[test]
+    fn imported_attr() {
+        check(
+            "
+#- /default.nix
+(import ./pkgs.nix).$0foo
+
+#- /pkgs.nix
+{ foo = 1; bar = 2; }
+            ",
+            expect!["/pkgs.nix: <foo> = 1;"],
+        );
+        check(
+            "
+#- /default.nix
+let pkgs = import ./pkgs.nix; in pkgs.foo.$0bar
+
+#- /pkgs.nix
+let x = 1; in { foo.bar = x; foo.baz = 2; }
+            ",
+            expect!["/pkgs.nix: foo.<bar> = x;"],
+        );
+        check_no(
+            "
+#- /default.nix
+(import ./pkgs.nix).$0baz
+
+#- /pkgs.nix
+{ foo = 1; }
+            ",
+        );
+        // Attributes of files not loaded are left to the caller.
+        check(
+            "
+#- /default.nix
+let pkgs = import ./not-loaded.nix; in pkgs.foo.$0bar.baz
+            ",
+            expect!["file:///not-loaded.nix: foo.bar"],
         );
     }
 
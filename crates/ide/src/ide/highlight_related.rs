@@ -182,6 +182,18 @@ mod tests {
         );
     }
 
+    #[test]
+    fn formal() {
+        check(
+            "{ $0pkgs, ... }: pkgs.hello",
+            expect!["{ <<pkgs>>, ... }: <pkgs>.hello"],
+        );
+        check(
+            "{ pkgs, lib ? pkgs.lib }: [ $0pkgs lib ]",
+            expect!["{ <<pkgs>>, lib ? <pkgs>.lib }: [ <pkgs> lib ]"],
+        );
+    }
+
     #[test]
     fn with() {
         check(
@@ -193,4 +205,30 @@ mod tests {
             expect!["with 1; a + (<<with>> 2; <a> + <b> (with 3; <a>))"],
         );
     }
+
+    #[test]
+    fn scoped() {
+        check(
+            "[ (let $0x = 1; in x) (let x = 2; in x) ]",
+            expect!["[ (let <<x>> = 1; in <x>) (let x = 2; in x) ]"],
+        );
+        check(
+            "[ (let x = 1; in x) (let x = 2; in $0x) ]",
+            expect!["[ (let x = 1; in x) (let <<x>> = 2; in <x>) ]"],
+        );
+    }
+
+    #[test]
+    fn not_identifier() {
+        for src in [
+            "let a = $01; in a",
+            "let a = 1; in a $0+ a",
+            "$0\"s\"",
+            "a: $0true",
+        ] {
+            let (db, f) = TestDB::from_fixture(src).unwrap();
+            let hls = super::highlight_related(&db, f[0]).unwrap_or_default();
+            assert_eq!(hls, [], "{src}");
+        }
+    }
 }
@@ -1,12 +1,14 @@
+use super::flake_inputs::flake_inputs;
+use super::option_declarations::option_declarations;
 use crate::def::{AstPtr, Expr, ResolveResult};
 use crate::ty::{DisplayConfig, Ty};
-use crate::{FilePos, NameKind, TyDatabase};
+use crate::{FilePos, ModuleKind, NameKind, TyDatabase};
 use builtin::ALL_BUILTINS;
 use if_chain::if_chain;
 use std::fmt::Write;
 use syntax::ast::{self, AstNode};
 use syntax::semantic::AttrKind;
-use syntax::{best_token_at_offset, match_ast, TextRange};
+use syntax::{best_token_at_offset, match_ast, SyntaxKind, TextRange};
 
 // Kinda detailed, but don't flood users with thousands of fields for `pkgs`.
 pub const TY_DETAILED_DISPLAY: DisplayConfig = DisplayConfig {
@@ -17,12 +19,91 @@ pub const TY_DETAILED_DISPLAY: DisplayConfig = DisplayConfig {
     lambda_need_parentheses: false,
 };
 
+const INCOMPLETE_NOTE: &str = "target file has syntax errors; results may be incomplete";
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct HoverResult {
     pub range: TextRange,
     pub markup: String,
 }
 
+impl HoverResult {
+    pub(crate) fn builder(range: TextRange) -> HoverBuilder {
+        HoverBuilder {
+            range,
+            title: String::new(),
+            signature: String::new(),
+            docs: String::new(),
+            value: String::new(),
+            note: String::new(),
+        }
+    }
+}
+
+/// Assemble hover markup from sections in a fixed order:
+/// a title line, a code block of the signature or definition, a docs paragraph,
+/// a line of the value or type, and a trailing note.
+/// Empty sections are omitted.
+#[derive(Debug)]
+pub(crate) struct HoverBuilder {
+    range: TextRange,
+    title: String,
+    signature: String,
+    docs: String,
+    value: String,
+    note: String,
+}
+
+impl HoverBuilder {
+    pub(crate) fn title(mut self, title: impl Into<String>) -> Self {
+        self.title = title.into();
+        self
+    }
+
+    pub(crate) fn signature(mut self, code: impl Into<String>) -> Self {
+        self.signature = code.into();
+        self
+    }
+
+    pub(crate) fn docs(mut self, docs: impl Into<String>) -> Self {
+        self.docs = docs.into();
+        self
+    }
+
+    pub(crate) fn value(mut self, value: impl Into<String>) -> Self {
+        self.value = value.into();
+        self
+    }
+
+    pub(crate) fn note(mut self, note: impl Into<String>) -> Self {
+        self.note = note.into();
+        self
+    }
+
+    pub(crate) fn build(self) -> HoverResult {
+        let signature = if self.signature.is_empty() {
+            String::new()
+        } else {
+            format!("```nix\n{}\n```", self.signature)
+        };
+        let markup = [
+            self.title.trim(),
+            &signature,
+            self.docs.trim(),
+            self.value.trim(),
+            self.note.trim(),
+        ]
+        .into_iter()
+        .filter(|section| !section.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n");
+        HoverResult {
+            range: self.range,
+            markup,
+        }
+    }
+}
+
 pub(crate) fn hover(db: &dyn TyDatabase, FilePos { file_id, pos }: FilePos) -> Option<HoverResult> {
     let parse = db.parse(file_id);
     let tok = best_token_at_offset(&parse.syntax_node(), pos)?;
@@ -48,6 +129,8 @@ pub(crate) fn hover(db: &dyn TyDatabase, FilePos { file_id, pos }: FilePos) -> O
     let source_map = db.source_map(file_id);
     let nameres = db.name_resolution(file_id);
     let infer = db.infer(file_id);
+    // Types may come from imported files which are broken.
+    let note = |incomplete: bool| if incomplete { INCOMPLETE_NOTE } else { "" };
 
     let mut name = None;
 
@@ -68,16 +151,23 @@ pub(crate) fn hover(db: &dyn TyDatabase, FilePos { file_id, pos }: FilePos) -> O
                     .ty_for_expr(expr)
                     .display_with(TY_DETAILED_DISPLAY)
                     .to_string();
-                let mut markup = format!("`with` attribute `{text}`\n`{ty}`\nEnvironments:");
+                let mut envs = "Environments:".to_owned();
                 for (&expr, i) in withs.iter().zip(1..) {
                     let ptr = source_map.node_for_expr(expr)?;
                     let with_node = ast::With::cast(ptr.to_node(&parse.syntax_node()))?;
                     let env_text = with_node
                         .environment()
                         .map_or("?", |env_node| &src[env_node.syntax().text_range()]);
-                    write!(markup, "\n{i}. `with {env_text};`").unwrap();
+                    write!(envs, "\n{i}. `with {env_text};`").unwrap();
                 }
-                return Some(HoverResult { range, markup });
+                return Some(
+                    HoverResult::builder(range)
+                        .title(format!("`with` attribute `{text}`"))
+                        .docs(envs)
+                        .value(format!("`{ty}`"))
+                        .note(note(infer.is_expr_incomplete(expr)))
+                        .build(),
+                );
             }
             Some(ResolveResult::Definition(def)) => {
                 name = Some(*def);
@@ -86,6 +176,27 @@ pub(crate) fn hover(db: &dyn TyDatabase, FilePos { file_id, pos }: FilePos) -> O
     }
 
     if let Some(name) = name.or_else(|| source_map.name_for_node(ptr)) {
+        if let Some(decl) = option_declarations(db, file_id)
+            .into_iter()
+            .find(|decl| decl.name == name)
+        {
+            let mut value = String::new();
+            if let Some(ty) = &decl.ty {
+                write!(value, "Type: `{ty}`").unwrap();
+            }
+            if let Some(default) = &decl.default {
+                let sep = if value.is_empty() { "" } else { "\n\n" };
+                write!(value, "{sep}Default: `{}`", one_line_preview(default)).unwrap();
+            }
+            return Some(
+                HoverResult::builder(range)
+                    .title(format!("Option `{}`", decl.path.join(".")))
+                    .docs(decl.description.unwrap_or_default())
+                    .value(value)
+                    .build(),
+            );
+        }
+
         let ty = infer
             .ty_for_name(name)
             .display_with(TY_DETAILED_DISPLAY)
@@ -98,10 +209,66 @@ pub(crate) fn hover(db: &dyn TyDatabase, FilePos { file_id, pos }: FilePos) -> O
             NameKind::Param => "Parameter",
             NameKind::PatField => "Field parameter",
         };
-        return Some(HoverResult {
-            range,
-            markup: format!("{kind} `{text}`\n`{ty}`"),
-        });
+        let def_node = source_map
+            .nodes_for_name(name)
+            .next()
+            .map(|ptr| ptr.to_node(&parse.syntax_node()));
+        let path = def_node
+            .as_ref()
+            .and_then(|node| attr_path(ast::Attr::cast(node.clone())?))
+            .unwrap_or_else(|| text.to_string());
+
+        let mut title = format!("{kind} `{path}`");
+        if let Some(node) = &def_node {
+            if module[name].kind == NameKind::PatField {
+                if let Some(pat) = node.ancestors().find_map(ast::Pat::cast) {
+                    let pat = one_line_preview(&src[pat.syntax().text_range()]);
+                    write!(title, " from pattern `{pat}`").unwrap();
+                }
+            }
+            let line = src[..usize::from(node.text_range().start())]
+                .matches('\n')
+                .count();
+            write!(title, ", defined at line {}", line + 1).unwrap();
+        }
+
+        // Flake inputs are shown with their sources.
+        let mut docs = String::new();
+        if let ModuleKind::FlakeNix {
+            explicit_inputs,
+            param_inputs,
+            ..
+        } = &*db.module_kind(file_id)
+        {
+            if explicit_inputs.get(text) == Some(&name) || param_inputs.get(text) == Some(&name) {
+                docs = match flake_inputs(db, file_id)
+                    .into_iter()
+                    .find(|input| input.name == *text)
+                    .and_then(|input| input.url)
+                {
+                    Some(url) => format!("Flake input from `{url}`"),
+                    None => "Flake input".into(),
+                };
+            }
+        }
+
+        // `a.b = 1` defines `a` with an implicit attrset, which has no source to preview.
+        let definition = module
+            .binding_value(name)
+            .and_then(|expr| source_map.node_for_expr(expr))
+            .filter(|ptr| ast::Expr::can_cast(ptr.kind()))
+            .map(|value| format!("{path} = {}", one_line_preview(&src[value.text_range()])))
+            .unwrap_or_default();
+
+        return Some(
+            HoverResult::builder(range)
+                .title(title)
+                .signature(definition)
+                .docs(docs)
+                .value(format!("`{ty}`"))
+                .note(note(infer.is_name_incomplete(name)))
+                .build(),
+        );
     }
 
     // Selected attr type.
@@ -111,8 +278,8 @@ pub(crate) fn hover(db: &dyn TyDatabase, FilePos { file_id, pos }: FilePos) -> O
         let path_node = ast::Attrpath::cast(name_node.syntax().parent()?)?;
         let set_node = match_ast! {
             match (path_node.syntax().parent()?) {
-                ast::HasAttr(n) => n.set(),
-                ast::Select(n) => n.set(),
+                ast::HasAttr(n) => n.set()?.flatten_paren(),
+                ast::Select(n) => n.set()?.flatten_paren(),
                 _ => None,
             }
         }?;
@@ -147,15 +314,16 @@ pub(crate) fn hover(db: &dyn TyDatabase, FilePos { file_id, pos }: FilePos) -> O
                 break;
             }
         }
-        let range = name_node.syntax().text_range();
-        let markup = format!(
-            "Field `{}`\n`{}`",
-            name_node
-                .token()
-                .map_or_else(String::new, |t| t.text().into()),
-            ty.display_with(TY_DETAILED_DISPLAY),
-        );
-        Some(HoverResult { range, markup })
+        let field = name_node
+            .token()
+            .map_or_else(String::new, |t| t.text().into());
+        Some(
+            HoverResult::builder(name_node.syntax().text_range())
+                .title(format!("Field `{field}`"))
+                .value(format!("`{}`", ty.display_with(TY_DETAILED_DISPLAY)))
+                .note(note(infer.is_expr_incomplete(expr)))
+                .build(),
+        )
     }) {
         return Some(ret);
     }
@@ -163,21 +331,99 @@ pub(crate) fn hover(db: &dyn TyDatabase, FilePos { file_id, pos }: FilePos) -> O
     None
 }
 
+/// The full attribute path of an attribute key, through nested attrpaths and attrsets,
+/// eg. `services.nginx.enable` for `enable` in `{ services.nginx = { enable = true; }; }`.
+fn attr_path(mut attr: ast::Attr) -> Option<String> {
+    let mut segments = Vec::new();
+    loop {
+        let path = ast::Attrpath::cast(attr.syntax().parent()?)?;
+        let prefix = path
+            .attrs()
+            .take_while(|a| a.syntax().text_range().start() <= attr.syntax().text_range().start())
+            .map(|a| a.syntax().to_string())
+            .collect::<Vec<_>>();
+        segments.splice(0..0, prefix);
+
+        let binding = ast::AttrpathValue::cast(path.syntax().parent()?)?;
+        let Some(outer) = binding
+            .syntax()
+            .parent()
+            .filter(|set| set.kind() == SyntaxKind::ATTR_SET)
+            .and_then(|set| set.parent())
+            .and_then(ast::AttrpathValue::cast)
+        else {
+            break;
+        };
+        attr = outer.attrpath()?.attrs().last()?;
+    }
+    Some(segments.join("."))
+}
+
+/// Collapse whitespaces and truncate long text, without scanning the whole text.
+fn one_line_preview(text: &str) -> String {
+    const MAX_LEN: usize = 80;
+    let mut ret = String::new();
+    let mut len = 0;
+    for word in text.split_whitespace() {
+        if len != 0 {
+            ret.push(' ');
+            len += 1;
+        }
+        for ch in word.chars() {
+            if len == MAX_LEN {
+                ret.push('…');
+                return ret;
+            }
+            ret.push(ch);
+            len += 1;
+        }
+    }
+    ret
+}
+
 fn hover_builtin(name: &str, range: TextRange) -> Option<HoverResult> {
     let b = ALL_BUILTINS.get(name)?;
-    let ty = crate::ty::known::BUILTINS
+    Some(
+        HoverResult::builder(range)
+            .title(format!("`builtins.{name}`"))
+            .signature(
+                builtin_ty(name)
+                    .display_with(TY_DETAILED_DISPLAY)
+                    .to_string(),
+            )
+            .docs(format!("{}\n{}", b.summary, builtin_doc(name, b.doc)))
+            .build(),
+    )
+}
+
+/// A Markdown document describing a builtin, which has no source to jump to.
+pub fn builtin_document(name: &str) -> Option<String> {
+    let b = ALL_BUILTINS.get(name)?;
+    Some(format!(
+        "# `builtins.{name}`\n\n```\n{}\n```\n\n{}\n\n{}\n",
+        builtin_ty(name).display_with(TY_DETAILED_DISPLAY),
+        b.summary,
+        builtin_doc(name, b.doc),
+    ))
+}
+
+/// The documentation from Nix, followed by usage examples for builtins whose arguments are easy
+/// to get wrong.
+fn builtin_doc(name: &str, doc: Option<&'static str>) -> String {
+    let mut ret = doc.unwrap_or("(No documentation from Nix)").to_owned();
+    if name == "replaceStrings" {
+        ret += "\n\nExample:\n\n```nix\nbuiltins.replaceStrings [ \"oo\" \"a\" ] [ \"a\" \"i\" ] \"foobar\"\n# => \"fabir\"\n```\n\n`from` and `to` lists must have the same length.";
+    }
+    ret
+}
+
+fn builtin_ty(name: &str) -> Ty {
+    crate::ty::known::BUILTINS
         .as_attrset()
         .unwrap()
         .get(name)
         .cloned()
-        .unwrap_or(Ty::Unknown);
-    let markup = format!(
-        "`builtins.{name}`\n`{}`\n\n{}\n{}",
-        ty.display_with(TY_DETAILED_DISPLAY),
-        b.summary,
-        b.doc.unwrap_or("(No documentation from Nix)"),
-    );
-    Some(HoverResult { range, markup })
+        .unwrap_or(Ty::Unknown)
 }
 
 #[cfg(test)]
@@ -213,7 +459,12 @@ mod tests {
             "let $0a = 1; in a",
             "a",
             expect![[r#"
-                Let binding `a`
+                Let binding `a`, defined at line 1
+
+                ```nix
+                a = 1
+                ```
+
                 `int`
             "#]],
         );
@@ -221,7 +472,12 @@ mod tests {
             "let a.$0a = 1; in a",
             "a",
             expect![[r#"
-                Attrset attribute `a`
+                Attrset attribute `a.a`, defined at line 1
+
+                ```nix
+                a.a = 1
+                ```
+
                 `int`
             "#]],
         );
@@ -229,7 +485,12 @@ mod tests {
             "{ $0a = 1; }",
             "a",
             expect![[r#"
-                Attrset attribute `a`
+                Attrset attribute `a`, defined at line 1
+
+                ```nix
+                a = 1
+                ```
+
                 `int`
             "#]],
         );
@@ -237,7 +498,12 @@ mod tests {
             "rec { $0a = 1; }",
             "a",
             expect![[r#"
-                Rec-attrset attribute `a`
+                Rec-attrset attribute `a`, defined at line 1
+
+                ```nix
+                a = 1
+                ```
+
                 `int`
             "#]],
         );
@@ -245,7 +511,8 @@ mod tests {
             "$0a: a",
             "a",
             expect![[r#"
-                Parameter `a`
+                Parameter `a`, defined at line 1
+
                 `?`
             "#]],
         );
@@ -253,7 +520,8 @@ mod tests {
             "{$0a}: a",
             "a",
             expect![[r#"
-                Field parameter `a`
+                Field parameter `a` from pattern `{a}`, defined at line 1
+
                 `?`
             "#]],
         );
@@ -265,7 +533,12 @@ mod tests {
             "let a = 1; in $0a",
             "a",
             expect![[r#"
-                Let binding `a`
+                Let binding `a`, defined at line 1
+
+                ```nix
+                a = 1
+                ```
+
                 `int`
             "#]],
         );
@@ -273,7 +546,12 @@ mod tests {
             "let a = 1; in { inherit $0a; }",
             "a",
             expect![[r#"
-                Let binding `a`
+                Let binding `a`, defined at line 1
+
+                ```nix
+                a = 1
+                ```
+
                 `int`
             "#]],
         );
@@ -281,7 +559,12 @@ mod tests {
             "let a = 1; in rec { inherit $0a; }",
             "a",
             expect![[r#"
-                Let binding `a`
+                Let binding `a`, defined at line 1
+
+                ```nix
+                a = 1
+                ```
+
                 `int`
             "#]],
         );
@@ -289,7 +572,8 @@ mod tests {
             "a: $0a",
             "a",
             expect![[r#"
-                Parameter `a`
+                Parameter `a`, defined at line 1
+
                 `?`
             "#]],
         );
@@ -297,12 +581,81 @@ mod tests {
             "{a}: $0a",
             "a",
             expect![[r#"
-                Field parameter `a`
+                Field parameter `a` from pattern `{a}`, defined at line 1
+
                 `?`
             "#]],
         );
     }
 
+    #[test]
+    fn attr_path_and_preview() {
+        check(
+            "
+{ config, ... }: {
+  services.nginx = {
+    $0enable = true;
+  };
+}
+            ",
+            "enable",
+            expect![[r#"
+                Attrset attribute `services.nginx.enable`, defined at line 3
+
+                ```nix
+                services.nginx.enable = true
+                ```
+
+                `bool`
+            "#]],
+        );
+        check(
+            "{ pkgs, lib, ... }: $0pkgs",
+            "pkgs",
+            expect![[r#"
+                Field parameter `pkgs` from pattern `{ pkgs, lib, ... }`, defined at line 1
+
+                `?`
+            "#]],
+        );
+        // Implicit attrsets have no source.
+        check(
+            "let a.b = 1; a.c = 2; in $0a",
+            "a",
+            expect![[r#"
+                Let binding `a`, defined at line 1
+
+                `{ b: int, c: int }`
+            "#]],
+        );
+        check(
+            &format!("let s = \"{}\"; in $0s", "x".repeat(1 << 20)),
+            "s",
+            expect![[r#"
+                Let binding `s`, defined at line 1
+
+                ```nix
+                s = "xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx…
+                ```
+
+                `string`
+            "#]],
+        );
+        check(
+            "let f = x:\n  x\n  + 1; in $0f",
+            "f",
+            expect![[r#"
+                Let binding `f`, defined at line 1
+
+                ```nix
+                f = x: x + 1
+                ```
+
+                `int → int`
+            "#]],
+        );
+    }
+
     #[test]
     fn with() {
         check(
@@ -310,9 +663,11 @@ mod tests {
             "a",
             expect![[r#"
                 `with` attribute `a`
-                `?`
+
                 Environments:
                 1. `with 1;`
+
+                `?`
             "#]],
         );
         check(
@@ -320,10 +675,12 @@ mod tests {
             "a",
             expect![[r#"
                 `with` attribute `a`
-                `?`
+
                 Environments:
                 1. `with 2;`
                 2. `with 1;`
+
+                `?`
             "#]],
         );
     }
@@ -335,7 +692,10 @@ mod tests {
             "true",
             expect![[r#"
                 `builtins.true`
-                `bool`
+
+                ```nix
+                bool
+                ```
 
                 `builtins.true`
                 Primitive value.
@@ -358,7 +718,10 @@ mod tests {
             "map",
             expect![[r#"
                 `builtins.map`
-                `(? → ?) → [?] → [?]`
+
+                ```nix
+                (? → ?) → [?] → [?]
+                ```
 
                 `builtins.map f list`
                 Apply the function *f* to each element in the list *list*. For
@@ -373,6 +736,43 @@ mod tests {
         );
     }
 
+    #[test]
+    fn builtin_sections() {
+        let (db, f) = TestDB::from_fixture("$0map").unwrap();
+        let markup = super::hover(&db, f[0]).unwrap().markup;
+        let sections = markup.split("\n\n").collect::<Vec<_>>();
+        assert_eq!(sections[0], "`builtins.map`");
+        assert_eq!(sections[1], "```nix\n(? → ?) → [?] → [?]\n```");
+        // The summary and documentation.
+        assert!(sections[2].starts_with("`builtins.map"), "{markup}");
+    }
+
+    #[test]
+    fn builtin_shadowed() {
+        check(
+            "let map = 1; in $0map",
+            "map",
+            expect![[r#"
+                Let binding `map`, defined at line 1
+
+                ```nix
+                map = 1
+                ```
+
+                `int`
+            "#]],
+        );
+        check(
+            "{ builtins }: $0builtins",
+            "builtins",
+            expect![[r#"
+                Field parameter `builtins` from pattern `{ builtins }`, defined at line 1
+
+                `?`
+            "#]],
+        );
+    }
+
     #[test]
     fn builtin_with() {
         check(
@@ -380,7 +780,10 @@ mod tests {
             "head",
             expect![[r#"
                 `builtins.head`
-                `[?] → ?`
+
+                ```nix
+                [?] → ?
+                ```
 
                 `builtins.head list`
                 Return the first element of a list; abort evaluation if the argument
@@ -397,7 +800,10 @@ mod tests {
             "head",
             expect![[r#"
                 `builtins.head`
-                `[?] → ?`
+
+                ```nix
+                [?] → ?
+                ```
 
                 `builtins.head list`
                 Return the first element of a list; abort evaluation if the argument
@@ -414,7 +820,10 @@ mod tests {
             "builtins.head",
             expect![[r#"
                 `builtins.head`
-                `[?] → ?`
+
+                ```nix
+                [?] → ?
+                ```
 
                 `builtins.head list`
                 Return the first element of a list; abort evaluation if the argument
@@ -428,7 +837,10 @@ mod tests {
             "builtins.true",
             expect![[r#"
                 `builtins.true`
-                `bool`
+
+                ```nix
+                bool
+                ```
 
                 `builtins.true`
                 Primitive value.
@@ -455,7 +867,10 @@ mod tests {
             "builtins",
             expect![[r#"
                 `builtins.builtins`
-                `{ abort: string → ?, add: float → float → float, addErrorContext: string → ? → ?, all: (? → bool) → [?] → bool, … }`
+
+                ```nix
+                { abort: string → ?, add: float → float → float, addErrorContext: string → ? → ?, all: (? → bool) → [?] → bool, … }
+                ```
 
                 `builtins.builtins`
                 Contains all the [built-in functions](@docroot@/language/builtins.md) and values.
@@ -476,7 +891,12 @@ mod tests {
             "let foo.$0bar = 1; in foo.bar",
             "bar",
             expect![[r#"
-                Attrset attribute `bar`
+                Attrset attribute `foo.bar`, defined at line 1
+
+                ```nix
+                foo.bar = 1
+                ```
+
                 `int`
             "#]],
         );
@@ -485,6 +905,7 @@ mod tests {
             "bar",
             expect![[r#"
                 Field `bar`
+
                 `int`
             "#]],
         );
@@ -493,8 +914,164 @@ mod tests {
             "bar",
             expect![[r#"
                 Field `bar`
+
+                `int`
+            "#]],
+        );
+    }
+
+    #[test]
+    fn import_broken_file() {
+        check(
+            "
+#- /default.nix
+let lib = import ./lib.nix; in lib.$0foo
+#- /lib.nix
+{ foo = 1; bar = ; baz = 2;
+",
+            "foo",
+            expect![[r#"
+                Field `foo`
+
+                `int`
+
+                target file has syntax errors; results may be incomplete
+            "#]],
+        );
+        // Types unrelated to the broken file are complete.
+        check(
+            "
+#- /default.nix
+let lib = import ./lib.nix; $0x = 1; in lib.foo + x
+#- /lib.nix
+{ foo = 1; bar = ;
+",
+            "x",
+            expect![[r#"
+                Let binding `x`, defined at line 1
+
+                ```nix
+                x = 1
+                ```
+
                 `int`
             "#]],
         );
+        check(
+            "
+#- /default.nix
+(import ./lib.nix).$0foo
+#- /lib.nix
+{ foo = 1; }
+",
+            "foo",
+            expect![[r#"
+                Field `foo`
+
+                `int`
+            "#]],
+        );
+    }
+
+    #[test]
+    fn flake_input() {
+        let fixture = |body: &str| {
+            format!(
+                r#"
+#- /flake.nix
+{{
+    inputs.nixpkgs.url = "github:NixOS/nixpkgs";
+    outputs = {{ self, nixpkgs, extra }}: {body};
+}}
+"#
+            )
+        };
+        check(
+            &fixture("$0nixpkgs"),
+            "nixpkgs",
+            expect![[r#"
+                Field parameter `nixpkgs` from pattern `{ self, nixpkgs, extra }`, defined at line 3
+
+                Flake input from `github:NixOS/nixpkgs`
+
+                `{ inputs: { }, lastModified: int, lastModifiedDate: string, narHash: string, … }`
+            "#]],
+        );
+        check(
+            &fixture("$0extra"),
+            "extra",
+            expect![[r#"
+                Field parameter `extra` from pattern `{ self, nixpkgs, extra }`, defined at line 3
+
+                Flake input
+
+                `{ inputs: { }, lastModified: int, lastModifiedDate: string, narHash: string, … }`
+            "#]],
+        );
+        check(
+            &fixture("$0self"),
+            "self",
+            expect![[r#"
+                Field parameter `self` from pattern `{ self, nixpkgs, extra }`, defined at line 3
+
+                `{ }`
+            "#]],
+        );
+    }
+
+    #[test]
+    fn option_declaration() {
+        let fixture = |enable: &str, name: &str| {
+            format!(
+                r#"
+{{ lib, ... }}:
+let
+  mkStrOption = description: lib.mkOption {{
+    type = lib.types.str;
+    inherit description;
+    default = "";
+  }};
+in {{
+  options.foo = {{
+    {enable} = lib.mkEnableOption "foo";
+    {name} = mkStrOption "The name of foo.";
+  }};
+}}
+"#
+            )
+        };
+        check(
+            &fixture("$0enable", "name"),
+            "enable",
+            expect![[r#"
+                Option `foo.enable`
+
+                Whether to enable foo.
+
+                Type: `types.bool`
+
+                Default: `false`
+            "#]],
+        );
+        check(
+            &fixture("enable", "$0name"),
+            "name",
+            expect![[r#"
+                Option `foo.name`
+
+                The name of foo.
+
+                Type: `lib.types.str`
+
+                Default: `""`
+            "#]],
+        );
+    }
+
+    #[test]
+    fn builtin_doc_example() {
+        let doc = super::builtin_doc("replaceStrings", Some("Docs from Nix."));
+        assert!(doc.starts_with("Docs from Nix.\n\nExample:"), "{doc}");
+        assert_eq!(super::builtin_doc("map", Some("Docs.")), "Docs.");
     }
 }
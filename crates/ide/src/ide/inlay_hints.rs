@@ -0,0 +1,272 @@
+use super::MAX_RESOLVE_DEPTH;
+use crate::def::{AstPtr, Expr, NameId, ResolveResult};
+use crate::{DefDatabase, FilePos, FileRange};
+use syntax::ast::{self, AstNode};
+use syntax::rowan::WalkEvent;
+use syntax::{SyntaxKind, TextRange, TextSize};
+
+/// Values spanning at least this many lines get a hint of their attribute names at the end.
+const ATTR_NAME_MIN_LINES: usize = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InlayHint {
+    pub pos: TextSize,
+    pub label: String,
+    pub kind: InlayHintKind,
+    /// Only computed by `inlay_hint_resolve`.
+    pub tooltip: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InlayHintKind {
+    /// The literal value of a parameter default, eg. `{ a ? 1 }:`, or the one it refers to, eg.
+    /// `{ a ? b }:` with `b = 1`.
+    DefaultValue,
+    /// The attribute name after a long multi-line value.
+    AttrName,
+}
+
+/// Inlay hints for nodes overlapping the given range, in the order of their positions.
+pub(crate) fn inlay_hints(db: &dyn DefDatabase, frange: FileRange) -> Vec<InlayHint> {
+    collect_hints(db, frange, false)
+}
+
+/// The inlay hint of `kind` at `fpos`, with its tooltip.
+pub(crate) fn inlay_hint_resolve(
+    db: &dyn DefDatabase,
+    FilePos { file_id, pos }: FilePos,
+    kind: InlayHintKind,
+) -> Option<InlayHint> {
+    collect_hints(db, FileRange::new(file_id, TextRange::empty(pos)), true)
+        .into_iter()
+        .find(|hint| hint.pos == pos && hint.kind == kind)
+}
+
+fn collect_hints(
+    db: &dyn DefDatabase,
+    FileRange { file_id, range }: FileRange,
+    resolve: bool,
+) -> Vec<InlayHint> {
+    let parse = db.parse(file_id);
+    let src = db.file_content(file_id);
+    let module = db.module(file_id);
+    let source_map = db.source_map(file_id);
+    let nameres = db.name_resolution(file_id);
+
+    let mut hints = Vec::new();
+    let mut iter = parse.syntax_node().preorder();
+    while let Some(event) = iter.next() {
+        let WalkEvent::Enter(node) = event else {
+            continue;
+        };
+        if node.text_range().intersect(range).is_none() {
+            iter.skip_subtree();
+            continue;
+        }
+
+        if let Some(field) = ast::PatField::cast(node.clone()) {
+            let Some(default_expr) = field.default_expr() else {
+                continue;
+            };
+            let Some(mut expr) = source_map.expr_for_node(AstPtr::new(default_expr.syntax()))
+            else {
+                continue;
+            };
+            // Follow references to a literal, eg. `{ a ? b }:` with `b = 1`.
+            let mut names = Vec::<NameId>::new();
+            for _ in 0..MAX_RESOLVE_DEPTH {
+                if matches!(module[expr], Expr::Literal(_)) {
+                    let Some(ptr) = source_map.node_for_expr(expr) else {
+                        break;
+                    };
+                    let text = &src[ptr.text_range()];
+                    if !text.contains('\n') {
+                        hints.push(InlayHint {
+                            pos: default_expr.syntax().text_range().end(),
+                            label: format!("= {text}"),
+                            kind: InlayHintKind::DefaultValue,
+                            tooltip: resolve.then(|| {
+                                if names.is_empty() {
+                                    return "Default value".into();
+                                }
+                                let chain = names
+                                    .iter()
+                                    .map(|&name| format!("`{}`", module[name].text))
+                                    .collect::<Vec<_>>()
+                                    .join(" → ");
+                                format!("Resolved through {chain}")
+                            }),
+                        });
+                    }
+                    break;
+                }
+                let Some(&ResolveResult::Definition(name)) = nameres.get(expr) else {
+                    break;
+                };
+                let Some(value) = module.binding_value(name) else {
+                    break;
+                };
+                names.push(name);
+                expr = value;
+            }
+        } else if let Some(path_value) = ast::AttrpathValue::cast(node.clone()) {
+            if !node
+                .parent()
+                .is_some_and(|p| p.kind() == SyntaxKind::ATTR_SET)
+            {
+                continue;
+            }
+            let (Some(path), Some(value)) = (path_value.attrpath(), path_value.value()) else {
+                continue;
+            };
+            let value_range = value.syntax().text_range();
+            let lines = src[value_range].lines().count();
+            if lines < ATTR_NAME_MIN_LINES {
+                continue;
+            }
+            let label = path
+                .syntax()
+                .to_string()
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ");
+            let tooltip = resolve.then(|| format!("End of `{label}`, spanning {lines} lines"));
+            hints.push(InlayHint {
+                pos: value_range.end(),
+                label,
+                kind: InlayHintKind::AttrName,
+                tooltip,
+            });
+        }
+    }
+    hints.sort_by_key(|hint| hint.pos);
+    hints
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InlayHintKind;
+    use crate::tests::TestDB;
+    use crate::{FilePos, FileRange, SourceDatabase};
+    use expect_test::{expect, Expect};
+    use syntax::TextRange;
+
+    #[track_caller]
+    fn check(fixture: &str, expect: Expect) {
+        let (db, file) = TestDB::single_file(fixture).unwrap();
+        let range = TextRange::up_to(db.file_content(file).len().try_into().unwrap());
+        check_range(&db, FileRange::new(file, range), expect);
+    }
+
+    #[track_caller]
+    fn check_range(db: &TestDB, frange: FileRange, expect: Expect) {
+        let got = super::inlay_hints(db, frange)
+            .into_iter()
+            .map(|hint| {
+                format!(
+                    "{:?} {:?}: {}\n",
+                    u32::from(hint.pos),
+                    hint.kind,
+                    hint.label
+                )
+            })
+            .collect::<String>();
+        expect.assert_eq(&got);
+    }
+
+    #[test]
+    fn default_value() {
+        check(
+            "let a = b; b = 42; c = { }; in { x ? a, y ? 1, z ? c, w ? w }: x",
+            expect![[r#"
+                38 DefaultValue: = 42
+                45 DefaultValue: = 1
+            "#]],
+        );
+        check(
+            r#"let s = "foo"; in { x ? s }: x"#,
+            expect![[r#"
+                25 DefaultValue: = "foo"
+            "#]],
+        );
+    }
+
+    #[test]
+    fn attr_name() {
+        check(
+            "
+{
+  short = [
+    1
+  ];
+  a.b = {
+    c = 1;
+    d = 2;
+    e = 3;
+  };
+  let_ = let
+    x = 1;
+    y = 2;
+    z = 3;
+  in x;
+}
+            ",
+            expect![[r#"
+                71 AttrName: a.b
+                125 AttrName: let_
+            "#]],
+        );
+    }
+
+    #[test]
+    fn range() {
+        let (db, f) =
+            TestDB::from_fixture("let a = 1; in [ ({ x ? a }: x) $0({ y ? a }: y)$1 ]").unwrap();
+        check_range(
+            &db,
+            f.unwrap_single_range_marker(),
+            expect![[r#"
+                39 DefaultValue: = 1
+            "#]],
+        );
+    }
+
+    #[test]
+    fn resolve_tooltip() {
+        let (db, file) =
+            TestDB::single_file("let a = b; b = 42; in [ ({ x ? a }: x) { y = [\n\n\n\n]; } ]")
+                .unwrap();
+        let range = TextRange::up_to(db.file_content(file).len().try_into().unwrap());
+        let hints = super::inlay_hints(&db, FileRange::new(file, range));
+        assert_eq!(hints.len(), 2);
+        assert!(hints.iter().all(|hint| hint.tooltip.is_none()));
+
+        let resolve = |i: usize| {
+            let hint = &hints[i];
+            super::inlay_hint_resolve(&db, FilePos::new(file, hint.pos), hint.kind)
+                .unwrap()
+                .tooltip
+                .unwrap()
+        };
+        assert_eq!(resolve(0), "Resolved through `a` → `b`");
+        assert_eq!(resolve(1), "End of `y`, spanning 5 lines");
+
+        assert_eq!(
+            super::inlay_hint_resolve(
+                &db,
+                FilePos::new(file, hints[0].pos),
+                InlayHintKind::AttrName
+            ),
+            None,
+        );
+
+        let (db, file) = TestDB::single_file("{ x ? 1 }: x").unwrap();
+        let hint = super::inlay_hint_resolve(
+            &db,
+            FilePos::new(file, 7.into()),
+            InlayHintKind::DefaultValue,
+        )
+        .unwrap();
+        assert_eq!(hint.tooltip.unwrap(), "Default value");
+    }
+}
@@ -218,6 +218,7 @@ mod tests {
             expect![[r#"
                 ./. -> /: /
                 ./foo.nix -> /foo.nix: /foo.nix
+                /bar -> /bar: /bar
             "#]],
         );
     }
@@ -1,42 +1,99 @@
 mod assists;
+mod attrpath_at;
 mod completion;
 mod diagnostics;
 mod expand_selection;
 mod file_references;
+mod flake_check;
+mod flake_inputs;
+mod folding_ranges;
+mod formatting;
 mod goto_definition;
 mod highlight_related;
 mod hover;
+mod inlay_hints;
 mod links;
+mod option_declarations;
 mod references;
 mod rename;
+mod resolve_import;
+mod signature_help;
 mod symbol_hierarchy;
 mod syntax_highlighting;
+mod workspace_symbols;
 
 use crate::base::SourceDatabaseStorage;
 use crate::def::DefDatabaseStorage;
-use crate::ty::TyDatabaseStorage;
+use crate::def::{Expr, ExprId, Literal, NameId, NameResolution, ResolveResult};
+use crate::ty::{TyDatabase, TyDatabaseStorage};
 use crate::{
-    Change, Diagnostic, FileId, FilePos, FileRange, FileSet, SourceRoot, VfsPath, WorkspaceEdit,
+    Change, Diagnostic, FileId, FilePos, FileRange, FileSet, Module, NameKind, SourceRoot,
+    TextEdit, VfsPath, WorkspaceEdit, DEFAULT_LIB_INHERIT_THRESHOLD, DEFAULT_MAX_NESTING,
 };
 use nix_interop::DEFAULT_IMPORT_FILE;
 use salsa::{Database, Durability, ParallelDatabase};
 use smol_str::SmolStr;
 use std::fmt;
 use std::sync::Arc;
+use syntax::semantic::is_valid_ident;
 use syntax::TextRange;
 
 pub use assists::{Assist, AssistKind};
-pub use completion::{CompletionItem, CompletionItemKind};
+pub use attrpath_at::AttrpathSegment;
+pub use completion::{CompletionItem, CompletionItemKind, PathCompletionContext};
+pub use flake_inputs::FlakeInput;
+pub use folding_ranges::{FoldKind, FoldRange};
+pub use formatting::FormattingRange;
 pub use goto_definition::GotoDefinitionResult;
 pub use highlight_related::HlRelated;
-pub use hover::HoverResult;
+pub use hover::{builtin_document, HoverResult};
+pub use inlay_hints::{InlayHint, InlayHintKind};
 pub use links::{Link, LinkTarget};
 pub use rename::RenameResult;
+pub use resolve_import::ResolvedImport;
+pub use signature_help::SignatureInfo;
 pub use symbol_hierarchy::SymbolTree;
 pub use syntax_highlighting::{HlAttrField, HlKeyword, HlOperator, HlPunct, HlRange, HlTag};
+pub use workspace_symbols::SymbolLocation;
 
 pub const DEFAULT_LRU_CAP: usize = 128;
 
+/// Limit the length of reference chains to follow, in case of cycles like `let a = a; in a`.
+const MAX_RESOLVE_DEPTH: usize = 8;
+
+/// The conventional name of nixpkgs `lib`.
+const LIB_NAME: &str = "lib";
+
+/// If `e` is a select like `lib.name` or `lib.name.more` which can be rewritten to use an
+/// inherited `name`, return the definition of `lib` and `name`.
+///
+/// `lib` must be a lambda parameter or a `let` binding, not an attrset key. Selects with
+/// invalid identifiers or defaults like `lib.name or x` are rejected.
+fn lib_select<'m>(
+    module: &'m Module,
+    nameres: &NameResolution,
+    e: ExprId,
+) -> Option<(NameId, &'m str)> {
+    let Expr::Select(set, path, default) = &module[e] else {
+        return None;
+    };
+    let Some(&ResolveResult::Definition(lib)) = nameres.get(*set) else {
+        return None;
+    };
+    let Expr::Literal(Literal::String(attr)) = &module[path[0]] else {
+        return None;
+    };
+    // `lib.a or x` cannot be rewritten to `a or x`.
+    let ok = module[lib].text == LIB_NAME
+        && !matches!(
+            module[lib].kind,
+            NameKind::PlainAttrset | NameKind::RecAttrset
+        )
+        && is_valid_ident(attr)
+        && !(default.is_some() && path.len() == 1);
+    ok.then_some((lib, &**attr))
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct NavigationTarget {
     pub file_id: FileId,
@@ -48,7 +105,18 @@ pub use salsa::Cancelled;
 
 pub type Cancellable<T> = Result<T, Cancelled>;
 
-#[salsa::database(SourceDatabaseStorage, DefDatabaseStorage, TyDatabaseStorage)]
+#[salsa::query_group(IdeDatabaseStorage)]
+pub trait IdeDatabase: TyDatabase {
+    #[salsa::invoke(workspace_symbols::symbol_index_query)]
+    fn symbol_index(&self, file_id: FileId) -> Arc<[workspace_symbols::IndexedSymbol]>;
+}
+
+#[salsa::database(
+    SourceDatabaseStorage,
+    DefDatabaseStorage,
+    TyDatabaseStorage,
+    IdeDatabaseStorage
+)]
 struct RootDatabase {
     storage: salsa::Storage<Self>,
 }
@@ -81,8 +149,15 @@ impl Default for RootDatabase {
             .in_db_mut(&mut db)
             .set_lru_capacity(DEFAULT_LRU_CAP);
 
+        db.set_source_root_ids_with_durability(Arc::new([]), Durability::HIGH);
         db.set_flake_graph_with_durability(Arc::default(), Durability::MEDIUM);
         db.set_nixos_options_with_durability(Arc::default(), Durability::MEDIUM);
+        db.set_search_path_with_durability(Arc::default(), Durability::MEDIUM);
+        db.set_max_nesting_with_durability(DEFAULT_MAX_NESTING, Durability::MEDIUM);
+        db.set_lib_inherit_threshold_with_durability(
+            DEFAULT_LIB_INHERIT_THRESHOLD,
+            Durability::MEDIUM,
+        );
         db
     }
 }
@@ -156,10 +231,30 @@ impl Analysis {
         self.with_db(|db| diagnostics::diagnostics(db, file))
     }
 
+    pub fn flake_check(
+        &self,
+        flake_file: FileId,
+        locked_inputs: Option<&[String]>,
+    ) -> Cancellable<Vec<(FileId, Diagnostic)>> {
+        self.with_db(|db| flake_check::flake_check(db, flake_file, locked_inputs))
+    }
+
+    pub fn flake_inputs(&self, file: FileId) -> Cancellable<Vec<FlakeInput>> {
+        self.with_db(|db| flake_inputs::flake_inputs(db, file))
+    }
+
     pub fn goto_definition(&self, pos: FilePos) -> Cancellable<Option<GotoDefinitionResult>> {
         self.with_db(|db| goto_definition::goto_definition(db, pos))
     }
 
+    pub fn attr_definitions(
+        &self,
+        file: FileId,
+        attrpath: &[SmolStr],
+    ) -> Cancellable<Vec<NavigationTarget>> {
+        self.with_db(|db| goto_definition::attr_definitions(db, file, attrpath))
+    }
+
     pub fn completions(
         &self,
         pos: FilePos,
@@ -168,8 +263,19 @@ impl Analysis {
         self.with_db(|db| completion::completions(db, pos, trigger_char))
     }
 
-    pub fn references(&self, pos: FilePos) -> Cancellable<Option<Vec<FileRange>>> {
-        self.with_db(|db| references::references(db, pos))
+    pub fn path_completion_context(
+        &self,
+        pos: FilePos,
+    ) -> Cancellable<Option<PathCompletionContext>> {
+        self.with_db(|db| completion::path_completion_context(db, pos))
+    }
+
+    pub fn references(
+        &self,
+        pos: FilePos,
+        include_declaration: bool,
+    ) -> Cancellable<Option<Vec<FileRange>>> {
+        self.with_db(|db| references::references(db, pos, include_declaration))
     }
 
     pub fn prepare_rename(&self, fpos: FilePos) -> Cancellable<RenameResult<(TextRange, SmolStr)>> {
@@ -192,6 +298,30 @@ impl Analysis {
         self.with_db(|db| symbol_hierarchy::symbol_hierarchy(db, file))
     }
 
+    pub fn symbols_under(&self, file: FileId, prefix: &[String]) -> Cancellable<Vec<SymbolTree>> {
+        self.with_db(|db| symbol_hierarchy::symbols_under(db, file, prefix))
+    }
+
+    pub fn workspace_symbols(&self, query: &str, limit: usize) -> Cancellable<Vec<SymbolLocation>> {
+        self.with_db(|db| workspace_symbols::workspace_symbols(db, query, limit))
+    }
+
+    pub fn folding_ranges(&self, file: FileId) -> Cancellable<Vec<FoldRange>> {
+        self.with_db(|db| folding_ranges::folding_ranges(db, file))
+    }
+
+    pub fn inlay_hints(&self, frange: FileRange) -> Cancellable<Vec<InlayHint>> {
+        self.with_db(|db| inlay_hints::inlay_hints(db, frange))
+    }
+
+    pub fn inlay_hint_resolve(
+        &self,
+        fpos: FilePos,
+        kind: InlayHintKind,
+    ) -> Cancellable<Option<InlayHint>> {
+        self.with_db(|db| inlay_hints::inlay_hint_resolve(db, fpos, kind))
+    }
+
     pub fn links(&self, file: FileId) -> Cancellable<Vec<Link>> {
         self.with_db(|db| links::links(db, file))
     }
@@ -200,6 +330,18 @@ impl Analysis {
         self.with_db(|db| links::link_resolve(db, frange))
     }
 
+    pub fn signature_help(&self, fpos: FilePos) -> Cancellable<Option<SignatureInfo>> {
+        self.with_db(|db| signature_help::signature_help(db, fpos))
+    }
+
+    pub fn attrpath_at(&self, fpos: FilePos) -> Cancellable<Vec<AttrpathSegment>> {
+        self.with_db(|db| attrpath_at::attrpath_at(db, fpos))
+    }
+
+    pub fn resolve_import(&self, fpos: FilePos) -> Cancellable<Result<ResolvedImport, String>> {
+        self.with_db(|db| resolve_import::resolve_import(db, fpos))
+    }
+
     pub fn assists(&self, frange: FileRange) -> Cancellable<Vec<Assist>> {
         self.with_db(|db| assists::assists(db, frange))
     }
@@ -208,6 +350,22 @@ impl Analysis {
         self.with_db(|db| highlight_related::highlight_related(db, fpos).unwrap_or_default())
     }
 
+    pub fn trim_trailing_whitespace(&self, file: FileId) -> Cancellable<Vec<TextEdit>> {
+        self.with_db(|db| formatting::trim_trailing_whitespace(db, file))
+    }
+
+    pub fn insert_final_newline(&self, file: FileId) -> Cancellable<Option<TextEdit>> {
+        self.with_db(|db| formatting::insert_final_newline(db, file))
+    }
+
+    pub fn formatting_range(&self, frange: FileRange) -> Cancellable<FormattingRange> {
+        self.with_db(|db| formatting::formatting_range(db, frange))
+    }
+
+    pub fn batch_fixes(&self, file: FileId) -> Cancellable<Vec<TextEdit>> {
+        self.with_db(|db| assists::batch_fixes(db, file))
+    }
+
     //// Custom extensions ////
 
     pub fn file_references(&self, file: FileId) -> Cancellable<Vec<FileId>> {
@@ -217,4 +375,12 @@ impl Analysis {
     pub fn file_referrers(&self, file: FileId) -> Cancellable<Vec<FileId>> {
         self.with_db(|db| file_references::file_referrers(db, file))
     }
+
+    pub fn rename_file_imports(
+        &self,
+        old_path: &VfsPath,
+        new_path: &VfsPath,
+    ) -> Cancellable<WorkspaceEdit> {
+        self.with_db(|db| file_references::rename_file_imports(db, old_path, new_path))
+    }
 }
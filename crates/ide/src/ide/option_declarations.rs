@@ -0,0 +1,384 @@
+//! Option declarations in `options` of modules, like `options.foo.enable = mkEnableOption "foo";`.
+//!
+//! Calls to `mkOption`, `mkEnableOption` and `mkPackageOption` are recognized by their names,
+//! like `lib.mkOption` or `mkOption` from `inherit (lib) mkOption;` or `with lib;`.
+//! Wrappers defined in the same file, whose bodies (possibly through one more lambda) call
+//! `mkOption`, are unfolded once with their arguments substituted into the fields. Calls inside
+//! wrappers are never unfolded again, so recursive wrappers are not a problem.
+use crate::def::{
+    BindingValue, Bindings, Expr, ExprId, Literal, NameId, NameResolution, ResolveResult,
+};
+use crate::{DefDatabase, FileId, Module, ModuleSourceMap};
+use smol_str::SmolStr;
+use std::collections::HashMap;
+use syntax::TextRange;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct OptionDeclaration {
+    /// The option path under `options`.
+    pub path: Vec<SmolStr>,
+    /// The last name of the path.
+    pub name: NameId,
+    /// The source text of `type`.
+    pub ty: Option<String>,
+    pub description: Option<String>,
+    /// The source text of `default`.
+    pub default: Option<String>,
+}
+
+/// All option declarations of `file` in source order.
+pub(crate) fn option_declarations(db: &dyn DefDatabase, file: FileId) -> Vec<OptionDeclaration> {
+    let module = db.module(file);
+    let ctx = Ctx {
+        module: &module,
+        source_map: &db.source_map(file),
+        nameres: &db.name_resolution(file),
+        src: &db.file_content(file),
+    };
+
+    // The module body, through the parameter `{ lib, ... }:`, `let`-in and `with`.
+    let mut body = module.entry_expr();
+    while let Expr::Lambda(_, _, e) | Expr::LetIn(_, e) | Expr::With(_, e) = &module[body] {
+        body = *e;
+    }
+    let (Expr::Attrset(bindings) | Expr::RecAttrset(bindings)) = &module[body] else {
+        return Vec::new();
+    };
+    let Some(options) = static_value(&module, bindings, "options") else {
+        return Vec::new();
+    };
+
+    let mut ret = Vec::new();
+    ctx.collect(options, None, &mut Vec::new(), &mut ret);
+    ret
+}
+
+/// Values of parameters of an unfolded wrapper.
+type Subst = HashMap<NameId, ExprId>;
+
+struct Ctx<'a> {
+    module: &'a Module,
+    source_map: &'a ModuleSourceMap,
+    nameres: &'a NameResolution,
+    src: &'a str,
+}
+
+enum Callee {
+    /// `mkOption` and friends from `lib`.
+    Lib(SmolStr),
+    /// A lambda defined in the current file.
+    Wrapper(ExprId),
+}
+
+impl Ctx<'_> {
+    fn collect(
+        &self,
+        e: ExprId,
+        name: Option<NameId>,
+        path: &mut Vec<SmolStr>,
+        out: &mut Vec<OptionDeclaration>,
+    ) {
+        if let Expr::Attrset(bindings) | Expr::RecAttrset(bindings) = &self.module[e] {
+            for &(name, value) in bindings.statics.iter() {
+                if let BindingValue::Expr(value) = value {
+                    path.push(self.module[name].text.clone());
+                    self.collect(value, Some(name), path, out);
+                    path.pop();
+                }
+            }
+            return;
+        }
+        let Some(name) = name else { return };
+        let (func, args) = self.spine(e);
+        let decl = match self.callee(func) {
+            Some(Callee::Lib(func)) => self.lib_option(&func, &args, &Subst::new()),
+            Some(Callee::Wrapper(lam)) => self.unfold(lam, &args),
+            None => None,
+        };
+        if let Some((ty, description, default)) = decl {
+            out.push(OptionDeclaration {
+                path: path.clone(),
+                name,
+                ty,
+                description,
+                default,
+            });
+        }
+    }
+
+    /// Split `f a b` into `f` and `[a, b]`.
+    fn spine(&self, mut e: ExprId) -> (ExprId, Vec<ExprId>) {
+        let mut args = Vec::new();
+        while let &Expr::Apply(func, arg) = &self.module[e] {
+            args.push(arg);
+            e = func;
+        }
+        args.reverse();
+        (e, args)
+    }
+
+    fn callee(&self, func: ExprId) -> Option<Callee> {
+        let lib_func = |name: &SmolStr| {
+            matches!(&**name, "mkOption" | "mkEnableOption" | "mkPackageOption")
+                .then(|| Callee::Lib(name.clone()))
+        };
+        match &self.module[func] {
+            // `lib.mkOption`
+            Expr::Select(_, path, None) => match &self.module[*path.last()?] {
+                Expr::Literal(Literal::String(name)) => lib_func(name),
+                _ => None,
+            },
+            Expr::Reference(name) => match self.nameres.get(func) {
+                Some(&ResolveResult::Definition(def)) => match self.module.binding_value(def) {
+                    Some(value) if matches!(self.module[value], Expr::Lambda(..)) => {
+                        Some(Callee::Wrapper(value))
+                    }
+                    // `inherit (lib) mkOption;` or `{ mkOption, ... }:`.
+                    _ => lib_func(name),
+                },
+                Some(ResolveResult::Builtin(_)) => None,
+                Some(ResolveResult::WithExprs(_)) | None => lib_func(name),
+            },
+            _ => None,
+        }
+    }
+
+    /// Unfold a call to a wrapper `lam` with `args`, at most two lambdas deep.
+    fn unfold(&self, lam: ExprId, args: &[ExprId]) -> Option<Decl> {
+        let mut subst = Subst::new();
+        let mut body = lam;
+        let mut args = args;
+        for _ in 0..2 {
+            let Expr::Lambda(param, pat, lam_body) = &self.module[body] else {
+                break;
+            };
+            let (&arg, rest) = args.split_first()?;
+            if let Some(param) = *param {
+                subst.insert(param, arg);
+            }
+            if let Some(pat) = pat {
+                let arg_bindings = match &self.module[arg] {
+                    Expr::Attrset(bindings) | Expr::RecAttrset(bindings) => Some(bindings),
+                    _ => None,
+                };
+                for &(field, default) in pat.fields.iter() {
+                    let Some(field) = field else { continue };
+                    let value = arg_bindings
+                        .and_then(|b| static_value(self.module, b, &self.module[field].text))
+                        .or(default);
+                    if let Some(value) = value {
+                        subst.insert(field, value);
+                    }
+                }
+            }
+            body = *lam_body;
+            args = rest;
+        }
+        if !args.is_empty() {
+            return None;
+        }
+        let (func, inner_args) = self.spine(body);
+        match self.callee(func)? {
+            Callee::Lib(func) => self.lib_option(&func, &inner_args, &subst),
+            // Only one level is unfolded.
+            Callee::Wrapper(_) => None,
+        }
+    }
+
+    fn lib_option(&self, func: &str, args: &[ExprId], subst: &Subst) -> Option<Decl> {
+        match (func, args) {
+            ("mkOption", &[arg]) => {
+                let (Expr::Attrset(bindings) | Expr::RecAttrset(bindings)) =
+                    &self.module[self.subst(arg, subst)]
+                else {
+                    return None;
+                };
+                let field = |name: &str| {
+                    bindings.statics.iter().find_map(|&(key, value)| {
+                        if self.module[key].text != name {
+                            return None;
+                        }
+                        match value {
+                            BindingValue::Expr(e) | BindingValue::Inherit(e) => {
+                                Some(self.subst(e, subst))
+                            }
+                            BindingValue::InheritFrom(_) => None,
+                        }
+                    })
+                };
+                Some((
+                    field("type").and_then(|e| self.text(e)),
+                    field("description").and_then(|e| self.string(e)),
+                    field("default").and_then(|e| self.text(e)),
+                ))
+            }
+            ("mkEnableOption", &[name]) => {
+                let name = self.string(self.subst(name, subst))?;
+                Some((
+                    Some("types.bool".into()),
+                    Some(format!("Whether to enable {name}.")),
+                    Some("false".into()),
+                ))
+            }
+            ("mkPackageOption", &[_pkgs, name] | &[_pkgs, name, _]) => {
+                let name = self.string(self.subst(name, subst))?;
+                Some((
+                    Some("types.package".into()),
+                    Some(format!("The {name} package to use.")),
+                    Some(format!("pkgs.{name}")),
+                ))
+            }
+            _ => None,
+        }
+    }
+
+    /// Substitute references to parameters of the unfolded wrapper.
+    fn subst(&self, e: ExprId, subst: &Subst) -> ExprId {
+        match self.nameres.get(e) {
+            Some(&ResolveResult::Definition(name)) => subst.get(&name).copied().unwrap_or(e),
+            _ => e,
+        }
+    }
+
+    fn text(&self, e: ExprId) -> Option<String> {
+        let range: TextRange = self.source_map.node_for_expr(e)?.text_range();
+        Some(self.src[range].trim().to_owned())
+    }
+
+    /// The content of string literals, or the source text of other expressions.
+    fn string(&self, e: ExprId) -> Option<String> {
+        match &self.module[e] {
+            Expr::Literal(Literal::String(s)) => Some(s.to_string()),
+            _ => self.text(e),
+        }
+    }
+}
+
+/// Fields `type`, `description` and `default` of an option.
+type Decl = (Option<String>, Option<String>, Option<String>);
+
+fn static_value(module: &Module, bindings: &Bindings, key: &str) -> Option<ExprId> {
+    bindings
+        .statics
+        .iter()
+        .find_map(|&(name, value)| match value {
+            BindingValue::Expr(e) if module[name].text == key => Some(e),
+            _ => None,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::TestDB;
+    use expect_test::{expect, Expect};
+
+    #[track_caller]
+    fn check(fixture: &str, expect: Expect) {
+        let (db, f) = TestDB::from_fixture(fixture).unwrap();
+        let got = super::option_declarations(&db, f.files()[0])
+            .into_iter()
+            .map(|decl| {
+                format!(
+                    "{}: {} | {} | {}\n",
+                    decl.path.join("."),
+                    decl.ty.as_deref().unwrap_or("-"),
+                    decl.description.as_deref().unwrap_or("-"),
+                    decl.default.as_deref().unwrap_or("-"),
+                )
+            })
+            .collect::<String>();
+        expect.assert_eq(&got);
+    }
+
+    #[test]
+    fn lib_functions() {
+        check(
+            r#"
+{ lib, pkgs, ... }:
+let
+  inherit (lib) mkOption types;
+in {
+  options.services.foo = {
+    enable = lib.mkEnableOption "foo";
+    package = lib.mkPackageOption pkgs "foo" { };
+    port = mkOption {
+      type = types.port;
+      default = 8080;
+      description = "The port to listen on.";
+    };
+    bar = 1;
+  };
+  config = { };
+}
+            "#,
+            expect![[r#"
+                services.foo.enable: types.bool | Whether to enable foo. | false
+                services.foo.package: types.package | The foo package to use. | pkgs.foo
+                services.foo.port: types.port | The port to listen on. | 8080
+            "#]],
+        );
+        check(
+            r#"
+{ lib, ... }: with lib; {
+  options.foo = mkOption { type = types.str; };
+}
+            "#,
+            expect![[r#"
+                foo: types.str | - | -
+            "#]],
+        );
+    }
+
+    #[test]
+    fn wrappers() {
+        check(
+            r#"
+{ lib, ... }:
+let
+  inherit (lib) mkOption types;
+  mkStrOption = default: mkOption { type = types.str; inherit default; };
+  mkDescribed = description: default: mkOption { inherit description default; };
+  mkPatOption = { default ? 1, description }: mkOption { inherit default description; };
+  mkEnable = name: lib.mkEnableOption name;
+in {
+  options = {
+    a = mkStrOption "hello";
+    b = mkDescribed "The b." [ ];
+    c = mkPatOption { description = "The c."; };
+    d = mkEnable "d";
+  };
+}
+            "#,
+            expect![[r#"
+                a: types.str | - | "hello"
+                b: - | The b. | [ ]
+                c: - | The c. | 1
+                d: types.bool | Whether to enable d. | false
+            "#]],
+        );
+    }
+
+    #[test]
+    fn no_unfold() {
+        // Wrappers of wrappers, partial applications and recursive wrappers.
+        check(
+            r#"
+{ lib, ... }:
+let
+  mkStr = default: lib.mkOption { inherit default; };
+  mkStr2 = default: mkStr default;
+  mkRec = x: mkRec x;
+  mkDeep = a: b: c: lib.mkOption { };
+in {
+  options = {
+    a = mkStr2 "a";
+    b = mkStr;
+    c = mkRec 1;
+    d = mkDeep 1 2 3;
+  };
+}
+            "#,
+            expect![""],
+        );
+    }
+}
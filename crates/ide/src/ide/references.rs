@@ -1,5 +1,5 @@
-use crate::def::{AstPtr, ResolveResult};
-use crate::{DefDatabase, FilePos, FileRange};
+use crate::def::{AstPtr, ImportRef, NameId, ResolveResult};
+use crate::{DefDatabase, FileId, FilePos, FileRange};
 use syntax::ast::{self, AstNode};
 use syntax::{best_token_at_offset, SyntaxKind, T};
 
@@ -8,9 +8,15 @@ enum DefKind {
     With(AstPtr),
 }
 
+/// Find all usages of the name or `with` at `pos`, respecting lexical scopes.
+/// Definitions are also included if `include_declaration` is set.
+///
+/// For names, usages in other files of the same source root through `import` are also
+/// included, like `foo` in `(import ./file.nix).foo`.
 pub(crate) fn references(
     db: &dyn DefDatabase,
     FilePos { file_id, pos }: FilePos,
+    include_declaration: bool,
 ) -> Option<Vec<FileRange>> {
     let parse = db.parse(file_id);
     let tok = best_token_at_offset(&parse.syntax_node(), pos)?;
@@ -29,52 +35,139 @@ pub(crate) fn references(
     };
 
     let source_map = db.source_map(file_id);
-    let nameres = db.name_resolution(file_id);
-    let nameref = db.name_reference(file_id);
-    let refs = match kind {
-        DefKind::Attr(ptr) => {
-            // If this is not a name definition, but a usage. We lookup its definition for the
-            // query. This is covered by the test `on_usage`.
-            let name = source_map.name_for_node(ptr).or_else(|| {
-                let expr = source_map.expr_for_node(ptr)?;
-                let ResolveResult::Definition(name) = nameres.get(expr)? else {
-                    return None;
-                };
-                Some(*name)
-            })?;
-            nameref.name_references(name)
-        }
+    let ptr = match kind {
+        DefKind::Attr(ptr) => ptr,
         DefKind::With(ptr) => {
             let expr = source_map.expr_for_node(ptr)?;
-            nameref.with_references(expr)
+            let with_node = ast::With::cast(ptr.to_node(&parse.syntax_node()))?;
+            let decls = with_node
+                .with_token()
+                .map(|tok| tok.text_range())
+                .filter(|_| include_declaration);
+            // When `with_references` returns None, it means no references, not a failure.
+            let refs = db.name_reference(file_id);
+            let refs = refs
+                .with_references(expr)
+                .into_iter()
+                .flatten()
+                .map(|&expr| {
+                    let ptr = source_map.node_for_expr(expr).expect("Id must be valid");
+                    ptr.text_range()
+                });
+            let ranges = decls
+                .into_iter()
+                .chain(refs)
+                .map(|range| FileRange::new(file_id, range))
+                .collect();
+            return Some(ranges);
+        }
+    };
+
+    // If this is not a name definition, but a usage. We lookup its definition for the
+    // query. This is covered by the test `on_usage`.
+    // The definition may be in another file if it is selected from an imported one.
+    let (def_file, name) = match source_map.name_for_node(ptr) {
+        Some(name) => (file_id, name),
+        None => {
+            let expr = source_map.expr_for_node(ptr)?;
+            match db.name_resolution(file_id).get(expr) {
+                Some(&ResolveResult::Definition(name)) => (file_id, name),
+                Some(_) => return None,
+                None => db.import_reference(file_id).definition_for_attr(expr)?,
+            }
         }
     };
-    // When {name,with}_references returns None, it means no references,
-    // not a failure.
-    let refs = refs.map_or(Vec::new(), |refs| {
-        refs.iter()
+    Some(name_references(db, def_file, name, include_declaration))
+}
+
+/// All usages of `name` defined in `file`, in the file itself and files of the same source root.
+fn name_references(
+    db: &dyn DefDatabase,
+    file: FileId,
+    name: NameId,
+    include_declaration: bool,
+) -> Vec<FileRange> {
+    let source_map = db.source_map(file);
+    let mut ranges = Vec::new();
+    if include_declaration {
+        ranges.extend(
+            source_map
+                .nodes_for_name(name)
+                .map(|ptr| FileRange::new(file, ptr.text_range())),
+        );
+    }
+    // When `name_references` returns None, it means no references, not a failure.
+    let nameref = db.name_reference(file);
+    ranges.extend(
+        nameref
+            .name_references(name)
+            .into_iter()
+            .flatten()
             .map(|&expr| {
                 let ptr = source_map.node_for_expr(expr).expect("Id must be valid");
-                FileRange::new(file_id, ptr.text_range())
-            })
-            .collect()
-    });
-    Some(refs)
+                FileRange::new(file, ptr.text_range())
+            }),
+    );
+
+    let source_root = db.source_root(db.file_source_root(file));
+    let mut files = source_root.files().map(|(f, _)| f).collect::<Vec<_>>();
+    files.sort();
+    for referrer in files {
+        let import_refs = db.import_reference(referrer);
+        let refs = import_refs.references(file, name);
+        if refs.is_empty() {
+            continue;
+        }
+        let referrer_source_map = db.source_map(referrer);
+        for &r in refs {
+            match r {
+                ImportRef::Attr(expr) => {
+                    let ptr = referrer_source_map
+                        .node_for_expr(expr)
+                        .expect("Id must be valid");
+                    ranges.push(FileRange::new(referrer, ptr.text_range()));
+                }
+                ImportRef::Inherit(inherit_name) => {
+                    ranges.extend(
+                        referrer_source_map
+                            .nodes_for_name(inherit_name)
+                            .map(|ptr| FileRange::new(referrer, ptr.text_range())),
+                    );
+                }
+            }
+        }
+    }
+    ranges
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::base::SourceDatabase;
     use crate::tests::TestDB;
 
     #[track_caller]
     fn check(fixture: &str) {
+        check_impl(fixture, false);
+    }
+
+    #[track_caller]
+    fn check_with_decl(fixture: &str) {
+        check_impl(fixture, true);
+    }
+
+    #[track_caller]
+    fn check_impl(fixture: &str, include_declaration: bool) {
         let (db, f) = TestDB::from_fixture(fixture).unwrap();
         assert!(!f.markers().is_empty());
-        let expect = f.markers()[1..].iter().map(|p| p.pos).collect::<Vec<_>>();
-        let mut got = super::references(&db, f[0])
+        let mut expect = f.markers()[1..]
+            .iter()
+            .map(|p| (p.file_id, p.pos))
+            .collect::<Vec<_>>();
+        expect.sort();
+        let mut got = super::references(&db, f[0], include_declaration)
             .into_iter()
             .flatten()
-            .map(|frange| frange.range.start())
+            .map(|frange| (frange.file_id, frange.range.start()))
             .collect::<Vec<_>>();
         got.sort();
         assert_eq!(got, expect);
@@ -119,6 +212,16 @@ mod tests {
         check("a: with {}; x + ($0with {}; { inherit a $1b; })");
     }
 
+    #[test]
+    fn include_declaration() {
+        check_with_decl("let $1a = 1; in $0$2a");
+        check_with_decl("let $0$1a = 1; in [ $2a $3a ]");
+        check_with_decl("$0$1with {}; $2a");
+        check_with_decl("let $1a.b = 1; $2a.c = 2; in $0$3a");
+        // Unrelated names in other scopes are excluded.
+        check_with_decl("[ (let $0$1a = 1; in $2a) (let a = 1; in a) ]");
+    }
+
     #[test]
     fn on_usage() {
         check("let x = 1; y = $0$1x; z = $2x; in z");
@@ -127,4 +230,72 @@ mod tests {
         // unrelated attributes as "references".
         check("with {}; $0a + b");
     }
+
+    #[test]
+    fn imported_attr() {
+        check_with_decl(
+            "
+#- /lib.nix
+{ $0$1foo = 1; bar = { foo = 2; }; }
+
+#- /default.nix
+let
+  lib = import ./lib.nix;
+  inherit (lib) $3foo;
+in [ lib.$2foo lib.bar.foo (import ./lib.nix).$4foo ]
+            ",
+        );
+        check(
+            "
+#- /lib.nix
+{ foo = 1; bar = { $0foo = 2; }; }
+
+#- /default.nix
+let lib = import ./lib.nix; in [ lib.foo lib.bar.$1foo (lib ? bar.$2foo) ]
+            ",
+        );
+    }
+
+    #[test]
+    fn imported_attr_on_usage() {
+        check_with_decl(
+            "
+#- /lib.nix
+let x = 1; in { $1foo = x; }
+
+#- /default.nix
+let lib = import ./lib.nix; in lib.$0$2foo
+
+#- /other.nix
+(import ./lib.nix).$3foo
+            ",
+        );
+        // Names of unknown files are not references.
+        check("let lib = import ./missing.nix; in lib.$0foo + lib.foo");
+    }
+
+    #[test]
+    fn reuse_unrelated_import_references() {
+        let (mut db, f) = TestDB::from_fixture(
+            "
+#- /lib.nix
+{ $0foo = 1; }
+
+#- /default.nix
+(import ./lib.nix).foo
+
+#- /other.nix
+1
+            ",
+        )
+        .unwrap();
+        super::references(&db, f[0], false);
+
+        db.set_file_content(f["/other.nix"], "2".into());
+        let executed = db.log_executed(|| {
+            super::references(&db, f[0], false);
+        });
+        let cnt = |query: &str| executed.iter().filter(|q| q.starts_with(query)).count();
+        assert_eq!(cnt("import_reference("), 1, "{executed:#?}");
+    }
 }
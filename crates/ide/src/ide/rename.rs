@@ -1,5 +1,5 @@
-use crate::def::{AstPtr, NameId, ResolveResult};
-use crate::{DefDatabase, FilePos, TextEdit, WorkspaceEdit};
+use crate::def::{AstPtr, BindingValue, Expr, ExprId, ModuleScopes, NameId, ResolveResult};
+use crate::{DefDatabase, FilePos, Module, TextEdit, WorkspaceEdit};
 use smol_str::SmolStr;
 use std::borrow::Cow;
 use syntax::ast::{self, AstNode};
@@ -12,7 +12,7 @@ pub(crate) fn prepare_rename(
     db: &dyn DefDatabase,
     fpos: FilePos,
 ) -> RenameResult<(TextRange, SmolStr)> {
-    let (range, name) = find_name(db, fpos).ok_or_else(|| "No references found".to_owned())?;
+    let (range, name) = find_name(db, fpos)?;
     let module = db.module(fpos.file_id);
     let text = module[name].text.clone();
     Ok((range, text))
@@ -23,7 +23,7 @@ pub(crate) fn rename(
     fpos: FilePos,
     new_name: &str,
 ) -> RenameResult<WorkspaceEdit> {
-    let (_, name) = find_name(db, fpos).ok_or_else(|| "No references found".to_owned())?;
+    let (_, name) = find_name(db, fpos)?;
 
     let new_attr = escape_literal_attr(new_name);
 
@@ -32,6 +32,12 @@ pub(crate) fn rename(
     let parse = db.parse(file_id);
     let module = db.module(fpos.file_id);
     let source_map = db.source_map(file_id);
+    let name_refs = db.name_reference(file_id);
+    let refs = name_refs.name_references(name).unwrap_or_default();
+
+    if module[name].text != new_name {
+        check_conflicts(&module, &db.scopes(file_id), name, refs, new_name)?;
+    }
 
     let old_attr = escape_literal_attr(&module[name].text);
 
@@ -43,6 +49,16 @@ pub(crate) fn rename(
 
         // Simple case for non-inherited names.
         let Some(i) = attr_node.parent().and_then(ast::Inherit::cast) else {
+            // Parameters cannot be string literals, unlike attribute names.
+            let is_param = attr_node.parent().is_some_and(|p| {
+                matches!(
+                    p.kind(),
+                    SyntaxKind::PARAM | SyntaxKind::PAT | SyntaxKind::PAT_FIELD
+                )
+            });
+            if is_param && matches!(new_attr, Cow::Owned(_)) {
+                return Err(format!("Invalid identifier for parameters: {new_name:?}"));
+            }
             edits.push(TextEdit {
                 delete: attr_node.text_range(),
                 insert: SmolStr::new(&new_attr),
@@ -55,7 +71,7 @@ pub(crate) fn rename(
         //
         // Note that renaming `rec { inherit old; }` => `rec { new = old; }`
         // would never collide with another field `old`, since `inherit`ed names are unique.
-        // TODO: Check if `new` collides with other fields.
+        // Collisions with `new` are checked in `check_conflicts`.
 
         // First remove the old binding.
         edits.push(TextEdit {
@@ -99,8 +115,6 @@ pub(crate) fn rename(
     }
 
     // Rename usages.
-    let name_refs = db.name_reference(file_id);
-    let refs = name_refs.name_references(name).unwrap_or_default();
     if matches!(new_attr, Cow::Owned(_)) && !refs.is_empty() {
         return Err("Cannot rename to a string literal while it is referenced".into());
     }
@@ -121,7 +135,7 @@ pub(crate) fn rename(
 
         // Here we are renaming the *reference* of an inherited name.
         // `inherit old;` => `old = new;`
-        // TODO: Check if `new` collides with another names.
+        // Collisions with `new` in a `rec` or `let` are checked in `check_conflicts`.
         assert!(
             i.from_expr().is_none(),
             "Expr::Ref can only be from Inherit without from_expr"
@@ -161,23 +175,131 @@ pub(crate) fn rename(
     })
 }
 
+/// Reject renames which would change the meaning of the code, that is,
+/// `new_name` is already defined in the same binding group, or some references would be
+/// resolved differently after renaming.
+fn check_conflicts(
+    module: &Module,
+    scopes: &ModuleScopes,
+    name: NameId,
+    refs: &[ExprId],
+    new_name: &str,
+) -> RenameResult<()> {
+    let old_name = &module[name].text;
+    let defined = || Err(format!("`{new_name}` is already defined in the same scope"));
+
+    // 1. Siblings in the same bindings or lambda parameters.
+    // For `inherit old;` references, the new binding `old = new;` is placed in the group of the
+    // `inherit` and would resolve `new` into it if it is a `rec` or `let`.
+    for (_, expr) in module.exprs() {
+        match expr {
+            Expr::Lambda(param, pat, _) => {
+                let mut names = param.iter().chain(
+                    pat.iter()
+                        .flat_map(|pat| pat.fields.iter().filter_map(|(n, _)| n.as_ref())),
+                );
+                if names.clone().any(|&n| n == name)
+                    && names.any(|&n| n != name && module[n].text == new_name)
+                {
+                    return defined();
+                }
+            }
+            Expr::Attrset(bindings)
+            | Expr::RecAttrset(bindings)
+            | Expr::LetIn(bindings, _)
+            | Expr::LetAttrset(bindings) => {
+                let is_rec = !matches!(expr, Expr::Attrset(_));
+                let contains = bindings.statics.iter().any(|&(n, value)| {
+                    n == name
+                        || is_rec && matches!(value, BindingValue::Inherit(e) if refs.contains(&e))
+                });
+                if contains
+                    && bindings
+                        .statics
+                        .iter()
+                        .any(|&(n, _)| n != name && module[n].text == new_name)
+                {
+                    return defined();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if !module[name].kind.is_definition() {
+        return Ok(());
+    }
+
+    // 2. References to `name` shadowed by another `new_name` in the middle.
+    for &expr in refs {
+        let Some(scope) = scopes.scope_for_expr(expr) else {
+            continue;
+        };
+        for defs in scopes
+            .ancestors(scope)
+            .filter_map(|data| data.as_definitions())
+        {
+            if defs.get(old_name) == Some(&name) {
+                break;
+            }
+            if defs.contains_key(new_name) {
+                return Err(format!(
+                    "Some references would be shadowed by another `{new_name}`"
+                ));
+            }
+        }
+    }
+
+    // 3. Other references to `new_name` captured by the renamed one.
+    for (expr, e) in module.exprs() {
+        if !matches!(e, Expr::Reference(text) if text == new_name) {
+            continue;
+        }
+        let Some(scope) = scopes.scope_for_expr(expr) else {
+            continue;
+        };
+        for defs in scopes
+            .ancestors(scope)
+            .filter_map(|data| data.as_definitions())
+        {
+            if defs.contains_key(new_name) {
+                break;
+            }
+            if defs.get(old_name) == Some(&name) {
+                return Err(format!(
+                    "Existing references to `{new_name}` would be shadowed by the renamed one"
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
 fn find_name(
     db: &dyn DefDatabase,
     FilePos { file_id, pos }: FilePos,
-) -> Option<(TextRange, NameId)> {
+) -> RenameResult<(TextRange, NameId)> {
+    let not_found = || "No references found".to_owned();
     let parse = db.parse(file_id);
-    let tok = best_token_at_offset(&parse.syntax_node(), pos)?;
-    let mut node = tok.parent_ancestors().find_map(|node| {
-        match_ast! {
-            match node {
-                ast::Ref(n) => Some(n.syntax().clone()),
-                ast::Name(n) => Some(n.syntax().clone()),
-                ast::String(n) => Some(n.syntax().clone()),
-                ast::Dynamic(n) => Some(n.syntax().clone()),
-                _ => None,
+    let tok = best_token_at_offset(&parse.syntax_node(), pos).ok_or_else(not_found)?;
+    if tok.kind().is_keyword() {
+        return Err("Cannot rename a keyword".into());
+    }
+    let mut node = tok
+        .parent_ancestors()
+        .find_map(|node| {
+            match_ast! {
+                match node {
+                    ast::Ref(n) => Some(n.syntax().clone()),
+                    ast::Name(n) => Some(n.syntax().clone()),
+                    ast::String(n) => Some(n.syntax().clone()),
+                    ast::Dynamic(n) => Some(n.syntax().clone()),
+                    _ => None,
+                }
             }
-        }
-    })?;
+        })
+        .ok_or_else(not_found)?;
 
     // Try to find the outermost Attr.
     // In case of `{ ${("foo")} = 1; }`
@@ -185,11 +307,11 @@ fn find_name(
         && matches!(node.parent(), Some(p) if p.kind() == SyntaxKind::PAREN)
     {
         loop {
-            node = node.parent()?;
+            node = node.parent().ok_or_else(not_found)?;
             match node.kind() {
                 SyntaxKind::DYNAMIC => break,
                 SyntaxKind::PAREN => {}
-                _ => return None,
+                _ => return Err(not_found()),
             }
         }
     }
@@ -197,17 +319,22 @@ fn find_name(
 
     let source_map = db.source_map(file_id);
     if let Some(name) = source_map.name_for_node(ptr) {
-        return Some((ptr.text_range(), name));
+        return Ok((ptr.text_range(), name));
     }
 
     if let Some(expr) = source_map.expr_for_node(ptr) {
         let nameres = db.name_resolution(file_id);
-        if let Some(ResolveResult::Definition(name)) = nameres.get(expr) {
-            return Some((ptr.text_range(), *name));
+        match nameres.get(expr) {
+            Some(ResolveResult::Definition(name)) => return Ok((ptr.text_range(), *name)),
+            Some(ResolveResult::Builtin(_)) => return Err("Cannot rename a builtin".into()),
+            Some(ResolveResult::WithExprs(_)) => {
+                return Err("Cannot rename a name from `with`".into())
+            }
+            None => {}
         }
     }
 
-    None
+    Err(not_found())
 }
 
 #[cfg(test)]
@@ -261,6 +388,19 @@ mod tests {
         check_prepare("{ a.$0b.c = 1; }", expect!["{ a.<b>.c = 1; }"]);
     }
 
+    #[test]
+    fn prepare_invalid() {
+        check_prepare("$0let a = 1; in a", expect!["Cannot rename a keyword"]);
+        check_prepare("{ a = $0rec { }; }", expect!["Cannot rename a keyword"]);
+        check_prepare("{ a = $0\"a\"; }", expect!["No references found"]);
+        check_prepare("{ a = $042; }", expect!["No references found"]);
+        check_prepare(
+            "with { a = 1; }; $0a",
+            expect!["Cannot rename a name from `with`"],
+        );
+        check_prepare("$0toString 1", expect!["Cannot rename a builtin"]);
+    }
+
     #[test]
     fn prepare_string() {
         check_prepare(
@@ -395,6 +535,39 @@ mod tests {
         );
     }
 
+    #[test]
+    fn rename_lambda_param_invalid() {
+        check(
+            "$0a: a",
+            "1",
+            expect![[r#"Invalid identifier for parameters: "1""#]],
+        );
+        check(
+            "{ $0a }: 1",
+            "a b",
+            expect![[r#"Invalid identifier for parameters: "a b""#]],
+        );
+        check(
+            "{ }@$0a: 1",
+            "let",
+            expect![[r#"Invalid identifier for parameters: "let""#]],
+        );
+    }
+
+    #[test]
+    fn rename_shadowed() {
+        check(
+            "let a = 1; in { x = a; y = let $0a = 2; in a; z = b: a; }",
+            "b",
+            expect!["let a = 1; in { x = a; y = let b = 2; in b; z = b: a; }"],
+        );
+        check(
+            "a: { x = a; y = a: $0a; }",
+            "b",
+            expect!["a: { x = a; y = b: b; }"],
+        );
+    }
+
     #[test]
     fn rename_merged() {
         check(
@@ -503,4 +676,83 @@ mod tests {
             expect![[r#"let b = 1; in { "1" = b; }"#]],
         );
     }
+
+    #[test]
+    fn rename_conflict_same_scope() {
+        check(
+            "let $0a = 1; b = 2; in a + b",
+            "b",
+            expect!["`b` is already defined in the same scope"],
+        );
+        check(
+            "{ $0a = 1; b = 2; }",
+            "b",
+            expect!["`b` is already defined in the same scope"],
+        );
+        check(
+            "{ $0a, b }: a",
+            "b",
+            expect!["`b` is already defined in the same scope"],
+        );
+        check(
+            "let a = 1; in { inherit $0a; b = 2; }",
+            "b",
+            expect!["`b` is already defined in the same scope"],
+        );
+        check(
+            "let $0a = 1; in rec { inherit a; b = 2; }",
+            "b",
+            expect!["`b` is already defined in the same scope"],
+        );
+        // The reference in a plain attrset is resolved outside.
+        check(
+            "let $0a = 1; in { inherit a; b = 2; }",
+            "b",
+            expect!["let b = 1; in { a = b; b = 2; }"],
+        );
+    }
+
+    #[test]
+    fn rename_conflict_shadowed() {
+        check(
+            "let $0a = 1; in let b = 2; in a + b",
+            "b",
+            expect!["Some references would be shadowed by another `b`"],
+        );
+        check(
+            "let $0a = 1; in b: a",
+            "b",
+            expect!["Some references would be shadowed by another `b`"],
+        );
+    }
+
+    #[test]
+    fn rename_conflict_capture() {
+        check(
+            "let b = 1; in let $0a = 2; in a + b",
+            "b",
+            expect!["Existing references to `b` would be shadowed by the renamed one"],
+        );
+        check(
+            "let $0a = 1; in toString a",
+            "toString",
+            expect!["Existing references to `toString` would be shadowed by the renamed one"],
+        );
+        // Unrelated references outside the scope are fine.
+        check(
+            "let b = 1; in { x = let $0a = 2; in a; y = b; }",
+            "b",
+            expect!["let b = 1; in { x = let b = 2; in b; y = b; }"],
+        );
+    }
+
+    #[test]
+    fn rename_to_keyword() {
+        check("{ $0a = 1; }", "rec", expect![[r#"{ "rec" = 1; }"#]]);
+        check(
+            "let $0a = 1; in a",
+            "rec",
+            expect!["Cannot rename to a string literal while it is referenced"],
+        );
+    }
 }
@@ -0,0 +1,151 @@
+use crate::def::{resolve_path_file, AstPtr, Expr, Literal, PathAnchor};
+use crate::{DefDatabase, FileId, FilePos, VfsPath};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedImport {
+    /// The path used for imports. It is the loaded file if any, including `default.nix` of
+    /// directories.
+    pub path: VfsPath,
+    /// The loaded file of `path`, or `None` if it is outside of the workspace.
+    pub file: Option<FileId>,
+}
+
+/// Resolve the path literal under the cursor the same way cross-file analyses do.
+/// Returns an error message describing why it is unresolved.
+pub(crate) fn resolve_import(
+    db: &dyn DefDatabase,
+    FilePos { file_id, pos }: FilePos,
+) -> Result<ResolvedImport, String> {
+    let module = db.module(file_id);
+    let source_map = db.source_map(file_id);
+    let parse = db.parse(file_id);
+
+    let path = parse
+        .syntax_node()
+        .token_at_offset(pos)
+        .right_biased()
+        .and_then(|tok| tok.parent())
+        .and_then(|node| source_map.expr_for_node(AstPtr::new(&node)))
+        .and_then(|expr| match &module[expr] {
+            &Expr::Literal(Literal::Path(path)) => Some(path),
+            _ => None,
+        })
+        .ok_or_else(|| "No path literal at the cursor".to_owned())?;
+
+    let Some(vpath) = path.resolve(db) else {
+        return Err(match path.data(db).anchor() {
+            PathAnchor::Relative(_) => "Relative paths in virtual files cannot be resolved".into(),
+            PathAnchor::Home => "Paths relative to `~` are not supported".into(),
+            PathAnchor::Search(name) => format!("`<{name}>` is not found in the search path"),
+            PathAnchor::Absolute => "Invalid absolute path".into(),
+        });
+    };
+    Ok(match resolve_path_file(db, file_id, path) {
+        Some(file) => {
+            let sid = db.file_source_root(file);
+            ResolvedImport {
+                path: db.source_root(sid).path_for_file(file).clone(),
+                file: Some(file),
+            }
+        }
+        None => ResolvedImport {
+            path: vpath,
+            file: None,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::TestDB;
+    use crate::{SearchPath, SearchPathEntry, SourceDatabase, VfsPath};
+    use expect_test::{expect, Expect};
+    use std::sync::Arc;
+
+    #[track_caller]
+    fn check(fixture: &str, expect: Expect) {
+        let (mut db, f) = TestDB::from_fixture(fixture).unwrap();
+        let entries = [("nixpkgs", "/nixpkgs"), ("", "/channels")]
+            .into_iter()
+            .map(|(prefix, path)| SearchPathEntry {
+                prefix: prefix.into(),
+                path: VfsPath::new(path),
+            })
+            .collect();
+        db.set_search_path(Arc::new(SearchPath { entries }));
+        let got = match super::resolve_import(&db, f[0]) {
+            Ok(ret) => format!(
+                "{}{}",
+                ret.path.display(),
+                if ret.file.is_some() {
+                    ""
+                } else {
+                    " (not loaded)"
+                },
+            ),
+            Err(msg) => format!("error: {msg}"),
+        };
+        expect.assert_eq(&got);
+    }
+
+    #[test]
+    fn relative() {
+        check(
+            "
+#- /dir/default.nix
+import $0./../lib.nix
+#- /lib.nix
+{ }
+            ",
+            expect!["/lib.nix"],
+        );
+        check(
+            "
+#- /default.nix
+import ./$0dir
+#- /dir/default.nix
+{ }
+            ",
+            expect!["/dir/default.nix"],
+        );
+        check("import $0./foo.nix", expect!["/foo.nix (not loaded)"]);
+    }
+
+    #[test]
+    fn absolute() {
+        check("import $0/a/../b.nix", expect!["/b.nix (not loaded)"]);
+    }
+
+    #[test]
+    fn search_path() {
+        check(
+            "import $0<nixpkgs/lib>",
+            expect!["/nixpkgs/lib (not loaded)"],
+        );
+        check(
+            "
+#- /default.nix
+import <foo/$0bar.nix>
+#- /channels/foo/bar.nix
+{ }
+            ",
+            expect!["/channels/foo/bar.nix"],
+        );
+        check(
+            "import $0<foo>",
+            expect!["error: `<foo>` is not found in the search path"],
+        );
+    }
+
+    #[test]
+    fn not_path() {
+        check(
+            "import $0foo",
+            expect!["error: No path literal at the cursor"],
+        );
+        check(
+            "import ~/foo$0.nix",
+            expect!["error: Paths relative to `~` are not supported"],
+        );
+    }
+}
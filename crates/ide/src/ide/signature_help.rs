@@ -0,0 +1,470 @@
+use super::MAX_RESOLVE_DEPTH;
+use crate::def::{resolve_import_file, AstPtr, BindingValue, Expr, ExprId, Literal, ResolveResult};
+use crate::{DefDatabase, FileId, FilePos};
+use std::ops::Range;
+use syntax::ast::{self, AstNode};
+use syntax::semantic::AttrKind;
+use syntax::{best_token_at_offset, NodeOrToken, SyntaxKind, SyntaxNode, TextRange, TextSize};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignatureInfo {
+    /// The rendered parameters of curried lambdas, eg. `a: { b, c ? …, ... }: …`.
+    pub label: String,
+    /// Labels of parameters, each of which is a substring of `label`.
+    /// Each field of a pattern is a separate parameter.
+    pub parameters: Vec<String>,
+    /// The index into `parameters` of the one being supplied.
+    pub active_parameter: Option<usize>,
+}
+
+/// Signature of the lambda being called when the cursor is inside, or just after, an argument.
+pub(crate) fn signature_help(
+    db: &dyn DefDatabase,
+    FilePos { file_id, pos }: FilePos,
+) -> Option<SignatureInfo> {
+    let parse = db.parse(file_id);
+    let root = parse.syntax_node();
+
+    // The innermost call wins, so typing `f (g $0` or `f { a = g $0` is about `g`.
+    if let Some((call, arg_idx)) = next_argument(&root, pos) {
+        if let Some(info) = signature_for_call(db, file_id, pos, &call, arg_idx, None) {
+            return Some(info);
+        }
+    }
+
+    let elem = root.covering_element(TextRange::empty(pos));
+    let mut node = match elem {
+        NodeOrToken::Node(n) => n,
+        NodeOrToken::Token(t) => t.parent()?,
+    };
+    loop {
+        if let Some(apply) = ast::Apply::cast(node.clone()) {
+            if apply.function()?.syntax().text_range().end() < pos {
+                let arg = apply.argument()?;
+                let (call, args) = flatten_call(apply.syntax().clone())?;
+                let arg_idx = args.iter().position(|a| a == arg.syntax())?;
+                let ret = signature_for_call(db, file_id, pos, &call, arg_idx, Some(arg));
+                if ret.is_some() {
+                    return ret;
+                }
+            }
+        }
+        node = node.parent()?;
+    }
+}
+
+/// If the cursor is separated by whitespace after a call or a callee, eg. `f a $0`,
+/// returns the outermost call node and the index of the next argument.
+fn next_argument(root: &SyntaxNode, pos: TextSize) -> Option<(SyntaxNode, usize)> {
+    let tok = root
+        .token_at_offset(pos)
+        .left_biased()
+        .filter(|tok| tok.kind().is_trivia())?
+        .prev_token()
+        .filter(|tok| !tok.kind().is_trivia())?;
+    let end = tok.text_range().end();
+    let mut node = tok.parent()?;
+    if ast::Expr::cast(node.clone()).is_none() || content_end(&node) != Some(end) {
+        return None;
+    }
+    while let Some(parent) = node
+        .parent()
+        .filter(|p| p.kind() == SyntaxKind::APPLY && content_end(p) == Some(end))
+    {
+        node = parent;
+    }
+    // List elements are not calls, eg. `[ f $0 ]`.
+    if node.parent()?.kind() == SyntaxKind::LIST {
+        return None;
+    }
+    // Not followed by another argument, eg. `f $0a`.
+    let (call, args) = flatten_call(node)?;
+    (content_end(&call) == Some(end)).then_some((call, args.len()))
+}
+
+/// The end of the last non-trivia token, since incomplete calls may contain trailing spaces.
+fn content_end(node: &SyntaxNode) -> Option<TextSize> {
+    let mut tok = node.last_token()?;
+    while tok.kind().is_trivia() {
+        tok = tok.prev_token()?;
+    }
+    Some(tok.text_range().end())
+}
+
+/// Climb up to the outermost `Apply` whose function is `node`,
+/// returning it with its arguments in order.
+fn flatten_call(mut node: SyntaxNode) -> Option<(SyntaxNode, Vec<SyntaxNode>)> {
+    while let Some(parent) = node.parent().and_then(ast::Apply::cast) {
+        if parent.function()?.syntax() != &node {
+            break;
+        }
+        node = parent.syntax().clone();
+    }
+    let top = node.clone();
+    let mut args = Vec::new();
+    while let Some(apply) = ast::Apply::cast(node.clone()) {
+        args.push(apply.argument()?.syntax().clone());
+        node = apply.function()?.syntax().clone();
+    }
+    args.reverse();
+    Some((top, args))
+}
+
+fn signature_for_call(
+    db: &dyn DefDatabase,
+    file_id: FileId,
+    pos: TextSize,
+    call: &SyntaxNode,
+    arg_idx: usize,
+    arg: Option<ast::Expr>,
+) -> Option<SignatureInfo> {
+    let mut callee = call.clone();
+    while let Some(apply) = ast::Apply::cast(callee.clone()) {
+        callee = apply.function()?.syntax().clone();
+    }
+    while let Some(paren) = ast::Paren::cast(callee.clone()) {
+        callee = paren.expr()?.syntax().clone();
+    }
+    let source_map = db.source_map(file_id);
+    let expr = source_map.expr_for_node(AstPtr::new(&callee))?;
+    let (file_id, lambda) = resolve_lambda(db, file_id, expr)?;
+
+    let module = db.module(file_id);
+    let mut label = String::new();
+    let mut parameters = Vec::new();
+    // The range of `parameters` taken by each curried lambda.
+    let mut lambdas = Vec::<(Range<usize>, bool)>::new();
+    let mut expr = lambda;
+    while let Expr::Lambda(name, pat, body) = &module[expr] {
+        let start = parameters.len();
+        let name = name.map(|name| &*module[name].text);
+        match pat {
+            None => {
+                let name = name.unwrap_or("_");
+                label += name;
+                parameters.push(name.to_owned());
+            }
+            Some(pat) => {
+                if let Some(name) = name {
+                    label += name;
+                    label += "@";
+                }
+                let mut fields = pat
+                    .fields
+                    .iter()
+                    .filter_map(|&(name, default)| {
+                        let name = &module[name?].text;
+                        Some(match default {
+                            Some(_) => format!("{name} ? …"),
+                            None => name.to_string(),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                let mut pat_label = fields.join(", ");
+                if pat.ellipsis {
+                    if !pat_label.is_empty() {
+                        pat_label += ", ";
+                    }
+                    pat_label += "...";
+                }
+                let pat_label = if pat_label.is_empty() {
+                    "{ }".to_owned()
+                } else {
+                    format!("{{ {pat_label} }}")
+                };
+                if fields.is_empty() {
+                    fields.push(pat_label.clone());
+                }
+                label += &pat_label;
+                parameters.extend(fields);
+            }
+        }
+        label += ": ";
+        lambdas.push((start..parameters.len(), pat.is_some()));
+        expr = *body;
+    }
+    label += "…";
+
+    let active_parameter = lambdas.get(arg_idx).map(|(range, is_pat)| {
+        let field = arg
+            .filter(|_| *is_pat)
+            .and_then(|arg| attr_under_cursor(arg, pos))
+            .and_then(|name| {
+                range
+                    .clone()
+                    .find(|&i| parameters[i] == name || parameters[i] == format!("{name} ? …"))
+            });
+        field.unwrap_or(range.start)
+    });
+
+    Some(SignatureInfo {
+        label,
+        parameters,
+        active_parameter,
+    })
+}
+
+/// Follow references, imports and static selects to a lambda.
+fn resolve_lambda(db: &dyn DefDatabase, file_id: FileId, expr: ExprId) -> Option<(FileId, ExprId)> {
+    let mut fuel = MAX_RESOLVE_DEPTH;
+    let (file_id, expr) = resolve_value(db, file_id, expr, &mut fuel)?;
+    matches!(db.module(file_id)[expr], Expr::Lambda(..)).then_some((file_id, expr))
+}
+
+/// Follow references, imports and static selects like `lib.mkOption` to the value expression.
+/// Each step takes one from `fuel`.
+fn resolve_value(
+    db: &dyn DefDatabase,
+    mut file_id: FileId,
+    mut expr: ExprId,
+    fuel: &mut usize,
+) -> Option<(FileId, ExprId)> {
+    loop {
+        *fuel = fuel.checked_sub(1)?;
+        let module = db.module(file_id);
+        match &module[expr] {
+            Expr::Reference(_) => {
+                let nameres = db.name_resolution(file_id);
+                let &ResolveResult::Definition(name) = nameres.get(expr)? else {
+                    return None;
+                };
+                expr = module.binding_value(name)?;
+            }
+            Expr::Apply(..) => {
+                file_id = resolve_import_file(db, file_id, expr)?;
+                expr = db.module(file_id).entry_expr();
+            }
+            Expr::Select(set, path, None) => {
+                (file_id, expr) = resolve_value(db, file_id, *set, fuel)?;
+                for (i, &attr) in path.iter().enumerate() {
+                    // Keys are in the selecting file, but the set may be imported.
+                    let Expr::Literal(Literal::String(key)) = &module[attr] else {
+                        return None;
+                    };
+                    if i != 0 {
+                        (file_id, expr) = resolve_value(db, file_id, expr, fuel)?;
+                    }
+                    let set_module = db.module(file_id);
+                    let (Expr::Attrset(bindings) | Expr::RecAttrset(bindings)) = &set_module[expr]
+                    else {
+                        return None;
+                    };
+                    expr = bindings
+                        .statics
+                        .iter()
+                        .find_map(|&(name, value)| match value {
+                            BindingValue::Expr(e) | BindingValue::Inherit(e)
+                                if set_module[name].text == *key =>
+                            {
+                                Some(e)
+                            }
+                            _ => None,
+                        })?;
+                }
+            }
+            _ => return Some((file_id, expr)),
+        }
+    }
+}
+
+/// The static name of the binding being written in an attrset argument, eg. `b` in `f { b = $0; }`.
+fn attr_under_cursor(arg: ast::Expr, pos: TextSize) -> Option<String> {
+    let mut arg = arg;
+    while let ast::Expr::Paren(paren) = arg {
+        arg = paren.expr()?;
+    }
+    let ast::Expr::AttrSet(set) = arg else {
+        return None;
+    };
+    let tok = best_token_at_offset(set.syntax(), pos)?;
+    let entry = tok
+        .parent_ancestors()
+        .find(|node| node.parent().as_ref() == Some(set.syntax()))?;
+    let attr = match ast::AttrpathValue::cast(entry) {
+        Some(path_value) => path_value.attrpath()?.attrs().next()?,
+        None => return None,
+    };
+    match AttrKind::of(attr) {
+        AttrKind::Static(Some(name)) => Some(name.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::TestDB;
+    use expect_test::{expect, Expect};
+
+    #[track_caller]
+    fn check(fixture: &str, expect: Expect) {
+        let (db, f) = TestDB::from_fixture(fixture).unwrap();
+        let got = match super::signature_help(&db, f[0]) {
+            None => String::new(),
+            Some(info) => {
+                let params = info
+                    .parameters
+                    .iter()
+                    .enumerate()
+                    .map(|(i, param)| {
+                        if Some(i) == info.active_parameter {
+                            format!("<{param}>")
+                        } else {
+                            param.clone()
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" | ");
+                format!("{}\n{params}\n", info.label)
+            }
+        };
+        expect.assert_eq(&got);
+    }
+
+    #[test]
+    fn pattern() {
+        check(
+            "let f = { a, b ? 1, ... }: a; in f { $0 }",
+            expect![[r#"
+                { a, b ? …, ... }: …
+                <a> | b ? …
+            "#]],
+        );
+        check(
+            "let f = { a, b ? 1 }: a; in f { a = 1; b = $0; }",
+            expect![[r#"
+                { a, b ? … }: …
+                a | <b ? …>
+            "#]],
+        );
+        check(
+            "let f = args@{ }: args; in f { $0 }",
+            expect![[r#"
+                args@{ }: …
+                <{ }>
+            "#]],
+        );
+    }
+
+    #[test]
+    fn curried() {
+        check(
+            "let f = a: { b }: c: a; in f 1 $0",
+            expect![[r#"
+                a: { b }: c: …
+                a | <b> | c
+            "#]],
+        );
+        check(
+            "let f = a: { b }: c: a; in f 1 { } $0",
+            expect![[r#"
+                a: { b }: c: …
+                a | b | <c>
+            "#]],
+        );
+        check(
+            "let f = a: b: a; in f $0",
+            expect![[r#"
+                a: b: …
+                <a> | b
+            "#]],
+        );
+        check(
+            "let f = a: b: a; in f 1$0 2",
+            expect![[r#"
+                a: b: …
+                <a> | b
+            "#]],
+        );
+        check(
+            "let f = a: b: a; in f 1 2 $0",
+            expect![[r#"
+                a: b: …
+                a | b
+            "#]],
+        );
+    }
+
+    #[test]
+    fn innermost_call() {
+        check(
+            "let f = a: a; g = { x }: x; in f (g { $0 })",
+            expect![[r#"
+                { x }: …
+                <x>
+            "#]],
+        );
+        check(
+            "let f = a: b: a; g = x: x; in f (g 1) $0",
+            expect![[r#"
+                a: b: …
+                a | <b>
+            "#]],
+        );
+        check(
+            "let f = a: a; g = 1; in f (g $0)",
+            expect![[r#"
+                a: …
+                <a>
+            "#]],
+        );
+    }
+
+    #[test]
+    fn through_references_and_imports() {
+        check(
+            "let f = a: a; g = f; in g $0",
+            expect![[r#"
+                a: …
+                <a>
+            "#]],
+        );
+        check(
+            "
+#- /default.nix
+let f = import ./f.nix; in f { $0 }
+#- /f.nix
+{ pkgs, lib ? pkgs.lib }: { }
+            ",
+            expect![[r#"
+                { pkgs, lib ? … }: …
+                <pkgs> | lib ? …
+            "#]],
+        );
+    }
+
+    #[test]
+    fn through_selects() {
+        check(
+            "let lib = { mkOption = { type ? null, default ? null }: { }; }; in lib.mkOption { $0 }",
+            expect![[r#"
+                { type ? …, default ? … }: …
+                <type ? …> | default ? …
+            "#]],
+        );
+        check(
+            "
+#- /default.nix
+let lib = import ./lib.nix; in lib.options.mkOption { $0 }
+#- /lib.nix
+rec {
+  options = { inherit mkOption; };
+  mkOption = { type }: type;
+}
+            ",
+            expect![[r#"
+                { type }: …
+                <type>
+            "#]],
+        );
+    }
+
+    #[test]
+    fn unresolved() {
+        check("f $0", expect![""]);
+        check("let f = 1; in f $0", expect![""]);
+        check("let f = a: a; in [ f $0 ]", expect![""]);
+        check("let f = f; in f $0", expect![""]);
+        check("let s = { }; in s.f $0", expect![""]);
+        check("let s = { s = s; }; in s.s.s.s.s.s.s.s.s.f $0", expect![""]);
+    }
+}
@@ -3,19 +3,30 @@ use crate::{DefDatabase, FileId, Module, ModuleSourceMap, NameKind};
 use smol_str::SmolStr;
 use syntax::ast::{self, AstNode};
 use syntax::rowan::WalkEvent;
+use syntax::semantic::AttrKind;
 use syntax::{SyntaxNode, TextRange};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SymbolTree {
     pub name: SmolStr,
     // TODO: Avoid saving `NameId` in the public API.
-    name_id: NameId,
+    /// `None` for dynamic attributes like `${foo}`, whose `name` is only a placeholder.
+    name_id: Option<NameId>,
     pub full_range: TextRange,
     pub focus_range: TextRange,
     pub kind: NameKind,
+    /// Whether the bound value is syntactically a lambda, like `f = x: x;`.
+    pub is_function: bool,
     pub children: Vec<SymbolTree>,
 }
 
+impl SymbolTree {
+    /// Whether this is a placeholder for a dynamic attribute.
+    pub fn is_dynamic(&self) -> bool {
+        self.name_id.is_none()
+    }
+}
+
 pub(crate) fn symbol_hierarchy(db: &dyn DefDatabase, file: FileId) -> Vec<SymbolTree> {
     let parse = db.parse(file);
     let module = db.module(file);
@@ -26,10 +37,33 @@ pub(crate) fn symbol_hierarchy(db: &dyn DefDatabase, file: FileId) -> Vec<Symbol
         source_map: &source_map,
         symbols: &mut symbols,
     };
+    // Parameters of the top-level lambda, like `{ pkgs, ... }: ...`.
+    if let Some(ast::Expr::Lambda(lam)) = parse.root().expr() {
+        collector.collect_params(&lam);
+    }
     collector.collect_node(&parse.syntax_node());
     symbols
 }
 
+/// Symbols nested under the attribute path `prefix`, like everything under `services.nginx`.
+/// Separated definitions of the same path, like `{ a.b = 1; c = 2; a.d = 3; }`, are all
+/// included. Returns an empty list if the path is not defined.
+pub(crate) fn symbols_under(
+    db: &dyn DefDatabase,
+    file: FileId,
+    prefix: &[String],
+) -> Vec<SymbolTree> {
+    let mut symbols = symbol_hierarchy(db, file);
+    for seg in prefix {
+        symbols = symbols
+            .into_iter()
+            .filter(|sym| !sym.is_dynamic() && sym.name == seg)
+            .flat_map(|sym| sym.children)
+            .collect();
+    }
+    symbols
+}
+
 #[derive(Debug)]
 struct Collector<'a, 'b> {
     module: &'a Module,
@@ -38,17 +72,58 @@ struct Collector<'a, 'b> {
 }
 
 impl Collector<'_, '_> {
-    fn push_symbol(&mut self, name_id: NameId, attr: &ast::Attr, full_range: TextRange) {
+    fn push_symbol(&mut self, name_id: NameId, focus: &SyntaxNode, full_range: TextRange) {
         self.symbols.push(SymbolTree {
             name: self.module[name_id].text.clone(),
-            name_id,
+            name_id: Some(name_id),
             full_range,
-            focus_range: attr.syntax().text_range(),
+            focus_range: focus.text_range(),
             kind: self.module[name_id].kind,
+            is_function: false,
+            children: Vec::new(),
+        });
+    }
+
+    /// Dynamic attributes are labeled by their source text, or `${…}` if it spans lines.
+    fn push_dynamic_symbol(&mut self, focus: &SyntaxNode, full_range: TextRange) {
+        let text = focus.text().to_string();
+        let name = if text.contains('\n') {
+            "${…}".into()
+        } else {
+            text.into()
+        };
+        self.symbols.push(SymbolTree {
+            name,
+            name_id: None,
+            full_range,
+            focus_range: focus.text_range(),
+            kind: NameKind::PlainAttrset,
+            is_function: false,
             children: Vec::new(),
         });
     }
 
+    fn collect_params(&mut self, lam: &ast::Lambda) {
+        let Some(param) = lam.param() else {
+            return;
+        };
+        let names = param
+            .pat()
+            .into_iter()
+            .flat_map(|pat| pat.fields())
+            .filter_map(|field| Some((field.name()?, field.syntax().text_range())))
+            .chain(param.name().map(|name| {
+                let range = name.syntax().text_range();
+                (name, range)
+            }));
+        for (name, full_range) in names {
+            let ptr = AstPtr::new(name.syntax());
+            if let Some(name_id) = self.source_map.name_for_node(ptr) {
+                self.push_symbol(name_id, name.syntax(), full_range);
+            }
+        }
+    }
+
     // TODO: Rewrite this in non-recursive form?
     // Nested mutable borrowing is hard to impl without recursion yet.
     fn collect_node(&mut self, n: &SyntaxNode) {
@@ -70,7 +145,7 @@ impl Collector<'_, '_> {
                     for attr in i.attrs() {
                         let ptr = AstPtr::new(attr.syntax());
                         if let Some(name_id) = self.source_map.name_for_node(ptr) {
-                            self.push_symbol(name_id, &attr, i.syntax().text_range());
+                            self.push_symbol(name_id, attr.syntax(), i.syntax().text_range());
                         }
                     }
                     // Continue traversing the from-expr. Attrs should be skipped automatically.
@@ -99,15 +174,20 @@ impl Collector<'_, '_> {
             return;
         };
 
+        let binding_end_pos = binding.syntax().text_range().end();
+        let is_function = attrs.clone().next().is_none()
+            && matches!(
+                binding.value().and_then(ast::Expr::flatten_paren),
+                Some(ast::Expr::Lambda(_))
+            );
+
         let ptr = AstPtr::new(attr.syntax());
         if let Some(name_id) = self.source_map.name_for_node(ptr) {
-            let binding_end_pos = binding.syntax().text_range().end();
-
             // Merge adjacent bindings to the same tree node.
             // Eg. `{ a.b = 1; a.c = 2; }`
             //        ^-------a-------^
             let current_sym = if allow_merge_to_last
-                && self.symbols.last().map(|tree| tree.name_id) == Some(name_id)
+                && self.symbols.last().and_then(|tree| tree.name_id) == Some(name_id)
             {
                 let last_sym = self.symbols.last_mut().unwrap();
                 last_sym.full_range = last_sym.full_range.cover_offset(binding_end_pos);
@@ -117,9 +197,10 @@ impl Collector<'_, '_> {
                 //        ---      focus
                 //        ======== full
                 let full_range = attr.syntax().text_range().cover_offset(binding_end_pos);
-                self.push_symbol(name_id, &attr, full_range);
+                self.push_symbol(name_id, attr.syntax(), full_range);
                 self.symbols.last_mut().unwrap()
             };
+            current_sym.is_function = is_function;
 
             Collector {
                 module: self.module,
@@ -127,8 +208,23 @@ impl Collector<'_, '_> {
                 symbols: &mut current_sym.children,
             }
             .collect_path_value(binding, attrs, allow_merge_to_last);
+        } else if let AttrKind::Dynamic(_) = AttrKind::of(attr.clone()) {
+            // `{ ${foo}.bar = 1; }`
+            //    ------      focus
+            //    ------------ full
+            self.collect_node(attr.syntax());
+            let full_range = attr.syntax().text_range().cover_offset(binding_end_pos);
+            self.push_dynamic_symbol(attr.syntax(), full_range);
+            let current_sym = self.symbols.last_mut().unwrap();
+            current_sym.is_function = is_function;
+            Collector {
+                module: self.module,
+                source_map: self.source_map,
+                symbols: &mut current_sym.children,
+            }
+            .collect_path_value(binding, attrs, false);
         } else {
-            // Incomplete or dynamic attributes.
+            // Incomplete attributes.
             self.collect_node(attr.syntax());
             self.collect_path_value(binding, attrs, false);
         }
@@ -155,10 +251,11 @@ mod tests {
         for sym in syms {
             writeln!(
                 out,
-                "{:indent$}{}: {:?}",
+                "{:indent$}{}: {:?}{}",
                 "",
                 sym.name,
                 sym.kind,
+                if sym.is_function { " (function)" } else { "" },
                 indent = indent
             )
             .unwrap();
@@ -166,6 +263,20 @@ mod tests {
         }
     }
 
+    #[track_caller]
+    fn check_under(fixture: &str, prefix: &str, expect: Expect) {
+        let (db, file) = TestDB::single_file(fixture).unwrap();
+        let prefix = prefix
+            .split('.')
+            .filter(|seg| !seg.is_empty())
+            .map(|seg| seg.to_owned())
+            .collect::<Vec<_>>();
+        let syms = symbols_under(&db, file, &prefix);
+        let mut got = String::new();
+        fmt_symbols(0, &syms, &mut got);
+        expect.assert_eq(&got);
+    }
+
     #[test]
     fn let_in() {
         check(
@@ -194,6 +305,27 @@ mod tests {
         );
     }
 
+    #[test]
+    fn top_level_lambda_params() {
+        check(
+            "{ pkgs, lib ? pkgs.lib, ... }@args: { a = x: x; }",
+            expect![[r#"
+                pkgs: PatField
+                lib: PatField
+                args: Param
+                a: PlainAttrset (function)
+            "#]],
+        );
+        check(
+            "pkgs: let b = 1; in { a = { c }: c; }",
+            expect![[r#"
+                pkgs: Param
+                b: LetIn
+                a: PlainAttrset (function)
+            "#]],
+        );
+    }
+
     #[test]
     fn attrset_merge() {
         check(
@@ -221,4 +353,73 @@ mod tests {
             "#]],
         );
     }
+
+    #[test]
+    fn functions() {
+        check(
+            "let f = x: x; in { a.g = (x: y: x); b = f; c = { d = x: x; }; }",
+            expect![[r#"
+                f: LetIn (function)
+                a: PlainAttrset
+                    g: PlainAttrset (function)
+                b: PlainAttrset
+                c: PlainAttrset
+                    d: PlainAttrset (function)
+            "#]],
+        );
+    }
+
+    #[test]
+    fn dynamic_attrs() {
+        check(
+            r#"{ ${foo}.a = 1; "${bar}" = x: x; ${"b"}.c = 2; ${
+              baz
+            } = { d = 3; }; }"#,
+            expect![[r#"
+                ${foo}: PlainAttrset
+                    a: PlainAttrset
+                "${bar}": PlainAttrset (function)
+                b: PlainAttrset
+                    c: PlainAttrset
+                ${…}: PlainAttrset
+                    d: PlainAttrset
+            "#]],
+        );
+    }
+
+    #[test]
+    fn under_prefix() {
+        let src = "
+{ config, ... }: {
+    services.nginx = {
+        enable = true;
+        virtualHosts.foo = { root = ./.; };
+    };
+    networking.hostName = \"x\";
+    services.nginx.user = \"nginx\";
+    services.${config}.enable = true;
+}
+        ";
+        check_under(
+            src,
+            "services.nginx",
+            expect![[r#"
+                enable: PlainAttrset
+                virtualHosts: PlainAttrset
+                    foo: PlainAttrset
+                        root: PlainAttrset
+                user: PlainAttrset
+            "#]],
+        );
+        check_under(
+            src,
+            "services.nginx.virtualHosts.foo",
+            expect![[r#"
+                root: PlainAttrset
+            "#]],
+        );
+        check_under(src, "services.nginx.enable", expect![""]);
+        check_under(src, "services.apache", expect![""]);
+        check_under(src, "networking.domain", expect![""]);
+    }
 }
@@ -4,6 +4,7 @@ use crate::def::{AstPtr, Expr, Literal, NameKind, ResolveResult};
 use crate::{DefDatabase, FileId};
 use builtin::{BuiltinKind, ALL_BUILTINS};
 use syntax::ast::AstNode;
+use syntax::semantic::is_doc_comment;
 use syntax::{ast, match_ast, SyntaxKind, SyntaxToken, TextRange, T};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -21,6 +22,7 @@ pub enum HlTag {
     AttrField(HlAttrField),
     Builtin(BuiltinKind),
     Comment,
+    DocComment,
     BoolLiteral,
     FloatLiteral,
     IntLiteral,
@@ -66,6 +68,8 @@ pub enum HlPunct {
     Colon,
     At,
     Ellipsis,
+    /// `${` and `}` around an interpolation or a dynamic attribute.
+    Interpolation,
 }
 
 pub(crate) fn highlight(
@@ -146,6 +150,7 @@ pub(crate) fn highlight(
     let token_tag = |tok: &SyntaxToken| -> Option<HlTag> {
         Some(match tok.kind() {
             SyntaxKind::SPACE => return None,
+            SyntaxKind::COMMENT if is_doc_comment(tok.text()) => HlTag::DocComment,
             SyntaxKind::COMMENT => HlTag::Comment,
             SyntaxKind::PATH | SyntaxKind::SEARCH_PATH => HlTag::Path,
             SyntaxKind::FLOAT => HlTag::FloatLiteral,
@@ -161,7 +166,11 @@ pub(crate) fn highlight(
             }
             T![+] | T![-] | T![*] | T![/] => HlTag::Operator(HlOperator::Arithmetic),
             T![++] | T!["//"] => HlTag::Operator(HlOperator::Aggregation),
-            T!['{'] | T!['}'] | T!["${"] => HlTag::Punct(HlPunct::Brace),
+            T!["${"] => HlTag::Punct(HlPunct::Interpolation),
+            T!['}'] if tok.parent().map(|n| n.kind()) == Some(SyntaxKind::DYNAMIC) => {
+                HlTag::Punct(HlPunct::Interpolation)
+            }
+            T!['{'] | T!['}'] => HlTag::Punct(HlPunct::Brace),
             T!['['] | T![']'] => HlTag::Punct(HlPunct::Bracket),
             T!['('] | T![')'] => HlTag::Punct(HlPunct::Paren),
             T![.] => HlTag::Punct(HlPunct::Dot),
@@ -250,6 +259,8 @@ mod tests {
     fn comment() {
         check("1/*$0a*/", expect!["Comment"]);
         check("1#$0a", expect!["Comment"]);
+        check("1/**$0 a */", expect!["DocComment"]);
+        check("1/*$0*/", expect!["Comment"]);
     }
 
     #[test]
@@ -264,6 +275,30 @@ mod tests {
         check("$0a:b", expect!["StringLiteral"]);
         check(r#"$0"string""#, expect!["StringLiteral"]);
         check(r#""st$0\nring""#, expect!["StringEscape"]);
+        check("'' a$0''$ ''", expect!["StringEscape"]);
+        check("'' a$0''' ''", expect!["StringEscape"]);
+    }
+
+    #[test]
+    fn interpolation() {
+        check(r#""a-$0${b}/c""#, expect!["Punct(Interpolation)"]);
+        check(r#""a-${b$0}/c""#, expect!["Punct(Interpolation)"]);
+        check(r#"let b = 1; in "a-${$0b}/c""#, expect!["NameRef(LetIn)"]);
+        check(r#""a-${{ b = 1; }.$0b}""#, expect!["AttrField(Select)"]);
+        check(r#""a-${{ b = 1; $0}.b}""#, expect!["Punct(Brace)"]);
+        check("./a/$0${b}", expect!["Punct(Interpolation)"]);
+        check("{ $0${a} = 1; }", expect!["Punct(Interpolation)"]);
+    }
+
+    #[test]
+    fn dynamic_attr() {
+        check("a: { ${$0a} = 1; }", expect!["NameRef(Param)"]);
+        check("a: { ${a$0} = 1; }", expect!["Punct(Interpolation)"]);
+        check("a: { ${a.$0b} = 1; }", expect!["AttrField(Select)"]);
+        check("a: { ${f $0a} = 1; }", expect!["NameRef(Param)"]);
+        check("a: { b.${$0a}.c = 1; }", expect!["NameRef(Param)"]);
+        check("a: { }.${$0a}", expect!["NameRef(Param)"]);
+        check("a: { } ? ${$0a}", expect!["NameRef(Param)"]);
     }
 
     #[test]
@@ -0,0 +1,396 @@
+use super::symbol_hierarchy::{symbol_hierarchy, SymbolTree};
+use crate::{FileId, FileRange, IdeDatabase, NameKind};
+use smol_str::SmolStr;
+use std::collections::{BTreeSet, HashSet};
+use std::sync::Arc;
+use syntax::TextRange;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolLocation {
+    pub name: SmolStr,
+    pub kind: NameKind,
+    pub is_function: bool,
+    /// The name of the enclosing symbol, eg. `foo` for `bar` in `{ foo.bar = 1; }`.
+    pub container_name: Option<SmolStr>,
+    /// The range of the name.
+    pub file_range: FileRange,
+}
+
+/// A searchable name of a file, flattened from the symbol hierarchy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexedSymbol {
+    name: SmolStr,
+    kind: NameKind,
+    is_function: bool,
+    /// Names of enclosing symbols, from the outermost one.
+    path: Box<[SmolStr]>,
+    focus_range: TextRange,
+}
+
+pub(crate) fn symbol_index_query(db: &dyn IdeDatabase, file: FileId) -> Arc<[IndexedSymbol]> {
+    fn collect(syms: &[SymbolTree], path: &mut Vec<SmolStr>, out: &mut Vec<IndexedSymbol>) {
+        for sym in syms {
+            // Placeholders of dynamic attributes are not names.
+            if !sym.is_dynamic() {
+                out.push(IndexedSymbol {
+                    name: sym.name.clone(),
+                    kind: sym.kind,
+                    is_function: sym.is_function,
+                    path: path.as_slice().into(),
+                    focus_range: sym.focus_range,
+                });
+            }
+            path.push(sym.name.clone());
+            collect(&sym.children, path, out);
+            path.pop();
+        }
+    }
+
+    let mut out = Vec::new();
+    collect(&symbol_hierarchy(db, file), &mut Vec::new(), &mut out);
+    out.into()
+}
+
+/// Names defined right under the attribute path `parent` in all files of all source roots,
+/// like `git` for `users.bob.programs`. Paths differing from `parent` only in one segment
+/// other than the first and the last are also siblings, eg. `users.alice.programs.git`.
+/// The last segment must match, since keys of eg. `services.nginx` and `services.openssh`
+/// are unrelated. Names already defined under exactly `parent` in `current` are excluded.
+pub(crate) fn sibling_keys(
+    db: &dyn IdeDatabase,
+    current: FileId,
+    parent: &[SmolStr],
+) -> BTreeSet<SmolStr> {
+    let is_sibling = |path: &[SmolStr]| {
+        path.len() == parent.len()
+            && path.first() == parent.first()
+            && path.last() == parent.last()
+            && path
+                .iter()
+                .zip(parent)
+                .filter(|(lhs, rhs)| lhs != rhs)
+                .count()
+                <= 1
+    };
+    let defined = db
+        .symbol_index(current)
+        .iter()
+        .filter(|sym| *sym.path == *parent)
+        .map(|sym| sym.name.clone())
+        .collect::<HashSet<_>>();
+    let mut keys = BTreeSet::new();
+    for &sid in db.source_root_ids().iter() {
+        let source_root = db.source_root(sid);
+        for (file, _) in source_root.files() {
+            db.unwind_if_cancelled();
+            keys.extend(
+                db.symbol_index(file)
+                    .iter()
+                    .filter(|sym| is_sibling(&sym.path) && !defined.contains(&sym.name))
+                    .map(|sym| sym.name.clone()),
+            );
+        }
+    }
+    keys
+}
+
+/// Fuzzy search names defined in all files of all source roots.
+///
+/// The query supports a small syntax:
+/// - `foo.bar` matches `bar` whose enclosing symbols end with `foo`.
+/// - A leading `#` anchors the path at the top level, eg. `#foo` only matches top-level `foo`.
+/// - A trailing `*` only accepts exact or prefix matches of the last segment.
+///
+/// Results are sorted by match quality: exact, prefix, substring, then subsequence matches,
+/// all case-insensitive. Ties prefer shallower symbols and more specific files than
+/// `default.nix`.
+pub(crate) fn workspace_symbols(
+    db: &dyn IdeDatabase,
+    query: &str,
+    limit: usize,
+) -> Vec<SymbolLocation> {
+    let query = Query::parse(query);
+    let mut matches = Vec::new();
+    for &sid in db.source_root_ids().iter() {
+        let source_root = db.source_root(sid);
+        for (file, path) in source_root.files() {
+            // Stop early if the query is outdated, eg. by typing more.
+            db.unwind_if_cancelled();
+
+            let is_default_nix = path
+                .as_path()
+                .and_then(|path| path.file_name())
+                .is_some_and(|name| name == "default.nix");
+            for sym in db.symbol_index(file).iter() {
+                let Some(score) = query.match_score(&sym.path, &sym.name) else {
+                    continue;
+                };
+                matches.push((
+                    (score, sym.path.len(), is_default_nix),
+                    SymbolLocation {
+                        name: sym.name.clone(),
+                        kind: sym.kind,
+                        is_function: sym.is_function,
+                        container_name: sym.path.last().cloned(),
+                        file_range: FileRange::new(file, sym.focus_range),
+                    },
+                ));
+            }
+        }
+    }
+    matches.sort_by(|(lhs_key, lhs), (rhs_key, rhs)| {
+        (lhs_key, lhs.name.len(), &lhs.name, lhs.file_range.file_id)
+            .cmp(&(rhs_key, rhs.name.len(), &rhs.name, rhs.file_range.file_id))
+            .then(
+                lhs.file_range
+                    .range
+                    .start()
+                    .cmp(&rhs.file_range.range.start()),
+            )
+    });
+    matches.truncate(limit);
+    matches.into_iter().map(|(_, sym)| sym).collect()
+}
+
+/// A parsed and lowercased query.
+#[derive(Debug)]
+struct Query {
+    /// Segments before the last `.`, matched against the trailing enclosing symbols.
+    containers: Vec<String>,
+    name: String,
+    top_level: bool,
+    prefix_only: bool,
+}
+
+impl Query {
+    fn parse(query: &str) -> Self {
+        let query = query.trim().to_lowercase();
+        let (top_level, query) = match query.strip_prefix('#') {
+            Some(rest) => (true, rest),
+            None => (false, &*query),
+        };
+        let (prefix_only, query) = match query.strip_suffix('*') {
+            Some(rest) => (true, rest),
+            None => (false, query),
+        };
+        let mut containers = query.split('.').map(str::to_owned).collect::<Vec<_>>();
+        let name = containers.pop().unwrap_or_default();
+        Self {
+            containers,
+            name,
+            top_level,
+            prefix_only,
+        }
+    }
+
+    /// Match a symbol with its enclosing symbols, from the outermost one.
+    fn match_score(&self, path: &[SmolStr], name: &str) -> Option<u8> {
+        if self.top_level && path.len() != self.containers.len() {
+            return None;
+        }
+        let ancestors = path.len().checked_sub(self.containers.len())?;
+        let mut score = match_score(name, &self.name).filter(|&s| !self.prefix_only || s <= 1)?;
+        for (seg, query) in path[ancestors..].iter().zip(&self.containers) {
+            score = score.max(match_score(seg, query)?);
+        }
+        Some(score)
+    }
+}
+
+/// Lower is better. `query` should be already lowercased.
+fn match_score(name: &str, query: &str) -> Option<u8> {
+    let name = name.to_lowercase();
+    if name == query {
+        Some(0)
+    } else if name.starts_with(query) {
+        Some(1)
+    } else if name.contains(query) {
+        Some(2)
+    } else {
+        let mut chars = name.chars();
+        query.chars().all(|qc| chars.any(|c| c == qc)).then_some(3)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::TestDB;
+    use crate::SourceDatabase;
+    use expect_test::{expect, Expect};
+
+    #[track_caller]
+    fn check(fixture: &str, query: &str, expect: Expect) {
+        let (db, _) = TestDB::from_fixture(fixture).unwrap();
+        let got = super::workspace_symbols(&db, query, 128)
+            .into_iter()
+            .map(|sym| {
+                let path = db
+                    .source_root(db.file_source_root(sym.file_range.file_id))
+                    .path_for_file(sym.file_range.file_id)
+                    .clone();
+                let container = sym
+                    .container_name
+                    .map(|name| format!(" in {name}"))
+                    .unwrap_or_default();
+                format!(
+                    "{}: {}{container} {:?}\n",
+                    path.display(),
+                    sym.name,
+                    sym.file_range.range,
+                )
+            })
+            .collect::<String>();
+        expect.assert_eq(&got);
+    }
+
+    #[test]
+    fn across_files() {
+        check(
+            "
+#- /default.nix
+{ foo = 1; bar.fooBar = 2; }
+#- /lib.nix
+let xfoo = 1; in { f_o_o = xfoo; }
+            ",
+            "foo",
+            expect![[r#"
+                /default.nix: foo 2..5
+                /default.nix: fooBar in bar 15..21
+                /lib.nix: xfoo 4..8
+                /lib.nix: f_o_o 19..24
+            "#]],
+        );
+    }
+
+    #[test]
+    fn case_insensitive() {
+        check(
+            "{ fooBar = 1; foobar = 2; baz = 3; }",
+            "FOOB",
+            expect![[r#"
+                /default.nix: fooBar 2..8
+                /default.nix: foobar 14..20
+            "#]],
+        );
+    }
+
+    #[test]
+    fn empty_query() {
+        check(
+            "{ a.b = 1; }",
+            "",
+            expect![[r#"
+                /default.nix: a 2..3
+                /default.nix: b in a 4..5
+            "#]],
+        );
+    }
+
+    #[test]
+    fn scoring_order() {
+        check(
+            "
+#- /default.nix
+{ foo = 1; bar.foo = 2; }
+#- /foo.nix
+{ foo = 1; bar.baz.foo = 2; fooo = 3; }
+            ",
+            "foo",
+            expect![[r#"
+                /foo.nix: foo 2..5
+                /default.nix: foo 2..5
+                /default.nix: foo in bar 15..18
+                /foo.nix: foo in baz 19..22
+                /foo.nix: fooo 28..32
+            "#]],
+        );
+    }
+
+    #[test]
+    fn top_level() {
+        check(
+            "{ foo = 1; bar.foo = 2; fooBar = 3; }",
+            "#foo",
+            expect![[r#"
+                /default.nix: foo 2..5
+                /default.nix: fooBar 24..30
+            "#]],
+        );
+        check(
+            "{ foo = 1; bar.foo = 2; baz.bar.foo = 3; }",
+            "#bar.foo",
+            expect![[r#"
+                /default.nix: foo in bar 15..18
+            "#]],
+        );
+    }
+
+    #[test]
+    fn container_path() {
+        check(
+            "{ foo = 1; bar.foo = 2; baz.bar.foo = 3; qux.foo = 4; }",
+            "bar.foo",
+            expect![[r#"
+                /default.nix: foo in bar 15..18
+                /default.nix: foo in bar 32..35
+            "#]],
+        );
+        check(
+            "{ a.b.c = 1; a.x.c = 2; }",
+            "a.b.",
+            expect![[r#"
+                /default.nix: c in b 6..7
+            "#]],
+        );
+    }
+
+    #[test]
+    fn prefix_only() {
+        check(
+            "{ foo = 1; fooBar = 2; xfoo = 3; f_o_o = 4; }",
+            "foo*",
+            expect![[r#"
+                /default.nix: foo 2..5
+                /default.nix: fooBar 11..17
+            "#]],
+        );
+    }
+
+    #[test]
+    fn limit() {
+        let (db, _) = TestDB::from_fixture("{ a = 1; ab = 2; abc = 3; }").unwrap();
+        let got = super::workspace_symbols(&db, "a", 2)
+            .into_iter()
+            .map(|sym| sym.name)
+            .collect::<Vec<_>>();
+        assert_eq!(got, ["a", "ab"]);
+    }
+
+    #[test]
+    fn reuse_index() {
+        let (mut db, f) = TestDB::from_fixture(
+            "
+#- /default.nix
+{ foo = 1; }
+#- /lib.nix
+{ bar = 1; }
+            ",
+        )
+        .unwrap();
+        super::workspace_symbols(&db, "foo", 128);
+
+        // Nothing is recomputed on the same revision, even for a different query.
+        let executed = db.log_executed(|| {
+            super::workspace_symbols(&db, "bar", 128);
+        });
+        assert_eq!(executed, Vec::<String>::new());
+
+        // Only the changed file is re-indexed.
+        db.set_file_content(f["/lib.nix"], "{ baz = 1; }".into());
+        let executed = db.log_executed(|| {
+            super::workspace_symbols(&db, "foo", 128);
+        });
+        let cnt = |query: &str| executed.iter().filter(|q| q.starts_with(query)).count();
+        assert_eq!(cnt("symbol_index("), 1, "{executed:#?}");
+    }
+}
@@ -11,13 +11,16 @@ mod text_edit;
 mod tests;
 
 pub use self::ide::{
-    Analysis, AnalysisHost, Assist, AssistKind, Cancelled, CompletionItem, CompletionItemKind,
+    builtin_document, Analysis, AnalysisHost, Assist, AssistKind, AttrpathSegment, Cancelled,
+    CompletionItem, CompletionItemKind, FlakeInput, FoldKind, FoldRange, FormattingRange,
     GotoDefinitionResult, HlAttrField, HlKeyword, HlOperator, HlPunct, HlRange, HlRelated, HlTag,
-    HoverResult, Link, LinkTarget, NavigationTarget, RenameResult, SymbolTree,
+    HoverResult, IdeDatabase, InlayHint, InlayHintKind, Link, LinkTarget, NavigationTarget,
+    PathCompletionContext, RenameResult, ResolvedImport, SignatureInfo, SymbolLocation, SymbolTree,
 };
 pub use base::{
-    Change, FileId, FilePos, FileRange, FileSet, FlakeGraph, FlakeInfo, InFile, SourceDatabase,
-    SourceRoot, SourceRootId, VfsPath,
+    Change, DirEntry, FileId, FilePos, FileRange, FileSet, FlakeGraph, FlakeInfo, InFile,
+    SearchPath, SearchPathEntry, SourceDatabase, SourceRoot, SourceRootId, VfsPath,
+    DEFAULT_LIB_INHERIT_THRESHOLD, DEFAULT_MAX_NESTING,
 };
 pub use builtin::BuiltinKind;
 pub use def::{DefDatabase, Module, ModuleKind, ModuleSourceMap, NameKind};
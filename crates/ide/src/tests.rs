@@ -1,28 +1,42 @@
 use crate::base::SourceDatabaseStorage;
 use crate::def::DefDatabaseStorage;
+use crate::ide::IdeDatabaseStorage;
 use crate::ty::TyDatabaseStorage;
 use crate::{
     Change, DefDatabase, FileId, FilePos, FileRange, FileSet, FlakeGraph, FlakeInfo,
-    SourceDatabase, SourceRoot, SourceRootId, VfsPath,
+    SourceDatabase, SourceRoot, SourceRootId, VfsPath, DEFAULT_LIB_INHERIT_THRESHOLD,
+    DEFAULT_MAX_NESTING,
 };
 use anyhow::{bail, ensure, Context, Result};
 use indexmap::IndexMap;
 use nix_interop::{DEFAULT_IMPORT_FILE, FLAKE_FILE};
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::{mem, ops};
 use syntax::ast::AstNode;
 use syntax::{NixLanguage, SyntaxNode, TextRange, TextSize};
 
 pub const MARKER_INDICATOR: char = '$';
 
-#[salsa::database(SourceDatabaseStorage, DefDatabaseStorage, TyDatabaseStorage)]
+#[salsa::database(
+    SourceDatabaseStorage,
+    DefDatabaseStorage,
+    TyDatabaseStorage,
+    IdeDatabaseStorage
+)]
 #[derive(Default)]
 pub struct TestDB {
     storage: salsa::Storage<Self>,
+    events: Mutex<Option<Vec<salsa::Event>>>,
 }
 
-impl salsa::Database for TestDB {}
+impl salsa::Database for TestDB {
+    fn salsa_event(&self, event: salsa::Event) {
+        if let Some(events) = &mut *self.events.lock().unwrap() {
+            events.push(event);
+        }
+    }
+}
 
 impl TestDB {
     pub fn single_file(fixture: &str) -> Result<(Self, FileId)> {
@@ -49,6 +63,9 @@ impl TestDB {
         };
         change.set_flake_graph(flake_graph);
         db.set_nixos_options(Arc::default());
+        db.set_search_path(Arc::default());
+        db.set_max_nesting(DEFAULT_MAX_NESTING);
+        db.set_lib_inherit_threshold(DEFAULT_LIB_INHERIT_THRESHOLD);
         change.apply(&mut db);
         Ok((db, f))
     }
@@ -65,6 +82,22 @@ impl TestDB {
     pub fn node_at<N: AstNode<Language = NixLanguage>>(&self, fpos: FilePos) -> Option<N> {
         self.find_node(fpos, N::cast)
     }
+
+    /// Run `f` and collect queries actually executed, ie. not reused from memoized results.
+    pub fn log_executed(&self, f: impl FnOnce()) -> Vec<String> {
+        *self.events.lock().unwrap() = Some(Vec::new());
+        f();
+        let events = self.events.lock().unwrap().take().unwrap();
+        events
+            .into_iter()
+            .filter_map(|event| match event.kind {
+                salsa::EventKind::WillExecute { database_key } => {
+                    Some(format!("{:?}", database_key.debug(self)))
+                }
+                _ => None,
+            })
+            .collect()
+    }
 }
 
 #[derive(Default, Debug)]
@@ -1,12 +1,14 @@
 use super::union_find::UnionFind;
 use super::{known, AttrSource, TyDatabase};
 use crate::def::{
-    BindingValue, Bindings, Expr, ExprId, Literal, NameId, NameResolution, ResolveResult,
+    is_reachable, resolve_path_file, BindingValue, Bindings, Expr, ExprId, Literal, NameId,
+    NameResolution, ResolveResult,
 };
 use crate::{FileId, Module};
 use la_arena::ArenaMap;
 use smol_str::SmolStr;
 use std::collections::btree_map::{BTreeMap, Entry};
+use std::collections::{HashMap, HashSet};
 use std::mem;
 use std::sync::Arc;
 use syntax::ast::{BinaryOpKind, UnaryOpKind};
@@ -61,6 +63,9 @@ struct Attrset {
 pub struct InferenceResult {
     name_ty_map: ArenaMap<NameId, super::Ty>,
     expr_ty_map: ArenaMap<ExprId, super::Ty>,
+    incomplete: bool,
+    incomplete_names: HashSet<NameId>,
+    incomplete_exprs: HashSet<ExprId>,
 }
 
 impl InferenceResult {
@@ -71,6 +76,22 @@ impl InferenceResult {
     pub fn ty_for_expr(&self, expr: ExprId) -> super::Ty {
         self.expr_ty_map[expr].clone()
     }
+
+    /// Whether some (transitively) imported files have syntax errors,
+    /// thus types from them may be incomplete.
+    pub fn is_incomplete(&self) -> bool {
+        self.incomplete
+    }
+
+    /// Whether the type of `name` comes from imported files with syntax errors.
+    pub fn is_name_incomplete(&self, name: NameId) -> bool {
+        self.incomplete_names.contains(&name)
+    }
+
+    /// Whether the type of `expr` comes from imported files with syntax errors.
+    pub fn is_expr_incomplete(&self, expr: ExprId) -> bool {
+        self.incomplete_exprs.contains(&expr)
+    }
 }
 
 pub(crate) fn infer_query(db: &dyn TyDatabase, file: FileId) -> Arc<InferenceResult> {
@@ -87,18 +108,28 @@ pub(crate) fn infer_with(
     let nameres = db.name_resolution(file);
     let table = UnionFind::new(module.names().len() + module.exprs().len(), |_| Ty::Unknown);
     let mut ctx = InferCtx {
+        db,
+        file,
         module: &module,
         nameres: &nameres,
         table,
+        incomplete: false,
+        incomplete_imports: Vec::new(),
+        external_parent: None,
+        external_children: Vec::new(),
+        map_attrs: Vec::new(),
     };
     let ty = ctx.infer_expr(module.entry_expr());
     if let Some(expect_ty) = expect_ty {
         ctx.unify_var_ty(ty, Ty::External(expect_ty));
     }
+    ctx.resolve_map_attrs();
     Arc::new(ctx.finish())
 }
 
 struct InferCtx<'db> {
+    db: &'db dyn TyDatabase,
+    file: FileId,
     module: &'db Module,
     nameres: &'db NameResolution,
 
@@ -106,6 +137,19 @@ struct InferCtx<'db> {
     /// First `module.names().len() + module.exprs().len()` elements are types of each names and
     /// exprs, to allow recursive definition.
     table: UnionFind<Ty>,
+
+    /// Whether types of some imported files are inferred from erroneous sources.
+    incomplete: bool,
+    /// Types of imports which are inferred from erroneous sources.
+    incomplete_imports: Vec<TyVar>,
+    /// The type being unified with an external type, if any.
+    external_parent: Option<TyVar>,
+    /// Parts of external types taken out as new type variables, with their parent types.
+    external_children: Vec<(TyVar, TyVar)>,
+
+    /// Results of `mapAttrs f set` with the types of `set` and mapped values.
+    /// Keys of `set` are only known after all other constraints are collected.
+    map_attrs: Vec<(TyVar, TyVar, TyVar)>,
 }
 
 impl<'db> InferCtx<'db> {
@@ -131,7 +175,11 @@ impl<'db> InferCtx<'db> {
             super::Ty::Path => Ty::Path,
             super::Ty::List(_) | super::Ty::Lambda(..) | super::Ty::Attrset(_) => Ty::External(ty),
         };
-        TyVar(self.table.push(ty))
+        let var = TyVar(self.table.push(ty));
+        if let Some(parent) = self.external_parent {
+            self.external_children.push((parent, var));
+        }
+        var
     }
 
     fn infer_expr(&mut self, e: ExprId) -> TyVar {
@@ -276,7 +324,13 @@ impl<'db> InferCtx<'db> {
                 let lam_ty = self.infer_expr(lam);
                 self.unify_var_ty(lam_ty, Ty::Lambda(param_ty, ret_ty));
                 let arg_ty = self.infer_expr(arg);
+                if let Some(map_attrs_ty) = self.infer_map_attrs(lam, arg_ty) {
+                    return map_attrs_ty;
+                }
                 self.unify_var(arg_ty, param_ty);
+                if let Some(import_ty) = self.infer_import(lam, arg) {
+                    self.unify_var(ret_ty, import_ty);
+                }
                 ret_ty
             }
             Expr::HasAttr(set_expr, path) => {
@@ -346,6 +400,84 @@ impl<'db> InferCtx<'db> {
         }
     }
 
+    /// Infer the type of `import ./path.nix` from the entry expression of the target file.
+    ///
+    /// Imports which may lead back to the current file are skipped, so that no query cycle
+    /// would happen. The target file may have syntax errors, in which case the type is
+    /// inferred from its error-recovered module and may be incomplete.
+    fn infer_import(&mut self, lam: ExprId, arg: ExprId) -> Option<TyVar> {
+        if self.nameres.check_builtin(lam, self.module) != Some("import") {
+            return None;
+        }
+        let &Expr::Literal(Literal::Path(path)) = &self.module[arg] else {
+            return None;
+        };
+        let target = resolve_path_file(self.db, self.file, path)?;
+        if is_reachable(self.db, target, self.file) {
+            return None;
+        }
+        let target_infer = self.db.infer(target);
+        let ty = target_infer.ty_for_expr(self.db.module(target).entry_expr());
+        // Sources of attributes refer to names of the target file, not the current one.
+        let var = self.import_external(forget_name_sources(&ty));
+        if target_infer.incomplete || !self.db.parse(target).errors().is_empty() {
+            self.incomplete = true;
+            self.incomplete_imports.push(var);
+        }
+        Some(var)
+    }
+
+    /// `mapAttrs f set` keeps keys of `set`, which are propagated in `resolve_map_attrs`.
+    /// The builtin signature is not unified with `set` or the result, since its `{ _: a }`
+    /// would hide the keys.
+    fn infer_map_attrs(&mut self, lam: ExprId, set_ty: TyVar) -> Option<TyVar> {
+        let &Expr::Apply(func, f) = &self.module[lam] else {
+            return None;
+        };
+        let is_map_attrs = match &self.module[func] {
+            // `builtins.mapAttrs`
+            Expr::Select(set, attrpath, None) => {
+                matches!(&**attrpath, [attr] if matches!(&self.module[*attr], Expr::Literal(Literal::String(s)) if s == "mapAttrs"))
+                    && self.nameres.check_builtin(*set, self.module) == Some("builtins")
+            }
+            _ => self.nameres.check_builtin(func, self.module) == Some("mapAttrs"),
+        };
+        if !is_map_attrs {
+            return None;
+        }
+        // `f` is already unified with the builtin signature, which loses its return type.
+        // So only literal lambdas `name: value: ...` are looked into.
+        let mapped_ty = match self.module[f] {
+            Expr::Lambda(_, _, inner) => match self.module[inner] {
+                Expr::Lambda(_, _, body) => Some(self.ty_for_expr(body)),
+                _ => None,
+            },
+            _ => None,
+        }
+        .unwrap_or_else(|| self.new_ty_var());
+        let ret_ty = self.new_ty_var();
+        self.map_attrs.push((ret_ty, set_ty, mapped_ty));
+        Some(ret_ty)
+    }
+
+    fn resolve_map_attrs(&mut self) {
+        for (ret_ty, set_ty, mapped_ty) in mem::take(&mut self.map_attrs) {
+            let root = self.table.find(set_ty.0);
+            let Ty::Attrset(set) = self.table.get_mut(root) else {
+                continue;
+            };
+            let ret = Attrset {
+                fields: set
+                    .fields
+                    .iter()
+                    .map(|(name, &(_, src))| (name.clone(), (mapped_ty, src)))
+                    .collect(),
+                dyn_ty: set.dyn_ty.map(|_| mapped_ty),
+            };
+            self.unify_var_ty(ret_ty, Ty::Attrset(ret));
+        }
+    }
+
     fn infer_bindings(&mut self, bindings: &Bindings) -> Attrset {
         let inherit_from_tys = bindings
             .inherit_froms
@@ -404,19 +536,17 @@ impl<'db> InferCtx<'db> {
                     None => set.dyn_ty = Some(next_ty),
                 },
             },
-            Ty::External(super::Ty::Attrset(set)) => match field {
-                Some(field) => {
-                    if let Some(ty) = set.get(&field).cloned() {
-                        return self.import_external(ty);
-                    }
-                }
-                None => {
-                    if let Some(rest) = &set.rest {
-                        let rest_ty = rest.0.clone();
-                        return self.import_external(rest_ty);
-                    }
+            Ty::External(super::Ty::Attrset(set)) => {
+                let field_ty = match field {
+                    Some(field) => set.get(&field).cloned(),
+                    None => set.rest.as_ref().map(|rest| rest.0.clone()),
+                };
+                if let Some(ty) = field_ty {
+                    let var = self.import_external(ty);
+                    self.external_children.push((set_ty, var));
+                    return var;
                 }
-            },
+            }
             k @ Ty::Unknown => {
                 *k = Ty::Attrset(match field {
                     Some(field) => Attrset {
@@ -436,7 +566,9 @@ impl<'db> InferCtx<'db> {
 
     fn unify_var_ty(&mut self, var: TyVar, rhs: Ty) {
         let lhs = mem::replace(self.table.get_mut(var.0), Ty::Unknown);
+        let parent = self.external_parent.replace(var);
         let ret = self.unify(lhs, rhs);
+        self.external_parent = parent;
         *self.table.get_mut(var.0) = ret;
     }
 
@@ -506,7 +638,59 @@ impl<'db> InferCtx<'db> {
         }
     }
 
+    /// Roots of all type variables which are (parts of) types of incomplete imports.
+    /// Known primitive types are complete wherever they come from.
+    fn incomplete_roots(&mut self) -> HashSet<u32> {
+        let mut external_children = HashMap::<u32, Vec<u32>>::new();
+        for &(parent, child) in &self.external_children {
+            let parent = self.table.find(parent.0);
+            external_children.entry(parent).or_default().push(child.0);
+        }
+        let mut visited = HashSet::new();
+        let mut stack = self
+            .incomplete_imports
+            .iter()
+            .map(|v| v.0)
+            .collect::<Vec<_>>();
+        while let Some(i) = stack.pop() {
+            let i = self.table.find(i);
+            if !visited.insert(i) {
+                continue;
+            }
+            stack.extend(external_children.get(&i).into_iter().flatten());
+            match self.table.get_mut(i) {
+                Ty::List(a) => stack.push(a.0),
+                Ty::Lambda(a, b) => stack.extend([a.0, b.0]),
+                Ty::Attrset(set) => stack.extend(
+                    set.fields
+                        .values()
+                        .map(|(v, _)| v.0)
+                        .chain(set.dyn_ty.map(|v| v.0)),
+                ),
+                Ty::Bool | Ty::Int | Ty::Float | Ty::String | Ty::Path => {
+                    visited.remove(&i);
+                }
+                Ty::Unknown | Ty::External(_) => {}
+            }
+        }
+        visited
+    }
+
     fn finish(mut self) -> InferenceResult {
+        let incomplete_roots = self.incomplete_roots();
+        let incomplete_names = self
+            .module
+            .names()
+            .map(|(name, _)| name)
+            .filter(|&name| incomplete_roots.contains(&self.table.find(self.ty_for_name(name).0)))
+            .collect();
+        let incomplete_exprs = self
+            .module
+            .exprs()
+            .map(|(expr, _)| expr)
+            .filter(|&expr| incomplete_roots.contains(&self.table.find(self.ty_for_expr(expr).0)))
+            .collect();
+
         let mut i = Collector::new(&mut self.table);
 
         let name_cnt = self.module.names().len();
@@ -525,10 +709,39 @@ impl<'db> InferCtx<'db> {
         InferenceResult {
             name_ty_map,
             expr_ty_map,
+            incomplete: self.incomplete,
+            incomplete_names,
+            incomplete_exprs,
         }
     }
 }
 
+fn forget_name_sources(ty: &super::Ty) -> super::Ty {
+    let forget_src = |src: AttrSource| match src {
+        AttrSource::Name(_) => AttrSource::Unknown,
+        src => src,
+    };
+    match ty {
+        super::Ty::List(elem) => super::Ty::List(Arc::new(forget_name_sources(elem))),
+        super::Ty::Lambda(arg, ret) => super::Ty::Lambda(
+            Arc::new(forget_name_sources(arg)),
+            Arc::new(forget_name_sources(ret)),
+        ),
+        super::Ty::Attrset(set) => super::Ty::Attrset(super::Attrset {
+            fields: set
+                .fields
+                .iter()
+                .map(|(name, ty, src)| (name.clone(), forget_name_sources(ty), forget_src(*src)))
+                .collect(),
+            rest: set
+                .rest
+                .as_ref()
+                .map(|rest| Arc::new((forget_name_sources(&rest.0), forget_src(rest.1)))),
+        }),
+        ty => ty.clone(),
+    }
+}
+
 /// Traverse the table and freeze all `Ty`s into immutable ones.
 struct Collector<'a> {
     cache: Vec<Option<super::Ty>>,
@@ -363,3 +363,106 @@ fn input_flake_ty() {
     expect_output.assert_eq(&ty_for_name("export_output"));
     assert_eq!(ty_for_name("export_pkg_name"), "string");
 }
+
+#[test]
+fn import_file() {
+    let (db, f) = TestDB::from_fixture(
+        "
+#- /default.nix
+let lib = import ./lib.nix; in lib.foo
+#- /lib.nix
+{ foo = 1; bar = \"a\"; }
+",
+    )
+    .unwrap();
+    let file = f["/default.nix"];
+    let infer = db.infer(file);
+    let ty = infer.ty_for_expr(db.module(file).entry_expr());
+    assert_eq!(ty.debug().to_string(), "int");
+    assert!(!infer.is_incomplete());
+}
+
+#[test]
+fn import_broken_file() {
+    let (db, f) = TestDB::from_fixture(
+        "
+#- /default.nix
+import ./lib.nix
+#- /lib.nix
+{ foo = 1; bar = ; baz = \"a\";
+",
+    )
+    .unwrap();
+    let file = f["/default.nix"];
+    let infer = db.infer(file);
+    let ty = infer.ty_for_expr(db.module(file).entry_expr());
+    expect!["{ bar: ?, baz: string, foo: int }"].assert_eq(&ty.debug().to_string());
+    assert!(infer.is_incomplete());
+}
+
+#[test]
+fn import_broken_file_partially_incomplete() {
+    let (db, f) = TestDB::from_fixture(
+        "
+#- /default.nix
+let lib = import ./lib.nix; x = lib.bar; y = lib.foo; z = 1; in { inherit x y z; }
+#- /lib.nix
+{ foo = 1; bar = ;
+",
+    )
+    .unwrap();
+    let file = f["/default.nix"];
+    let module = db.module(file);
+    let infer = db.infer(file);
+    let incomplete = module
+        .names()
+        .filter(|&(name, _)| infer.is_name_incomplete(name))
+        .map(|(_, name)| name.text.as_str())
+        .collect::<Vec<_>>();
+    // The binding `x` and the inherited attribute `x`.
+    assert_eq!(incomplete, ["lib", "x", "x"]);
+    assert!(!infer.is_expr_incomplete(module.entry_expr()));
+}
+
+#[test]
+fn import_cycle() {
+    let (db, f) = TestDB::from_fixture(
+        "
+#- /default.nix
+{ a = 1; b = (import ./b.nix).b; }
+#- /b.nix
+{ a = (import ./default.nix).a; b = 2; }
+",
+    )
+    .unwrap();
+    let file = f["/default.nix"];
+    let ty = db.infer(file).ty_for_expr(db.module(file).entry_expr());
+    // Imports in a cycle are not followed.
+    expect!["{ a: int, b: ? }"].assert_eq(&ty.debug().to_string());
+    let file = f["/b.nix"];
+    let ty = db.infer(file).ty_for_expr(db.module(file).entry_expr());
+    expect!["{ a: ?, b: int }"].assert_eq(&ty.debug().to_string());
+}
+
+#[test]
+fn map_attrs() {
+    check(
+        "builtins.mapAttrs (k: v: v + 1) { a = 1; b = 2; }",
+        expect!["{ a: int, b: int }"],
+    );
+    check(
+        r#"let inherit (builtins) mapAttrs; s = { a = 1; }; in mapAttrs (k: v: "x") s"#,
+        expect!["{ a: string }"],
+    );
+    // Keys of sets defined later are propagated as well.
+    check(
+        "let m = builtins.mapAttrs (k: v: v) s; s = { a = 1; }; in m",
+        expect!["{ a: ? }"],
+    );
+    check(
+        "with builtins; mapAttrs (k: v: [ v ]) (mapAttrs (k: v: v) { a = 1; })",
+        expect!["{ a: [?] }"],
+    );
+    // Unknown sets.
+    check("s: builtins.mapAttrs (k: v: v) s", expect!["? → ?"]);
+}
@@ -0,0 +1,67 @@
+use std::ops::ControlFlow;
+use std::task::{Context, Poll};
+
+use async_lsp::{AnyEvent, AnyNotification, AnyRequest, ClientSocket, LspService};
+use tower::{Layer, Service};
+
+/// Emitted before handling each request or notification from the client.
+pub struct ClientActivityEvent;
+
+pub struct ClientActivity<S> {
+    service: S,
+    client: ClientSocket,
+}
+
+impl<S> ClientActivity<S> {
+    fn record(&self) {
+        // Only fails if the main loop is already stopped.
+        let _: Result<_, _> = self.client.emit(ClientActivityEvent);
+    }
+}
+
+impl<S: LspService> Service<AnyRequest> for ClientActivity<S> {
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: AnyRequest) -> Self::Future {
+        self.record();
+        self.service.call(req)
+    }
+}
+
+impl<S: LspService> LspService for ClientActivity<S> {
+    fn notify(&mut self, notif: AnyNotification) -> ControlFlow<async_lsp::Result<()>> {
+        self.record();
+        self.service.notify(notif)
+    }
+
+    fn emit(&mut self, event: AnyEvent) -> ControlFlow<async_lsp::Result<()>> {
+        self.service.emit(event)
+    }
+}
+
+pub struct ClientActivityLayer {
+    client: ClientSocket,
+}
+
+impl ClientActivityLayer {
+    pub fn new(client: ClientSocket) -> Self {
+        Self { client }
+    }
+}
+
+impl<S> Layer<S> for ClientActivityLayer {
+    type Service = ClientActivity<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ClientActivity {
+            service: inner,
+            client: self.client.clone(),
+        }
+    }
+}
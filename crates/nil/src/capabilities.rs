@@ -1,12 +1,20 @@
+use crate::config::Features;
+use crate::lsp_ext::{
+    CLEAR_EVAL_CACHE_COMMAND, RECORD_COMPLETION_COMMAND, VIRTUAL_DOCUMENT_SCHEME,
+};
 use crate::semantic_tokens::{SEMANTIC_TOKEN_MODIFIERS, SEMANTIC_TOKEN_TYPES};
 use lsp_types::{
-    CodeActionProviderCapability, CompletionOptions, DocumentLinkOptions, HoverProviderCapability,
-    InitializeParams, OneOf, RenameOptions, SelectionRangeProviderCapability,
+    CodeActionProviderCapability, CompletionOptions, DocumentLinkOptions, ExecuteCommandOptions,
+    FoldingRangeProviderCapability, HoverProviderCapability, InitializeParams, InlayHintOptions,
+    InlayHintServerCapabilities, OneOf, RenameOptions, SelectionRangeProviderCapability,
     SemanticTokensFullOptions, SemanticTokensLegend, SemanticTokensOptions,
-    SemanticTokensServerCapabilities, ServerCapabilities, TextDocumentSyncCapability,
-    TextDocumentSyncKind, TextDocumentSyncOptions, WorkDoneProgressOptions,
+    SemanticTokensServerCapabilities, ServerCapabilities, SignatureHelpOptions,
+    TextDocumentSyncCapability, TextDocumentSyncKind, TextDocumentSyncOptions,
+    WorkDoneProgressOptions, WorkspaceFoldersServerCapabilities, WorkspaceServerCapabilities,
 };
 
+use std::collections::HashSet;
+
 macro_rules! test {
     ($lhs:ident $(.$field:ident)*) => {
         Some($lhs)
@@ -23,8 +31,25 @@ pub(crate) fn negotiate_capabilities(
         .client_info
         .as_ref()
         .map_or(false, |info| info.name == "Neovim");
+    let is_vscode = matches!(
+        &init_params.client_info,
+        Some(info) if info.name.starts_with("Visual Studio Code") || info.name == "VSCodium"
+    );
+
+    // Client capabilities of `textDocument/fooBar` are all at `textDocument.fooBar`.
+    let text_document_caps = serde_json::to_value(&client_caps.text_document).unwrap_or_default();
+    let feature_dynamic_registration = Features::default()
+        .iter()
+        .map(|(_, method, _)| method)
+        .filter(|method| {
+            let name = method.strip_prefix("textDocument/").unwrap_or(method);
+            text_document_caps.pointer(&format!("/{name}/dynamicRegistration"))
+                == Some(&serde_json::Value::Bool(true))
+        })
+        .collect();
 
     let final_caps = NegotiatedCapabilities {
+        feature_dynamic_registration,
         client_show_message_request: test!(
             client_caps
                 .window
@@ -33,7 +58,28 @@ pub(crate) fn negotiate_capabilities(
                 // This is required for knowing which action is performed.
                 .additional_properties_support
         ),
+        formatting_dynamic_registration: test!(
+            client_caps.text_document.formatting.dynamic_registration
+        ),
+        range_formatting_dynamic_registration: test!(
+            client_caps
+                .text_document
+                .range_formatting
+                .dynamic_registration
+        ),
         server_initiated_progress: test!(client_caps.window.work_done_progress),
+        text_document_sync_dynamic_registration: test!(
+            client_caps
+                .text_document
+                .synchronization
+                .dynamic_registration
+        ),
+        will_save_wait_until: test!(
+            client_caps
+                .text_document
+                .synchronization
+                .will_save_wait_until
+        ),
         watch_files: test!(
             client_caps
                 .workspace
@@ -49,6 +95,12 @@ pub(crate) fn negotiate_capabilities(
                     .relative_pattern_support
             ),
         workspace_configuration: test!(client_caps.workspace.configuration),
+        // Not yet in `lsp_types`. See `lsp_ext::TextDocumentContent`.
+        text_document_content: matches!(
+            client_caps.experimental.as_ref().and_then(|caps| caps.get("textDocumentContent")),
+            Some(caps) if caps.is_object() || caps == true
+        ),
+        vscode_commands: is_vscode,
     };
 
     let server_caps = ServerCapabilities {
@@ -57,13 +109,15 @@ pub(crate) fn negotiate_capabilities(
                 open_close: Some(true),
                 change: Some(TextDocumentSyncKind::INCREMENTAL),
                 will_save: None,
+                // NB. This may be set or registered later depending on configurations.
+                // See `Server::update_will_save_registration`.
                 will_save_wait_until: None,
                 save: None,
             },
         )),
         definition_provider: Some(OneOf::Left(true)),
         completion_provider: Some(CompletionOptions {
-            trigger_characters: Some(vec![".".into(), "?".into()]),
+            trigger_characters: Some(vec![".".into(), "?".into(), "/".into()]),
             ..Default::default()
         }),
         references_provider: Some(OneOf::Left(true)),
@@ -80,29 +134,108 @@ pub(crate) fn negotiate_capabilities(
                     token_modifiers: SEMANTIC_TOKEN_MODIFIERS.to_vec(),
                 },
                 range: Some(true),
-                full: Some(SemanticTokensFullOptions::Delta { delta: Some(false) }),
+                full: Some(SemanticTokensFullOptions::Delta { delta: Some(true) }),
             },
         )),
         hover_provider: Some(HoverProviderCapability::Simple(true)),
+        signature_help_provider: Some(SignatureHelpOptions {
+            // Arguments are separated by spaces.
+            trigger_characters: Some(vec![" ".into()]),
+            retrigger_characters: None,
+            work_done_progress_options: WorkDoneProgressOptions::default(),
+        }),
         document_symbol_provider: Some(OneOf::Left(true)),
+        workspace_symbol_provider: Some(OneOf::Left(true)),
+        folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+        inlay_hint_provider: Some(OneOf::Right(InlayHintServerCapabilities::Options(
+            InlayHintOptions {
+                resolve_provider: Some(true),
+                work_done_progress_options: WorkDoneProgressOptions::default(),
+            },
+        ))),
+        // NB. This may be unset or registered later depending on configurations.
+        // See `Server::update_formatting_registration`.
         document_formatting_provider: Some(OneOf::Left(true)),
+        document_range_formatting_provider: Some(OneOf::Left(true)),
         document_link_provider: Some(DocumentLinkOptions {
             resolve_provider: Some(true),
             work_done_progress_options: WorkDoneProgressOptions::default(),
         }),
         code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
         document_highlight_provider: Some(OneOf::Left(true)),
+        workspace: Some(WorkspaceServerCapabilities {
+            workspace_folders: Some(WorkspaceFoldersServerCapabilities {
+                supported: Some(true),
+                change_notifications: Some(OneOf::Left(true)),
+            }),
+            file_operations: None,
+        }),
+        execute_command_provider: Some(ExecuteCommandOptions {
+            commands: vec![
+                CLEAR_EVAL_CACHE_COMMAND.into(),
+                RECORD_COMPLETION_COMMAND.into(),
+            ],
+            work_done_progress_options: WorkDoneProgressOptions::default(),
+        }),
+        // Not yet in `lsp_types`. See `lsp_ext::TextDocumentContent`.
+        experimental: Some(serde_json::json!({
+            "textDocumentContent": {
+                "schemes": [VIRTUAL_DOCUMENT_SCHEME],
+            },
+        })),
         ..Default::default()
     };
 
     (server_caps, final_caps)
 }
 
+/// Remove the static capability of a `textDocument/fooBar` request, that is `fooBarProvider`,
+/// and return the options to register it dynamically instead.
+pub(crate) fn take_feature_capability(
+    server_caps: &mut ServerCapabilities,
+    method: &str,
+) -> serde_json::Value {
+    fn to_value(provider: Option<impl serde::Serialize>) -> Option<serde_json::Value> {
+        provider.map(|provider| serde_json::to_value(provider).expect("Serializable"))
+    }
+
+    let provider = match method {
+        "textDocument/semanticTokens" => to_value(server_caps.semantic_tokens_provider.take()),
+        "textDocument/foldingRange" => to_value(server_caps.folding_range_provider.take()),
+        "textDocument/documentSymbol" => to_value(server_caps.document_symbol_provider.take()),
+        "textDocument/inlayHint" => to_value(server_caps.inlay_hint_provider.take()),
+        "textDocument/documentLink" => to_value(server_caps.document_link_provider.take()),
+        "textDocument/codeAction" => to_value(server_caps.code_action_provider.take()),
+        _ => unreachable!("Not a method of `Features`: {method}"),
+    };
+
+    // Providers enabled by `true` have no options.
+    let mut options = match provider {
+        Some(serde_json::Value::Object(options)) => options,
+        _ => serde_json::Map::new(),
+    };
+    // Use the client-side document selector.
+    options.insert("documentSelector".into(), serde_json::Value::Null);
+    options.into()
+}
+
 #[derive(Clone, Debug, Default)]
 pub(crate) struct NegotiatedCapabilities {
+    /// Methods of `Features` the client can dynamically (un)register.
+    pub feature_dynamic_registration: HashSet<&'static str>,
     pub client_show_message_request: bool,
+    pub formatting_dynamic_registration: bool,
+    pub range_formatting_dynamic_registration: bool,
     pub server_initiated_progress: bool,
+    pub text_document_sync_dynamic_registration: bool,
+    pub will_save_wait_until: bool,
     pub watch_files: bool,
     pub watch_files_relative_pattern: bool,
     pub workspace_configuration: bool,
+    /// The client can fetch documents of `VIRTUAL_DOCUMENT_SCHEME`.
+    pub text_document_content: bool,
+    /// The client understands the editor commands `editor.action.triggerSuggest` and
+    /// `editor.action.rename`. There is no standard way to request them, so only VS Code and
+    /// forks are assumed to.
+    pub vscode_commands: bool,
 }
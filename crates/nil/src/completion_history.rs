@@ -0,0 +1,57 @@
+use std::collections::VecDeque;
+
+/// The number of recently accepted completion labels to remember.
+const CAPACITY: usize = 32;
+
+/// Completion labels accepted in this session, with the most recent first.
+///
+/// This only lives in memory and is shared by all documents.
+#[derive(Debug, Default)]
+pub struct CompletionHistory {
+    labels: VecDeque<String>,
+}
+
+impl CompletionHistory {
+    /// Mark `label` as the most recently used one, evicting the least recently used one if full.
+    pub fn record(&mut self, label: &str) {
+        if let Some(idx) = self.labels.iter().position(|l| l == label) {
+            let label = self.labels.remove(idx).unwrap();
+            self.labels.push_front(label);
+            return;
+        }
+        if self.labels.len() == CAPACITY {
+            self.labels.pop_back();
+        }
+        self.labels.push_front(label.to_owned());
+    }
+
+    /// The recency of `label`, where 0 is the most recently used one.
+    pub fn rank(&self, label: &str) -> Option<usize> {
+        self.labels.iter().position(|l| l == label)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CompletionHistory, CAPACITY};
+
+    #[test]
+    fn lru() {
+        let mut history = CompletionHistory::default();
+        history.record("a");
+        history.record("b");
+        assert_eq!(history.rank("b"), Some(0));
+        assert_eq!(history.rank("a"), Some(1));
+        assert_eq!(history.rank("c"), None);
+
+        history.record("a");
+        assert_eq!(history.rank("a"), Some(0));
+        assert_eq!(history.rank("b"), Some(1));
+
+        for i in 0..CAPACITY - 1 {
+            history.record(&i.to_string());
+        }
+        assert_eq!(history.rank("a"), Some(CAPACITY - 1));
+        assert_eq!(history.rank("b"), None);
+    }
+}
@@ -1,6 +1,13 @@
 use anyhow::ensure;
+use ide::{
+    Diagnostic, SearchPath, SearchPathEntry, VfsPath, DEFAULT_LIB_INHERIT_THRESHOLD,
+    DEFAULT_MAX_NESTING,
+};
 use lsp_types::Url;
+use nix_interop::eval_cache::EvalCache;
+use serde::Deserialize;
 use std::collections::HashSet;
+use std::num::NonZeroUsize;
 use std::path::PathBuf;
 
 pub const CONFIG_KEY: &str = "nil";
@@ -33,6 +40,8 @@ macro_rules! define_config {
                 }
             }
 
+            /// Apply settings in `v`. Invalid ones are reset to their defaults, with the
+            /// reasons pushed into `errors`.
             #[allow(clippy::redundant_closure_call)]
             pub fn update(&mut self, mut v: serde_json::Value, errors: &mut Vec<String>) {
                 $($(
@@ -42,11 +51,14 @@ macro_rules! define_config {
                         $(.and_then(|v| $parse(self, v)))?
                     {
                         Ok(v) => self.$field = v,
-                        Err(err) => errors.push(format!(
-                            "invalid value of `{}`: {}",
-                            $pointer[1..].replace('/', "."),
-                            err,
-                        )),
+                        Err(err) => {
+                            self.$field = define_config!(@default $($default)?);
+                            errors.push(format!(
+                                "invalid value of `{}`: {}",
+                                $pointer[1..].replace('/', "."),
+                                err,
+                            ));
+                        }
                     }
                 }
                 )?)*
@@ -61,6 +73,69 @@ macro_rules! define_config {
     };
 }
 
+macro_rules! define_features {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $features:ident {
+            $(
+            $(#[doc = $doc:literal])*
+            $field:ident : $key:literal => $method:literal,
+            )*
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+        #[serde(default)]
+        $vis struct $features {
+            $(
+            $(#[doc = $doc])*
+            #[serde(rename = $key)]
+            pub $field: bool,
+            )*
+        }
+
+        impl Default for $features {
+            fn default() -> Self {
+                Self {
+                    $($field: true,)*
+                }
+            }
+        }
+
+        impl $features {
+            /// All features with their config keys, LSP methods and whether they are enabled.
+            pub fn iter(&self) -> impl Iterator<Item = (&'static str, &'static str, bool)> {
+                [$(($key, $method, self.$field)),*].into_iter()
+            }
+        }
+    };
+}
+
+define_features! {
+    /// Toggles of providers under `nil.features.*`, all enabled by default.
+    ///
+    /// Each feature belongs to the LSP request it affects. A request is dynamically unregistered
+    /// when all of its features are disabled and the client supports it.
+    pub struct Features {
+        semantic_tokens: "semanticTokens" => "textDocument/semanticTokens",
+        folding_range: "foldingRange" => "textDocument/foldingRange",
+        document_symbol: "documentSymbol" => "textDocument/documentSymbol",
+        inlay_hint: "inlayHint" => "textDocument/inlayHint",
+        document_link: "documentLink" => "textDocument/documentLink",
+        /// Code actions of `AssistKind::QuickFix`.
+        quick_fix: "quickFix" => "textDocument/codeAction",
+        /// Code actions of `AssistKind::RefactorRewrite`.
+        refactor: "refactor" => "textDocument/codeAction",
+    }
+}
+
+impl Features {
+    /// Whether any feature of an LSP request is enabled.
+    pub fn method_enabled(&self, method: &str) -> bool {
+        self.iter().any(|(_, m, enabled)| m == method && enabled)
+    }
+}
+
 #[macro_rules_attribute::apply(define_config!)]
 #[derive(Debug, Clone)]
 pub struct Config {
@@ -70,18 +145,48 @@ pub struct Config {
     pub diagnostics_excluded_files: Vec<Url>,
     #[parse("/diagnostics/ignored")]
     pub diagnostics_ignored: HashSet<String>,
+    #[parse("/diagnostics/enabled")]
+    pub diagnostics_enabled: HashSet<String>,
+    #[parse("/diagnostics/maxNesting", default = DEFAULT_MAX_NESTING)]
+    pub diagnostics_max_nesting: usize,
+    #[parse("/diagnostics/libInheritThreshold", default = DEFAULT_LIB_INHERIT_THRESHOLD)]
+    pub diagnostics_lib_inherit_threshold: usize,
     #[parse("/formatting/command", parse = Config::parse_optional_command)]
     pub formatting_command: Option<Vec<String>>,
-    #[parse("/nix/binary", default = "nix".into())]
+    #[parse("/formatting/trimTrailingWhitespace")]
+    pub formatting_trim_trailing_whitespace: bool,
+    #[parse("/features")]
+    pub features: Features,
+    #[parse("/fixOnSave")]
+    pub fix_on_save: bool,
+    #[parse("/idleShutdownMs")]
+    pub idle_shutdown_ms: Option<u64>,
+    #[parse("/maxFileSizeMB", default = 128)]
+    pub max_file_size_mb: u64,
+    #[parse("/nix/binary", default = "nix".into(), parse = Config::parse_program)]
     pub nix_binary: PathBuf,
     #[parse("/nix/maxMemoryMB", default = Some(2048))]
     pub nix_max_memory_mb: Option<u64>,
+    #[parse("/nix/searchPath", default = search_path_from_env(), parse = Config::parse_search_path)]
+    pub nix_search_path: SearchPath,
+    #[parse("/nix/maxConcurrency", default = NonZeroUsize::new(2).unwrap())]
+    pub nix_max_concurrency: NonZeroUsize,
     #[parse("/nix/flake/autoArchive")]
     pub nix_flake_auto_archive: Option<bool>,
     #[parse("/nix/flake/autoEvalInputs")]
     pub nix_flake_auto_eval_inputs: bool,
     #[parse("/nix/flake/nixpkgsInputName", default = Some("nixpkgs".into()))]
     pub nix_flake_nixpkgs_input_name: Option<String>,
+    #[parse("/nix/flake/evalCache/enable", default = true)]
+    pub nix_flake_eval_cache_enable: bool,
+    #[parse("/nix/flake/evalCache/directory", parse = Config::parse_optional_rooted_path)]
+    pub nix_flake_eval_cache_directory: Option<PathBuf>,
+    #[parse("/nix/flake/evalCache/maxSizeMB", default = 256)]
+    pub nix_flake_eval_cache_max_size_mb: u64,
+    #[parse("/onSave/insertFinalNewline")]
+    pub on_save_insert_final_newline: bool,
+    #[parse("/onSave/trimTrailingWhitespace")]
+    pub on_save_trim_trailing_whitespace: bool,
 }
 
 impl Config {
@@ -97,11 +202,134 @@ impl Config {
         &mut self,
         v: Option<Vec<String>>,
     ) -> anyhow::Result<Option<Vec<String>>> {
-        ensure!(v != Some(Vec::new()), "command must not be empty");
+        if let Some(cmd) = &v {
+            ensure!(!cmd.is_empty(), "command must not be empty");
+            self.parse_program(PathBuf::from(&cmd[0]))?;
+        }
+        Ok(v)
+    }
+
+    fn parse_program(&mut self, v: PathBuf) -> anyhow::Result<PathBuf> {
+        ensure!(!v.as_os_str().is_empty(), "program must not be empty");
         Ok(v)
     }
 
+    fn parse_optional_rooted_path(
+        &mut self,
+        v: Option<PathBuf>,
+    ) -> anyhow::Result<Option<PathBuf>> {
+        Ok(v.map(|path| self.root_path.join(path)))
+    }
+
+    /// Parse entries in the `NIX_PATH` format, eg. `nixpkgs=/path/to/nixpkgs` or `/path`.
+    fn parse_search_path(&mut self, v: Vec<String>) -> anyhow::Result<SearchPath> {
+        let entries = v
+            .into_iter()
+            .map(|entry| {
+                let (prefix, path) = entry.split_once('=').unwrap_or(("", &entry));
+                ensure!(
+                    !path.is_empty(),
+                    "empty path in search path entry {entry:?}"
+                );
+                Ok(SearchPathEntry {
+                    prefix: prefix.into(),
+                    path: VfsPath::new(self.root_path.join(path)),
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(SearchPath { entries })
+    }
+
+    /// Whether a diagnostic should be reported. Opt-in ones must be enabled explicitly.
+    pub fn diagnostic_enabled(&self, diag: &Diagnostic) -> bool {
+        let code = diag.code();
+        !self.diagnostics_ignored.contains(code)
+            && (!diag.is_opt_in() || self.diagnostics_enabled.contains(code))
+    }
+
+    /// Whether `textDocument/formatting` does anything under this configuration.
+    pub fn formatting_enabled(&self) -> bool {
+        self.formatting_command.is_some() || self.formatting_trim_trailing_whitespace
+    }
+
+    /// Whether `textDocument/willSaveWaitUntil` does anything under this configuration.
+    pub fn on_save_enabled(&self) -> bool {
+        self.fix_on_save
+            || self.on_save_insert_final_newline
+            || self.on_save_trim_trailing_whitespace
+    }
+
+    /// The file length limit in bytes, clamped to the hard limit of `u32::MAX`.
+    pub fn max_file_len(&self) -> usize {
+        let len = self.max_file_size_mb.saturating_mul(1 << 20);
+        len.min(u32::MAX.into()) as usize
+    }
+
     pub fn nix_max_memory(&self) -> Option<u64> {
         self.nix_max_memory_mb?.checked_mul(1 << 20)
     }
+
+    /// The evaluation cache of this workspace, or `None` if it is disabled.
+    pub fn eval_cache(&self) -> Option<EvalCache> {
+        if !self.nix_flake_eval_cache_enable {
+            return None;
+        }
+        let dir = self
+            .nix_flake_eval_cache_directory
+            .clone()
+            .or_else(EvalCache::default_dir)?;
+        let max_size = self
+            .nix_flake_eval_cache_max_size_mb
+            .saturating_mul(1 << 20);
+        Some(EvalCache::new(dir, &self.root_path, max_size))
+    }
+}
+
+/// The default search path from the `NIX_PATH` environment variable.
+fn search_path_from_env() -> SearchPath {
+    parse_nix_path(&std::env::var("NIX_PATH").unwrap_or_default())
+}
+
+/// Parse the colon-separated `NIX_PATH`. Entries which are not absolute paths, eg. URLs or
+/// `flake:` references, cannot be looked up locally and are skipped.
+fn parse_nix_path(s: &str) -> SearchPath {
+    let mut pieces = s.split(':').peekable();
+    let mut raw_entries = Vec::new();
+    while let Some(piece) = pieces.next() {
+        let mut entry = piece.to_owned();
+        // The colon of `scheme://` is not a separator.
+        while let Some(rest) = pieces.next_if(|next| next.starts_with("//")) {
+            entry += ":";
+            entry += rest;
+        }
+        raw_entries.push(entry);
+    }
+
+    let entries = raw_entries
+        .iter()
+        .filter_map(|entry| {
+            let (prefix, path) = entry.split_once('=').unwrap_or(("", entry));
+            let path = PathBuf::from(path);
+            path.is_absolute().then(|| SearchPathEntry {
+                prefix: prefix.into(),
+                path: VfsPath::new(path),
+            })
+        })
+        .collect();
+    SearchPath { entries }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_nix_path;
+
+    #[test]
+    fn nix_path() {
+        let got = parse_nix_path("nixpkgs=/a/nixpkgs:/b:nixos=flake:nixpkgs:c=https://x.y:d=rel:")
+            .entries
+            .into_iter()
+            .map(|entry| format!("{}={}", entry.prefix, entry.path.display()))
+            .collect::<Vec<_>>();
+        assert_eq!(got, ["nixpkgs=/a/nixpkgs", "=/b"]);
+    }
 }
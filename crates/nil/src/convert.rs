@@ -1,16 +1,19 @@
+use crate::lsp_ext::{RECORD_COMPLETION_COMMAND, VIRTUAL_DOCUMENT_SCHEME};
 use crate::{semantic_tokens, LineMap, Result, Vfs};
 use async_lsp::{ErrorCode, ResponseError};
 use ide::{
     Assist, AssistKind, CompletionItem, CompletionItemKind, Diagnostic, FileId, FilePos, FileRange,
-    HlRange, HlRelated, HoverResult, Link, LinkTarget, NameKind, Severity, SymbolTree, TextEdit,
+    FoldKind, FoldRange, HlRange, HlRelated, HoverResult, InlayHint, InlayHintKind, Link,
+    LinkTarget, NameKind, Severity, SignatureInfo, SymbolLocation, SymbolTree, TextEdit,
     WorkspaceEdit,
 };
 use lsp_types::{
     self as lsp, CodeAction, CodeActionKind, CodeActionOrCommand, DiagnosticRelatedInformation,
     DiagnosticSeverity, DiagnosticTag, DocumentHighlight, DocumentHighlightKind, DocumentLink,
-    DocumentSymbol, Documentation, Hover, Location, MarkupContent, MarkupKind, NumberOrString,
-    Position, PrepareRenameResponse, Range, SemanticToken, SymbolKind, TextDocumentIdentifier,
-    TextDocumentPositionParams, Url,
+    DocumentSymbol, Documentation, FoldingRange, FoldingRangeKind, Hover, Location, MarkupContent,
+    MarkupKind, NumberOrString, ParameterInformation, ParameterLabel, Position,
+    PrepareRenameResponse, Range, SemanticToken, SignatureHelp, SignatureInformation,
+    SymbolInformation, SymbolKind, TextDocumentIdentifier, TextDocumentPositionParams, Url,
 };
 use nix_interop::DEFAULT_IMPORT_FILE;
 use std::sync::Arc;
@@ -49,13 +52,34 @@ pub(crate) fn from_range(
 pub(crate) fn to_location(vfs: &Vfs, frange: FileRange) -> Location {
     let uri = vfs.uri_for_file(frange.file_id);
     let line_map = vfs.line_map_for_file(frange.file_id);
-    Location::new(uri, to_range(&line_map, frange.range))
+    Location::new(uri, range_to_lsp(&line_map, frange.range))
 }
 
-pub(crate) fn to_range(line_map: &LineMap, range: TextRange) -> Range {
-    let (line1, col1) = line_map.line_col_for_pos(range.start());
-    let (line2, col2) = line_map.line_col_for_pos(range.end());
-    Range::new(Position::new(line1, col1), Position::new(line2, col2))
+pub(crate) fn to_builtin_document_uri(name: &str) -> Url {
+    format!("{VIRTUAL_DOCUMENT_SCHEME}:///builtins/{name}.md")
+        .parse()
+        .expect("Builtin names are valid in URI")
+}
+
+pub(crate) fn from_builtin_document_uri(uri: &Url) -> Option<&str> {
+    if uri.scheme() != VIRTUAL_DOCUMENT_SCHEME {
+        return None;
+    }
+    uri.path().strip_prefix("/builtins/")?.strip_suffix(".md")
+}
+
+pub(crate) fn to_position(line_map: &LineMap, pos: TextSize) -> Position {
+    let (line, col) = line_map.line_col_for_pos(pos);
+    Position::new(line, col)
+}
+
+/// Convert a range to LSP. The end of file is at the end of the last line, which is the start
+/// of an empty line if the text ends with a newline, eg. (1, 0) for `"foo\n"`.
+pub(crate) fn range_to_lsp(line_map: &LineMap, range: TextRange) -> Range {
+    Range::new(
+        to_position(line_map, range.start()),
+        to_position(line_map, range.end()),
+    )
 }
 
 pub(crate) fn to_diagnostics(
@@ -70,8 +94,10 @@ pub(crate) fn to_diagnostics(
             severity: match diag.severity() {
                 Severity::Error | Severity::IncompleteSyntax => Some(DiagnosticSeverity::ERROR),
                 Severity::Warning => Some(DiagnosticSeverity::WARNING),
+                Severity::Info => Some(DiagnosticSeverity::INFORMATION),
+                Severity::Hint => Some(DiagnosticSeverity::HINT),
             },
-            range: to_range(line_map, diag.range),
+            range: range_to_lsp(line_map, diag.range),
             code: Some(NumberOrString::String(diag.code().into())),
             code_description: None,
             source: None,
@@ -81,7 +107,10 @@ pub(crate) fn to_diagnostics(
                     diag.notes
                         .iter()
                         .map(|(frange, msg)| DiagnosticRelatedInformation {
-                            location: Location::new(uri.clone(), to_range(line_map, frange.range)),
+                            location: Location::new(
+                                uri.clone(),
+                                range_to_lsp(line_map, frange.range),
+                            ),
                             message: msg.to_owned(),
                         })
                         .collect(),
@@ -109,13 +138,13 @@ pub(crate) fn to_diagnostics(
 
             ret.push(lsp::Diagnostic {
                 severity: Some(DiagnosticSeverity::HINT),
-                range: to_range(line_map, frange.range),
+                range: range_to_lsp(line_map, frange.range),
                 code: primary_diag.code.clone(),
                 code_description: primary_diag.code_description.clone(),
                 source: primary_diag.source.clone(),
                 message: msg.into(),
                 related_information: Some(vec![DiagnosticRelatedInformation {
-                    location: Location::new(uri.clone(), to_range(line_map, diag.range)),
+                    location: Location::new(uri.clone(), range_to_lsp(line_map, diag.range)),
                     message: "original diagnostic".into(),
                 }]),
                 tags: None,
@@ -129,7 +158,14 @@ pub(crate) fn to_diagnostics(
     ret
 }
 
-pub(crate) fn to_completion_item(line_map: &LineMap, item: CompletionItem) -> lsp::CompletionItem {
+/// `recent_rank` is the recency of the label in the completion history, if it was used before.
+/// `trigger_suggest` is whether the client supports `editor.action.triggerSuggest`.
+pub(crate) fn to_completion_item(
+    line_map: &LineMap,
+    item: CompletionItem,
+    recent_rank: Option<usize>,
+    trigger_suggest: bool,
+) -> lsp::CompletionItem {
     let kind = match item.kind {
         CompletionItemKind::Keyword => lsp::CompletionItemKind::KEYWORD,
         CompletionItemKind::Param => lsp::CompletionItemKind::VARIABLE,
@@ -138,18 +174,57 @@ pub(crate) fn to_completion_item(line_map: &LineMap, item: CompletionItem) -> ls
         CompletionItemKind::BuiltinConst => lsp::CompletionItemKind::CONSTANT,
         CompletionItemKind::BuiltinFunction => lsp::CompletionItemKind::FUNCTION,
         CompletionItemKind::BuiltinAttrset => lsp::CompletionItemKind::CLASS,
+        CompletionItemKind::SearchPath => lsp::CompletionItemKind::FOLDER,
+        CompletionItemKind::File => lsp::CompletionItemKind::FILE,
+        CompletionItemKind::Folder => lsp::CompletionItemKind::FOLDER,
+    };
+    // Recently used labels come first, most recent first. Others are sorted by labels,
+    // except that files other than Nix files come last.
+    let sort_text = match recent_rank {
+        Some(rank) => format!("0{rank:02}"),
+        None if item.kind == CompletionItemKind::File && !item.label.ends_with(".nix") => {
+            format!("2{}", item.label)
+        }
+        None => format!("1{}", item.label),
+    };
+    // Continue completing inside of the directory if the client can. An item has only one
+    // command, so the folder is not recorded then.
+    let command = if item.kind == CompletionItemKind::Folder && trigger_suggest {
+        lsp::Command {
+            title: String::new(),
+            command: "editor.action.triggerSuggest".into(),
+            arguments: None,
+        }
+    } else {
+        lsp::Command {
+            title: String::new(),
+            command: RECORD_COMPLETION_COMMAND.into(),
+            arguments: Some(vec![item.label.to_string().into()]),
+        }
     };
     lsp::CompletionItem {
         label: item.label.into(),
+        sort_text: Some(sort_text),
+        command: Some(command),
         kind: Some(kind),
         insert_text: None,
-        insert_text_format: Some(lsp::InsertTextFormat::PLAIN_TEXT),
+        insert_text_format: Some(if item.is_snippet {
+            lsp::InsertTextFormat::SNIPPET
+        } else {
+            lsp::InsertTextFormat::PLAIN_TEXT
+        }),
         // We don't support indentation yet.
         insert_text_mode: Some(lsp::InsertTextMode::ADJUST_INDENTATION),
         text_edit: Some(lsp::CompletionTextEdit::Edit(lsp::TextEdit {
-            range: to_range(line_map, item.replace_range),
+            range: range_to_lsp(line_map, item.replace_range),
             new_text: item.replace.into(),
         })),
+        additional_text_edits: (!item.additional_edits.is_empty()).then(|| {
+            item.additional_edits
+                .into_iter()
+                .map(|edit| to_text_edit(line_map, edit))
+                .collect()
+        }),
         detail: item.description,
         documentation: item.documentation.map(|doc| {
             Documentation::MarkupContent(MarkupContent {
@@ -175,7 +250,7 @@ pub(crate) fn to_prepare_rename_response(
     range: TextRange,
     text: String,
 ) -> PrepareRenameResponse {
-    let range = to_range(line_map, range);
+    let range = range_to_lsp(line_map, range);
     PrepareRenameResponse::RangeWithPlaceholder {
         range,
         placeholder: text,
@@ -207,7 +282,7 @@ pub(crate) fn to_workspace_edit(vfs: &Vfs, ws_edit: WorkspaceEdit) -> lsp::Works
 
 pub(crate) fn to_text_edit(line_map: &LineMap, edit: TextEdit) -> lsp::TextEdit {
     lsp::TextEdit {
-        range: to_range(line_map, edit.delete),
+        range: range_to_lsp(line_map, edit.delete),
         new_text: edit.insert.into(),
     }
 }
@@ -220,7 +295,7 @@ pub(crate) fn to_semantic_tokens(line_map: &LineMap, hls: &[HlRange]) -> Vec<Sem
     let (mut prev_line, mut prev_start) = (0, 0);
     for hl in hls {
         let (ty_idx, mod_set) = semantic_tokens::to_semantic_type_and_modifiers(hl.tag);
-        let range = to_range(line_map, hl.range);
+        let range = range_to_lsp(line_map, hl.range);
         for line in range.start.line..=range.end.line.min(last_line) {
             // N.B. For relative encoding, column offset is relative to
             // the previous token *in the same line*.
@@ -262,7 +337,7 @@ pub(crate) fn to_semantic_tokens(line_map: &LineMap, hls: &[HlRange]) -> Vec<Sem
 
 pub(crate) fn to_hover(line_map: &LineMap, hover: HoverResult) -> Hover {
     Hover {
-        range: Some(to_range(line_map, hover.range)),
+        range: Some(range_to_lsp(line_map, hover.range)),
         contents: lsp::HoverContents::Markup(MarkupContent {
             kind: MarkupKind::Markdown,
             value: hover.markup,
@@ -284,29 +359,172 @@ fn to_document_symbol(line_map: &LineMap, sym: SymbolTree) -> DocumentSymbol {
     DocumentSymbol {
         name: sym.name.into(),
         detail: None,
-        kind: match sym.kind {
-            NameKind::PlainAttrset | NameKind::RecAttrset => SymbolKind::FIELD,
-            NameKind::LetIn | NameKind::Param | NameKind::PatField => SymbolKind::VARIABLE,
-        },
+        kind: to_symbol_kind(sym.kind, sym.is_function),
         tags: None,
         deprecated: None,
-        range: to_range(line_map, sym.full_range),
-        selection_range: to_range(line_map, sym.focus_range),
+        range: range_to_lsp(line_map, sym.full_range),
+        selection_range: range_to_lsp(line_map, sym.focus_range),
         children: Some(to_document_symbols(line_map, sym.children)),
     }
 }
 
-pub(crate) fn to_code_action(vfs: &Vfs, assist: Assist) -> CodeActionOrCommand {
+pub(crate) fn to_symbol_information(vfs: &Vfs, sym: SymbolLocation) -> SymbolInformation {
+    #[allow(deprecated)]
+    SymbolInformation {
+        name: sym.name.into(),
+        kind: to_symbol_kind(sym.kind, sym.is_function),
+        tags: None,
+        deprecated: None,
+        location: to_location(vfs, sym.file_range),
+        container_name: sym.container_name.map(Into::into),
+    }
+}
+
+pub(crate) fn to_folding_range(line_map: &LineMap, fold: FoldRange) -> Option<FoldingRange> {
+    let (start_line, _) = line_map.line_col_for_pos(fold.range.start());
+    let (mut end_line, _) = line_map.line_col_for_pos(fold.range.end());
+    // Clients may only fold whole lines. Keep the line with the closing delimiter visible.
+    if fold.kind == FoldKind::Region {
+        end_line -= 1;
+    }
+    if start_line >= end_line {
+        return None;
+    }
+    Some(FoldingRange {
+        start_line,
+        start_character: None,
+        end_line,
+        end_character: None,
+        kind: Some(match fold.kind {
+            FoldKind::Region => FoldingRangeKind::Region,
+            FoldKind::Comment => FoldingRangeKind::Comment,
+        }),
+        collapsed_text: None,
+    })
+}
+
+pub(crate) fn to_inlay_hint(line_map: &LineMap, file_uri: &Url, hint: InlayHint) -> lsp::InlayHint {
+    let kind = match hint.kind {
+        InlayHintKind::DefaultValue => "default_value",
+        InlayHintKind::AttrName => "attr_name",
+    };
+    lsp::InlayHint {
+        position: to_position(line_map, hint.pos),
+        label: lsp::InlayHintLabel::String(hint.label),
+        kind: None,
+        text_edits: None,
+        tooltip: hint.tooltip.map(lsp::InlayHintTooltip::String),
+        padding_left: Some(true),
+        padding_right: None,
+        // Pass the URI and the kind to `InlayHintResolveRequest`.
+        data: Some(serde_json::json!({ "uri": file_uri, "kind": kind })),
+    }
+}
+
+pub(crate) fn from_inlay_hint(
+    vfs: &Vfs,
+    hint: &lsp::InlayHint,
+) -> Result<(Url, FilePos, InlayHintKind, Arc<LineMap>)> {
+    let data = hint.data.as_ref();
+    let uri = data
+        .and_then(|v| v["uri"].as_str())
+        .and_then(|s| Url::parse(s).ok());
+    let kind = data.and_then(|v| match v["kind"].as_str()? {
+        "default_value" => Some(InlayHintKind::DefaultValue),
+        "attr_name" => Some(InlayHintKind::AttrName),
+        _ => None,
+    });
+    let (Some(uri), Some(kind)) = (uri, kind) else {
+        return Err(ResponseError::new(ErrorCode::INVALID_PARAMS, "invalid `data` field").into());
+    };
+    let file_id = vfs.file_for_uri(&uri)?;
+    let line_map = vfs.line_map_for_file(file_id);
+    let pos = from_pos(&line_map, hint.position)?;
+    Ok((uri, FilePos::new(file_id, pos), kind, line_map))
+}
+
+pub(crate) fn to_signature_help(info: SignatureInfo) -> SignatureHelp {
+    let active_parameter = info.active_parameter.map(|idx| idx as u32);
+    let parameters = info
+        .parameters
+        .into_iter()
+        .map(|label| ParameterInformation {
+            label: ParameterLabel::Simple(label),
+            documentation: None,
+        })
+        .collect();
+    SignatureHelp {
+        signatures: vec![SignatureInformation {
+            label: info.label,
+            documentation: None,
+            parameters: Some(parameters),
+            active_parameter,
+        }],
+        active_signature: Some(0),
+        active_parameter,
+    }
+}
+
+fn to_symbol_kind(kind: NameKind, is_function: bool) -> SymbolKind {
+    if is_function {
+        return SymbolKind::FUNCTION;
+    }
+    match kind {
+        NameKind::PlainAttrset | NameKind::RecAttrset => SymbolKind::FIELD,
+        NameKind::LetIn | NameKind::Param | NameKind::PatField => SymbolKind::VARIABLE,
+    }
+}
+
+/// Convert an assist to a code action, attaching the diagnostics in `context_diags` it fixes.
+/// `rename_command` is whether the client supports `editor.action.rename`.
+pub(crate) fn to_code_action(
+    vfs: &Vfs,
+    assist: Assist,
+    context_diags: &[lsp::Diagnostic],
+    rename_command: bool,
+) -> CodeActionOrCommand {
+    let diagnostics = assist
+        .fixes
+        .map(|kind| {
+            let code = NumberOrString::String(kind.code().into());
+            let fixed_ranges = assist
+                .edits
+                .content_edits
+                .iter()
+                .flat_map(|(&file, edits)| {
+                    let line_map = vfs.line_map_for_file(file);
+                    edits
+                        .iter()
+                        .map(move |edit| range_to_lsp(&line_map, edit.delete))
+                })
+                .collect::<Vec<_>>();
+            context_diags
+                .iter()
+                .filter(|diag| {
+                    diag.code.as_ref() == Some(&code)
+                        && fixed_ranges.iter().any(|range| {
+                            range.start <= diag.range.end && diag.range.start <= range.end
+                        })
+                })
+                .cloned()
+                .collect::<Vec<_>>()
+        })
+        .filter(|diags| !diags.is_empty());
     CodeActionOrCommand::CodeAction(CodeAction {
         title: assist.label,
         kind: Some(match assist.kind {
             AssistKind::QuickFix => CodeActionKind::QUICKFIX,
             AssistKind::RefactorRewrite => CodeActionKind::REFACTOR_REWRITE,
         }),
-        diagnostics: None,
+        is_preferred: diagnostics.is_some().then_some(true),
+        diagnostics,
+        // The command runs after the edit, renaming the new name under the cursor.
+        command: (assist.rename && rename_command).then(|| lsp::Command {
+            title: "Rename".into(),
+            command: "editor.action.rename".into(),
+            arguments: None,
+        }),
         edit: Some(to_workspace_edit(vfs, assist.edits)),
-        command: None,
-        is_preferred: None,
         disabled: None,
         data: None,
     })
@@ -318,7 +536,7 @@ pub(crate) fn to_document_highlight(
 ) -> Vec<DocumentHighlight> {
     hls.iter()
         .map(|hl| DocumentHighlight {
-            range: to_range(line_map, hl.range),
+            range: range_to_lsp(line_map, hl.range),
             kind: Some(if hl.is_definition {
                 DocumentHighlightKind::WRITE
             } else {
@@ -360,7 +578,7 @@ pub(crate) fn to_document_link(
         }
     };
     Some(DocumentLink {
-        range: to_range(line_map, range),
+        range: range_to_lsp(line_map, range),
         target,
         tooltip,
         // Pass the URI to `DocumentLinkResolve`.
@@ -1,24 +1,33 @@
-use crate::{convert, StateSnapshot};
+use crate::{convert, lsp_ext, scan, semantic_tokens, LineMap, StateSnapshot, UrlExt};
 use anyhow::{ensure, Context, Result};
 use async_lsp::{ErrorCode, ResponseError};
-use ide::{FileRange, GotoDefinitionResult};
+use ide::{AnalysisHost, AssistKind, FileRange, GotoDefinitionResult};
 use lsp_types::{
     CodeActionParams, CodeActionResponse, CompletionParams, CompletionResponse,
     DocumentFormattingParams, DocumentHighlight, DocumentHighlightParams, DocumentLink,
-    DocumentLinkParams, DocumentSymbolParams, DocumentSymbolResponse, GotoDefinitionParams,
-    GotoDefinitionResponse, Hover, HoverParams, Location, Position, PrepareRenameResponse, Range,
-    ReferenceParams, RenameParams, SelectionRange, SelectionRangeParams, SemanticTokens,
-    SemanticTokensParams, SemanticTokensRangeParams, SemanticTokensRangeResult,
-    SemanticTokensResult, TextDocumentPositionParams, TextEdit, Url, WorkspaceEdit,
+    DocumentLinkParams, DocumentRangeFormattingParams, DocumentSymbolParams,
+    DocumentSymbolResponse, ExecuteCommandParams, FoldingRange, FoldingRangeParams,
+    GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverParams, InlayHint, InlayHintParams,
+    Location, PrepareRenameResponse, Range, ReferenceParams, RenameParams, SelectionRange,
+    SelectionRangeParams, SemanticTokens, SemanticTokensDelta, SemanticTokensDeltaParams,
+    SemanticTokensFullDeltaResult, SemanticTokensParams, SemanticTokensRangeParams,
+    SemanticTokensRangeResult, SemanticTokensResult, SignatureHelp, SignatureHelpParams,
+    TextDocumentPositionParams, TextDocumentSaveReason, TextEdit, Url, WillSaveTextDocumentParams,
+    WorkspaceEdit, WorkspaceSymbolParams, WorkspaceSymbolResponse,
 };
 use nix_interop::DEFAULT_IMPORT_FILE;
-use std::process;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use text_size::TextRange;
+use std::{fs, process};
+use text_size::{TextRange, TextSize};
+
+/// Limit the number of results to keep the client responsive on large workspaces.
+const MAX_WORKSPACE_SYMBOLS: usize = 128;
 
 pub(crate) fn goto_definition(
     snap: StateSnapshot,
-    params: GotoDefinitionParams,
+    params: &GotoDefinitionParams,
 ) -> Result<Option<GotoDefinitionResponse>> {
     let (fpos, _) = convert::from_file_pos(&snap.vfs(), &params.text_document_position_params)?;
     let ret = snap.analysis.goto_definition(fpos)?;
@@ -26,15 +35,7 @@ pub(crate) fn goto_definition(
     let targets = match ret {
         None => return Ok(None),
         Some(GotoDefinitionResult::Path(vpath)) => {
-            let Some(path) = vpath.as_path() else {
-                return Ok(None);
-            };
-            let default_child = path.join(DEFAULT_IMPORT_FILE);
-            let target_path = if path.is_file() {
-                path
-            } else if default_child.is_file() {
-                &default_child
-            } else {
+            let Some(target_path) = vpath.as_path().and_then(imported_file_path) else {
                 return Ok(None);
             };
             vec![Location {
@@ -42,6 +43,41 @@ pub(crate) fn goto_definition(
                 range: Range::default(),
             }]
         }
+        // Files not loaded are read from disk and analyzed alone, to look the attribute up.
+        Some(GotoDefinitionResult::ImportedAttr { path, attrpath }) => {
+            let Some(target_path) = path.as_path().and_then(imported_file_path) else {
+                return Ok(None);
+            };
+            let src = match fs::read_to_string(&target_path) {
+                Ok(src) if src.len() <= vfs.max_file_len() => src,
+                Ok(_) => return Ok(None),
+                Err(err) => {
+                    tracing::warn!("Ignore file {target_path:?}: {err}");
+                    return Ok(None);
+                }
+            };
+            let (src, line_map) = LineMap::normalize(src);
+            let (host, file) = AnalysisHost::new_single_file(&src);
+            let uri = Url::from_file_path(&target_path).unwrap();
+            host.snapshot()
+                .attr_definitions(file, &attrpath)?
+                .into_iter()
+                .map(|target| {
+                    Location::new(
+                        uri.clone(),
+                        convert::range_to_lsp(&line_map, target.focus_range),
+                    )
+                })
+                .collect()
+        }
+        // The document can only be opened if the client fetches its content from us.
+        Some(GotoDefinitionResult::Builtin(_)) if !snap.capabilities.text_document_content => {
+            return Ok(None);
+        }
+        Some(GotoDefinitionResult::Builtin(name)) => vec![Location {
+            uri: convert::to_builtin_document_uri(name),
+            range: Range::default(),
+        }],
         Some(GotoDefinitionResult::Targets(targets)) => targets
             .into_iter()
             .map(|target| {
@@ -52,12 +88,40 @@ pub(crate) fn goto_definition(
     Ok(Some(GotoDefinitionResponse::Array(targets)))
 }
 
+/// The file imported by `import path`, which is `path` itself or its `default.nix`.
+fn imported_file_path(path: &Path) -> Option<PathBuf> {
+    let default_child = path.join(DEFAULT_IMPORT_FILE);
+    if path.is_file() {
+        Some(path.to_owned())
+    } else if default_child.is_file() {
+        Some(default_child)
+    } else {
+        None
+    }
+}
+
+pub(crate) fn text_document_content(
+    _snap: StateSnapshot,
+    params: &lsp_ext::TextDocumentContentParams,
+) -> Result<lsp_ext::TextDocumentContentResult> {
+    let text = convert::from_builtin_document_uri(&params.uri)
+        .and_then(ide::builtin_document)
+        .ok_or_else(|| {
+            anyhow::Error::new(ResponseError::new(
+                ErrorCode::INVALID_PARAMS,
+                format!("unknown document: {}", params.uri),
+            ))
+        })?;
+    Ok(lsp_ext::TextDocumentContentResult { text })
+}
+
 pub(crate) fn references(
     snap: StateSnapshot,
-    params: ReferenceParams,
+    params: &ReferenceParams,
 ) -> Result<Option<Vec<Location>>> {
     let (fpos, _) = convert::from_file_pos(&snap.vfs(), &params.text_document_position)?;
-    let Some(refs) = snap.analysis.references(fpos)? else {
+    let include_declaration = params.context.include_declaration;
+    let Some(refs) = snap.analysis.references(fpos, include_declaration)? else {
         return Ok(None);
     };
     let vfs = snap.vfs();
@@ -70,23 +134,45 @@ pub(crate) fn references(
 
 pub(crate) fn completion(
     snap: StateSnapshot,
-    params: CompletionParams,
+    params: &CompletionParams,
 ) -> Result<Option<CompletionResponse>> {
     let (fpos, line_map) = convert::from_file_pos(&snap.vfs(), &params.text_document_position)?;
     let trigger_char = params
         .context
-        .and_then(|ctx| ctx.trigger_character?.chars().next());
-    let items = snap.analysis.completions(fpos, trigger_char)?;
+        .as_ref()
+        .and_then(|ctx| ctx.trigger_character.as_ref()?.chars().next());
+    let mut items = snap.analysis.completions(fpos, trigger_char)?;
+    // The Vfs only contains Nix files of the workspace, so also list the directory on disk.
+    if let Some(path_ctx) = snap.analysis.path_completion_context(fpos)? {
+        if let Some(dir) = path_ctx.dir.as_path() {
+            let known = items
+                .iter()
+                .map(|item| item.label.clone())
+                .collect::<HashSet<_>>();
+            // Only entries from the Vfs are returned if the disk is too slow.
+            let entries = scan::list_dir_with_timeout(dir, scan::LIST_DIR_TIMEOUT);
+            let on_disk = path_ctx.items(entries.unwrap_or_default());
+            items.extend(
+                on_disk
+                    .into_iter()
+                    .filter(|item| !known.contains(&item.label)),
+            );
+        }
+    }
+    let history = snap.completion_history.lock().unwrap();
     let items = items
         .into_iter()
-        .map(|item| convert::to_completion_item(&line_map, item))
+        .map(|item| {
+            let rank = history.rank(&item.label);
+            convert::to_completion_item(&line_map, item, rank, snap.capabilities.vscode_commands)
+        })
         .collect::<Vec<_>>();
     Ok(Some(CompletionResponse::Array(items)))
 }
 
 pub(crate) fn selection_range(
     snap: StateSnapshot,
-    params: SelectionRangeParams,
+    params: &SelectionRangeParams,
 ) -> Result<Option<Vec<SelectionRange>>> {
     let (file, line_map) = convert::from_file(&snap.vfs(), &params.text_document)?;
     let ret = params
@@ -102,12 +188,12 @@ pub(crate) fn selection_range(
             }
 
             let mut ret = SelectionRange {
-                range: convert::to_range(&line_map, *ranges.last().unwrap()),
+                range: convert::range_to_lsp(&line_map, *ranges.last().unwrap()),
                 parent: None,
             };
             for &r in ranges.iter().rev().skip(1) {
                 ret = SelectionRange {
-                    range: convert::to_range(&line_map, r),
+                    range: convert::range_to_lsp(&line_map, r),
                     parent: Some(ret.into()),
                 };
             }
@@ -120,9 +206,9 @@ pub(crate) fn selection_range(
 
 pub(crate) fn prepare_rename(
     snap: StateSnapshot,
-    params: TextDocumentPositionParams,
+    params: &TextDocumentPositionParams,
 ) -> Result<Option<PrepareRenameResponse>> {
-    let (fpos, line_map) = convert::from_file_pos(&snap.vfs(), &params)?;
+    let (fpos, line_map) = convert::from_file_pos(&snap.vfs(), params)?;
     let (range, text) = snap
         .analysis
         .prepare_rename(fpos)?
@@ -131,7 +217,7 @@ pub(crate) fn prepare_rename(
     Ok(Some(resp))
 }
 
-pub(crate) fn rename(snap: StateSnapshot, params: RenameParams) -> Result<Option<WorkspaceEdit>> {
+pub(crate) fn rename(snap: StateSnapshot, params: &RenameParams) -> Result<Option<WorkspaceEdit>> {
     let (fpos, _) = convert::from_file_pos(&snap.vfs(), &params.text_document_position)?;
     let ws_edit = snap
         .analysis
@@ -143,28 +229,77 @@ pub(crate) fn rename(snap: StateSnapshot, params: RenameParams) -> Result<Option
 
 pub(crate) fn semantic_token_full(
     snap: StateSnapshot,
-    params: SemanticTokensParams,
+    params: &SemanticTokensParams,
 ) -> Result<Option<SemanticTokensResult>> {
+    if !snap.config.features.semantic_tokens {
+        return Ok(None);
+    }
     let (file, line_map) = convert::from_file(&snap.vfs(), &params.text_document)?;
     let hls = snap.analysis.syntax_highlight(file, None)?;
     let toks = convert::to_semantic_tokens(&line_map, &hls);
+    let result_id = snap
+        .semantic_tokens_cache
+        .lock()
+        .unwrap()
+        .insert(params.text_document.uri.clone(), toks.clone());
     Ok(Some(SemanticTokensResult::Tokens(SemanticTokens {
-        result_id: None,
+        result_id: Some(result_id),
         data: toks,
     })))
 }
 
+pub(crate) fn semantic_token_full_delta(
+    snap: StateSnapshot,
+    params: &SemanticTokensDeltaParams,
+) -> Result<Option<SemanticTokensFullDeltaResult>> {
+    if !snap.config.features.semantic_tokens {
+        return Ok(None);
+    }
+    let (file, line_map) = convert::from_file(&snap.vfs(), &params.text_document)?;
+    let hls = snap.analysis.syntax_highlight(file, None)?;
+    let toks = convert::to_semantic_tokens(&line_map, &hls);
+
+    let uri = &params.text_document.uri;
+    let mut cache = snap.semantic_tokens_cache.lock().unwrap();
+    // Unknown or stale ids fall back to full results.
+    let edits = cache
+        .get(uri, &params.previous_result_id)
+        .map(|prev| semantic_tokens::diff_tokens(prev, &toks));
+    let result_id = cache.insert(uri.clone(), toks.clone());
+    Ok(Some(match edits {
+        Some(edits) => SemanticTokensFullDeltaResult::TokensDelta(SemanticTokensDelta {
+            result_id: Some(result_id),
+            edits,
+        }),
+        None => SemanticTokensFullDeltaResult::Tokens(SemanticTokens {
+            result_id: Some(result_id),
+            data: toks,
+        }),
+    }))
+}
+
 pub(crate) fn semantic_token_range(
     snap: StateSnapshot,
-    params: SemanticTokensRangeParams,
+    params: &SemanticTokensRangeParams,
 ) -> Result<Option<SemanticTokensRangeResult>> {
+    if !snap.config.features.semantic_tokens {
+        return Ok(None);
+    }
     let (file, range, line_map) = {
         let vfs = snap.vfs();
         let (file, line_map) = convert::from_file(&vfs, &params.text_document)?;
         let (_, range) = convert::from_range(&vfs, file, params.range)?;
         (file, range, line_map)
     };
-    let hls = snap.analysis.syntax_highlight(file, Some(range))?;
+    let mut hls = snap.analysis.syntax_highlight(file, Some(range))?;
+    // Tokens straddling the boundary are clipped, rather than dropped.
+    hls.retain_mut(|hl| match hl.range.intersect(range) {
+        Some(clipped) if !clipped.is_empty() => {
+            hl.range = clipped;
+            true
+        }
+        _ => false,
+    });
     let toks = convert::to_semantic_tokens(&line_map, &hls);
     Ok(Some(SemanticTokensRangeResult::Tokens(SemanticTokens {
         result_id: None,
@@ -172,62 +307,142 @@ pub(crate) fn semantic_token_range(
     })))
 }
 
-pub(crate) fn hover(snap: StateSnapshot, params: HoverParams) -> Result<Option<Hover>> {
+pub(crate) fn hover(snap: StateSnapshot, params: &HoverParams) -> Result<Option<Hover>> {
     let (fpos, line_map) =
         convert::from_file_pos(&snap.vfs(), &params.text_document_position_params)?;
     let ret = snap.analysis.hover(fpos)?;
     Ok(ret.map(|hover| convert::to_hover(&line_map, hover)))
 }
 
+pub(crate) fn signature_help(
+    snap: StateSnapshot,
+    params: &SignatureHelpParams,
+) -> Result<Option<SignatureHelp>> {
+    let (fpos, _) = convert::from_file_pos(&snap.vfs(), &params.text_document_position_params)?;
+    let ret = snap.analysis.signature_help(fpos)?;
+    Ok(ret.map(convert::to_signature_help))
+}
+
 pub(crate) fn document_symbol(
     snap: StateSnapshot,
-    params: DocumentSymbolParams,
+    params: &DocumentSymbolParams,
 ) -> Result<Option<DocumentSymbolResponse>> {
+    if !snap.config.features.document_symbol {
+        return Ok(None);
+    }
     let (file, line_map) = convert::from_file(&snap.vfs(), &params.text_document)?;
     let syms = snap.analysis.symbol_hierarchy(file)?;
     let syms = convert::to_document_symbols(&line_map, syms);
     Ok(Some(DocumentSymbolResponse::Nested(syms)))
 }
 
+pub(crate) fn folding_range(
+    snap: StateSnapshot,
+    params: &FoldingRangeParams,
+) -> Result<Option<Vec<FoldingRange>>> {
+    if !snap.config.features.folding_range {
+        return Ok(None);
+    }
+    let (file, line_map) = convert::from_file(&snap.vfs(), &params.text_document)?;
+    let folds = snap.analysis.folding_ranges(file)?;
+    let folds = folds
+        .into_iter()
+        .filter_map(|fold| convert::to_folding_range(&line_map, fold))
+        .collect();
+    Ok(Some(folds))
+}
+
+pub(crate) fn inlay_hint(
+    snap: StateSnapshot,
+    params: &InlayHintParams,
+) -> Result<Option<Vec<InlayHint>>> {
+    if !snap.config.features.inlay_hint {
+        return Ok(None);
+    }
+    let (file, _) = convert::from_file(&snap.vfs(), &params.text_document)?;
+    let (line_map, range) = convert::from_range(&snap.vfs(), file, params.range)?;
+    let hints = snap.analysis.inlay_hints(FileRange::new(file, range))?;
+    let hints = hints
+        .into_iter()
+        .map(|hint| convert::to_inlay_hint(&line_map, &params.text_document.uri, hint))
+        .collect();
+    Ok(Some(hints))
+}
+
+pub(crate) fn inlay_hint_resolve(snap: StateSnapshot, params: &InlayHint) -> Result<InlayHint> {
+    let (uri, fpos, kind, line_map) = convert::from_inlay_hint(&snap.vfs(), params)?;
+    // The document may have changed since the hint was sent. Keep it as is then.
+    Ok(snap.analysis.inlay_hint_resolve(fpos, kind)?.map_or_else(
+        || params.clone(),
+        |hint| convert::to_inlay_hint(&line_map, &uri, hint),
+    ))
+}
+
+pub(crate) fn workspace_symbol(
+    snap: StateSnapshot,
+    params: &WorkspaceSymbolParams,
+) -> Result<Option<WorkspaceSymbolResponse>> {
+    let syms = snap
+        .analysis
+        .workspace_symbols(&params.query, MAX_WORKSPACE_SYMBOLS)?;
+    let vfs = snap.vfs();
+    let syms = syms
+        .into_iter()
+        .map(|sym| convert::to_symbol_information(&vfs, sym))
+        .collect();
+    Ok(Some(WorkspaceSymbolResponse::Flat(syms)))
+}
+
+fn run_with_stdin(cmd: &[String], stdin_data: impl AsRef<[u8]> + Send + 'static) -> Result<String> {
+    let mut child = process::Command::new(&cmd[0])
+        .args(&cmd[1..])
+        .stdin(process::Stdio::piped())
+        .stdout(process::Stdio::piped())
+        .stderr(process::Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().unwrap();
+    std::thread::spawn(move || {
+        let _ = std::io::copy(&mut stdin_data.as_ref(), &mut stdin);
+    });
+    let output = child.wait_with_output()?;
+    ensure!(
+        output.status.success(),
+        "Formatter exited with {}, stderr: {}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr),
+    );
+    let stdout = String::from_utf8(output.stdout)?;
+    Ok(stdout)
+}
+
 // FIXME: This is sync now.
 pub(crate) fn formatting(
     snap: StateSnapshot,
     params: DocumentFormattingParams,
 ) -> Result<Option<Vec<TextEdit>>> {
-    fn run_with_stdin(
-        cmd: &[String],
-        stdin_data: impl AsRef<[u8]> + Send + 'static,
-    ) -> Result<String> {
-        let mut child = process::Command::new(&cmd[0])
-            .args(&cmd[1..])
-            .stdin(process::Stdio::piped())
-            .stdout(process::Stdio::piped())
-            .stderr(process::Stdio::piped())
-            .spawn()?;
-        let mut stdin = child.stdin.take().unwrap();
-        std::thread::spawn(move || {
-            let _ = std::io::copy(&mut stdin_data.as_ref(), &mut stdin);
-        });
-        let output = child.wait_with_output()?;
-        ensure!(
-            output.status.success(),
-            "Formatter exited with {}, stderr: {}",
-            output.status,
-            String::from_utf8_lossy(&output.stderr),
-        );
-        let stdout = String::from_utf8(output.stdout)?;
-        Ok(stdout)
-    }
-
-    let cmd =
-        snap.config.formatting_command.as_ref().context(
-            "No formatter configured. Set the nil.formatting.command LSP server setting.",
-        )?;
-
-    let (file_content, line_map) = {
+    // NB. `params.options` like `tabSize` are ignored. External formatters have their own
+    // configurations, and trimming only removes whitespaces.
+    let (file, file_content, line_map) = {
         let vfs = snap.vfs();
         let (file, line_map) = convert::from_file(&vfs, &params.text_document)?;
-        (vfs.content_for_file(file), line_map)
+        (file, vfs.content_for_file(file), line_map)
+    };
+
+    // The external formatter takes precedence, since it already handles trailing whitespaces.
+    let Some(cmd) = &snap.config.formatting_command else {
+        ensure!(
+            snap.config.formatting_trim_trailing_whitespace,
+            "No formatter configured. Set the nil.formatting.command LSP server setting.",
+        );
+        let edits = snap.analysis.trim_trailing_whitespace(file)?;
+        if edits.is_empty() {
+            return Ok(None);
+        }
+        let edits = edits
+            .into_iter()
+            .map(|edit| convert::to_text_edit(&line_map, edit))
+            .collect();
+        return Ok(Some(edits));
     };
 
     let new_content = run_with_stdin(cmd, <Arc<[u8]>>::from(file_content.clone()))
@@ -238,26 +453,196 @@ pub(crate) fn formatting(
     }
 
     // Replace the whole file.
-    let last_line = line_map.last_line();
     Ok(Some(vec![TextEdit {
-        range: Range {
-            start: Position {
-                line: 0,
-                character: 0,
-            },
-            end: Position {
-                line: last_line,
-                character: line_map.end_col_for_line(last_line),
-            },
-        },
+        range: convert::range_to_lsp(&line_map, TextRange::up_to(line_map.end_pos())),
         new_text: new_content,
     }]))
 }
 
+/// Format the smallest binding or list containing the range.
+///
+/// Lists are formatted alone and re-indented to their original place. Otherwise, since Nix
+/// formatters only accept whole files, the whole file is formatted and only changes touching
+/// the range are kept.
+pub(crate) fn range_formatting(
+    snap: StateSnapshot,
+    params: DocumentRangeFormattingParams,
+) -> Result<Option<Vec<TextEdit>>> {
+    let (file, file_content, line_map, range) = {
+        let vfs = snap.vfs();
+        let (file, _) = convert::from_file(&vfs, &params.text_document)?;
+        let (line_map, range) = convert::from_range(&vfs, file, params.range)?;
+        (file, vfs.content_for_file(file), line_map, range)
+    };
+    let target = snap
+        .analysis
+        .formatting_range(FileRange::new(file, range))?;
+
+    let edits = match &snap.config.formatting_command {
+        None => {
+            ensure!(
+                snap.config.formatting_trim_trailing_whitespace,
+                "No formatter configured. Set the nil.formatting.command LSP server setting.",
+            );
+            let mut edits = snap.analysis.trim_trailing_whitespace(file)?;
+            edits.retain(|edit| edit_within(target.range, edit));
+            edits
+        }
+        Some(cmd) if target.is_standalone => {
+            let text = <Arc<[u8]>>::from(file_content[target.range].as_bytes());
+            let formatted = run_with_stdin(cmd, text)
+                .with_context(|| format!("Failed to run formatter {cmd:?}"))?;
+            let line_start = file_content[..usize::from(target.range.start())]
+                .rfind('\n')
+                .map_or(0, |i| i + 1);
+            let indent = &file_content[line_start..];
+            let indent = &indent[..indent.len() - indent.trim_start_matches([' ', '\t']).len()];
+            let formatted = reindent(formatted.trim_end_matches('\n'), indent);
+            if formatted == file_content[target.range] {
+                Vec::new()
+            } else {
+                vec![ide::TextEdit {
+                    delete: target.range,
+                    insert: formatted.into(),
+                }]
+            }
+        }
+        Some(cmd) => {
+            let new_content = run_with_stdin(cmd, <Arc<[u8]>>::from(file_content.clone()))
+                .with_context(|| format!("Failed to run formatter {cmd:?}"))?;
+            let mut edits = diff(&file_content, &new_content);
+            edits.retain(|edit| edit_within(target.range, edit));
+            edits
+        }
+    };
+
+    if edits.is_empty() {
+        return Ok(None);
+    }
+    let edits = edits
+        .into_iter()
+        .map(|edit| convert::to_text_edit(&line_map, edit))
+        .collect();
+    Ok(Some(edits))
+}
+
+/// Whether `edit` changes text inside `range`. Edits only touching its boundaries are excluded,
+/// except insertions exactly on them.
+fn edit_within(range: TextRange, edit: &ide::TextEdit) -> bool {
+    if edit.delete.is_empty() {
+        range.contains_range(edit.delete)
+    } else {
+        range
+            .intersect(edit.delete)
+            .is_some_and(|common| !common.is_empty())
+    }
+}
+
+/// Indent all lines of `text` but the first one by `indent`. Empty lines are kept empty.
+fn reindent(text: &str, indent: &str) -> String {
+    let mut ret = String::with_capacity(text.len());
+    for (i, line) in text.split('\n').enumerate() {
+        if i != 0 {
+            ret.push('\n');
+            if !line.is_empty() {
+                ret.push_str(indent);
+            }
+        }
+        ret.push_str(line);
+    }
+    ret
+}
+
+/// Minimal edits changing `old` into `new`.
+fn diff(old: &str, new: &str) -> Vec<ide::TextEdit> {
+    let mut edits = Vec::new();
+    let mut pos = TextSize::from(0);
+    let mut pending: Option<(TextRange, String)> = None;
+    for chunk in dissimilar::diff(old, new) {
+        match chunk {
+            dissimilar::Chunk::Equal(text) => {
+                if let Some((delete, insert)) = pending.take() {
+                    edits.push(ide::TextEdit {
+                        delete,
+                        insert: insert.into(),
+                    });
+                }
+                pos += TextSize::of(text);
+            }
+            dissimilar::Chunk::Delete(text) => {
+                let (delete, _) = pending.get_or_insert((TextRange::empty(pos), String::new()));
+                pos += TextSize::of(text);
+                *delete = delete.cover_offset(pos);
+            }
+            dissimilar::Chunk::Insert(text) => {
+                let (_, insert) = pending.get_or_insert((TextRange::empty(pos), String::new()));
+                insert.push_str(text);
+            }
+        }
+    }
+    if let Some((delete, insert)) = pending {
+        edits.push(ide::TextEdit {
+            delete,
+            insert: insert.into(),
+        });
+    }
+    edits
+}
+
+pub(crate) fn will_save_wait_until(
+    snap: StateSnapshot,
+    params: WillSaveTextDocumentParams,
+) -> Result<Option<Vec<TextEdit>>> {
+    // Auto-saves can happen while typing, where edits would be surprising.
+    if params.reason != TextDocumentSaveReason::MANUAL {
+        return Ok(None);
+    }
+
+    let (file, line_map) = convert::from_file(&snap.vfs(), &params.text_document)?;
+    let config = &snap.config;
+    let mut edits = if config.fix_on_save {
+        snap.analysis.batch_fixes(file)?
+    } else {
+        Vec::new()
+    };
+
+    // Whitespaces are left to format-on-save if formatting is enabled, otherwise both would
+    // edit the same places.
+    if !config.formatting_enabled() {
+        let mut ws_edits = Vec::new();
+        if config.on_save_trim_trailing_whitespace {
+            ws_edits.extend(snap.analysis.trim_trailing_whitespace(file)?);
+        }
+        if config.on_save_insert_final_newline {
+            ws_edits.extend(snap.analysis.insert_final_newline(file)?);
+        }
+        // Fixes may remove whitespaces together.
+        ws_edits.retain(|ws_edit| {
+            edits
+                .iter()
+                .all(|edit| edit.delete.intersect(ws_edit.delete).is_none())
+        });
+        edits.extend(ws_edits);
+    }
+
+    if edits.is_empty() {
+        return Ok(None);
+    }
+    edits.sort_by_key(|edit| edit.delete.start());
+    let edits = edits
+        .into_iter()
+        .map(|edit| convert::to_text_edit(&line_map, edit))
+        .collect();
+    Ok(Some(edits))
+}
+
 pub(crate) fn document_links(
     snap: StateSnapshot,
-    params: DocumentLinkParams,
+    params: &DocumentLinkParams,
 ) -> Result<Option<Vec<DocumentLink>>> {
+    if !snap.config.features.document_link {
+        return Ok(None);
+    }
     let (file, line_map) = convert::from_file(&snap.vfs(), &params.text_document)?;
     let links = snap.analysis.links(file)?;
     let links = links
@@ -269,9 +654,9 @@ pub(crate) fn document_links(
 
 pub(crate) fn document_link_resolve(
     snap: StateSnapshot,
-    params: DocumentLink,
+    params: &DocumentLink,
 ) -> Result<DocumentLink> {
-    let (uri, frange, line_map) = convert::from_document_link(&snap.vfs(), &params)?;
+    let (uri, frange, line_map) = convert::from_document_link(&snap.vfs(), params)?;
     snap.analysis
         .link_resolve(frange)?
         .and_then(|link| convert::to_document_link(&line_map, &uri, link))
@@ -285,22 +670,34 @@ pub(crate) fn document_link_resolve(
 
 pub(crate) fn code_action(
     snap: StateSnapshot,
-    params: CodeActionParams,
+    params: &CodeActionParams,
 ) -> Result<Option<CodeActionResponse>> {
     let (file_id, _) = convert::from_file(&snap.vfs(), &params.text_document)?;
     let (_, range) = convert::from_range(&snap.vfs(), file_id, params.range)?;
     let assists = snap.analysis.assists(FileRange { file_id, range })?;
+    let features = &snap.config.features;
     let vfs = snap.vfs();
     let actions = assists
         .into_iter()
-        .map(|assist| convert::to_code_action(&vfs, assist))
+        .filter(|assist| match assist.kind {
+            AssistKind::QuickFix => features.quick_fix,
+            AssistKind::RefactorRewrite => features.refactor,
+        })
+        .map(|assist| {
+            convert::to_code_action(
+                &vfs,
+                assist,
+                &params.context.diagnostics,
+                snap.capabilities.vscode_commands,
+            )
+        })
         .collect();
     Ok(Some(actions))
 }
 
 pub(crate) fn document_highlight(
     snap: StateSnapshot,
-    params: DocumentHighlightParams,
+    params: &DocumentHighlightParams,
 ) -> Result<Option<Vec<DocumentHighlight>>> {
     let (fpos, line_map) =
         convert::from_file_pos(&snap.vfs(), &params.text_document_position_params)?;
@@ -311,7 +708,7 @@ pub(crate) fn document_highlight(
 
 pub(crate) fn parent_module(
     snap: StateSnapshot,
-    params: TextDocumentPositionParams,
+    params: &TextDocumentPositionParams,
 ) -> Result<Option<GotoDefinitionResponse>> {
     let (file, _) = convert::from_file(&snap.vfs(), &params.text_document)?;
     let files = snap.analysis.file_referrers(file)?;
@@ -322,3 +719,82 @@ pub(crate) fn parent_module(
         .collect();
     Ok(Some(GotoDefinitionResponse::Array(locs)))
 }
+
+pub(crate) fn resolve_import(
+    snap: StateSnapshot,
+    params: &TextDocumentPositionParams,
+) -> Result<lsp_ext::ResolveImportResult> {
+    let (fpos, _) = convert::from_file_pos(&snap.vfs(), params)?;
+    let ret = snap
+        .analysis
+        .resolve_import(fpos)?
+        .map_err(|msg| ResponseError::new(ErrorCode::REQUEST_FAILED, msg))?;
+    Ok(lsp_ext::ResolveImportResult {
+        uri: Url::from_vfs_path(&ret.path),
+        loaded: ret.file.is_some(),
+    })
+}
+
+pub(crate) fn attrpath_at_position(
+    snap: StateSnapshot,
+    params: &TextDocumentPositionParams,
+) -> Result<Option<lsp_ext::AttrpathAtPositionResult>> {
+    let (fpos, line_map) = convert::from_file_pos(&snap.vfs(), params)?;
+    let segments = snap.analysis.attrpath_at(fpos)?;
+    if segments.is_empty() {
+        return Ok(None);
+    }
+    let path = segments
+        .iter()
+        .map(|seg| &*seg.name)
+        .collect::<Vec<_>>()
+        .join(".");
+    let segments = segments
+        .into_iter()
+        .map(|seg| lsp_ext::AttrpathSegment {
+            name: seg.name,
+            range: convert::range_to_lsp(&line_map, seg.range),
+        })
+        .collect();
+    Ok(Some(lsp_ext::AttrpathAtPositionResult { path, segments }))
+}
+
+pub(crate) fn status(snap: StateSnapshot, &(): &()) -> Result<lsp_ext::StatusResult> {
+    let features = snap
+        .config
+        .features
+        .iter()
+        .map(|(key, _, enabled)| (key.to_owned(), enabled))
+        .collect();
+    Ok(lsp_ext::StatusResult { features })
+}
+
+pub(crate) fn execute_command(
+    snap: StateSnapshot,
+    params: &ExecuteCommandParams,
+) -> Result<Option<serde_json::Value>> {
+    match &*params.command {
+        lsp_ext::CLEAR_EVAL_CACHE_COMMAND => {
+            if let Some(cache) = snap.config.eval_cache() {
+                cache.clear()?;
+            }
+            Ok(None)
+        }
+        lsp_ext::RECORD_COMPLETION_COMMAND => {
+            let Some(serde_json::Value::String(label)) = params.arguments.first() else {
+                return Err(ResponseError::new(
+                    ErrorCode::INVALID_PARAMS,
+                    "expecting a completion label",
+                )
+                .into());
+            };
+            snap.completion_history.lock().unwrap().record(label);
+            Ok(None)
+        }
+        cmd => Err(ResponseError::new(
+            ErrorCode::INVALID_PARAMS,
+            format!("unknown command: {cmd}"),
+        )
+        .into()),
+    }
+}
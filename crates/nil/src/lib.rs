@@ -1,29 +1,54 @@
+mod activity;
 mod capabilities;
+mod completion_history;
 mod config;
 mod convert;
 mod handler;
 mod lsp_ext;
 mod meter;
+mod scan;
 mod semantic_tokens;
 mod server;
 mod vfs;
 
-use anyhow::{Context, Result};
+// The in-process LSP client of integration tests, also used by unit tests needing extra routes.
+#[cfg(test)]
+extern crate self as nil;
+#[cfg(test)]
+#[path = "../tests/support/mod.rs"]
+mod test_support;
+
+use anyhow::{ensure, Context, Result};
 use async_lsp::client_monitor::ClientProcessMonitorLayer;
 use async_lsp::concurrency::ConcurrencyLayer;
+use async_lsp::panic::CatchUnwindLayer;
+use async_lsp::router::Router;
 use async_lsp::server::LifecycleLayer;
 use async_lsp::stdio::{PipeStdin, PipeStdout};
 use async_lsp::tracing::TracingLayer;
+use async_lsp::{ClientSocket, LspService, ResponseError};
+use futures::io::{AsyncRead, AsyncWrite};
 use ide::VfsPath;
 use lsp_types::Url;
+use std::io;
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::pin::Pin;
+use std::task::{Context as TaskContext, Poll};
+use std::time::Duration;
+use tokio::io::ReadBuf;
+use tokio::net::TcpListener;
 use tower::ServiceBuilder;
 
+pub use scan::collect_nix_files;
 pub(crate) use server::{Server, StateSnapshot};
 pub(crate) use vfs::{LineMap, Vfs};
 
+use crate::activity::ClientActivityLayer;
 use crate::meter::MeterLayer;
 
-/// The file length limit. Files larger than this will be rejected from all interactions.
+/// The default file length limit, configurable by `nil.maxFileSizeMB`. Files larger than the
+/// limit will be rejected from all interactions.
 /// The hard limit is `u32::MAX` due to following conditions.
 /// - The parser and the `rowan` library uses `u32` based indices.
 /// - `vfs::LineMap` uses `u32` based indices.
@@ -60,6 +85,126 @@ impl UrlExt for Url {
 }
 
 pub async fn run_server_stdio() -> Result<()> {
+    let stdin = PipeStdin::lock_tokio().context("stdin is not pipe-like")?;
+    let stdout = PipeStdout::lock_tokio().context("stdout is not pipe-like")?;
+    run_server(stdin, stdout).await
+}
+
+/// The delay before accepting again after `accept` fails, eg. when out of file descriptors.
+/// It doubles on each consecutive failure, up to the maximum.
+const ACCEPT_RETRY_DELAY_MIN: Duration = Duration::from_millis(10);
+const ACCEPT_RETRY_DELAY_MAX: Duration = Duration::from_secs(1);
+
+/// A language server listening on TCP, serving each accepted connection as a separate client.
+///
+/// Clients are served concurrently. A client disconnecting or failing only ends its own session,
+/// so the process keeps accepting new connections, eg. to reattach an editor or a debugger.
+pub struct TcpServer {
+    listener: TcpListener,
+}
+
+impl TcpServer {
+    /// Listen on `addr`, which may have port 0 to pick any free port.
+    ///
+    /// Anyone connected can read files and run `nix` as the current user, so addresses other
+    /// than loopback are refused unless `allow_remote` is set.
+    pub async fn bind(addr: SocketAddr, allow_remote: bool) -> Result<Self> {
+        ensure!(
+            allow_remote || addr.ip().is_loopback(),
+            "Refusing to listen on the non-loopback address {addr}",
+        );
+        let listener = TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("Failed to listen on {addr}"))?;
+        Ok(Self { listener })
+    }
+
+    /// The address actually listened on.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Serve connections forever.
+    pub async fn run(self) -> Result<()> {
+        tracing::info!("Listening on {}", self.local_addr()?);
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async move {
+                let mut retry_delay = ACCEPT_RETRY_DELAY_MIN;
+                loop {
+                    let (stream, peer) = match self.listener.accept().await {
+                        Ok(conn) => conn,
+                        Err(err) => {
+                            tracing::error!(
+                                "Failed to accept a connection, retrying in {retry_delay:?}: {err}",
+                            );
+                            tokio::time::sleep(retry_delay).await;
+                            retry_delay = (retry_delay * 2).min(ACCEPT_RETRY_DELAY_MAX);
+                            continue;
+                        }
+                    };
+                    retry_delay = ACCEPT_RETRY_DELAY_MIN;
+                    tracing::info!("Accepted connection from {peer}");
+                    let (input, output) = stream.into_split();
+                    tokio::task::spawn_local(async move {
+                        match run_server(TokioCompat(input), TokioCompat(output)).await {
+                            Ok(()) => tracing::info!("Connection from {peer} closed"),
+                            Err(err) => tracing::error!("Connection from {peer} failed: {err:#}"),
+                        }
+                    });
+                }
+            })
+            .await
+    }
+}
+
+/// Adapter of `tokio` I/O types to `futures` I/O traits expected by `async-lsp`.
+struct TokioCompat<T>(T);
+
+impl<T: tokio::io::AsyncRead + Unpin> AsyncRead for TokioCompat<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut buf = ReadBuf::new(buf);
+        futures::ready!(Pin::new(&mut self.0).poll_read(cx, &mut buf))?;
+        Poll::Ready(Ok(buf.filled().len()))
+    }
+}
+
+impl<T: tokio::io::AsyncWrite + Unpin> AsyncWrite for TokioCompat<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+/// Run the server speaking LSP over `input` and `output`, until the client exits.
+///
+/// This is the transport-agnostic version of [`run_server_stdio`], for embedding the server,
+/// eg. in-process over in-memory pipes in tests.
+pub async fn run_server(input: impl AsyncRead, output: impl AsyncWrite) -> Result<()> {
+    run_server_with_router(input, output, |_| {}).await
+}
+
+/// Same as [`run_server`], with additional routes set up by `setup_router`.
+pub(crate) async fn run_server_with_router(
+    input: impl AsyncRead,
+    output: impl AsyncWrite,
+    setup_router: impl FnOnce(&mut Router<Server>),
+) -> Result<()> {
     let concurrency = match std::thread::available_parallelism() {
         // Double the concurrency limit since many handlers are blocking anyway.
         Ok(n) => n.saturating_mul(2.try_into().expect("2 is not 0")),
@@ -72,24 +217,39 @@ pub async fn run_server_stdio() -> Result<()> {
 
     let init_messages = Vec::new();
 
-    let stdin = PipeStdin::lock_tokio().context("stdin is not pipe-like")?;
-    let stdout = PipeStdout::lock_tokio().context("stdout is not pipe-like")?;
-
     let (mainloop, _) = async_lsp::MainLoop::new_server(|client| {
-        ServiceBuilder::new()
-            .layer(
-                TracingLayer::new()
-                    .request(|r| tracing::info_span!("request", method = r.method))
-                    .notification(|n| tracing::info_span!("notification", method = n.method))
-                    .event(|e| tracing::info_span!("event", method = e.type_name())),
-            )
-            .layer(MeterLayer)
-            .layer(LifecycleLayer::default())
-            // TODO: Use `CatchUnwindLayer`.
-            .layer(ConcurrencyLayer::new(concurrency))
-            .layer(ClientProcessMonitorLayer::new(client.clone()))
-            .service(Server::new_router(client, init_messages))
+        let mut router = Server::new_router(client.clone(), init_messages);
+        setup_router(&mut router);
+        new_service(client, concurrency, router)
     });
 
-    Ok(mainloop.run_buffered(stdin, stdout).await?)
+    Ok(mainloop.run_buffered(input, output).await?)
+}
+
+/// Wrap the request `router` with all middlewares of the server.
+fn new_service<S>(
+    client: ClientSocket,
+    concurrency: NonZeroUsize,
+    router: S,
+) -> impl LspService<Response = serde_json::Value, Error = ResponseError>
+where
+    S: LspService<Response = serde_json::Value, Error = ResponseError>,
+    S::Future: 'static,
+{
+    ServiceBuilder::new()
+        .layer(
+            TracingLayer::new()
+                .request(|r| tracing::info_span!("request", method = r.method))
+                .notification(|n| tracing::info_span!("notification", method = n.method))
+                .event(|e| tracing::info_span!("event", method = e.type_name())),
+        )
+        .layer(MeterLayer)
+        .layer(LifecycleLayer::default())
+        .layer(ClientActivityLayer::new(client.clone()))
+        // Outside of `ConcurrencyLayer`, so that a panicking request only fails itself with an
+        // internal error, and its concurrency slot and cancellation state are still released.
+        .layer(CatchUnwindLayer::default())
+        .layer(ConcurrencyLayer::new(concurrency))
+        .layer(ClientProcessMonitorLayer::new(client))
+        .service(router)
 }
@@ -1,5 +1,8 @@
 use lsp_types::notification::Notification;
 use lsp_types::request::Request;
+use lsp_types::Url;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 /// <https://github.com/microsoft/language-server-protocol/issues/1002>
 pub enum ParentModule {}
@@ -10,9 +13,97 @@ impl Request for ParentModule {
     const METHOD: &'static str = "experimental/parentModule";
 }
 
+/// Resolve the path literal at a position to the absolute URI used by cross-file analyses.
+/// It fails with a message if the path cannot be resolved.
+pub enum ResolveImport {}
+
+impl Request for ResolveImport {
+    type Params = lsp_types::TextDocumentPositionParams;
+    type Result = ResolveImportResult;
+    const METHOD: &'static str = "nil/resolveImport";
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResolveImportResult {
+    pub uri: Url,
+    /// Whether the target is a loaded file of the workspace.
+    pub loaded: bool,
+}
+
+/// The attribute path of the binding chain enclosing a position, for breadcrumbs.
+/// It is syntax-only and cheap enough to be called on every cursor move.
+pub enum AttrpathAtPosition {}
+
+impl Request for AttrpathAtPosition {
+    type Params = lsp_types::TextDocumentPositionParams;
+    type Result = Option<AttrpathAtPositionResult>;
+    const METHOD: &'static str = "nil/attrpathAtPosition";
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttrpathAtPositionResult {
+    /// The dotted path, like `programs.neovim.plugins.[3].config`.
+    pub path: String,
+    /// Segments from the outermost one.
+    pub segments: Vec<AttrpathSegment>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttrpathSegment {
+    /// The attribute name as written, or `[N]` for the N-th list element.
+    pub name: String,
+    /// The range of the attribute, or the whole list element.
+    pub range: lsp_types::Range,
+}
+
+/// Report the server state for debugging.
+pub enum Status {}
+
+impl Request for Status {
+    type Params = ();
+    type Result = StatusResult;
+    const METHOD: &'static str = "nil/status";
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatusResult {
+    /// The effective state of each `nil.features.*` toggle.
+    pub features: BTreeMap<String, bool>,
+}
+
+/// `workspace/executeCommand` to remove all cached flake evaluation results.
+pub const CLEAR_EVAL_CACHE_COMMAND: &str = "nil.clearEvalCache";
+
+/// `workspace/executeCommand` attached to completion items, to record an accepted label for
+/// ranking later completions. The only argument is the label string.
+pub const RECORD_COMPLETION_COMMAND: &str = "nil.recordCompletion";
+
 pub enum ReloadFlake {}
 
 impl Notification for ReloadFlake {
     type Params = ();
     const METHOD: &'static str = "nil/reloadFlake";
 }
+
+/// The URI scheme of read-only documents generated by the server, like `nil:///builtins/map.md`.
+pub const VIRTUAL_DOCUMENT_SCHEME: &str = "nil";
+
+/// Fetch the content of a document of `VIRTUAL_DOCUMENT_SCHEME`.
+/// This mirrors `workspace/textDocumentContent` from LSP 3.18.
+pub enum TextDocumentContent {}
+
+impl Request for TextDocumentContent {
+    type Params = TextDocumentContentParams;
+    type Result = TextDocumentContentResult;
+    const METHOD: &'static str = "workspace/textDocumentContent";
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TextDocumentContentParams {
+    pub uri: Url,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TextDocumentContentResult {
+    pub text: String,
+}
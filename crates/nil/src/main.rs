@@ -1,12 +1,20 @@
-use anyhow::{Context, Result};
+use anyhow::{ensure, Context, Result};
 use argh::FromArgs;
 use codespan_reporting::term::termcolor::WriteColor;
-use ide::{AnalysisHost, Severity};
+use ide::{
+    AnalysisHost, Change, FileId, FileSet, FlakeGraph, FlakeInfo, Severity, SourceRoot,
+    SourceRootId, VfsPath,
+};
+use nix_interop::eval_cache::EvalCache;
+use nix_interop::{flake_lock, FLAKE_FILE, FLAKE_LOCK_FILE};
+use std::collections::HashMap;
 use std::io::IsTerminal;
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
 use std::{env, fs, io, process};
-use text_size::TextRange;
+use text_size::{TextRange, TextSize};
 use tracing_subscriber::fmt::writer::BoxMakeWriter;
 use tracing_subscriber::EnvFilter;
 
@@ -25,6 +33,14 @@ struct Args {
     /// warnings when either stdin or stdout is tty.
     #[argh(switch)]
     stdio: bool,
+    /// listen on the TCP address, eg. `127.0.0.1:9257`, instead of stdin and stdout. Each
+    /// connection is served as a separate client, until the process is killed.
+    #[argh(option)]
+    listen: Option<SocketAddr>,
+    /// allow `--listen` on addresses other than loopback. Anyone able to connect can read files
+    /// and run `nix` as the current user.
+    #[argh(switch)]
+    listen_remote: bool,
     #[argh(subcommand)]
     subcommand: Option<Subcommand>,
 }
@@ -32,11 +48,52 @@ struct Args {
 #[derive(Debug, FromArgs)]
 #[argh(subcommand)]
 enum Subcommand {
+    Cache(CacheArgs),
+    CheckFlake(CheckFlakeArgs),
     Diagnostics(DiagnosticsArgs),
     Parse(ParseArgs),
     Ssr(SsrArgs),
 }
 
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "cache")]
+/// Manage the on-disk cache of flake evaluation results.
+struct CacheArgs {
+    #[argh(subcommand)]
+    subcommand: CacheSubcommand,
+}
+
+#[derive(Debug, FromArgs)]
+#[argh(subcommand)]
+enum CacheSubcommand {
+    Clear(CacheClearArgs),
+}
+
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "clear")]
+/// Remove all cached evaluation results of all workspaces.
+struct CacheClearArgs {
+    /// the cache directory, if it is configured to a non-default one via
+    /// `nix.flake.evalCache.directory`.
+    #[argh(option)]
+    directory: Option<PathBuf>,
+}
+
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "check-flake")]
+/// Check the structure of a flake statically, without evaluation, `nix` or network access.
+/// It checks flake outputs, inputs against `flake.lock`, and module files reachable by
+/// `imports` or `modules`. Syntax errors of all reachable files are also reported.
+/// Exit with non-zero code if there are any errors.
+struct CheckFlakeArgs {
+    /// the flake directory or its `flake.nix`. Default to the current directory.
+    #[argh(positional, default = "PathBuf::from(\".\")")]
+    path: PathBuf,
+    /// print diagnostics as JSON, one array of all diagnostics.
+    #[argh(switch)]
+    json: bool,
+}
+
 #[derive(Debug, FromArgs)]
 #[argh(subcommand, name = "diagnostics")]
 /// Check and print diagnostics for a file.
@@ -78,6 +135,8 @@ fn main() {
 
     if let Some(subcommand) = args.subcommand {
         return match subcommand {
+            Subcommand::Cache(args) => main_cache(args),
+            Subcommand::CheckFlake(args) => main_check_flake(args),
             Subcommand::Diagnostics(args) => main_diagnostics(args),
             Subcommand::Parse(args) => main_parse(args),
             Subcommand::Ssr(args) => main_ssr(args),
@@ -86,7 +145,10 @@ fn main() {
 
     setup_logger();
 
-    if !args.stdio && (io::stdin().is_terminal() || io::stdout().is_terminal()) {
+    if args.listen.is_none()
+        && !args.stdio
+        && (io::stdin().is_terminal() || io::stdout().is_terminal())
+    {
         // TODO: Make this a hard error.
         eprintln!(
             "\
@@ -100,7 +162,17 @@ fn main() {
         .enable_all()
         .build()
         .expect("Failed to spawn tokio runtime")
-        .block_on(nil::run_server_stdio());
+        .block_on(async {
+            match args.listen {
+                Some(addr) => {
+                    nil::TcpServer::bind(addr, args.listen_remote)
+                        .await?
+                        .run()
+                        .await
+                }
+                None => nil::run_server_stdio().await,
+            }
+        });
     match ret {
         Ok(()) => {}
         Err(err) => {
@@ -110,6 +182,136 @@ fn main() {
     }
 }
 
+fn main_cache(args: CacheArgs) {
+    let ret = (|| -> Result<()> {
+        match args.subcommand {
+            CacheSubcommand::Clear(args) => {
+                let dir = args
+                    .directory
+                    .or_else(EvalCache::default_dir)
+                    .context("Failed to determine the cache directory")?;
+                EvalCache::clear_dir(&dir)?;
+                eprintln!("Removed cached results in {}", dir.display());
+            }
+        }
+        Ok(())
+    })();
+    if let Err(err) = ret {
+        eprintln!("{err:#}");
+        process::exit(1);
+    }
+}
+
+fn main_check_flake(args: CheckFlakeArgs) {
+    use codespan_reporting::term::termcolor::{ColorChoice, StandardStream};
+
+    let ret = (|| -> Result<Option<Severity>> {
+        let dir = if args.path.is_file() {
+            // `Path::parent` of a bare file name like `flake.nix` is empty.
+            match args.path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            }
+        } else {
+            &args.path
+        };
+        let root = dir
+            .canonicalize()
+            .with_context(|| format!("Failed to open {}", dir.display()))?;
+        let flake_path = root.join(FLAKE_FILE);
+        ensure!(flake_path.is_file(), "{} is not a flake", dir.display());
+
+        // Share the walker of the language server, so both see the same set of files.
+        let mut paths = nil::collect_nix_files(
+            &root,
+            nil::MAX_FILE_LEN,
+            &mut Vec::new(),
+            &AtomicBool::new(false),
+        );
+        paths.sort();
+
+        let mut change = Change::default();
+        let mut file_set = FileSet::default();
+        let mut files = HashMap::new();
+        for path in paths {
+            // Like the language server, skip unreadable files instead of failing the whole check.
+            let src = match fs::read_to_string(&path) {
+                Ok(src) => <Arc<str>>::from(src),
+                Err(err) => {
+                    eprintln!("Ignore file {}: {err}", path.display());
+                    continue;
+                }
+            };
+            let file = FileId(files.len() as u32);
+            change.change_file(file, src.clone());
+            file_set.insert(file, VfsPath::new(&path));
+            files.insert(file, (path, src));
+        }
+        let flake_file = file_set
+            .file_for_path(&VfsPath::new(&flake_path))
+            .context("Failed to load flake.nix")?;
+        change.set_roots(vec![SourceRoot::new_local(file_set, Some(flake_file))]);
+        change.set_flake_graph(FlakeGraph {
+            nodes: HashMap::from_iter([(
+                SourceRootId(0),
+                FlakeInfo {
+                    flake_file,
+                    input_store_paths: HashMap::new(),
+                    input_flake_outputs: HashMap::new(),
+                },
+            )]),
+        });
+
+        let locked_inputs = match fs::read(root.join(FLAKE_LOCK_FILE)) {
+            Ok(lock_src) => Some(flake_lock::root_input_names(&lock_src)?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err).context("Failed to read flake.lock"),
+        };
+
+        let mut host = AnalysisHost::new();
+        host.apply_change(change);
+        let diags = host
+            .snapshot()
+            .flake_check(flake_file, locked_inputs.as_deref())
+            .expect("No cancellation");
+
+        // Show paths relative to the given one.
+        let display_path = |file: FileId| {
+            let path = &files[&file].0;
+            path.strip_prefix(&root)
+                .map_or_else(|_| path.clone(), |rel| dir.join(rel))
+        };
+
+        if args.json {
+            let json = diags
+                .iter()
+                .map(|(file, diag)| diagnostic_to_json(&display_path(*file), &files[file].1, diag))
+                .collect::<Vec<_>>();
+            println!("{}", serde_json::Value::Array(json));
+        } else {
+            let mut writer = StandardStream::stdout(ColorChoice::Auto);
+            for (file, diag) in &diags {
+                emit_diagnostics(
+                    &display_path(*file),
+                    &files[file].1,
+                    &mut writer,
+                    &mut std::iter::once(diag.clone()),
+                )?;
+            }
+        }
+
+        Ok(diags.iter().map(|(_, diag)| diag.severity()).max())
+    })();
+    match ret {
+        Ok(Some(max_severity)) if max_severity > Severity::Warning => process::exit(1),
+        Ok(_) => process::exit(0),
+        Err(err) => {
+            eprintln!("{err:#}");
+            process::exit(1);
+        }
+    }
+}
+
 fn main_diagnostics(args: DiagnosticsArgs) {
     use codespan_reporting::term::termcolor::{ColorChoice, StandardStream};
 
@@ -123,10 +325,11 @@ fn main_diagnostics(args: DiagnosticsArgs) {
         };
 
         let (analysis, file) = AnalysisHost::new_single_file(&src);
-        let diags = analysis
+        let mut diags = analysis
             .snapshot()
             .diagnostics(file)
             .expect("No cancellation");
+        diags.retain(|diag| !diag.is_opt_in());
 
         let mut writer = StandardStream::stdout(ColorChoice::Auto);
         emit_diagnostics(path, &src, &mut writer, &mut diags.iter().cloned())?;
@@ -269,6 +472,8 @@ fn emit_diagnostics(
         let severity = match diag.severity() {
             ide::Severity::IncompleteSyntax | ide::Severity::Error => Severity::Error,
             ide::Severity::Warning => Severity::Warning,
+            ide::Severity::Info => Severity::Note,
+            ide::Severity::Hint => Severity::Help,
         };
         let labels = std::iter::once(Label::primary(cr_file, to_range(diag.range)))
             .chain(diag.notes.iter().map(|(frange, note)| {
@@ -286,6 +491,51 @@ fn emit_diagnostics(
     Ok(())
 }
 
+/// Convert a diagnostic into JSON. The schema is:
+/// `{ file, range, severity, code, message, notes: [{ range, message }] }`,
+/// where `range` is `{ start: { line, column }, end: { line, column } }`, and both `line` and
+/// `column` are 1-based, with `column` counted in Unicode scalar values.
+fn diagnostic_to_json(path: &Path, src: &str, diag: &ide::Diagnostic) -> serde_json::Value {
+    let pos_to_json = |pos: TextSize| {
+        let before = &src[..usize::from(pos)];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        serde_json::json!({
+            "line": before.matches('\n').count() + 1,
+            "column": before[line_start..].chars().count() + 1,
+        })
+    };
+    let range_to_json = |range: TextRange| {
+        serde_json::json!({
+            "start": pos_to_json(range.start()),
+            "end": pos_to_json(range.end()),
+        })
+    };
+    let severity = match diag.severity() {
+        Severity::IncompleteSyntax | Severity::Error => "error",
+        Severity::Warning => "warning",
+        Severity::Info => "info",
+        Severity::Hint => "hint",
+    };
+    let notes = diag
+        .notes
+        .iter()
+        .map(|(frange, note)| {
+            serde_json::json!({
+                "range": range_to_json(frange.range),
+                "message": note,
+            })
+        })
+        .collect::<Vec<_>>();
+    serde_json::json!({
+        "file": path.display().to_string(),
+        "range": range_to_json(diag.range),
+        "severity": severity,
+        "code": diag.code(),
+        "message": diag.message(),
+        "notes": notes,
+    })
+}
+
 fn setup_logger() {
     let file = env::var_os(LOG_PATH_ENV).and_then(|path| {
         let path = PathBuf::from(path);
@@ -0,0 +1,435 @@
+//! Scanning and loading of Nix files from disk.
+//!
+//! Disk reads may be arbitrarily slow, eg. on network filesystems, so they never run on the
+//! async executor. Files are read on the blocking thread pool with a bounded concurrency and a
+//! per-file timeout, and results are delivered in batches as soon as they are ready.
+use ide::DirEntry;
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Semaphore};
+use tokio::task;
+
+/// Directory names never descended into.
+const IGNORED_DIRS: &[&str] = &[".git"];
+
+/// The maximum number of files being read at the same time.
+const MAX_CONCURRENT_READS: usize = 16;
+
+/// Reading a single file taking longer than this is abandoned.
+pub(crate) const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Listing a directory for interactive requests taking longer than this is abandoned.
+pub(crate) const LIST_DIR_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// The maximum number of files returned by [`recv_batch`] at once.
+const MAX_BATCH_LEN: usize = 256;
+
+/// Blocking filesystem operations, abstracted for testing.
+pub(crate) trait FileSystem: Send + Sync + 'static {
+    /// Read the content of a regular file.
+    fn read_to_string(&self, path: &Path) -> io::Result<String>;
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct RealFileSystem;
+
+impl FileSystem for RealFileSystem {
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        #[cfg(unix)]
+        use rustix::fs::{fcntl_getfl, fcntl_setfl, OFlags, OpenOptionsExt};
+
+        // Rule out non-regular files which may block `open()` infinitely
+        // (eg. FIFO). We open it with `O_NONBLOCK` and check it before reading.
+        let mut options = fs::File::options();
+        options.read(true);
+        #[cfg(unix)]
+        options.custom_flags(OFlags::NONBLOCK.bits() as _);
+
+        let mut file = options.open(path)?;
+        let ft = file.metadata()?.file_type();
+        if !ft.is_file() {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("non-regular file type: {ft:?}"),
+            ));
+        }
+
+        // Remove the O_NONBLOCK flag for blocking read.
+        #[cfg(unix)]
+        {
+            let flags = fcntl_getfl(&file)? - OFlags::NONBLOCK;
+            fcntl_setfl(&file, flags)?;
+        }
+
+        let mut buf = String::new();
+        file.read_to_string(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+/// Recursively collect all `*.nix` files under `root`. This blocks.
+/// The walk stops early once `cancelled` is set, returning files collected so far.
+///
+/// Symlinks are followed only if they point inside `root`, and each directory is visited only
+/// once, so symlink cycles and links to outside trees (eg. `result` links into the Nix store)
+/// are skipped. Files larger than `max_len` bytes are pushed into `too_large` instead.
+pub fn collect_nix_files(
+    root: &Path,
+    max_len: usize,
+    too_large: &mut Vec<PathBuf>,
+    cancelled: &AtomicBool,
+) -> Vec<PathBuf> {
+    let mut ret = Vec::new();
+    let Ok(canonical_root) = root.canonicalize() else {
+        return ret;
+    };
+    let mut visited = HashSet::from([canonical_root.clone()]);
+    let mut stack = vec![root.to_owned()];
+    while let Some(dir) = stack.pop() {
+        if cancelled.load(Ordering::Relaxed) {
+            break;
+        }
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                tracing::warn!("Ignore directory {dir:?}: {err}");
+                continue;
+            }
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Ok(mut ft) = entry.file_type() else {
+                continue;
+            };
+            if ft.is_symlink() {
+                let Ok(target) = path.canonicalize() else {
+                    continue;
+                };
+                if !target.starts_with(&canonical_root) {
+                    continue;
+                }
+                let Ok(meta) = fs::metadata(&target) else {
+                    continue;
+                };
+                ft = meta.file_type();
+                if ft.is_dir() && !visited.insert(target) {
+                    continue;
+                }
+            } else if ft.is_dir() {
+                let Ok(target) = path.canonicalize() else {
+                    continue;
+                };
+                if !visited.insert(target) {
+                    continue;
+                }
+            }
+
+            if ft.is_dir() {
+                if !IGNORED_DIRS.iter().any(|name| entry.file_name() == **name) {
+                    stack.push(path);
+                }
+            } else if ft.is_file() && path.extension().is_some_and(|ext| ext == "nix") {
+                match entry.metadata() {
+                    Ok(meta) if meta.len() > max_len as u64 => {
+                        tracing::warn!("Ignore too large file {path:?} ({} bytes)", meta.len());
+                        too_large.push(path);
+                    }
+                    _ => ret.push(path),
+                }
+            }
+        }
+    }
+    ret
+}
+
+/// List entries of directory `dir` on disk, following symlinks. This blocks.
+/// Unreadable entries are skipped.
+pub(crate) fn list_dir(dir: &Path) -> Vec<DirEntry> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            tracing::debug!("Cannot list directory {dir:?}: {err}");
+            return Vec::new();
+        }
+    };
+    let mut ret = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let is_dir = fs::metadata(entry.path()).ok()?.is_dir();
+            Some(DirEntry { name, is_dir })
+        })
+        .collect::<Vec<_>>();
+    ret.sort_by(|lhs, rhs| lhs.name.cmp(&rhs.name));
+    ret
+}
+
+/// Like [`list_dir`], but give up and return `None` if it takes longer than `timeout`.
+///
+/// The listing runs on its own thread, which is left running if it hangs. At most
+/// [`MAX_CONCURRENT_READS`] listings are in flight, and `None` is returned immediately while
+/// all of them are hanging.
+pub(crate) fn list_dir_with_timeout(dir: &Path, timeout: Duration) -> Option<Vec<DirEntry>> {
+    static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+    let dir = dir.to_owned();
+    run_bounded(&IN_FLIGHT, timeout, move || list_dir(&dir))
+}
+
+/// Run blocking `f` on a new thread and wait for at most `timeout`, if fewer than
+/// [`MAX_CONCURRENT_READS`] calls counted by `in_flight` are still running.
+fn run_bounded<T: Send + 'static>(
+    in_flight: &'static AtomicUsize,
+    timeout: Duration,
+    f: impl FnOnce() -> T + Send + 'static,
+) -> Option<T> {
+    let reserved = in_flight.fetch_update(Ordering::AcqRel, Ordering::Acquire, |cnt| {
+        (cnt < MAX_CONCURRENT_READS).then_some(cnt + 1)
+    });
+    if reserved.is_err() {
+        tracing::debug!("Too many blocking listings in flight");
+        return None;
+    }
+    let (tx, rx) = std::sync::mpsc::sync_channel(1);
+    std::thread::spawn(move || {
+        let _: Result<_, _> = tx.send(f());
+        in_flight.fetch_sub(1, Ordering::AcqRel);
+    });
+    rx.recv_timeout(timeout).ok()
+}
+
+/// Read `paths` in background, sending results in the order of completion.
+///
+/// At most [`MAX_CONCURRENT_READS`] files are read at the same time. A read exceeding `timeout`
+/// results in an [`io::ErrorKind::TimedOut`] error, but its slot is only released when the
+/// underlying read returns, so hanging reads cannot pile up blocking threads.
+/// Files larger than `max_len` bytes result in [`io::ErrorKind::InvalidData`] errors.
+///
+/// Remaining reads are skipped once the receiver is dropped.
+pub(crate) fn read_files(
+    fs: Arc<dyn FileSystem>,
+    paths: Vec<PathBuf>,
+    timeout: Duration,
+    max_len: usize,
+) -> mpsc::UnboundedReceiver<(PathBuf, io::Result<String>)> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let limiter = Arc::new(Semaphore::new(MAX_CONCURRENT_READS));
+    task::spawn(async move {
+        for path in paths {
+            let Ok(permit) = limiter.clone().acquire_owned().await else {
+                break;
+            };
+            if tx.is_closed() {
+                break;
+            }
+            let (fs, tx) = (fs.clone(), tx.clone());
+            task::spawn(async move {
+                let read = task::spawn_blocking({
+                    let path = path.clone();
+                    move || {
+                        let ret = fs.read_to_string(&path);
+                        drop(permit);
+                        ret
+                    }
+                });
+                let ret = match tokio::time::timeout(timeout, read).await {
+                    Ok(Ok(Ok(text))) if text.len() > max_len => Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("too large file ({} bytes)", text.len()),
+                    )),
+                    Ok(Ok(ret)) => ret,
+                    Ok(Err(err)) => Err(io::Error::new(io::ErrorKind::Other, err)),
+                    Err(_) => Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("reading took longer than {timeout:?}"),
+                    )),
+                };
+                let _: Result<_, _> = tx.send((path, ret));
+            });
+        }
+    });
+    rx
+}
+
+/// Wait for the next result, and take it along with all other results already available.
+/// Returns `None` if all results are received.
+pub(crate) async fn recv_batch<T>(rx: &mut mpsc::UnboundedReceiver<T>) -> Option<Vec<T>> {
+    let mut batch = vec![rx.recv().await?];
+    while batch.len() < MAX_BATCH_LEN {
+        let Ok(item) = rx.try_recv() else {
+            break;
+        };
+        batch.push(item);
+    }
+    Some(batch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        collect_nix_files, list_dir, read_files, recv_batch, run_bounded, FileSystem,
+        RealFileSystem, MAX_CONCURRENT_READS,
+    };
+    use ide::DirEntry;
+    use std::fs;
+    use std::io;
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicBool, AtomicUsize};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn scan() {
+        let root = std::env::temp_dir().join(format!("nil-scan-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("sub/.git")).unwrap();
+        fs::write(root.join("default.nix"), "1").unwrap();
+        fs::write(root.join("README.md"), "").unwrap();
+        fs::write(root.join("sub/foo.nix"), "2").unwrap();
+        fs::write(root.join("sub/.git/bar.nix"), "3").unwrap();
+        fs::write(root.join("sub/large.nix"), "too large").unwrap();
+        #[cfg(unix)]
+        {
+            // A cycle.
+            std::os::unix::fs::symlink(&root, root.join("sub/loop")).unwrap();
+            // Outside of the root.
+            std::os::unix::fs::symlink(std::env::temp_dir(), root.join("result")).unwrap();
+        }
+
+        let mut too_large = Vec::new();
+        let mut files = collect_nix_files(&root, 4, &mut too_large, &AtomicBool::new(false))
+            .into_iter()
+            .map(|path| {
+                let text = RealFileSystem.read_to_string(&path).unwrap();
+                let path = path.strip_prefix(&root).unwrap().to_owned();
+                (path.display().to_string(), text)
+            })
+            .collect::<Vec<_>>();
+        files.sort();
+        let entries = list_dir(&root);
+        let cancelled = collect_nix_files(&root, 4, &mut Vec::new(), &AtomicBool::new(true));
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(cancelled, Vec::<PathBuf>::new());
+
+        assert_eq!(
+            files,
+            [
+                ("default.nix".into(), "1".into()),
+                ("sub/foo.nix".into(), "2".into())
+            ],
+        );
+        assert_eq!(too_large, [root.join("sub/large.nix")]);
+        let entry = |name: &str, is_dir| DirEntry {
+            name: name.into(),
+            is_dir,
+        };
+        let mut expect = vec![entry("README.md", false), entry("default.nix", false)];
+        if cfg!(unix) {
+            expect.push(entry("result", true));
+        }
+        expect.push(entry("sub", true));
+        assert_eq!(entries, expect);
+    }
+
+    /// Files named `slow*` block for a long time, others are read immediately.
+    struct SlowFileSystem;
+
+    impl FileSystem for SlowFileSystem {
+        fn read_to_string(&self, path: &Path) -> io::Result<String> {
+            let name = path.to_str().unwrap();
+            if name.starts_with("slow") {
+                std::thread::sleep(Duration::from_secs(1));
+            }
+            Ok(name.to_owned())
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn slow_reads() {
+        // Slow files come first, but are fewer than the concurrency limit.
+        let mut paths = (0..4)
+            .map(|i| PathBuf::from(format!("slow{i}")))
+            .collect::<Vec<_>>();
+        paths.extend((0..100).map(|i| PathBuf::from(format!("fast{i}"))));
+
+        let mut rx = read_files(
+            Arc::new(SlowFileSystem),
+            paths,
+            Duration::from_millis(500),
+            usize::MAX,
+        );
+        let mut loaded = Vec::new();
+        let mut timed_out = Vec::new();
+        let mut batches = 0;
+        while let Some(batch) = recv_batch(&mut rx).await {
+            batches += 1;
+            for (path, ret) in batch {
+                match ret {
+                    Ok(text) => {
+                        // Fast files are not delayed by slow ones.
+                        assert_eq!(timed_out, Vec::<String>::new());
+                        loaded.push(text);
+                    }
+                    Err(err) => {
+                        assert_eq!(err.kind(), io::ErrorKind::TimedOut, "{err}");
+                        timed_out.push(path.display().to_string());
+                    }
+                }
+            }
+        }
+        loaded.sort();
+        timed_out.sort();
+        assert_eq!(loaded.len(), 100);
+        assert!(loaded.iter().all(|name| name.starts_with("fast")));
+        assert_eq!(timed_out, ["slow0", "slow1", "slow2", "slow3"]);
+        assert!(batches > 1);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn timed_out_reads_hold_slots() {
+        let mut paths = (0..MAX_CONCURRENT_READS)
+            .map(|i| PathBuf::from(format!("slow{i}")))
+            .collect::<Vec<_>>();
+        paths.push("fast".into());
+
+        let mut rx = read_files(
+            Arc::new(SlowFileSystem),
+            paths,
+            Duration::from_millis(100),
+            usize::MAX,
+        );
+        let mut order = Vec::new();
+        while let Some((path, _)) = rx.recv().await {
+            order.push(path);
+        }
+        // The fast file waits for a slot until a slow read actually returns.
+        assert_eq!(order.len(), MAX_CONCURRENT_READS + 1);
+        assert_eq!(order.last().unwrap(), Path::new("fast"));
+    }
+
+    #[test]
+    fn bounded_blocking_calls() {
+        static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+        let hang = || std::thread::sleep(Duration::from_secs(1));
+        assert_eq!(
+            run_bounded(&IN_FLIGHT, Duration::from_secs(10), || 42),
+            Some(42)
+        );
+        for _ in 0..MAX_CONCURRENT_READS {
+            assert_eq!(
+                run_bounded(&IN_FLIGHT, Duration::from_millis(10), hang),
+                None
+            );
+        }
+        // All slots are taken by hanging calls, so it fails without running.
+        assert_eq!(
+            run_bounded(&IN_FLIGHT, Duration::from_secs(10), || 42),
+            None
+        );
+    }
+}
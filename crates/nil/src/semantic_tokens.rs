@@ -1,5 +1,6 @@
 use ide::{BuiltinKind, HlAttrField, HlKeyword, HlPunct, HlTag, NameKind};
-use lsp_types::{SemanticTokenModifier, SemanticTokenType};
+use lsp_types::{SemanticToken, SemanticTokenModifier, SemanticTokenType, SemanticTokensEdit, Url};
+use std::collections::HashMap;
 
 macro_rules! def_index {
     (
@@ -41,7 +42,9 @@ def_index! {
     Conditional => SemanticTokenModifier::new("conditional"),
     Definition => SemanticTokenModifier::DEFINITION,
     Delimiter => SemanticTokenModifier::new("delimiter"),
+    Documentation => SemanticTokenModifier::DOCUMENTATION,
     Escape => SemanticTokenModifier::new("escape"),
+    Interpolation => SemanticTokenModifier::new("interpolation"),
     Parenthesis => SemanticTokenModifier::new("parenthesis"),
     Readonly => SemanticTokenModifier::READONLY,
     Unresolved => SemanticTokenModifier::new("unresolved"),
@@ -99,6 +102,10 @@ pub(crate) fn to_semantic_type_and_modifiers(tag: HlTag) -> (TokenTypeIdx, Token
             }
         }
         HlTag::Comment => TokenTypeIdx::Comment,
+        HlTag::DocComment => {
+            mods.insert(TokenModIdx::Documentation);
+            TokenTypeIdx::Comment
+        }
         HlTag::StringEscape => {
             mods.insert(TokenModIdx::Escape);
             TokenTypeIdx::String
@@ -129,6 +136,7 @@ pub(crate) fn to_semantic_type_and_modifiers(tag: HlTag) -> (TokenTypeIdx, Token
                 | HlPunct::Colon
                 | HlPunct::Equal
                 | HlPunct::At => mods.insert(TokenModIdx::Delimiter),
+                HlPunct::Interpolation => mods.insert(TokenModIdx::Interpolation),
                 HlPunct::Ellipsis => {}
             }
             TokenTypeIdx::Punctuation
@@ -137,3 +145,128 @@ pub(crate) fn to_semantic_type_and_modifiers(tag: HlTag) -> (TokenTypeIdx, Token
     };
     (ty, mods)
 }
+
+/// Full semantic tokens last returned for each document, for computing deltas.
+///
+/// Only the latest result of each document is kept, so deltas against older ones fall back to
+/// full results.
+#[derive(Debug, Default)]
+pub struct SemanticTokensCache {
+    next_id: u64,
+    results: HashMap<Url, (String, Vec<SemanticToken>)>,
+}
+
+impl SemanticTokensCache {
+    /// Remember `tokens` as the latest result of `uri`, returning a new result id for it.
+    pub fn insert(&mut self, uri: Url, tokens: Vec<SemanticToken>) -> String {
+        self.next_id += 1;
+        let result_id = self.next_id.to_string();
+        self.results.insert(uri, (result_id.clone(), tokens));
+        result_id
+    }
+
+    /// The tokens of `uri` returned with `result_id`, if it is the latest one.
+    pub fn get(&self, uri: &Url, result_id: &str) -> Option<&[SemanticToken]> {
+        let (id, tokens) = self.results.get(uri)?;
+        (id == result_id).then_some(&**tokens)
+    }
+
+    pub fn remove(&mut self, uri: &Url) {
+        self.results.remove(uri);
+    }
+}
+
+/// Edits transforming `old` into `new`, by replacing everything between the common prefix and
+/// the common suffix.
+pub(crate) fn diff_tokens(old: &[SemanticToken], new: &[SemanticToken]) -> Vec<SemanticTokensEdit> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (old_mid, new_mid) = (
+        &old[prefix..old.len() - suffix],
+        &new[prefix..new.len() - suffix],
+    );
+    if old_mid.is_empty() && new_mid.is_empty() {
+        return Vec::new();
+    }
+    // Each token takes 5 integers in the encoded data.
+    vec![SemanticTokensEdit {
+        start: prefix as u32 * 5,
+        delete_count: old_mid.len() as u32 * 5,
+        data: Some(new_mid.to_vec()),
+    }]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        diff_tokens, to_semantic_type_and_modifiers, SemanticTokensCache, TokenModIdx, TokenTypeIdx,
+    };
+    use ide::HlTag;
+    use lsp_types::{SemanticToken, Url};
+
+    fn tok(delta_start: u32) -> SemanticToken {
+        SemanticToken {
+            delta_line: 0,
+            delta_start,
+            length: 1,
+            token_type: 0,
+            token_modifiers_bitset: 0,
+        }
+    }
+
+    #[test]
+    fn doc_comment() {
+        let doc_bit = TokenModIdx::Documentation.to_bit();
+        let (ty, mods) = to_semantic_type_and_modifiers(HlTag::DocComment);
+        assert_eq!(ty, TokenTypeIdx::Comment);
+        assert_eq!(mods.0 & doc_bit, doc_bit);
+        let (ty, mods) = to_semantic_type_and_modifiers(HlTag::Comment);
+        assert_eq!(ty, TokenTypeIdx::Comment);
+        assert_eq!(mods.0 & doc_bit, 0);
+    }
+
+    #[test]
+    fn diff() {
+        let old = [tok(1), tok(2), tok(3), tok(4)];
+        assert_eq!(diff_tokens(&old, &old), []);
+
+        let edits = diff_tokens(&old, &[tok(1), tok(5), tok(6), tok(4)]);
+        assert_eq!(edits.len(), 1);
+        assert_eq!((edits[0].start, edits[0].delete_count), (5, 10));
+        assert_eq!(edits[0].data, Some(vec![tok(5), tok(6)]));
+
+        // Insertion.
+        let edits = diff_tokens(&old, &[tok(1), tok(2), tok(9), tok(3), tok(4)]);
+        assert_eq!((edits[0].start, edits[0].delete_count), (10, 0));
+        assert_eq!(edits[0].data, Some(vec![tok(9)]));
+
+        // Deletion, where prefix and suffix would overlap.
+        let edits = diff_tokens(&[tok(1), tok(1), tok(1)], &[tok(1), tok(1)]);
+        assert_eq!((edits[0].start, edits[0].delete_count), (10, 5));
+        assert_eq!(edits[0].data, Some(vec![]));
+
+        let edits = diff_tokens(&[], &[tok(1)]);
+        assert_eq!((edits[0].start, edits[0].delete_count), (0, 0));
+    }
+
+    #[test]
+    fn cache() {
+        let uri = Url::parse("file:///default.nix").unwrap();
+        let mut cache = SemanticTokensCache::default();
+        let id1 = cache.insert(uri.clone(), vec![tok(1)]);
+        assert_eq!(cache.get(&uri, &id1), Some(&[tok(1)][..]));
+
+        let id2 = cache.insert(uri.clone(), vec![tok(2)]);
+        assert_ne!(id1, id2);
+        assert_eq!(cache.get(&uri, &id1), None);
+        assert_eq!(cache.get(&uri, &id2), Some(&[tok(2)][..]));
+
+        cache.remove(&uri);
+        assert_eq!(cache.get(&uri, &id2), None);
+    }
+}
@@ -1,52 +1,70 @@
-use crate::capabilities::{negotiate_capabilities, NegotiatedCapabilities};
+use crate::activity::ClientActivityEvent;
+use crate::capabilities::{
+    negotiate_capabilities, take_feature_capability, NegotiatedCapabilities,
+};
+use crate::completion_history::CompletionHistory;
 use crate::config::{Config, CONFIG_KEY};
-use crate::{convert, handler, lsp_ext, UrlExt, Vfs, MAX_FILE_LEN};
-use anyhow::{bail, ensure, Context, Result};
+use crate::scan::{FileSystem, RealFileSystem};
+use crate::semantic_tokens::SemanticTokensCache;
+use crate::{convert, handler, lsp_ext, scan, UrlExt, Vfs};
+use anyhow::{anyhow, bail, ensure, Context, Result};
 use async_lsp::router::Router;
 use async_lsp::{ClientSocket, ErrorCode, LanguageClient, ResponseError};
+use futures::FutureExt;
 use ide::{Analysis, AnalysisHost, Cancelled, FlakeInfo, VfsPath};
 use lsp_types::notification::Notification;
 use lsp_types::request::{self as req, Request};
 use lsp_types::{
     notification as notif, ConfigurationItem, ConfigurationParams, DidChangeConfigurationParams,
     DidChangeTextDocumentParams, DidChangeWatchedFilesParams,
-    DidChangeWatchedFilesRegistrationOptions, DidCloseTextDocumentParams,
-    DidOpenTextDocumentParams, FileChangeType, FileEvent, FileSystemWatcher, GlobPattern,
+    DidChangeWatchedFilesRegistrationOptions, DidChangeWorkspaceFoldersParams,
+    DidCloseTextDocumentParams, DidOpenTextDocumentParams, DocumentFormattingParams,
+    DocumentRangeFormattingParams, FileChangeType, FileEvent, FileSystemWatcher, GlobPattern,
     InitializeParams, InitializeResult, InitializedParams, MessageActionItem,
     MessageActionItemProperty, MessageType, NumberOrString, OneOf, ProgressParams,
     ProgressParamsValue, PublishDiagnosticsParams, Registration, RegistrationParams,
-    RelativePattern, ServerInfo, ShowMessageParams, ShowMessageRequestParams, Url,
-    WorkDoneProgress, WorkDoneProgressBegin, WorkDoneProgressCreateParams, WorkDoneProgressEnd,
-    WorkDoneProgressReport,
+    RelativePattern, ServerInfo, ShowMessageParams, ShowMessageRequestParams,
+    TextDocumentRegistrationOptions, TextDocumentSyncCapability, TextEdit, Unregistration,
+    UnregistrationParams, Url, WillSaveTextDocumentParams, WorkDoneProgress, WorkDoneProgressBegin,
+    WorkDoneProgressCreateParams, WorkDoneProgressEnd, WorkDoneProgressReport, WorkspaceFolder,
 };
+use nix_interop::eval_cache::EvalCache;
+use nix_interop::flake_output::FlakeOutput;
 use nix_interop::nixos_options::{self, NixosOptions};
 use nix_interop::{flake_lock, flake_output, FlakeUrl, FLAKE_FILE, FLAKE_LOCK_FILE};
 use std::backtrace::Backtrace;
 use std::borrow::BorrowMut;
 use std::cell::Cell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::future::{ready, Future};
-use std::io::{ErrorKind, Read};
+use std::io::{self, ErrorKind};
 use std::ops::ControlFlow;
-use std::panic::UnwindSafe;
-use std::path::Path;
-use std::pin::pin;
-use std::sync::{Arc, Once, RwLock};
-use std::time::Duration;
+use std::panic::{AssertUnwindSafe, RefUnwindSafe, UnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::{Arc, Mutex, Once, RwLock};
+use std::time::{Duration, Instant};
 use std::{fmt, panic};
-use tokio::sync::watch;
+use tokio::sync::{mpsc, oneshot, watch, Semaphore};
 use tokio::task;
-use tokio::task::JoinHandle;
+use tokio::task::{JoinHandle, JoinSet};
 
 const LSP_SERVER_NAME: &str = "nil";
 const FLAKE_ARCHIVE_PROGRESS_TOKEN: &str = "nil/flakeArchiveProgress";
 const LOAD_INPUT_FLAKE_PROGRESS_TOKEN: &str = "nil/loadInputFlakeProgress";
 const LOAD_NIXOS_OPTIONS_PROGRESS_TOKEN: &str = "nil/loadNixosOptionsProgress";
+const SCAN_WORKSPACE_PROGRESS_TOKEN: &str = "nil/scanWorkspaceProgress";
 
 const MAX_DIAGNOSTICS_CNT: usize = 128;
 
+/// Watch all Nix files to keep files not opened in the client up-to-date.
+/// This includes `flake.nix`.
+const WATCHED_NIX_FILES_GLOB: &str = "**/*.nix";
+
 const PROGRESS_REPORT_PERIOD: Duration = Duration::from_millis(100);
 const LOAD_FLAKE_WORKSPACE_DEBOUNCE_DURATION: Duration = Duration::from_millis(100);
+/// Clients usually wait at most 1s or so for `textDocument/willSaveWaitUntil`.
+const WILL_SAVE_TIMEOUT: Duration = Duration::from_millis(400);
 
 type NotifyResult = ControlFlow<async_lsp::Result<()>>;
 
@@ -54,6 +72,17 @@ struct UpdateConfigEvent(serde_json::Value);
 struct UpdateDiagnostics(u64, Vec<(Url, Vec<lsp_types::Diagnostic>)>);
 struct SetFlakeInfoEvent(Option<FlakeInfo>);
 struct SetNixosOptionsEvent(NixosOptions);
+/// Files loaded by the workspace scan of the given generation.
+struct ScannedFilesEvent(u64, Vec<(PathBuf, String)>);
+/// Files reloaded from disk after `workspace/didChangeWatchedFiles`, with the generation of the
+/// notification requesting them.
+struct ReloadedFilesEvent(u64, Vec<(Url, io::Result<String>)>);
+/// Reload the flake workspace, after changed flake files are reloaded.
+struct ReloadFlakeEvent;
+/// The idle timer of the given generation expired.
+struct IdleTimeoutEvent(u64);
+/// Re-run a request which was cancelled by a concurrent change, with a new snapshot.
+struct RetryRequestEvent(Box<dyn FnOnce(&mut Server) + Send>);
 
 pub struct Server {
     // States.
@@ -62,23 +91,70 @@ pub struct Server {
     vfs: Arc<RwLock<Vfs>>,
     opened_files: HashMap<Url, FileData>,
     config: Arc<Config>,
+    /// Accepted completion labels, shared with snapshots to rank completions.
+    completion_history: Arc<Mutex<CompletionHistory>>,
+    /// Latest full semantic tokens of each document, shared with snapshots to compute deltas.
+    semantic_tokens_cache: Arc<Mutex<SemanticTokensCache>>,
     /// Tried to load flake?
     /// This is used to reload flake only once after the configuration is first loaded.
     tried_flake_load: bool,
     /// Is this workspace a flake?
     workspace_is_flake: bool,
+    /// Whether `textDocument/formatting` is dynamically (un)registered on configuration changes,
+    /// instead of being statically advertised.
+    formatting_dynamic: bool,
+    /// Whether `textDocument/formatting` is dynamically registered currently.
+    formatting_registered: bool,
+    /// Same as `formatting_dynamic` but for `textDocument/rangeFormatting`.
+    range_formatting_dynamic: bool,
+    /// Whether `textDocument/rangeFormatting` is dynamically registered currently.
+    range_formatting_registered: bool,
+    /// Same as `formatting_dynamic` but for `textDocument/willSaveWaitUntil`.
+    will_save_dynamic: bool,
+    /// Whether `textDocument/willSaveWaitUntil` is dynamically registered currently.
+    will_save_registered: bool,
+    /// Requests of `Features` which are dynamically (un)registered by their toggles, keyed by
+    /// methods.
+    feature_registrations: HashMap<&'static str, FeatureRegistration>,
+    /// Pending updates of dynamic registrations. See `spawn_update_registration`.
+    registration_tx: Option<mpsc::UnboundedSender<(&'static str, Option<serde_json::Value>)>>,
     diagnostic_version: u64,
+    /// The generation of the last reload requested for each watched file which is being read.
+    /// Results of outdated reads are discarded.
+    pending_reloads: HashMap<Url, u64>,
+    reload_generation: u64,
+    /// Bumped whenever the idle timer is reset, so that expirations of stale timers are ignored.
+    idle_generation: u64,
+    /// Bumped whenever a workspace scan starts. Results of previous scans are discarded.
+    scan_generation: u64,
+    /// Files reported by the watcher since the current scan started. Their scanned contents may
+    /// be older than the watcher's, and are discarded.
+    changed_since_scan: HashSet<PathBuf>,
 
     // Ongoing tasks.
     load_flake_workspace_fut: Option<JoinHandle<()>>,
+    scan_workspace_fut: Option<JoinHandle<()>>,
+    /// Stops the directory walk of the current scan, which cannot be aborted with its task.
+    scan_cancelled: Arc<AtomicBool>,
+    idle_timer_fut: Option<JoinHandle<()>>,
+    /// Limits the number of concurrent `nix` processes.
+    nix_limiter: Arc<Semaphore>,
 
     // Immutable (mostly).
+    /// Disk access, which is replaced in tests.
+    fs: Arc<dyn FileSystem>,
     client: ClientSocket,
-    capabilities: NegotiatedCapabilities,
+    capabilities: Arc<NegotiatedCapabilities>,
     /// Messages to show once initialized.
     init_messages: Vec<ShowMessageParams>,
 }
 
+#[derive(Debug)]
+struct FeatureRegistration {
+    register_options: serde_json::Value,
+    registered: bool,
+}
+
 #[derive(Debug, Default)]
 struct FileData {
     // XXX: `lsp_types::Diagnostic` has a very large memory footprint.
@@ -104,6 +180,7 @@ impl Server {
             // > In former implementations clients pushed file events without the server actively asking for it.
             // Ref: https://microsoft.github.io/language-server-protocol/specifications/lsp/3.17/specification/#workspace_didChangeWatchedFiles
             .notification::<notif::DidChangeWatchedFiles>(Self::on_did_change_watched_files)
+            .notification::<notif::DidChangeWorkspaceFolders>(Self::on_did_change_workspace_folders)
             .notification::<lsp_ext::ReloadFlake>(Self::on_reload_flake)
             //// Requests ////
             .request_snap::<req::GotoDefinition>(handler::goto_definition)
@@ -113,41 +190,81 @@ impl Server {
             .request_snap::<req::PrepareRenameRequest>(handler::prepare_rename)
             .request_snap::<req::Rename>(handler::rename)
             .request_snap::<req::SemanticTokensFullRequest>(handler::semantic_token_full)
+            .request_snap::<req::SemanticTokensFullDeltaRequest>(handler::semantic_token_full_delta)
             .request_snap::<req::SemanticTokensRangeRequest>(handler::semantic_token_range)
             .request_snap::<req::HoverRequest>(handler::hover)
+            .request_snap::<req::SignatureHelpRequest>(handler::signature_help)
             .request_snap::<req::DocumentSymbolRequest>(handler::document_symbol)
-            .request_snap::<req::Formatting>(handler::formatting)
+            .request_snap::<req::WorkspaceSymbolRequest>(handler::workspace_symbol)
+            .request_snap::<req::FoldingRangeRequest>(handler::folding_range)
+            .request_snap::<req::InlayHintRequest>(handler::inlay_hint)
+            .request_snap::<req::InlayHintResolveRequest>(handler::inlay_hint_resolve)
+            .request::<req::Formatting, _>(Self::on_formatting)
+            .request::<req::RangeFormatting, _>(Self::on_range_formatting)
+            .request::<req::WillSaveWaitUntil, _>(Self::on_will_save_wait_until)
             .request_snap::<req::DocumentLinkRequest>(handler::document_links)
             .request_snap::<req::DocumentLinkResolve>(handler::document_link_resolve)
             .request_snap::<req::CodeActionRequest>(handler::code_action)
             .request_snap::<req::DocumentHighlightRequest>(handler::document_highlight)
             .request_snap::<lsp_ext::ParentModule>(handler::parent_module)
+            .request_snap::<lsp_ext::TextDocumentContent>(handler::text_document_content)
+            .request_snap::<lsp_ext::ResolveImport>(handler::resolve_import)
+            .request_snap::<lsp_ext::AttrpathAtPosition>(handler::attrpath_at_position)
+            .request_snap::<lsp_ext::Status>(handler::status)
+            .request_snap::<req::ExecuteCommand>(handler::execute_command)
             //// Events ////
             .event(Self::on_set_flake_info)
             .event(Self::on_set_nixos_options)
+            .event(Self::on_scanned_files)
+            .event(Self::on_reloaded_files)
+            .event(Self::on_reload_flake_event)
             .event(Self::on_update_config)
             .event(Self::on_update_diagnostics)
+            .event(Self::on_client_activity)
+            .event(Self::on_idle_timeout)
+            .event(Self::on_retry_request)
             // Loopback event.
             .event(Self::on_did_change_watched_files);
         router
     }
 
     pub fn new(client: ClientSocket, init_messages: Vec<ShowMessageParams>) -> Self {
+        // Will be set during initialization.
+        let config = Config::new("/non-existing-path".into());
         Self {
             host: AnalysisHost::default(),
             vfs: Arc::new(RwLock::new(Vfs::new())),
             opened_files: HashMap::default(),
-            // Will be set during initialization.
-            config: Arc::new(Config::new("/non-existing-path".into())),
+            nix_limiter: Arc::new(Semaphore::new(config.nix_max_concurrency.get())),
+            config: Arc::new(config),
             tried_flake_load: false,
             workspace_is_flake: false,
+            formatting_dynamic: false,
+            formatting_registered: false,
+            range_formatting_dynamic: false,
+            range_formatting_registered: false,
+            will_save_dynamic: false,
+            will_save_registered: false,
+            feature_registrations: HashMap::default(),
+            registration_tx: None,
+            completion_history: Arc::default(),
+            semantic_tokens_cache: Arc::default(),
             diagnostic_version: 0,
+            pending_reloads: HashMap::default(),
+            reload_generation: 0,
+            idle_generation: 0,
+            scan_generation: 0,
+            changed_since_scan: HashSet::default(),
 
             load_flake_workspace_fut: None,
+            scan_workspace_fut: None,
+            scan_cancelled: Arc::default(),
+            idle_timer_fut: None,
 
+            fs: Arc::new(RealFileSystem),
             client,
             // Will be set during initialization.
-            capabilities: NegotiatedCapabilities::default(),
+            capabilities: Arc::default(),
             init_messages,
         }
     }
@@ -158,22 +275,37 @@ impl Server {
     ) -> impl Future<Output = Result<InitializeResult, ResponseError>> {
         tracing::info!("Init params: {params:?}");
 
-        let (server_caps, final_caps) = negotiate_capabilities(&params);
-        self.capabilities = final_caps;
+        let (mut server_caps, final_caps) = negotiate_capabilities(&params);
+        self.capabilities = Arc::new(final_caps);
 
-        // TODO: Use `workspaceFolders`.
+        let folders = params
+            .workspace_folders
+            .iter()
+            .flatten()
+            .filter_map(|folder| folder.uri.to_file_path().ok())
+            .collect::<Vec<_>>();
+        // The primary root is used for configurations and flake loading.
         let root_path = match params
             .root_uri
             .as_ref()
             .and_then(|uri| uri.to_file_path().ok())
+            .or_else(|| folders.first().cloned())
         {
             Some(path) => path,
             None => std::env::current_dir().expect("Failed to the current directory"),
         };
+        let mut roots = vec![root_path.clone()];
+        roots.extend(folders.into_iter().filter(|path| *path != root_path));
+        self.vfs.write().unwrap().set_roots(roots);
 
         // Allow the client to pass initial settings through `initializationOptions`, especially
         // when they do not support `workspace/configuration`.
         *Arc::get_mut(&mut self.config).expect("No concurrent access yet") = Config::new(root_path);
+        // The default search path comes from `NIX_PATH`.
+        self.vfs
+            .write()
+            .unwrap()
+            .set_search_path(self.config.nix_search_path.clone());
         if let Some(options) = params.initialization_options {
             if options.as_object().filter(|o| !o.is_empty()).is_some() {
                 tracing::debug!("Initialization options: {options}");
@@ -181,6 +313,45 @@ impl Server {
             }
         }
 
+        // Only advertise formatting if there is a formatter configured. When supported, it will
+        // be registered later once the configuration enables it.
+        if self.capabilities.formatting_dynamic_registration && !self.config.formatting_enabled() {
+            server_caps.document_formatting_provider = None;
+            self.formatting_dynamic = true;
+        }
+        if self.capabilities.range_formatting_dynamic_registration
+            && !self.config.formatting_enabled()
+        {
+            server_caps.document_range_formatting_provider = None;
+            self.range_formatting_dynamic = true;
+        }
+
+        // Pre-save fixups are all off by default, so they are usually registered later if ever.
+        if self.capabilities.will_save_wait_until {
+            if self.config.on_save_enabled() {
+                if let Some(TextDocumentSyncCapability::Options(opts)) =
+                    &mut server_caps.text_document_sync
+                {
+                    opts.will_save_wait_until = Some(true);
+                }
+            } else if self.capabilities.text_document_sync_dynamic_registration {
+                self.will_save_dynamic = true;
+            }
+        }
+
+        // Toggleable providers are registered after initialization if supported, so that they
+        // can be unregistered later.
+        for &method in &self.capabilities.feature_dynamic_registration {
+            let register_options = take_feature_capability(&mut server_caps, method);
+            self.feature_registrations.insert(
+                method,
+                FeatureRegistration {
+                    register_options,
+                    registered: false,
+                },
+            );
+        }
+
         ready(Ok(InitializeResult {
             capabilities: server_caps,
             server_info: Some(ServerInfo {
@@ -201,6 +372,9 @@ impl Server {
         // FIXME: This is still racy since `on_did_open` can also trigger flake reloading and would
         // read uninitialized configs.
         self.spawn_reload_config();
+        self.update_feature_registrations();
+
+        self.spawn_scan_workspace();
 
         // Make a virtual event to trigger loading of flake files for flake info.
         let flake_files_changed_event = DidChangeWatchedFilesParams {
@@ -250,7 +424,9 @@ impl Server {
             kind: None,
         };
         let register_options = DidChangeWatchedFilesRegistrationOptions {
-            watchers: [FLAKE_LOCK_FILE, FLAKE_FILE].map(to_watcher).into(),
+            watchers: [FLAKE_LOCK_FILE, WATCHED_NIX_FILES_GLOB]
+                .map(to_watcher)
+                .into(),
         };
         let params = RegistrationParams {
             registrations: vec![Registration {
@@ -262,20 +438,24 @@ impl Server {
         if let Err(err) = client.register_capability(params).await {
             client.show_message_ext(
                 MessageType::ERROR,
-                format!("Failed to watch flake files: {err:#}"),
+                format!("Failed to watch files: {err:#}"),
             );
         }
-        tracing::info!("Registered file watching for flake files");
+        tracing::info!("Registered file watching for Nix and flake files");
     }
 
     fn on_did_open(&mut self, params: DidOpenTextDocumentParams) -> NotifyResult {
         // Ignore the open event for unsupported files, thus all following interactions
         // will error due to unopened files.
         let len = params.text_document.text.len();
-        if len > MAX_FILE_LEN {
+        let max_len = self.vfs.read().unwrap().max_file_len();
+        if len > max_len {
             self.client.show_message_ext(
                 MessageType::WARNING,
-                "Disable LSP functionalities for too large file ({len} > {MAX_FILE_LEN})",
+                format!(
+                    "Disable LSP functionalities for too large file {} ({len} > {max_len} bytes)",
+                    params.text_document.uri,
+                ),
             );
             return ControlFlow::Continue(());
         }
@@ -306,16 +486,31 @@ impl Server {
         // `DidCloseTextDocument` means the client ends its maintenance to a file but
         // not deletes it.
         self.opened_files.remove(&params.text_document.uri);
+        self.semantic_tokens_cache
+            .lock()
+            .unwrap()
+            .remove(&params.text_document.uri);
 
         // Clear diagnostics for closed files.
         self.client
             .publish_diagnostics(PublishDiagnosticsParams {
-                uri: params.text_document.uri,
+                uri: params.text_document.uri.clone(),
                 diagnostics: Vec::new(),
                 version: None,
             })
             .expect("inside main loop");
 
+        // Virtual documents, like Nix snippets embedded in other documents, only live as long as
+        // the client maintains them, since there is nothing on disk to reload.
+        if let VfsPath::Virtual(_) = params.text_document.uri.to_vfs_path() {
+            let _: Result<_> = self
+                .vfs
+                .write()
+                .unwrap()
+                .remove_uri(&params.text_document.uri);
+            self.apply_vfs_change();
+        }
+
         ControlFlow::Continue(())
     }
 
@@ -330,20 +525,23 @@ impl Server {
             let ret = (|| {
                 let del_range = match change.range {
                     None => None,
-                    Some(range) => Some(convert::from_range(&vfs, file, range).ok()?.1),
+                    Some(range) => Some(convert::from_range(&vfs, file, range)?.1),
                 };
                 vfs.change_file_content(file, del_range, &change.text)
-                    .ok()?;
-                Some(())
             })();
-            if ret.is_none() {
+            if let Err(err) = ret {
                 tracing::error!(
-                    "File is out of sync! Failed to apply change for {uri}: {change:?}"
+                    "File is out of sync! Failed to apply change for {uri}: {err:#} {change:?}"
+                );
+                self.client.show_message_ext(
+                    MessageType::WARNING,
+                    format!("Disable LSP functionalities for {uri}: {err:#}"),
                 );
 
                 // Clear file states to minimize pollution of the broken state.
                 self.opened_files.remove(&uri);
                 let _: Result<_, _> = vfs.remove_uri(&uri);
+                break;
             }
         }
         drop(vfs);
@@ -356,96 +554,382 @@ impl Server {
 
     fn on_did_change_configuration(
         &mut self,
-        _params: DidChangeConfigurationParams,
+        params: DidChangeConfigurationParams,
     ) -> NotifyResult {
         // As stated in https://github.com/microsoft/language-server-protocol/issues/676,
         // this notification's parameters should be ignored and the actual config queried separately.
-        self.spawn_reload_config();
+        if self.capabilities.workspace_configuration {
+            self.spawn_reload_config();
+            return ControlFlow::Continue(());
+        }
+
+        // Otherwise, the client can only push settings here, either under our section or as is,
+        // like `initializationOptions`. Diagnostics are refreshed if their filters changed.
+        let mut settings = params.settings;
+        if let Some(section) = settings.get_mut(CONFIG_KEY) {
+            settings = section.take();
+        }
+        if settings.as_object().is_some_and(|o| !o.is_empty()) {
+            tracing::debug!("Pushed settings: {settings}");
+            self.on_update_config(UpdateConfigEvent(settings))?;
+        }
         ControlFlow::Continue(())
     }
 
     fn on_did_change_watched_files(&mut self, params: DidChangeWatchedFilesParams) -> NotifyResult {
         tracing::debug!("Watched files changed: {params:?}");
 
+        self.reload_generation += 1;
+        let generation = self.reload_generation;
         let mut flake_files_changed = false;
-        for &FileEvent { ref uri, mut typ } in &params.changes {
+        let mut files_removed = false;
+        let mut to_read = Vec::new();
+        for FileEvent { uri, typ } in params.changes {
             // Don't reload files maintained by the client.
-            if self.opened_files.contains_key(uri) {
+            if self.opened_files.contains_key(&uri) {
                 continue;
             }
             let Ok(path) = uri.to_file_path() else {
                 continue;
             };
+            if self
+                .scan_workspace_fut
+                .as_ref()
+                .is_some_and(|fut| !fut.is_finished())
+            {
+                self.changed_since_scan.insert(path.clone());
+            }
 
+            if let Ok(relative) = path.strip_prefix(&self.config.root_path) {
+                if relative == Path::new(FLAKE_FILE) || relative == Path::new(FLAKE_LOCK_FILE) {
+                    flake_files_changed = true;
+                }
+            }
+
+            // Later events override pending reads.
+            self.pending_reloads.remove(&uri);
             if matches!(typ, FileChangeType::CREATED | FileChangeType::CHANGED) {
-                match (|| -> std::io::Result<_> {
-                    #[cfg(unix)]
-                    use rustix::fs::{fcntl_getfl, fcntl_setfl, OFlags, OpenOptionsExt};
-
-                    // Rule out non-regular files which may block `open()` infinitely
-                    // (eg. FIFO). We open it with `O_NONBLOCK` and check it before reading.
-                    let mut options = std::fs::File::options();
-                    options.read(true);
-                    #[cfg(unix)]
-                    options.custom_flags(OFlags::NONBLOCK.bits() as _);
-
-                    let mut file = options.open(&path)?;
-                    let ft = file.metadata()?.file_type();
-                    if !ft.is_file() {
-                        return Err(std::io::Error::new(
-                            std::io::ErrorKind::Other,
-                            format!("non-regular file type: {ft:?}"),
-                        ));
-                    }
+                self.pending_reloads.insert(uri.clone(), generation);
+                to_read.push((uri, path));
+            } else if typ == FileChangeType::DELETED {
+                self.remove_vfs_file(&uri);
+                files_removed = true;
+            }
+        }
 
-                    // Remove the O_NONBLOCK flag for blocking read.
-                    #[cfg(unix)]
-                    {
-                        let flags = fcntl_getfl(&file)? - OFlags::NONBLOCK;
-                        fcntl_setfl(&file, flags)?;
-                    }
+        if files_removed {
+            self.apply_vfs_change();
+        }
 
-                    let mut buf = String::new();
-                    file.read_to_string(&mut buf)?;
-                    Ok(buf)
-                })() {
-                    Ok(text) => self.set_vfs_file_content(uri, text),
-                    Err(err) if matches!(err.kind(), ErrorKind::NotFound) => {
-                        // File gets removed at the time calling `open()`.
-                        typ = FileChangeType::DELETED;
+        if !to_read.is_empty() {
+            self.spawn_reload_files(generation, to_read, flake_files_changed);
+        } else if flake_files_changed {
+            self.spawn_load_flake_workspace();
+        }
+
+        ControlFlow::Continue(())
+    }
+
+    /// Spawn a task to read changed files from disk, without blocking the main loop.
+    /// The flake workspace is reloaded afterwards if `flake_files_changed`.
+    fn spawn_reload_files(
+        &mut self,
+        generation: u64,
+        files: Vec<(Url, PathBuf)>,
+        flake_files_changed: bool,
+    ) {
+        let fs = self.fs.clone();
+        let client = self.client.clone();
+        task::spawn(async move {
+            let (uris, paths): (HashMap<_, _>, Vec<_>) = files
+                .into_iter()
+                .map(|(uri, path)| ((path.clone(), uri), path))
+                .unzip();
+            // The configurable limit is checked by `Vfs`, so that too large files are reported.
+            let mut rx = scan::read_files(fs, paths, scan::READ_TIMEOUT, u32::MAX as usize);
+            while let Some(batch) = scan::recv_batch(&mut rx).await {
+                let files = batch
+                    .into_iter()
+                    .map(|(path, ret)| (uris[&path].clone(), ret))
+                    .collect();
+                let _: Result<_, _> = client.emit(ReloadedFilesEvent(generation, files));
+            }
+            if flake_files_changed {
+                let _: Result<_, _> = client.emit(ReloadFlakeEvent);
+            }
+        });
+    }
+
+    fn on_reloaded_files(
+        &mut self,
+        ReloadedFilesEvent(generation, files): ReloadedFilesEvent,
+    ) -> NotifyResult {
+        let mut changed = false;
+        for (uri, ret) in files {
+            // Skip if the file is re-requested, deleted or opened in the meantime.
+            if self.pending_reloads.get(&uri) != Some(&generation) {
+                continue;
+            }
+            self.pending_reloads.remove(&uri);
+            if self.opened_files.contains_key(&uri) {
+                continue;
+            }
+            match ret {
+                Ok(text) => {
+                    let ret = self
+                        .vfs
+                        .write()
+                        .unwrap()
+                        .set_path_content(uri.to_vfs_path(), text);
+                    if let Err(err) = ret {
+                        self.client
+                            .show_message_ext(MessageType::WARNING, format!("{err:#}"));
+                        self.remove_vfs_file(&uri);
                     }
-                    Err(err) => tracing::error!("Ignore file {path:?}: {err}"),
+                }
+                // File gets removed at the time calling `open()`.
+                Err(err) if matches!(err.kind(), ErrorKind::NotFound) => self.remove_vfs_file(&uri),
+                Err(err) => {
+                    tracing::error!("Ignore file {uri}: {err}");
+                    continue;
                 }
             }
-            if typ == FileChangeType::DELETED {
-                let _: Result<_> = self.vfs.write().unwrap().remove_uri(uri);
+            changed = true;
+        }
+        if changed {
+            self.apply_vfs_change();
+        }
+        ControlFlow::Continue(())
+    }
+
+    /// Remove a file not opened by the client from the VFS.
+    /// `apply_vfs_change` should be called afterwards.
+    fn remove_vfs_file(&mut self, uri: &Url) {
+        let _: Result<_> = self.vfs.write().unwrap().remove_uri(uri);
+        // Diagnostics of unopened files may be published by previous versions.
+        let _: Result<_, _> = self.client.publish_diagnostics(PublishDiagnosticsParams {
+            uri: uri.clone(),
+            diagnostics: Vec::new(),
+            version: None,
+        });
+    }
+
+    fn on_did_change_workspace_folders(
+        &mut self,
+        params: DidChangeWorkspaceFoldersParams,
+    ) -> NotifyResult {
+        let to_paths = |folders: Vec<WorkspaceFolder>| {
+            folders
+                .into_iter()
+                .filter_map(|folder| folder.uri.to_file_path().ok())
+                .collect::<Vec<_>>()
+        };
+        let removed = to_paths(params.event.removed);
+        let added = to_paths(params.event.added);
+        {
+            let mut vfs = self.vfs.write().unwrap();
+            let mut roots = vfs.roots().to_vec();
+            // The primary root is kept since configurations and the flake are bound to it.
+            let primary = roots.first().cloned();
+            roots.retain(|root| Some(root) == primary.as_ref() || !removed.contains(root));
+            for path in added {
+                if !roots.contains(&path) {
+                    roots.push(path);
+                }
             }
+            tracing::info!("Workspace roots changed: {roots:?}");
+            vfs.set_roots(roots);
+        }
+        self.apply_vfs_change();
+        self.spawn_scan_workspace();
+        ControlFlow::Continue(())
+    }
 
-            if let Ok(relative) = path.strip_prefix(&self.config.root_path) {
-                if relative == Path::new(FLAKE_FILE) || relative == Path::new(FLAKE_LOCK_FILE) {
-                    flake_files_changed = true;
+    /// Spawn a task to (re)load all Nix files under workspace roots from disk.
+    fn spawn_scan_workspace(&mut self) {
+        let vfs = self.vfs.read().unwrap();
+        let (roots, max_len) = (vfs.roots().to_vec(), vfs.max_file_len());
+        drop(vfs);
+        self.scan_generation += 1;
+        self.changed_since_scan.clear();
+        self.scan_cancelled.store(true, atomic::Ordering::Relaxed);
+        self.scan_cancelled = Arc::default();
+        let fut = task::spawn(Self::scan_workspace(
+            self.scan_generation,
+            self.scan_cancelled.clone(),
+            roots,
+            max_len,
+            self.fs.clone(),
+            self.capabilities.clone(),
+            self.client.clone(),
+        ));
+        if let Some(prev_fut) = self.scan_workspace_fut.replace(fut) {
+            prev_fut.abort();
+        }
+    }
+
+    async fn scan_workspace(
+        generation: u64,
+        cancelled: Arc<AtomicBool>,
+        roots: Vec<PathBuf>,
+        max_len: usize,
+        fs: Arc<dyn FileSystem>,
+        caps: Arc<NegotiatedCapabilities>,
+        mut client: ClientSocket,
+    ) {
+        tracing::info!("Scanning workspace roots: {roots:?}");
+        let progress = Progress::new(
+            &client,
+            &caps,
+            SCAN_WORKSPACE_PROGRESS_TOKEN,
+            "Scanning workspace",
+            None,
+        )
+        .await;
+
+        let walk = task::spawn_blocking(move || {
+            let mut too_large = Vec::new();
+            let paths = roots
+                .iter()
+                .flat_map(|root| scan::collect_nix_files(root, max_len, &mut too_large, &cancelled))
+                .collect::<Vec<_>>();
+            (paths, too_large)
+        });
+        let (paths, too_large) = match walk.await {
+            Ok(ret) => ret,
+            Err(err) => {
+                client.show_message_ext(
+                    MessageType::ERROR,
+                    format!("Failed to scan workspace: {err}"),
+                );
+                progress.done(None);
+                return;
+            }
+        };
+        if let Some(first) = too_large.first() {
+            client.show_message_ext(
+                MessageType::WARNING,
+                format!(
+                    "Ignored {} file(s) larger than {max_len} bytes, eg. {}. \
+                     The limit can be changed by `nil.maxFileSizeMB`.",
+                    too_large.len(),
+                    first.display(),
+                ),
+            );
+        }
+        let total = paths.len();
+        progress.report_message(format!("0/{total} files"));
+
+        // Load files in batches, so that diagnostics of loaded files are not delayed by slow
+        // ones.
+        let mut rx = scan::read_files(fs, paths, scan::READ_TIMEOUT, max_len);
+        let mut cnt = 0;
+        let mut last_report = Instant::now();
+        while let Some(batch) = scan::recv_batch(&mut rx).await {
+            let files = batch
+                .into_iter()
+                .filter_map(|(path, ret)| match ret {
+                    Ok(text) => Some((path, text)),
+                    Err(err) => {
+                        tracing::warn!("Ignore file {path:?}: {err}");
+                        None
+                    }
+                })
+                .collect::<Vec<_>>();
+            cnt += files.len();
+            let _: Result<_, _> = client.emit(ScannedFilesEvent(generation, files));
+            if last_report.elapsed() >= PROGRESS_REPORT_PERIOD {
+                last_report = Instant::now();
+                progress.report_message(format!("{cnt}/{total} files"));
+            }
+        }
+
+        tracing::info!("Loaded {cnt} files from workspace");
+        progress.done(Some(format!("{cnt} files")));
+    }
+
+    fn on_scanned_files(
+        &mut self,
+        ScannedFilesEvent(generation, files): ScannedFilesEvent,
+    ) -> NotifyResult {
+        if generation != self.scan_generation {
+            return ControlFlow::Continue(());
+        }
+        {
+            let mut vfs = self.vfs.write().unwrap();
+            for (path, text) in files {
+                // Changes from the watcher are newer than the scan.
+                if self.changed_since_scan.contains(&path) {
+                    continue;
+                }
+                // Files opened by the client are more up-to-date than the disk.
+                let Ok(uri) = Url::from_file_path(&path) else {
+                    continue;
+                };
+                if !self.opened_files.contains_key(&uri) {
+                    if let Err(err) = vfs.set_path_content(path.into(), text) {
+                        tracing::warn!("{err:#}");
+                    }
                 }
             }
         }
+        self.apply_vfs_change();
+        ControlFlow::Continue(())
+    }
 
-        if flake_files_changed {
-            self.spawn_load_flake_workspace();
+    fn on_client_activity(&mut self, _: ClientActivityEvent) -> NotifyResult {
+        self.reset_idle_timer();
+        ControlFlow::Continue(())
+    }
+
+    fn on_idle_timeout(&mut self, IdleTimeoutEvent(generation): IdleTimeoutEvent) -> NotifyResult {
+        if generation != self.idle_generation {
+            return ControlFlow::Continue(());
         }
+        tracing::info!("No client activity for a while, shutting down");
+        ControlFlow::Break(Ok(()))
+    }
 
+    fn on_retry_request(&mut self, RetryRequestEvent(f): RetryRequestEvent) -> NotifyResult {
+        f(self);
         ControlFlow::Continue(())
     }
 
+    /// (Re)start the timer to shut down the server after `idleShutdownMs` without any client
+    /// activity. The timer is stopped if it is disabled.
+    fn reset_idle_timer(&mut self) {
+        self.idle_generation += 1;
+        if let Some(prev_fut) = self.idle_timer_fut.take() {
+            prev_fut.abort();
+        }
+        let Some(idle_ms) = self.config.idle_shutdown_ms else {
+            return;
+        };
+        let generation = self.idle_generation;
+        let client = self.client.clone();
+        self.idle_timer_fut = Some(task::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(idle_ms)).await;
+            let _: Result<_, _> = client.emit(IdleTimeoutEvent(generation));
+        }));
+    }
+
     fn on_reload_flake(&mut self, (): ()) -> NotifyResult {
         self.spawn_load_flake_workspace();
         ControlFlow::Continue(())
     }
 
+    fn on_reload_flake_event(&mut self, ReloadFlakeEvent: ReloadFlakeEvent) -> NotifyResult {
+        self.spawn_load_flake_workspace();
+        ControlFlow::Continue(())
+    }
+
     /// Spawn a task to (re)load the flake workspace via `flake.{nix,lock}`, including flake info,
     /// NixOS options and outputs (TODO).
     fn spawn_load_flake_workspace(&mut self) {
         let fut = task::spawn(Self::load_flake_workspace(
             self.vfs.clone(),
             self.config.clone(),
+            self.nix_limiter.clone(),
             self.capabilities.clone(),
             self.client.clone(),
         ));
@@ -457,7 +941,8 @@ impl Server {
     async fn load_flake_workspace(
         vfs: Arc<RwLock<Vfs>>,
         config: Arc<Config>,
-        caps: NegotiatedCapabilities,
+        nix_limiter: Arc<Semaphore>,
+        caps: Arc<NegotiatedCapabilities>,
         mut client: ClientSocket,
     ) {
         // Delay the loading to debounce. Later triggers will cancel previous tasks at here.
@@ -465,7 +950,7 @@ impl Server {
 
         tracing::info!("Loading flake workspace");
 
-        let flake_info = match Self::load_flake_info(&vfs, &config).await {
+        let flake_info = match Self::load_flake_info(&vfs, &config, &nix_limiter).await {
             Ok(ret) => {
                 let _: Result<_, _> = client.emit(SetFlakeInfoEvent(ret.clone()));
                 ret
@@ -547,16 +1032,19 @@ impl Server {
                 )
                 .await;
                 let flake_url = FlakeUrl::new_path(&config.root_path);
-                let ret = flake_lock::archive(&config.nix_binary, &flake_url)
-                    .await
-                    .and_then(|()| {
-                        let missing = missing_paths().collect::<Vec<_>>();
-                        ensure!(
-                            missing.is_empty(),
-                            "command succeeded but some paths are still missing: {missing:?}"
-                        );
-                        Ok(())
-                    });
+                let ret = with_nix_permit(
+                    &nix_limiter,
+                    flake_lock::archive(&config.nix_binary, &flake_url),
+                )
+                .await
+                .and_then(|()| {
+                    let missing = missing_paths().collect::<Vec<_>>();
+                    ensure!(
+                        missing.is_empty(),
+                        "command succeeded but some paths are still missing: {missing:?}"
+                    );
+                    Ok(())
+                });
                 progress.done(None);
 
                 if let Err(err) = ret {
@@ -589,9 +1077,12 @@ impl Server {
             )
             .await;
 
-            let ret = nixos_options::eval_all_options(&config.nix_binary, nixpkgs_path)
-                .await
-                .context("Failed to evaluate NixOS options");
+            let ret = with_nix_permit(
+                &nix_limiter,
+                nixos_options::eval_all_options(&config.nix_binary, nixpkgs_path),
+            )
+            .await
+            .context("Failed to evaluate NixOS options");
             match ret {
                 // Sanity check.
                 Ok(opts) if !opts.is_empty() => {
@@ -606,13 +1097,49 @@ impl Server {
         }
 
         if config.nix_flake_auto_eval_inputs {
-            Self::load_input_flakes(flake_info, &config, &caps, &mut client).await;
+            let eval_cache = Self::open_eval_cache(&vfs, &config).await;
+            Self::load_input_flakes(
+                flake_info,
+                &config,
+                eval_cache.as_ref(),
+                &nix_limiter,
+                &caps,
+                &mut client,
+            )
+            .await;
+        }
+    }
+
+    /// Open the evaluation cache for the current `flake.lock`, removing stale entries.
+    async fn open_eval_cache(vfs: &RwLock<Vfs>, config: &Config) -> Option<(EvalCache, String)> {
+        let cache = config.eval_cache()?;
+        let lock_hash = {
+            let vfs = vfs.read().unwrap();
+            let lock_vpath = VfsPath::new(config.root_path.join(FLAKE_LOCK_FILE));
+            let lock_file = vfs.file_for_path(&lock_vpath).ok()?;
+            EvalCache::lock_hash(vfs.content_for_file(lock_file).as_bytes())
+        };
+        // It walks the whole cache directory.
+        let gc = tokio::task::spawn_blocking({
+            let cache = cache.clone();
+            let live_lock_hashes = HashSet::from([lock_hash.clone()]);
+            move || cache.gc(&live_lock_hashes)
+        });
+        match gc.await {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => {
+                tracing::warn!("Failed to garbage collect the evaluation cache: {err:#}");
+            }
+            Err(err) => tracing::warn!("Evaluation cache garbage collection panicked: {err}"),
         }
+        Some((cache, lock_hash))
     }
 
     async fn load_input_flakes(
         mut flake_info: FlakeInfo,
         config: &Config,
+        eval_cache: Option<&(EvalCache, String)>,
+        nix_limiter: &Arc<Semaphore>,
         caps: &NegotiatedCapabilities,
         client: &mut ClientSocket,
     ) {
@@ -623,9 +1150,21 @@ impl Server {
             .filter_map(|(input_name, path)| {
                 let path = path.as_path().expect("Must be real paths");
                 // FIXME: Filter `flake = true` inputs.
-                path.join(FLAKE_FILE).exists().then_some((input_name, path))
+                path.join(FLAKE_FILE)
+                    .exists()
+                    .then(|| (input_name.clone(), path.to_owned()))
             })
-            .collect::<Vec<_>>();
+            .collect::<Vec<(String, PathBuf)>>();
+
+        // Reuse cached outputs of the same locked inputs.
+        if let Some((cache, lock_hash)) = eval_cache {
+            let cached_cnt =
+                take_cached_flake_outputs(cache, lock_hash, &mut flake_info, &mut input_paths);
+            if cached_cnt != 0 {
+                tracing::info!("Loaded {cached_cnt} flake inputs from cache");
+                let _: Result<_, _> = client.emit(SetFlakeInfoEvent(Some(flake_info.clone())));
+            }
+        }
 
         // Fast path.
         if input_paths.is_empty() {
@@ -633,7 +1172,7 @@ impl Server {
         }
 
         // Sort by input names to keep evaluation order stable.
-        input_paths.sort_by_key(|&(name, _)| name);
+        input_paths.sort();
 
         let input_cnt = input_paths.len();
         tracing::info!("Evaluating {input_cnt} flake inputs");
@@ -647,48 +1186,81 @@ impl Server {
         )
         .await;
 
-        let include_legacy = match nix_interop::info::get(&config.nix_binary).await {
-            Ok(info) => {
-                tracing::debug!("Nix info: {info:?}");
-                info.flake_show_filter_systems
-            }
-            Err(err) => {
-                client.show_message_ext(
-                    MessageType::ERROR,
-                    format!("Failed to get information about Nix: {err:#}"),
-                );
-                false
-            }
-        };
-
-        let mut error_cnt = 0;
-        for (i, (input_name, path)) in input_paths.iter().copied().enumerate() {
-            let report = |path: &str| {
-                let dot = if path.is_empty() { "" } else { "." };
-                progress.report(
-                    (i * 100 / input_cnt) as u32,
-                    format!("[{i}/{input_cnt}] {input_name}{dot}{path}"),
-                );
+        let include_legacy =
+            match with_nix_permit(nix_limiter, nix_interop::info::get(&config.nix_binary)).await {
+                Ok(info) => {
+                    tracing::debug!("Nix info: {info:?}");
+                    info.flake_show_filter_systems
+                }
+                Err(err) => {
+                    client.show_message_ext(
+                        MessageType::ERROR,
+                        format!("Failed to get information about Nix: {err:#}"),
+                    );
+                    false
+                }
             };
-            report("");
-
-            tracing::info!("Evaluating flake input {input_name:?}");
 
+        // Evaluations are queued and run concurrently up to the limit of `nix_limiter`.
+        // They are aborted when the set is dropped.
+        let mut tasks = JoinSet::new();
+        let mut watchers = Vec::with_capacity(input_cnt);
+        for (input_name, path) in input_paths {
             let (watcher_tx, watcher_rx) = watch::channel(String::new());
-            let flake_url = FlakeUrl::new_path(path);
-            let mut eval_fut = pin!(flake_output::eval_flake_output(
-                &config.nix_binary,
-                &flake_url,
-                Some(watcher_tx),
-                include_legacy,
-                config.nix_max_memory(),
-            ));
-            let ret = loop {
-                match tokio::time::timeout(PROGRESS_REPORT_PERIOD, eval_fut.as_mut()).await {
-                    Ok(ret) => break ret,
-                    Err(_) => report(&watcher_rx.borrow()),
+            watchers.push((input_name.clone(), watcher_rx));
+            let nix_binary = config.nix_binary.clone();
+            let memory_limit = config.nix_max_memory();
+            let nix_limiter = nix_limiter.clone();
+            tasks.spawn(async move {
+                let _permit = nix_limiter.acquire().await.expect("Never closed");
+                tracing::info!("Evaluating flake input {input_name:?}");
+                let flake_url = FlakeUrl::new_path(path);
+                let ret = AssertUnwindSafe(flake_output::eval_flake_output(
+                    &nix_binary,
+                    &flake_url,
+                    Some(watcher_tx),
+                    include_legacy,
+                    memory_limit,
+                ))
+                .catch_unwind()
+                .await
+                .unwrap_or_else(|_| Err(anyhow!("Evaluation panicked")));
+                (input_name, ret)
+            });
+        }
+
+        let report = |done: usize, watchers: &[(String, watch::Receiver<String>)]| {
+            // Show one of the running evaluations.
+            let running = watchers.iter().find_map(|(input_name, rx)| {
+                let path = &**rx.borrow();
+                (!path.is_empty()).then(|| format!(" {input_name}.{path}"))
+            });
+            progress.report(
+                (done * 100 / input_cnt) as u32,
+                format!("[{done}/{input_cnt}]{}", running.unwrap_or_default()),
+            );
+        };
+
+        let mut done = 0;
+        let mut error_cnt = 0;
+        loop {
+            let ret = match tokio::time::timeout(PROGRESS_REPORT_PERIOD, tasks.join_next()).await {
+                Ok(Some(Ok(ret))) => ret,
+                // Panics are caught inside tasks. This only happens if tasks are cancelled.
+                Ok(Some(Err(err))) => {
+                    tracing::error!("Flake input evaluation task failed: {err}");
+                    continue;
+                }
+                Ok(None) => break,
+                Err(_) => {
+                    report(done, &watchers);
+                    continue;
                 }
             };
+            done += 1;
+            let (input_name, ret) = ret;
+            watchers.retain(|(name, _)| *name != input_name);
+            report(done, &watchers);
 
             let output = match ret {
                 Ok(output) => output,
@@ -704,9 +1276,12 @@ impl Server {
                     continue;
                 }
             };
-            flake_info
-                .input_flake_outputs
-                .insert(input_name.clone(), output);
+            if let Some((cache, lock_hash)) = eval_cache {
+                if let Err(err) = cache.put(lock_hash, &input_name, &output) {
+                    tracing::warn!("Failed to cache flake input {input_name:?}: {err:#}");
+                }
+            }
+            flake_info.input_flake_outputs.insert(input_name, output);
             let _: Result<_, _> = client.emit(SetFlakeInfoEvent(Some(flake_info.clone())));
         }
 
@@ -716,7 +1291,11 @@ impl Server {
         progress.done(msg);
     }
 
-    async fn load_flake_info(vfs: &RwLock<Vfs>, config: &Config) -> Result<Option<FlakeInfo>> {
+    async fn load_flake_info(
+        vfs: &RwLock<Vfs>,
+        config: &Config,
+        nix_limiter: &Semaphore,
+    ) -> Result<Option<FlakeInfo>> {
         tracing::info!("Loading flake info");
 
         let (flake_file, lock_src) = {
@@ -740,10 +1319,12 @@ impl Server {
             (flake_file, lock_src)
         };
 
-        let inputs =
-            flake_lock::resolve_flake_locked_inputs(&config.nix_binary, lock_src.as_bytes())
-                .await
-                .context("Failed to resolve flake inputs from lock file")?;
+        let inputs = with_nix_permit(
+            nix_limiter,
+            flake_lock::resolve_flake_locked_inputs(&config.nix_binary, lock_src.as_bytes()),
+        )
+        .await
+        .context("Failed to resolve flake inputs from lock file")?;
 
         // We only need the map for input -> store path.
         let input_store_paths = inputs
@@ -807,23 +1388,78 @@ impl Server {
         let mut errors = Vec::new();
         config.update(value.0, &mut errors);
 
+        let updated_search_path = self.config.nix_search_path != config.nix_search_path;
+        let updated_max_nesting =
+            self.config.diagnostics_max_nesting != config.diagnostics_max_nesting;
+        let updated_lib_inherit_threshold = self.config.diagnostics_lib_inherit_threshold
+            != config.diagnostics_lib_inherit_threshold;
+        let updated_idle_shutdown = self.config.idle_shutdown_ms != config.idle_shutdown_ms;
+        let updated_max_file_len = self.config.max_file_len() != config.max_file_len();
         let updated_diagnostics = (
             &self.config.diagnostics_excluded_files,
             &self.config.diagnostics_ignored,
+            &self.config.diagnostics_enabled,
         ) != (
             &config.diagnostics_excluded_files,
             &config.diagnostics_ignored,
+            &config.diagnostics_enabled,
         );
 
+        if config.nix_max_concurrency != self.config.nix_max_concurrency {
+            // Running processes keep their permits of the old limiter.
+            self.nix_limiter = Arc::new(Semaphore::new(config.nix_max_concurrency.get()));
+        }
+
         tracing::info!("Updated config, errors: {errors:?}, config: {config:?}");
         self.config = Arc::new(config);
 
         if !errors.is_empty() {
-            let msg = ["Failed to apply some settings:"]
+            let msg = ["Some settings are invalid and reset to defaults:"]
                 .into_iter()
                 .chain(errors.iter().flat_map(|s| ["\n- ", s]))
                 .collect::<String>();
-            self.client.show_message_ext(MessageType::ERROR, msg);
+            self.client.show_message_ext(MessageType::WARNING, msg);
+        }
+
+        self.update_formatting_registration();
+        self.update_will_save_registration();
+        self.update_feature_registrations();
+
+        if updated_idle_shutdown {
+            self.reset_idle_timer();
+        }
+
+        if updated_search_path {
+            self.vfs
+                .write()
+                .unwrap()
+                .set_search_path(self.config.nix_search_path.clone());
+            self.apply_vfs_change();
+        }
+
+        if updated_max_nesting {
+            self.vfs
+                .write()
+                .unwrap()
+                .set_max_nesting(self.config.diagnostics_max_nesting);
+            self.apply_vfs_change();
+        }
+
+        if updated_lib_inherit_threshold {
+            self.vfs
+                .write()
+                .unwrap()
+                .set_lib_inherit_threshold(self.config.diagnostics_lib_inherit_threshold);
+            self.apply_vfs_change();
+        }
+
+        // Rescan to load files previously skipped for their sizes.
+        if updated_max_file_len {
+            self.vfs
+                .write()
+                .unwrap()
+                .set_max_file_len(self.config.max_file_len());
+            self.spawn_scan_workspace();
         }
 
         // If this is the first load, load the flake workspace, which depends on `nix.binary`.
@@ -840,6 +1476,163 @@ impl Server {
         ControlFlow::Continue(())
     }
 
+    fn update_formatting_registration(&mut self) {
+        let enabled = self.config.formatting_enabled();
+        if self.formatting_dynamic && enabled != self.formatting_registered {
+            self.formatting_registered = enabled;
+            self.spawn_update_registration(
+                req::Formatting::METHOD,
+                enabled.then(text_document_register_options),
+            );
+        }
+        if self.range_formatting_dynamic && enabled != self.range_formatting_registered {
+            self.range_formatting_registered = enabled;
+            self.spawn_update_registration(
+                req::RangeFormatting::METHOD,
+                enabled.then(text_document_register_options),
+            );
+        }
+    }
+
+    fn update_will_save_registration(&mut self) {
+        let enabled = self.config.on_save_enabled();
+        if !self.will_save_dynamic || enabled == self.will_save_registered {
+            return;
+        }
+        self.will_save_registered = enabled;
+        self.spawn_update_registration(
+            req::WillSaveWaitUntil::METHOD,
+            enabled.then(text_document_register_options),
+        );
+    }
+
+    fn update_feature_registrations(&mut self) {
+        let mut updates = Vec::new();
+        for (&method, reg) in &mut self.feature_registrations {
+            let enabled = self.config.features.method_enabled(method);
+            if enabled != reg.registered {
+                reg.registered = enabled;
+                updates.push((method, enabled.then(|| reg.register_options.clone())));
+            }
+        }
+        for (method, register_options) in updates {
+            self.spawn_update_registration(method, register_options);
+        }
+    }
+
+    /// Register `method` with `register_options`, or unregister it if `None`.
+    ///
+    /// Updates are sent one by one in order by a single task, so that toggling a feature quickly
+    /// cannot reorder its registration and unregistration.
+    fn spawn_update_registration(
+        &mut self,
+        method: &'static str,
+        register_options: Option<serde_json::Value>,
+    ) {
+        let tx = self.registration_tx.get_or_insert_with(|| {
+            let (tx, mut rx) = mpsc::unbounded_channel::<(&str, Option<serde_json::Value>)>();
+            let mut client = self.client.clone();
+            tokio::spawn(async move {
+                while let Some((method, register_options)) = rx.recv().await {
+                    let enabled = register_options.is_some();
+                    let ret = if let Some(register_options) = register_options {
+                        client
+                            .register_capability(RegistrationParams {
+                                registrations: vec![Registration {
+                                    id: method.into(),
+                                    method: method.into(),
+                                    register_options: Some(register_options),
+                                }],
+                            })
+                            .await
+                    } else {
+                        client
+                            .unregister_capability(UnregistrationParams {
+                                unregisterations: vec![Unregistration {
+                                    id: method.into(),
+                                    method: method.into(),
+                                }],
+                            })
+                            .await
+                    };
+                    if let Err(err) = ret {
+                        client.show_message_ext(
+                            MessageType::ERROR,
+                            format!("Failed to update capability {method}: {err:#}"),
+                        );
+                    }
+                    tracing::info!("Updated registration of {method}: {enabled}");
+                }
+            });
+            tx
+        });
+        let _: Result<_, _> = tx.send((method, register_options));
+    }
+
+    /// Formatting failures, most likely from the external formatter, are shown to the user
+    /// instead of replied as errors, since format-on-save usually silently ignores them.
+    fn on_formatting(
+        &mut self,
+        params: DocumentFormattingParams,
+    ) -> impl Future<Output = Result<Option<Vec<TextEdit>>, ResponseError>> {
+        self.spawn_formatting(req::Formatting::METHOD, params, handler::formatting)
+    }
+
+    fn on_range_formatting(
+        &mut self,
+        params: DocumentRangeFormattingParams,
+    ) -> impl Future<Output = Result<Option<Vec<TextEdit>>, ResponseError>> {
+        self.spawn_formatting(
+            req::RangeFormatting::METHOD,
+            params,
+            handler::range_formatting,
+        )
+    }
+
+    fn spawn_formatting<P: Send + UnwindSafe + 'static>(
+        &mut self,
+        method: &'static str,
+        params: P,
+        f: fn(StateSnapshot, P) -> Result<Option<Vec<TextEdit>>>,
+    ) -> impl Future<Output = Result<Option<Vec<TextEdit>>, ResponseError>> {
+        let mut client = self.client.clone();
+        let task = self
+            .spawn_with_snapshot(move |snap| with_catch_unwind(method, move || f(snap, params)));
+        async move {
+            match task.await.expect("Already catch_unwind") {
+                Err(err) if !err.is::<Cancelled>() => {
+                    client.show_message_ext(MessageType::WARNING, format!("{err:#}"));
+                    Ok(None)
+                }
+                ret => ret.map_err(error_to_response),
+            }
+        }
+    }
+
+    /// The client may block saving until we reply, so give up and reply no edits if it takes too
+    /// long, eg. when the analysis is still busy with a previous change.
+    fn on_will_save_wait_until(
+        &mut self,
+        params: WillSaveTextDocumentParams,
+    ) -> impl Future<Output = Result<Option<Vec<TextEdit>>, ResponseError>> {
+        let task = self.spawn_with_snapshot(move |snap| {
+            with_catch_unwind(req::WillSaveWaitUntil::METHOD, move || {
+                handler::will_save_wait_until(snap, params)
+            })
+        });
+        async move {
+            match tokio::time::timeout(WILL_SAVE_TIMEOUT, task).await {
+                Ok(ret) => ret
+                    .expect("Already catch_unwind")
+                    .map_err(error_to_response),
+                Err(_) => {
+                    tracing::warn!("Timeout computing edits before saving");
+                    Ok(None)
+                }
+            }
+        }
+    }
+
     fn spawn_update_diagnostics(&mut self) {
         self.diagnostic_version += 1;
         let version = self.diagnostic_version;
@@ -864,9 +1657,7 @@ impl Server {
                     .map(|(uri, file, line_map)| {
                         let diags = if !snap.config.diagnostics_excluded_files.contains(&uri) {
                             let mut diags = snap.analysis.diagnostics(file)?;
-                            diags.retain(|diag| {
-                                !snap.config.diagnostics_ignored.contains(diag.code())
-                            });
+                            diags.retain(|diag| snap.config.diagnostic_enabled(diag));
                             diags.truncate(MAX_DIAGNOSTICS_CNT);
                             convert::to_diagnostics(&uri, file, &line_map, &diags)
                         } else {
@@ -931,13 +1722,20 @@ impl Server {
             analysis: self.host.snapshot(),
             vfs: Arc::clone(&self.vfs),
             config: Arc::clone(&self.config),
+            capabilities: Arc::clone(&self.capabilities),
+            completion_history: Arc::clone(&self.completion_history),
+            semantic_tokens_cache: Arc::clone(&self.semantic_tokens_cache),
         };
         task::spawn_blocking(move || f(snap))
     }
 
     fn set_vfs_file_content(&mut self, uri: &Url, text: String) {
         let vpath = uri.to_vfs_path();
-        self.vfs.write().unwrap().set_path_content(vpath, text);
+        let ret = self.vfs.write().unwrap().set_path_content(vpath, text);
+        if let Err(err) = ret {
+            self.client
+                .show_message_ext(MessageType::WARNING, format!("{err:#}"));
+        }
         self.apply_vfs_change();
     }
 
@@ -954,22 +1752,40 @@ impl Server {
 }
 
 trait RouterExt: BorrowMut<Router<Server>> {
+    /// Register a request handler running on a snapshot in a blocking thread.
+    ///
+    /// If the handler is cancelled by a concurrent change, it is retried once on a new snapshot.
+    /// But if the request document was moved out of all workspace roots in the meantime, an
+    /// empty result is returned instead, since the old results may refer to files no longer in
+    /// the workspace.
     fn request_snap<R: Request>(
         &mut self,
-        f: impl Fn(StateSnapshot, R::Params) -> Result<R::Result> + Send + Copy + UnwindSafe + 'static,
+        f: impl Fn(StateSnapshot, &R::Params) -> Result<R::Result> + Send + Clone + UnwindSafe + 'static,
     ) -> &mut Self
     where
-        R::Params: Send + UnwindSafe + 'static,
+        R::Params: RequestDocument + Send + Sync + RefUnwindSafe + 'static,
         R::Result: Send + 'static,
     {
         self.borrow_mut().request::<R, _>(move |this, params| {
-            let task = this.spawn_with_snapshot(move |snap| {
-                with_catch_unwind(R::METHOD, move || f(snap, params))
+            let client = this.client.clone();
+            let doc_path = params
+                .document_uri()
+                .map(|uri| uri.to_vfs_path())
+                .filter(|path| this.vfs.read().unwrap().is_in_roots(path));
+            let params = Arc::new(params);
+            let f = f.clone();
+            let task = this.spawn_with_snapshot({
+                let (params, f) = (params.clone(), f.clone());
+                move |snap| with_catch_unwind(R::METHOD, move || f(snap, &params))
             });
             async move {
-                task.await
-                    .expect("Already catch_unwind")
-                    .map_err(error_to_response)
+                let ret = match task.await.expect("Already catch_unwind") {
+                    Err(err) if err.is::<Cancelled>() => {
+                        retry_request::<R>(client, doc_path, params, f).await
+                    }
+                    ret => ret,
+                };
+                ret.map_err(error_to_response)
             }
         });
         self
@@ -978,6 +1794,88 @@ trait RouterExt: BorrowMut<Router<Server>> {
 
 impl RouterExt for Router<Server> {}
 
+/// Typed access to the document of request parameters, if any.
+trait RequestDocument {
+    fn document_uri(&self) -> Option<&Url>;
+}
+
+macro_rules! impl_request_document {
+    ($($ty:ty => |$p:ident| $uri:expr,)*) => {
+        $(
+            impl RequestDocument for $ty {
+                fn document_uri(&self) -> Option<&Url> {
+                    let $p = self;
+                    $uri
+                }
+            }
+        )*
+    };
+}
+
+impl_request_document! {
+    () => |_p| None,
+    lsp_types::CodeActionParams => |p| Some(&p.text_document.uri),
+    lsp_types::CompletionParams => |p| Some(&p.text_document_position.text_document.uri),
+    lsp_types::DocumentHighlightParams => |p| Some(&p.text_document_position_params.text_document.uri),
+    lsp_types::DocumentLink => |_p| None,
+    lsp_types::DocumentLinkParams => |p| Some(&p.text_document.uri),
+    lsp_types::DocumentSymbolParams => |p| Some(&p.text_document.uri),
+    lsp_types::ExecuteCommandParams => |_p| None,
+    lsp_types::FoldingRangeParams => |p| Some(&p.text_document.uri),
+    lsp_types::GotoDefinitionParams => |p| Some(&p.text_document_position_params.text_document.uri),
+    lsp_types::HoverParams => |p| Some(&p.text_document_position_params.text_document.uri),
+    lsp_types::InlayHint => |_p| None,
+    lsp_types::InlayHintParams => |p| Some(&p.text_document.uri),
+    lsp_types::ReferenceParams => |p| Some(&p.text_document_position.text_document.uri),
+    lsp_types::RenameParams => |p| Some(&p.text_document_position.text_document.uri),
+    lsp_types::SelectionRangeParams => |p| Some(&p.text_document.uri),
+    lsp_types::SemanticTokensDeltaParams => |p| Some(&p.text_document.uri),
+    lsp_types::SemanticTokensParams => |p| Some(&p.text_document.uri),
+    lsp_types::SemanticTokensRangeParams => |p| Some(&p.text_document.uri),
+    lsp_types::SignatureHelpParams => |p| Some(&p.text_document_position_params.text_document.uri),
+    lsp_types::TextDocumentPositionParams => |p| Some(&p.text_document.uri),
+    lsp_types::WorkspaceSymbolParams => |_p| None,
+    // Virtual documents are not in workspace roots.
+    lsp_ext::TextDocumentContentParams => |_p| None,
+}
+
+async fn retry_request<R: Request>(
+    client: ClientSocket,
+    doc_path: Option<VfsPath>,
+    params: Arc<R::Params>,
+    f: impl FnOnce(StateSnapshot, &R::Params) -> Result<R::Result> + Send + UnwindSafe + 'static,
+) -> Result<R::Result>
+where
+    R::Params: Send + Sync + RefUnwindSafe + 'static,
+    R::Result: Send + 'static,
+{
+    let (tx, rx) = oneshot::channel();
+    client.emit(RetryRequestEvent(Box::new(move |this| {
+        if let Some(path) = doc_path.filter(|path| !this.vfs.read().unwrap().is_in_roots(path)) {
+            tracing::info!(
+                "Drop the result of {} since {} is no longer in workspace roots",
+                R::METHOD,
+                path.display(),
+            );
+            let _: Result<_, _> = tx.send(None);
+            return;
+        }
+        let task = this.spawn_with_snapshot(move |snap| {
+            with_catch_unwind(R::METHOD, move || f(snap, &params))
+        });
+        let _: Result<_, _> = tx.send(Some(task));
+    })))?;
+    match rx.await {
+        Ok(Some(task)) => task.await.expect("Already catch_unwind"),
+        // Requests with empty results all have optional results.
+        Ok(None) => {
+            serde_json::from_value(serde_json::Value::Null).map_err(|_| content_modified().into())
+        }
+        // The main loop is shutting down.
+        Err(_) => Err(content_modified().into()),
+    }
+}
+
 trait ClientExt: BorrowMut<ClientSocket> {
     fn show_message_ext(&mut self, typ: MessageType, msg: impl fmt::Display) {
         // Maybe connect all tracing::* to LSP ShowMessage?
@@ -1032,6 +1930,14 @@ impl Progress {
         });
     }
 
+    fn report_message(&self, message: String) {
+        self.notify(WorkDoneProgress::Report(WorkDoneProgressReport {
+            cancellable: None,
+            message: Some(message),
+            percentage: None,
+        }));
+    }
+
     fn report(&self, percentage: u32, message: String) {
         assert!((0..=100).contains(&percentage));
         self.notify(WorkDoneProgress::Report(WorkDoneProgressReport {
@@ -1054,6 +1960,41 @@ impl Drop for Progress {
     }
 }
 
+fn text_document_register_options() -> serde_json::Value {
+    let opts = TextDocumentRegistrationOptions {
+        // Use the client-side document selector.
+        document_selector: None,
+    };
+    serde_json::to_value(opts).unwrap()
+}
+
+/// Move cached outputs of `input_paths` into `flake_info`, leaving only the ones to be evaluated.
+/// Return the number of inputs found in the cache.
+fn take_cached_flake_outputs(
+    cache: &EvalCache,
+    lock_hash: &str,
+    flake_info: &mut FlakeInfo,
+    input_paths: &mut Vec<(String, PathBuf)>,
+) -> usize {
+    let input_cnt = input_paths.len();
+    input_paths.retain(|(input_name, _)| {
+        let Some(output) = cache.get::<FlakeOutput>(lock_hash, input_name) else {
+            return true;
+        };
+        flake_info
+            .input_flake_outputs
+            .insert(input_name.clone(), output);
+        false
+    });
+    input_cnt - input_paths.len()
+}
+
+/// Run a future spawning `nix`, queueing it if there are too many running ones.
+async fn with_nix_permit<T>(limiter: &Semaphore, fut: impl Future<Output = T>) -> T {
+    let _permit = limiter.acquire().await.expect("Never closed");
+    fut.await
+}
+
 fn with_catch_unwind<T>(ctx: &str, f: impl FnOnce() -> Result<T> + UnwindSafe) -> Result<T> {
     static INSTALL_PANIC_HOOK: Once = Once::new();
     thread_local! {
@@ -1093,9 +2034,14 @@ fn with_catch_unwind<T>(ctx: &str, f: impl FnOnce() -> Result<T> + UnwindSafe) -
     }
 }
 
+fn content_modified() -> ResponseError {
+    ResponseError::new(ErrorCode::CONTENT_MODIFIED, "Content modified")
+}
+
 fn error_to_response(err: anyhow::Error) -> ResponseError {
+    // Queries are only cancelled by changes on the server side. Clients are expected to retry.
     if err.is::<Cancelled>() {
-        return ResponseError::new(ErrorCode::REQUEST_CANCELLED, "Client cancelled");
+        return content_modified();
     }
     match err.downcast::<ResponseError>() {
         Ok(resp) => resp,
@@ -1108,6 +2054,9 @@ pub struct StateSnapshot {
     pub(crate) analysis: Analysis,
     vfs: Arc<RwLock<Vfs>>,
     pub(crate) config: Arc<Config>,
+    pub(crate) capabilities: Arc<NegotiatedCapabilities>,
+    pub(crate) completion_history: Arc<Mutex<CompletionHistory>>,
+    pub(crate) semantic_tokens_cache: Arc<Mutex<SemanticTokensCache>>,
 }
 
 impl StateSnapshot {
@@ -1115,3 +2064,203 @@ impl StateSnapshot {
         self.vfs.read().unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TestClient;
+    use serde_json::json;
+
+    /// The first attempt on each document keeps running queries until cancelled.
+    /// Returns the number of attempts.
+    enum PollUntilCancelled {}
+
+    impl Request for PollUntilCancelled {
+        type Params = lsp_types::TextDocumentPositionParams;
+        type Result = Option<usize>;
+        const METHOD: &'static str = "test/pollUntilCancelled";
+    }
+
+    fn poll_until_cancelled(
+        attempts: &Mutex<Vec<Url>>,
+        snap: StateSnapshot,
+        params: &lsp_types::TextDocumentPositionParams,
+    ) -> Result<Option<usize>> {
+        let uri = &params.text_document.uri;
+        let attempt = {
+            let mut attempts = attempts.lock().unwrap();
+            attempts.push(uri.clone());
+            attempts.iter().filter(|&u| u == uri).count()
+        };
+        let (fpos, _) = convert::from_file_pos(&snap.vfs(), params)?;
+        if attempt == 1 {
+            // Cancellation is only observed by running queries.
+            // Bail out if never cancelled, which fails the test.
+            let deadline = Instant::now() + Duration::from_secs(10);
+            while Instant::now() < deadline {
+                snap.analysis.references(fpos, true)?;
+                std::thread::yield_now();
+            }
+        }
+        Ok(Some(attempt))
+    }
+
+    /// Panic while handling the request, either immediately or in the returned future.
+    enum Panic {}
+
+    impl Request for Panic {
+        type Params = bool;
+        type Result = ();
+        const METHOD: &'static str = "test/panic";
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn panicking_request() {
+        let serve = |input, output| {
+            crate::run_server_with_router(input, output, |router| {
+                router.request::<Panic, _>(|_, in_future| {
+                    assert!(in_future, "Deliberate panic");
+                    async { panic!("Deliberate panic in future") }
+                });
+            })
+        };
+        TestClient::run_with("", serve, |client| async move {
+            client.initialize(json!({})).await;
+            let ids = [false, true, false]
+                .map(|in_future| client.request_async(Panic::METHOD, in_future.into()));
+            for id in ids {
+                let resp = client.response(id).await;
+                assert_eq!(
+                    resp["error"]["code"],
+                    i64::from(ErrorCode::INTERNAL_ERROR.0),
+                    "{resp}",
+                );
+            }
+            // The server is still alive.
+            client.shutdown().await;
+        })
+        .await;
+    }
+
+    #[test]
+    fn cached_flake_outputs() {
+        let dir = temp_root("eval-cache");
+        let cache = EvalCache::new(dir.join("cache"), &dir, u64::MAX);
+        let output = FlakeOutput::Attrset(HashMap::new());
+        cache.put("lock", "cached", &output).unwrap();
+
+        let mut flake_info = FlakeInfo {
+            flake_file: ide::FileId(0),
+            input_store_paths: HashMap::new(),
+            input_flake_outputs: HashMap::new(),
+        };
+        let mut input_paths = vec![
+            ("cached".to_owned(), dir.join("cached")),
+            ("fresh".to_owned(), dir.join("fresh")),
+        ];
+        let cnt = take_cached_flake_outputs(&cache, "lock", &mut flake_info, &mut input_paths);
+        assert_eq!(cnt, 1);
+        assert_eq!(input_paths, [("fresh".to_owned(), dir.join("fresh"))]);
+        assert_eq!(
+            flake_info.input_flake_outputs,
+            HashMap::from([("cached".to_owned(), output)]),
+        );
+
+        // Outputs of another lock file are not reused.
+        let cnt = take_cached_flake_outputs(&cache, "other", &mut flake_info, &mut input_paths);
+        assert_eq!(cnt, 0);
+        assert_eq!(input_paths.len(), 1);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn stale_scanned_files() {
+        let root = temp_root("stale-scan");
+        let mut server = Server::new(ClientSocket::new_closed(), Vec::new());
+        server.vfs.write().unwrap().set_roots(vec![root.clone()]);
+        server.scan_generation = 2;
+        server.scan_workspace_fut = Some(task::spawn(std::future::pending()));
+
+        let (a, b, c) = (root.join("a.nix"), root.join("b.nix"), root.join("c.nix"));
+        let _ = server.on_did_change_watched_files(DidChangeWatchedFilesParams {
+            changes: vec![FileEvent {
+                uri: Url::from_file_path(&a).unwrap(),
+                typ: FileChangeType::CHANGED,
+            }],
+        });
+        let _ = server.on_scanned_files(ScannedFilesEvent(
+            2,
+            vec![(a.clone(), "old".into()), (b.clone(), "b".into())],
+        ));
+        // From an aborted scan.
+        let _ = server.on_scanned_files(ScannedFilesEvent(1, vec![(c.clone(), "c".into())]));
+
+        let vfs = server.vfs.read().unwrap();
+        let loaded = |path: &Path| vfs.file_for_path(&VfsPath::new(path)).is_ok();
+        assert!(!loaded(&a));
+        assert!(loaded(&b));
+        assert!(!loaded(&c));
+    }
+
+    fn temp_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("nil-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        root
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn workspace_folder_removed_during_request() {
+        let fixture = "
+#- /a/default.nix
+#- /b/x.nix
+#- /c/y.nix
+#- /d/default.nix
+";
+        let serve = |input, output| {
+            crate::run_server_with_router(input, output, |router| {
+                let attempts = Arc::new(Mutex::new(Vec::new()));
+                router.request_snap::<PollUntilCancelled>(move |snap, params| {
+                    poll_until_cancelled(&attempts, snap, params)
+                });
+            })
+        };
+
+        TestClient::run_with(fixture, serve, |client| async move {
+            let folder = |name: &str| json!({ "uri": client.workspace.uri(name), "name": "" });
+            let [a, b, c, d] = ["/a", "/b", "/c", "/d"].map(folder);
+            let remove_folder = |folder: &serde_json::Value| {
+                client.notify(
+                    "workspace/didChangeWorkspaceFolders",
+                    json!({ "event": { "added": [], "removed": [folder] } }),
+                );
+            };
+            client
+                .initialize_with(json!({
+                    "processId": null,
+                    "rootUri": a["uri"],
+                    "workspaceFolders": [a, b, c, d],
+                    "capabilities": {},
+                }))
+                .await;
+            client.did_open("/b/x.nix", "let a = 1; in a");
+            client.did_open("/c/y.nix", "let a = 1; in a");
+            // The document is moved out of workspace roots. Results are dropped.
+            let id1 = client.request_async(
+                PollUntilCancelled::METHOD,
+                client.position("/c/y.nix", 0, 4),
+            );
+            remove_folder(&c);
+            // The document is still in workspace roots. It is retried.
+            let id2 = client.request_async(
+                PollUntilCancelled::METHOD,
+                client.position("/b/x.nix", 0, 4),
+            );
+            remove_folder(&d);
+
+            let resp = client.response(id1).await;
+            assert_eq!(resp["result"], serde_json::Value::Null, "{resp}");
+            let resp = client.response(id2).await;
+            assert_eq!(resp["result"], 2, "{resp}");
+        })
+        .await;
+    }
+}
@@ -1,10 +1,13 @@
-use crate::UrlExt;
+use crate::{UrlExt, MAX_FILE_LEN};
 use anyhow::{ensure, Context, Result};
-use ide::{Change, FileId, FileSet, FlakeGraph, FlakeInfo, SourceRoot, SourceRootId, VfsPath};
+use ide::{
+    Change, FileId, FileSet, FlakeGraph, FlakeInfo, SearchPath, SourceRoot, SourceRootId, VfsPath,
+};
 use lsp_types::Url;
 use nix_interop::nixos_options::NixosOptions;
 use slab::Slab;
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::{fmt, mem};
 use text_size::{TextRange, TextSize};
@@ -12,10 +15,18 @@ use text_size::{TextRange, TextSize};
 /// Vfs stores file contents with line mapping, and a mapping between
 /// filesystem paths and `FileId`s.
 /// The query system is built on `FileId`'s.
+///
+/// Files are grouped into one `SourceRoot` per workspace root, by the longest root prefix.
+/// Files outside all workspace roots belong to an extra fallback root at the end.
 pub struct Vfs {
+    /// Slots of removed files are reused for new files, so `FileId`s do not grow unboundedly.
     files: Slab<(Arc<str>, Arc<LineMap>)>,
     local_file_set: FileSet,
+    /// Workspace roots. The first one is the primary root, which may have flake info.
+    roots: Vec<PathBuf>,
     root_changed: bool,
+    /// Files longer than this are rejected. It never exceeds `u32::MAX`.
+    max_file_len: usize,
     change: Change,
 }
 
@@ -23,7 +34,9 @@ impl fmt::Debug for Vfs {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Vfs")
             .field("file_cnt", &self.files.len())
+            .field("roots", &self.roots)
             .field("root_changed", &self.root_changed)
+            .field("max_file_len", &self.max_file_len)
             .field("change", &self.change)
             .finish_non_exhaustive()
     }
@@ -34,11 +47,24 @@ impl Vfs {
         Self {
             files: Slab::new(),
             local_file_set: FileSet::default(),
+            roots: Vec::new(),
             root_changed: false,
+            max_file_len: MAX_FILE_LEN,
             change: Change::default(),
         }
     }
 
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.roots
+    }
+
+    /// Set workspace roots. The first one is the primary root.
+    pub fn set_roots(&mut self, roots: Vec<PathBuf>) {
+        self.roots = roots;
+        self.root_changed = true;
+    }
+
+    /// Flake info always applies to the primary root.
     pub fn set_flake_info(&mut self, flake_info: Option<FlakeInfo>) {
         self.change.set_flake_graph(FlakeGraph {
             nodes: HashMap::from_iter(flake_info.map(|info| (SourceRootId(0), info))),
@@ -49,7 +75,35 @@ impl Vfs {
         self.change.set_nixos_options(opts);
     }
 
-    pub fn set_path_content(&mut self, path: VfsPath, text: String) -> FileId {
+    pub fn set_search_path(&mut self, search_path: SearchPath) {
+        self.change.set_search_path(search_path);
+    }
+
+    pub fn set_max_nesting(&mut self, max_nesting: usize) {
+        self.change.set_max_nesting(max_nesting);
+    }
+
+    pub fn set_lib_inherit_threshold(&mut self, threshold: usize) {
+        self.change.set_lib_inherit_threshold(threshold);
+    }
+
+    pub fn max_file_len(&self) -> usize {
+        self.max_file_len
+    }
+
+    /// Set the file length limit for later changes, clamped to `u32::MAX`.
+    /// Already loaded files are kept.
+    pub fn set_max_file_len(&mut self, max_file_len: usize) {
+        self.max_file_len = max_file_len.min(u32::MAX as usize);
+    }
+
+    pub fn set_path_content(&mut self, path: VfsPath, text: String) -> Result<FileId> {
+        ensure!(
+            text.len() <= self.max_file_len,
+            "File {path:?} is too large ({} > {} bytes)",
+            text.len(),
+            self.max_file_len,
+        );
         let (text, line_map) = LineMap::normalize(text);
         let text = <Arc<str>>::from(text);
         let line_map = Arc::new(line_map);
@@ -58,7 +112,7 @@ impl Vfs {
                 self.files[file.0 as usize] = (text.clone(), line_map);
                 self.change.change_file(file, text);
                 self.root_changed = true;
-                file
+                Ok(file)
             }
             None => {
                 let next_entry = self.files.vacant_entry();
@@ -67,7 +121,7 @@ impl Vfs {
                 self.root_changed = true;
                 next_entry.insert((text.clone(), line_map));
                 self.change.change_file(file, text);
-                file
+                Ok(file)
             }
         }
     }
@@ -95,6 +149,12 @@ impl Vfs {
                 buf
             }
         };
+        ensure!(
+            new_text.len() <= self.max_file_len,
+            "File is too large after the change ({} > {} bytes)",
+            new_text.len(),
+            self.max_file_len,
+        );
         // This is not quite efficient, but we already do many O(n) traversals.
         let (new_text, line_map) = LineMap::normalize(new_text);
         let new_text = <Arc<str>>::from(new_text);
@@ -109,6 +169,7 @@ impl Vfs {
         let file = self.file_for_uri(uri)?;
         self.local_file_set.remove_file(file);
         self.files.remove(file.0 as usize);
+        self.root_changed = true;
         // We cannot free a `FileId` from database. The best we can do is setting it to empty.
         self.change.change_file(file, "".into());
         Ok(())
@@ -132,15 +193,38 @@ impl Vfs {
     pub fn take_change(&mut self) -> Change {
         let mut change = mem::take(&mut self.change);
         if mem::take(&mut self.root_changed) {
-            change.set_roots(vec![SourceRoot::new_local(
-                self.local_file_set.clone(),
-                // TODO: Entry.
-                None,
-            )]);
+            let mut file_sets = vec![FileSet::default(); self.roots.len() + 1];
+            for (file, path) in self.local_file_set.iter() {
+                let idx = self.root_idx_for_path(path).unwrap_or(self.roots.len());
+                file_sets[idx].insert(file, path.clone());
+            }
+            change.set_roots(
+                file_sets
+                    .into_iter()
+                    // TODO: Entry.
+                    .map(|file_set| SourceRoot::new_local(file_set, None))
+                    .collect(),
+            );
         }
         change
     }
 
+    /// Find the innermost workspace root containing `path`.
+    /// Whether the path is under any workspace root.
+    pub fn is_in_roots(&self, path: &VfsPath) -> bool {
+        self.root_idx_for_path(path).is_some()
+    }
+
+    fn root_idx_for_path(&self, path: &VfsPath) -> Option<usize> {
+        let path = path.as_path()?;
+        self.roots
+            .iter()
+            .enumerate()
+            .filter(|(_, root)| path.starts_with(root))
+            .max_by_key(|(_, root)| root.components().count())
+            .map(|(idx, _)| idx)
+    }
+
     pub fn content_for_file(&self, file: FileId) -> Arc<str> {
         self.files[file.0 as usize].0.clone()
     }
@@ -167,8 +251,13 @@ enum CodeUnitsDiff {
 }
 
 impl LineMap {
-    fn normalize(mut text: String) -> (String, Self) {
-        text.retain(|c| c != '\r');
+    /// Normalize line terminators to `\n` and build the map for the normalized text.
+    /// LSP treats `\r\n`, `\n` and a lone `\r` all as line terminators, so lines and columns
+    /// are kept the same as the client's.
+    pub(crate) fn normalize(mut text: String) -> (String, Self) {
+        if text.contains('\r') {
+            text = text.replace("\r\n", "\n").replace('\r', "\n");
+        }
 
         // Must be valid for `TextSize`.
         let text_len = u32::try_from(text.len()).expect("Text too long");
@@ -219,8 +308,18 @@ impl LineMap {
         self.line_starts.len() as u32 - 1
     }
 
-    pub fn pos_for_line_col(&self, line: u32, mut col: u32) -> TextSize {
-        let pos = self.line_starts.get(line as usize).copied().unwrap_or(0);
+    /// The position of the end of file.
+    pub fn end_pos(&self) -> TextSize {
+        self.len.into()
+    }
+
+    /// Convert a line and a UTF-16 column to a position. Columns after the end of the line are
+    /// clamped to the end of the line, and lines after the last one to the end of file.
+    pub fn pos_for_line_col(&self, line: u32, col: u32) -> TextSize {
+        let Some(&pos) = self.line_starts.get(line as usize) else {
+            return self.end_pos();
+        };
+        let mut col = col.min(self.end_col_for_line(line));
         if let Some(diffs) = self.char_diffs.get(&line) {
             for &(char_pos, diff) in diffs {
                 if char_pos < col {
@@ -231,8 +330,10 @@ impl LineMap {
         (pos + col).into()
     }
 
+    /// Convert a position to a line and a UTF-16 column.
+    /// Positions after the end of file are clamped to the end of file.
     pub fn line_col_for_pos(&self, pos: TextSize) -> (u32, u32) {
-        let pos = u32::from(pos);
+        let pos = u32::from(pos).min(self.len);
         let line = self
             .line_starts
             .partition_point(|&i| i <= pos)
@@ -265,8 +366,54 @@ impl LineMap {
 
 #[cfg(test)]
 mod tests {
-    use super::{CodeUnitsDiff, LineMap};
+    use super::{CodeUnitsDiff, LineMap, Vfs};
+    use ide::VfsPath;
     use std::collections::HashMap;
+    use text_size::{TextRange, TextSize};
+
+    #[test]
+    fn multi_roots() {
+        let mut vfs = Vfs::new();
+        vfs.set_roots(vec![
+            "/flake".into(),
+            "/nixpkgs".into(),
+            "/flake/sub".into(),
+        ]);
+        let paths = [
+            "/flake/flake.nix",
+            "/flake/sub/default.nix",
+            "/nixpkgs/default.nix",
+            "/flakey.nix",
+            "/single.nix",
+        ];
+        let files = paths.map(|path| {
+            vfs.set_path_content(VfsPath::new(path), String::new())
+                .unwrap()
+        });
+
+        let roots = vfs.take_change().roots.unwrap();
+        let got = roots
+            .iter()
+            .map(|root| {
+                let mut files = root
+                    .files()
+                    .map(|(file, _)| files.iter().position(|&f| f == file).unwrap())
+                    .collect::<Vec<_>>();
+                files.sort();
+                files
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(got, [vec![0], vec![2], vec![1], vec![3, 4]]);
+
+        // Adding files and changing roots both refresh roots.
+        vfs.set_roots(vec!["/flake".into()]);
+        let roots = vfs.take_change().roots.unwrap();
+        assert_eq!(
+            roots.iter().map(|r| r.files().len()).collect::<Vec<_>>(),
+            [2, 3]
+        );
+        assert!(vfs.take_change().roots.is_none());
+    }
 
     #[test]
     fn line_map_ascii() {
@@ -352,6 +499,37 @@ mod tests {
         assert_eq!(map.end_col_for_line(3), 3);
     }
 
+    #[test]
+    fn end_of_file() {
+        // See comments in `line_map_unicode`.
+        for (text, (line, col)) in [
+            ("", (0, 0)),
+            ("foo", (0, 3)),
+            ("foo\n", (1, 0)),
+            ("foo\nbar", (1, 3)),
+            ("foo\nbar\n\n", (3, 0)),
+            ("foo\nbß", (1, 2)),
+            ("foo\nb💣", (1, 3)),
+            ("foo\nb💣\n", (2, 0)),
+        ] {
+            let (_, map) = LineMap::normalize(text.into());
+            let eof = map.end_pos();
+            assert_eq!(u32::from(eof) as usize, text.len());
+            assert_eq!(map.line_col_for_pos(eof), (line, col), "{text:?}");
+            assert_eq!(map.pos_for_line_col(line, col), eof, "{text:?}");
+            assert_eq!(map.end_col_for_line(map.last_line()), col, "{text:?}");
+
+            // Out of range positions are clamped to the end of file.
+            assert_eq!(map.pos_for_line_col(line, col + 1), eof, "{text:?}");
+            assert_eq!(map.pos_for_line_col(line + 1, 0), eof, "{text:?}");
+            assert_eq!(map.line_col_for_pos(eof + TextSize::from(1)), (line, col));
+        }
+
+        // Columns after the end of a non-last line are clamped before the newline.
+        let (_, map) = LineMap::normalize("aß\nb".into());
+        assert_eq!(map.pos_for_line_col(0, 5), 3.into());
+    }
+
     #[test]
     fn cr_lf() {
         let (_, map) = LineMap::normalize("hello\r\nworld!".into());
@@ -361,5 +539,92 @@ mod tests {
 
         // `\r` should be stripped. Thus only 5 chars + 1 newline for the first line.
         assert_eq!(map.line_col_for_pos(6.into()), (1, 0));
+
+        let (norm, map) = LineMap::normalize("a\r\nb\r\nc".into());
+        assert_eq!(norm, "a\nb\nc");
+        assert_eq!(map.last_line(), 2);
+        let pos = map.pos_for_line_col(2, 0);
+        assert_eq!(&norm[usize::from(pos)..], "c");
+        assert_eq!(map.line_col_for_pos(pos), (2, 0));
+        assert_eq!(map.line_col_for_pos(map.end_pos()), (2, 1));
+        assert_eq!(map.pos_for_line_col(2, 1), map.end_pos());
+    }
+
+    #[test]
+    fn lone_cr() {
+        // A lone `\r` is a line terminator in LSP.
+        let (norm, map) = LineMap::normalize("a\rbc\r\n\rd".into());
+        assert_eq!(norm, "a\nbc\n\nd");
+        assert_eq!(map.last_line(), 3);
+        assert_eq!(map.end_col_for_line(1), 2);
+        assert_eq!(map.pos_for_line_col(3, 0), 6.into());
+        assert_eq!(map.line_col_for_pos(6.into()), (3, 0));
+    }
+
+    #[test]
+    fn cr_lf_change() {
+        let mut vfs = Vfs::new();
+        let file = vfs
+            .set_path_content(VfsPath::new("/a.nix"), "a\r\nb\r\nc".into())
+            .unwrap();
+        let map = vfs.line_map_for_file(file);
+        let pos = map.pos_for_line_col(2, 0);
+        vfs.change_file_content(file, Some(TextRange::empty(pos)), "x\r\n")
+            .unwrap();
+        assert_eq!(&*vfs.content_for_file(file), "a\nb\nx\nc");
+    }
+
+    #[test]
+    fn reuse_removed_file_id() {
+        let mut vfs = Vfs::new();
+        let a = vfs
+            .set_path_content(VfsPath::new("/a.nix"), "a".into())
+            .unwrap();
+        let b = vfs
+            .set_path_content(VfsPath::new("/b.nix"), "b".into())
+            .unwrap();
+        vfs.remove_uri(&"file:///a.nix".parse().unwrap()).unwrap();
+        let c = vfs
+            .set_path_content(VfsPath::new("/c.nix"), "c".into())
+            .unwrap();
+        assert_eq!(c, a);
+        assert_ne!(c, b);
+
+        // The reused id only refers to the new path and content.
+        assert!(vfs.file_for_path(&VfsPath::new("/a.nix")).is_err());
+        assert_eq!(vfs.uri_for_file(c).as_str(), "file:///c.nix");
+        let change = vfs.take_change();
+        assert_eq!(change.file_changes.last(), Some(&(c, "c".into())));
+        let roots = change.roots.unwrap();
+        let mut files = roots
+            .iter()
+            .flat_map(|root| root.files())
+            .map(|(file, path)| (file, path.clone()))
+            .collect::<Vec<_>>();
+        files.sort_by_key(|(file, _)| *file);
+        let mut expect = vec![(b, VfsPath::new("/b.nix")), (c, VfsPath::new("/c.nix"))];
+        expect.sort_by_key(|(file, _)| *file);
+        assert_eq!(files, expect);
+    }
+
+    #[test]
+    fn max_file_len() {
+        let mut vfs = Vfs::new();
+        vfs.set_max_file_len(4);
+        assert!(vfs
+            .set_path_content(VfsPath::new("/a.nix"), "12345".into())
+            .is_err());
+        assert!(vfs.file_for_path(&VfsPath::new("/a.nix")).is_err());
+
+        let file = vfs
+            .set_path_content(VfsPath::new("/a.nix"), "1234".into())
+            .unwrap();
+        assert!(vfs.change_file_content(file, None, "12345").is_err());
+        vfs.change_file_content(file, Some(TextRange::empty(0.into())), "")
+            .unwrap();
+        assert_eq!(&*vfs.content_for_file(file), "1234");
+
+        vfs.set_max_file_len(usize::MAX);
+        assert_eq!(vfs.max_file_len(), u32::MAX as usize);
     }
 }
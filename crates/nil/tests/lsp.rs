@@ -0,0 +1,1187 @@
+//! End-to-end tests talking to the real server over LSP.
+mod support;
+
+use serde_json::{json, Value};
+use support::{caps, TestClient};
+
+fn codes(diags: &[Value]) -> Vec<&str> {
+    diags
+        .iter()
+        .map(|diag| diag["code"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn document_lifecycle() {
+    TestClient::run("#- /default.nix\nlet a = 1; in a\n", |client| async move {
+        let init = client.initialize(caps::minimal()).await;
+        let sync = &init["capabilities"]["textDocumentSync"];
+        assert_eq!(sync["openClose"], true, "{init}");
+
+        client.did_open_on_disk("/default.nix");
+        let hover = client
+            .request("textDocument/hover", client.position("/default.nix", 0, 14))
+            .await;
+        assert!(
+            hover["result"]["contents"]["value"]
+                .as_str()
+                .is_some_and(|s| s.contains("int")),
+            "{hover}",
+        );
+
+        // Edits are applied to the opened content, not the one on disk.
+        client.did_change("/default.nix", 1, "let a = \"s\"; in a");
+        let hover = client
+            .request("textDocument/hover", client.position("/default.nix", 0, 16))
+            .await;
+        assert!(
+            hover["result"]["contents"]["value"]
+                .as_str()
+                .unwrap()
+                .contains("string"),
+            "{hover}",
+        );
+
+        // Diagnostics are cleared on close.
+        client.did_change("/default.nix", 2, "let a = 1; in b");
+        let diags = client.wait_for_diagnostics(1, "/default.nix").await;
+        assert!(!diags.is_empty());
+        client.did_close("/default.nix");
+        let diags = client.wait_for_diagnostics(2, "/default.nix").await;
+        assert_eq!(diags, Vec::<Value>::new());
+
+        client.shutdown().await;
+    })
+    .await;
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn publish_diagnostics() {
+    TestClient::run("#- /default.nix\n", |client| async move {
+        client.initialize(caps::minimal()).await;
+        client.did_open("/default.nix", "let a = 1; in b");
+        let diags = client.wait_for_diagnostics(1, "/default.nix").await;
+        assert_eq!(codes(&diags), ["undefined_name", "unused_binding"]);
+        assert_eq!(
+            diags[0]["range"],
+            json!({
+                "start": { "line": 0, "character": 14 },
+                "end": { "line": 0, "character": 15 },
+            }),
+        );
+
+        client.did_change("/default.nix", 1, "let a = 1; in a");
+        let diags = client.wait_for_diagnostics(2, "/default.nix").await;
+        assert_eq!(diags, Vec::<Value>::new());
+    })
+    .await;
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn incremental_sync() {
+    TestClient::run("#- /default.nix\n", |client| async move {
+        let init = client.initialize(caps::minimal()).await;
+        assert_eq!(
+            init["capabilities"]["textDocumentSync"]["change"], 2,
+            "{init}"
+        );
+
+        // Columns are in UTF-16 code units, where `💣` takes 2 and `ß` takes 1.
+        client.did_open("/default.nix", "let a = \"💣\"; in a");
+        let range = |(l1, c1), (l2, c2)| {
+            json!({
+                "start": { "line": l1, "character": c1 },
+                "end": { "line": l2, "character": c2 },
+            })
+        };
+        // Each edit applies to the result of the previous ones.
+        client.notify(
+            "textDocument/didChange",
+            json!({
+                "textDocument": { "uri": client.workspace.uri("/default.nix"), "version": 1 },
+                "contentChanges": [
+                    { "range": range((0, 9), (0, 11)), "text": "ßx" },
+                    { "range": range((0, 17), (0, 18)), "text": "b" },
+                    { "range": range((0, 0), (0, 0)), "text": "# 💣\r\n" },
+                ],
+            }),
+        );
+        let diags = client.wait_for_diagnostics(1, "/default.nix").await;
+        assert_eq!(codes(&diags), ["undefined_name", "unused_binding"]);
+        assert_eq!(diags[0]["range"], range((1, 17), (1, 18)));
+
+        client.shutdown().await;
+    })
+    .await;
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn pulled_configuration() {
+    TestClient::run("#- /default.nix\n", |client| async move {
+        // Settings are pulled by `workspace/configuration` on initialization.
+        client.set_settings(json!({
+            "nil": { "diagnostics": { "ignored": ["unused_binding"] } },
+        }));
+        client.initialize(caps::full()).await;
+        client
+            .wait_for(|msg| msg["method"] == "workspace/configuration")
+            .await;
+        client.did_open("/default.nix", "let a = 1; in b");
+        // Diagnostics may be published before settings are loaded.
+        let mut n = 1;
+        loop {
+            let diags = client.wait_for_diagnostics(n, "/default.nix").await;
+            if codes(&diags) == ["undefined_name"] {
+                break;
+            }
+            n += 1;
+        }
+    })
+    .await;
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn goto_definition() {
+    let fixture = "
+#- /default.nix
+let foo = import ./foo.nix; in foo
+#- /foo.nix
+42
+";
+    TestClient::run(fixture, |client| async move {
+        client.initialize(caps::full()).await;
+        // `foo.nix` is not opened, but loaded from the disk.
+        client.wait_for_scan().await;
+        client.did_open_on_disk("/default.nix");
+
+        // A local binding.
+        let resp = client
+            .request(
+                "textDocument/definition",
+                client.position("/default.nix", 0, 32),
+            )
+            .await;
+        let uri = client.workspace.uri("/default.nix");
+        assert_eq!(
+            resp["result"],
+            json!([{
+                "uri": uri,
+                "range": {
+                    "start": { "line": 0, "character": 4 },
+                    "end": { "line": 0, "character": 7 },
+                },
+            }]),
+        );
+
+        // A path to another file.
+        let resp = client
+            .request(
+                "textDocument/definition",
+                client.position("/default.nix", 0, 20),
+            )
+            .await;
+        let uri = client.workspace.uri("/foo.nix");
+        assert_eq!(resp["result"][0]["uri"], json!(uri), "{resp}");
+    })
+    .await;
+}
+
+/// Request full semantic tokens of `path`, decoded into absolute positions with names of types
+/// and modifiers, like `1:3+3 string escape`.
+async fn semantic_tokens(client: &TestClient, legend: &Value, path: &str) -> Vec<String> {
+    let resp = client
+        .request(
+            "textDocument/semanticTokens/full",
+            json!({ "textDocument": { "uri": client.workspace.uri(path) } }),
+        )
+        .await;
+    let data = resp["result"]["data"].as_array().expect("tokens");
+    let (mut line, mut col) = (0, 0);
+    data.chunks(5)
+        .enumerate()
+        .map(|(i, tok)| {
+            let [delta_line, delta_col, len, ty, mods] =
+                [0, 1, 2, 3, 4].map(|i| tok[i].as_u64().unwrap());
+            // Tokens must be strictly ordered and must not overlap.
+            assert!(i == 0 || delta_line != 0 || delta_col != 0, "{resp}");
+            if delta_line != 0 {
+                col = 0;
+            }
+            line += delta_line;
+            col += delta_col;
+            let mods = (0..64)
+                .filter(|i| mods & (1 << i) != 0)
+                .map(|i| legend["tokenModifiers"][i].as_str().unwrap())
+                .collect::<Vec<_>>()
+                .join(",");
+            let ty = legend["tokenTypes"][ty as usize].as_str().unwrap();
+            format!("{line}:{col}+{len} {ty} {mods}")
+        })
+        .collect()
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn semantic_tokens_interpolation() {
+    let src = "''\n  a''$b ${\n    c\n  }\n''\n";
+    TestClient::run("#- /default.nix\n", |client| async move {
+        let init = client.initialize(caps::minimal()).await;
+        let legend = &init["capabilities"]["semanticTokensProvider"]["legend"];
+        client.did_open("/default.nix", src);
+        assert_eq!(
+            semantic_tokens(&client, legend, "/default.nix").await,
+            [
+                "0:0+2 string ",
+                "1:3+3 string escape",
+                "1:8+2 punctuation interpolation",
+                "2:4+1 variable unresolved",
+                "3:2+1 punctuation interpolation",
+                "4:0+2 string ",
+            ],
+        );
+    })
+    .await;
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn semantic_tokens_multiline_escapes() {
+    let src = "[\n  \"a\\n\n  b\\t${c}\\\\\"\n  ''\n    ''\\n''${d}\n  ''\n]\n";
+    TestClient::run("#- /default.nix\n", |client| async move {
+        let init = client.initialize(caps::minimal()).await;
+        let legend = &init["capabilities"]["semanticTokensProvider"]["legend"];
+        client.did_open("/default.nix", src);
+        assert_eq!(
+            semantic_tokens(&client, legend, "/default.nix").await,
+            [
+                "0:0+1 punctuation parenthesis",
+                "1:2+1 string ",
+                "1:4+2 string escape",
+                "2:3+2 string escape",
+                "2:5+2 punctuation interpolation",
+                "2:7+1 variable unresolved",
+                "2:8+1 punctuation interpolation",
+                "2:9+2 string escape",
+                "2:11+1 string ",
+                "3:2+2 string ",
+                "4:4+4 string escape",
+                "4:8+3 string escape",
+                "5:2+2 string ",
+                "6:0+1 punctuation parenthesis",
+            ],
+        );
+    })
+    .await;
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn semantic_tokens_dynamic_attr() {
+    let src = "a: { ${a} = 1; }";
+    TestClient::run("#- /default.nix\n", |client| async move {
+        let init = client.initialize(caps::minimal()).await;
+        let legend = &init["capabilities"]["semanticTokensProvider"]["legend"];
+        client.did_open("/default.nix", src);
+        assert_eq!(
+            semantic_tokens(&client, legend, "/default.nix").await,
+            [
+                "0:0+1 parameter definition",
+                "0:1+1 punctuation delimiter",
+                "0:3+1 punctuation parenthesis",
+                "0:5+2 punctuation interpolation",
+                "0:7+1 parameter ",
+                "0:8+1 punctuation interpolation",
+                "0:10+1 punctuation delimiter",
+                "0:12+1 number ",
+                "0:13+1 punctuation delimiter",
+                "0:15+1 punctuation parenthesis",
+            ],
+        );
+    })
+    .await;
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn code_action_fixes_diagnostic() {
+    TestClient::run("#- /default.nix\n", |client| async move {
+        client.initialize(caps::minimal()).await;
+        client.did_open("/default.nix", "{\n  a = 1;\n  inherit;\n}\n");
+        let diags = client.wait_for_diagnostics(1, "/default.nix").await;
+        assert_eq!(codes(&diags), ["empty_inherit"]);
+
+        let uri = client.workspace.uri("/default.nix");
+        let resp = client
+            .request(
+                "textDocument/codeAction",
+                json!({
+                    "textDocument": { "uri": uri },
+                    "range": diags[0]["range"],
+                    "context": { "diagnostics": diags },
+                }),
+            )
+            .await;
+        let actions = resp["result"].as_array().expect("actions");
+        let fix = actions
+            .iter()
+            .find(|action| action["title"] == "Remove the empty `inherit`")
+            .unwrap_or_else(|| panic!("{resp}"));
+        assert_eq!(fix["kind"], "quickfix");
+        assert_eq!(fix["isPreferred"], true);
+        assert_eq!(fix["diagnostics"], json!(diags));
+        assert_eq!(
+            fix["edit"]["changes"][uri.as_str()],
+            json!([{
+                "range": {
+                    "start": { "line": 1, "character": 8 },
+                    "end": { "line": 2, "character": 10 },
+                },
+                "newText": "",
+            }]),
+        );
+    })
+    .await;
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn inlay_hint_resolve() {
+    TestClient::run("#- /default.nix\n", |client| async move {
+        let init = client.initialize(caps::minimal()).await;
+        let provider = &init["capabilities"]["inlayHintProvider"];
+        assert_eq!(provider["resolveProvider"], true, "{init}");
+        client.did_open("/default.nix", "let a = b; b = 42; in { x ? a }: x");
+
+        let uri = client.workspace.uri("/default.nix");
+        let resp = client
+            .request(
+                "textDocument/inlayHint",
+                json!({
+                    "textDocument": { "uri": uri },
+                    "range": {
+                        "start": { "line": 0, "character": 0 },
+                        "end": { "line": 0, "character": 35 },
+                    },
+                }),
+            )
+            .await;
+        let hints = resp["result"].as_array().expect("hints");
+        assert_eq!(hints.len(), 1, "{resp}");
+        assert_eq!(hints[0]["label"], "= 42");
+        assert_eq!(hints[0]["tooltip"], Value::Null);
+
+        let resp = client.request("inlayHint/resolve", hints[0].clone()).await;
+        assert_eq!(resp["result"]["label"], "= 42", "{resp}");
+        assert_eq!(resp["result"]["tooltip"], "Resolved through `a` → `b`");
+
+        // A stale hint is returned unchanged.
+        client.did_change("/default.nix", 1, "{ x }: x");
+        let resp = client.request("inlayHint/resolve", hints[0].clone()).await;
+        assert_eq!(resp["result"], hints[0], "{resp}");
+    })
+    .await;
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn tcp_listen() {
+    use futures::{AsyncReadExt as _, AsyncWriteExt as _};
+    use nil::TcpServer;
+    use std::net::SocketAddr;
+    use support::{PipeReader, PipeWriter};
+    use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+    use tokio::net::TcpStream;
+
+    // Forward the test client over a TCP connection, until the server closes it.
+    async fn connect(
+        addr: SocketAddr,
+        mut input: PipeReader,
+        mut output: PipeWriter,
+    ) -> anyhow::Result<()> {
+        let (mut tcp_input, mut tcp_output) = TcpStream::connect(addr).await?.into_split();
+        let mut buf = [0u8; 4096];
+        let mut tcp_buf = [0u8; 4096];
+        let to_server = async {
+            loop {
+                let n = input.read(&mut buf).await?;
+                if n == 0 {
+                    return anyhow::Ok(());
+                }
+                tcp_output.write_all(&buf[..n]).await?;
+            }
+        };
+        let from_server = async {
+            loop {
+                let n = tcp_input.read(&mut tcp_buf).await?;
+                if n == 0 {
+                    return anyhow::Ok(());
+                }
+                output.write_all(&tcp_buf[..n]).await?;
+            }
+        };
+        tokio::select! {
+            ret = from_server => ret,
+            Err(err) = to_server => Err(err),
+        }
+    }
+
+    let any_port = |ip: &str| SocketAddr::new(ip.parse().unwrap(), 0);
+    assert!(TcpServer::bind(any_port("0.0.0.0"), false).await.is_err());
+    TcpServer::bind(any_port("0.0.0.0"), true).await.unwrap();
+
+    let server = TcpServer::bind(any_port("127.0.0.1"), false).await.unwrap();
+    let addr = server.local_addr().unwrap();
+    let clients = async {
+        // Closing a connection doesn't stop the server from accepting the next one.
+        for _ in 0..2 {
+            let serve = |input, output| connect(addr, input, output);
+            TestClient::run_with("#- /default.nix\n", serve, |client| async move {
+                let init = client.initialize(caps::minimal()).await;
+                assert!(init["capabilities"].is_object(), "{init}");
+                client.shutdown().await;
+            })
+            .await;
+        }
+    };
+    tokio::select! {
+        ret = server.run() => panic!("Server exited: {ret:?}"),
+        () = clients => {}
+    }
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn embedded_snippet() {
+    TestClient::run("#- /README.md\n", |client| async move {
+        client.initialize(caps::minimal()).await;
+
+        // A snippet of the code fence in `README.md`, with content supplied by the client.
+        let uri = "nix-embedded:/README.md%230";
+        client.notify(
+            "textDocument/didOpen",
+            json!({
+                "textDocument": {
+                    "uri": uri,
+                    "languageId": "nix",
+                    "version": 0,
+                    "text": "let abc = 1; in a",
+                },
+            }),
+        );
+        let is_diags = |msg: &Value| {
+            msg["method"] == "textDocument/publishDiagnostics" && msg["params"]["uri"] == uri
+        };
+        let msg = client.wait_for(is_diags).await;
+        let diags = msg["params"]["diagnostics"].as_array().unwrap();
+        assert_eq!(codes(diags), ["undefined_name", "unused_binding"]);
+
+        let position = json!({
+            "textDocument": { "uri": uri },
+            "position": { "line": 0, "character": 17 },
+        });
+        let resp = client
+            .request("textDocument/completion", position.clone())
+            .await;
+        let items = resp["result"].as_array().expect("completions");
+        assert!(items.iter().any(|item| item["label"] == "abc"), "{resp}");
+
+        // Closed snippets are dropped, since they cannot be reloaded from disk.
+        client.notify(
+            "textDocument/didClose",
+            json!({ "textDocument": { "uri": uri } }),
+        );
+        client.wait_for_nth(2, is_diags).await;
+        let resp = client.request("textDocument/completion", position).await;
+        assert!(resp["error"].is_object(), "{resp}");
+    })
+    .await;
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn range_formatting() {
+    let src = "{\n  a   = [ 1    2 ];\n  b = {\n    c = [\n  1\n    2   ];\n  };\n}\n";
+    TestClient::run("#- /default.nix\n", |client| async move {
+        client.initialize(caps::minimal()).await;
+        client.did_open("/default.nix", src);
+        let uri = client.workspace.uri("/default.nix");
+        let format = |command: Value, (l1, c1), (l2, c2)| {
+            client.notify(
+                "workspace/didChangeConfiguration",
+                json!({ "settings": { "nil": { "formatting": { "command": command } } } }),
+            );
+            client.request(
+                "textDocument/rangeFormatting",
+                json!({
+                    "textDocument": { "uri": uri },
+                    "range": {
+                        "start": { "line": l1, "character": c1 },
+                        "end": { "line": l2, "character": c2 },
+                    },
+                    "options": { "tabSize": 2, "insertSpaces": true },
+                }),
+            )
+        };
+
+        // The binding is formatted with the whole file, but changes elsewhere are dropped.
+        let collapse_spaces = json!(["sed", r"s/\([^ ]\)  */\1 /g"]);
+        let resp = format(collapse_spaces, (1, 2), (1, 3)).await;
+        let mut text = src.to_owned();
+        apply_edits(&mut text, &resp["result"]);
+        assert_eq!(
+            text,
+            "{\n  a = [ 1 2 ];\n  b = {\n    c = [\n  1\n    2   ];\n  };\n}\n",
+        );
+
+        // Changes only touching the binding, like its own indentation, are dropped too.
+        let dedent = json!(["sed", "s/^ *//"]);
+        let resp = format(dedent, (3, 4), (3, 5)).await;
+        let mut text = src.to_owned();
+        apply_edits(&mut text, &resp["result"]);
+        assert_eq!(
+            text,
+            "{\n  a   = [ 1    2 ];\n  b = {\n    c = [\n1\n2   ];\n  };\n}\n",
+        );
+
+        // The list is formatted alone, and re-indented to where it is.
+        let flatten = json!(["sed", r"s/^ *//; s/\([^ ]\)  */\1 /g"]);
+        let resp = format(flatten, (4, 2), (4, 3)).await;
+        let mut text = src.to_owned();
+        apply_edits(&mut text, &resp["result"]);
+        assert_eq!(
+            text,
+            "{\n  a   = [ 1    2 ];\n  b = {\n    c = [\n    1\n    2 ];\n  };\n}\n",
+        );
+    })
+    .await;
+}
+
+/// Apply LSP `TextEdit`s to a text of only ASCII characters.
+fn apply_edits(text: &mut String, edits: &Value) {
+    let offset = |text: &str, pos: &Value| {
+        let line = pos["line"].as_u64().unwrap() as usize;
+        let col = pos["character"].as_u64().unwrap() as usize;
+        text.split_inclusive('\n')
+            .take(line)
+            .map(str::len)
+            .sum::<usize>()
+            + col
+    };
+    let mut edits = edits.as_array().expect("edits").clone();
+    edits.sort_by_key(|edit| offset(text, &edit["range"]["start"]));
+    for edit in edits.iter().rev() {
+        let start = offset(text, &edit["range"]["start"]);
+        let end = offset(text, &edit["range"]["end"]);
+        text.replace_range(start..end, edit["newText"].as_str().unwrap());
+    }
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn goto_search_path() {
+    TestClient::run(
+        "#- /default.nix\n#- /pkgs/default.nix\n{ }: { }\n",
+        |client| async move {
+            client.initialize(caps::minimal()).await;
+            client.notify(
+                "workspace/didChangeConfiguration",
+                json!({ "settings": { "nil": { "nix": { "searchPath": ["nixpkgs=./pkgs"] } } } }),
+            );
+            client.did_open("/default.nix", "[ (import <nixpkgs> { }) <missing> ]");
+            let goto = |character| {
+                client.request(
+                    "textDocument/definition",
+                    client.position("/default.nix", 0, character),
+                )
+            };
+
+            let resp = goto(14).await;
+            assert_eq!(
+                resp["result"],
+                json!([{
+                    "uri": client.workspace.uri("/pkgs/default.nix"),
+                    "range": {
+                        "start": { "line": 0, "character": 0 },
+                        "end": { "line": 0, "character": 0 },
+                    },
+                }]),
+            );
+
+            // Entries not found are no navigation target.
+            let resp = goto(30).await;
+            assert_eq!(resp["result"], Value::Null, "{resp}");
+        },
+    )
+    .await;
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn goto_import() {
+    // Files without the `.nix` extension are not loaded by the workspace scan.
+    TestClient::run(
+        "#- /default.nix\n#- /loaded.nix\n{ foo = 1; }\n#- /not-loaded\n{\n  bar.baz = 2;\n}\n",
+        |client| async move {
+            client.initialize(caps::full()).await;
+            client.wait_for_scan().await;
+            client.did_open(
+                "/default.nix",
+                "[ (import ./loaded.nix).foo (import ./not-loaded) (import ./not-loaded).bar.baz ]",
+            );
+            let goto = |character| {
+                client.request(
+                    "textDocument/definition",
+                    client.position("/default.nix", 0, character),
+                )
+            };
+            let location = |path: &str, (l1, c1), (l2, c2)| {
+                json!([{
+                    "uri": client.workspace.uri(path),
+                    "range": {
+                        "start": { "line": l1, "character": c1 },
+                        "end": { "line": l2, "character": c2 },
+                    },
+                }])
+            };
+
+            let resp = goto(13).await;
+            assert_eq!(resp["result"], location("/loaded.nix", (0, 0), (0, 0)));
+            let resp = goto(25).await;
+            assert_eq!(resp["result"], location("/loaded.nix", (0, 2), (0, 5)));
+            let resp = goto(38).await;
+            assert_eq!(resp["result"], location("/not-loaded", (0, 0), (0, 0)));
+
+            // Attributes of files not loaded are looked up on disk.
+            let resp = goto(77).await;
+            assert_eq!(resp["result"], location("/not-loaded", (1, 6), (1, 9)));
+        },
+    )
+    .await;
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn goto_builtin() {
+    let goto_map = |caps: Value| async move {
+        let mut ret = Value::Null;
+        let ret_mut = &mut ret;
+        TestClient::run("#- /default.nix\n", |client| async move {
+            client.initialize(caps).await;
+            client.did_open("/default.nix", "builtins.map");
+            let resp = client
+                .request(
+                    "textDocument/definition",
+                    client.position("/default.nix", 0, 10),
+                )
+                .await;
+            *ret_mut = resp["result"].clone();
+            if let Some(uri) = resp["result"][0]["uri"].as_str() {
+                let resp = client
+                    .request("workspace/textDocumentContent", json!({ "uri": uri }))
+                    .await;
+                assert!(
+                    resp["result"]["text"].as_str().unwrap().contains("map"),
+                    "{resp}"
+                );
+            }
+        })
+        .await;
+        ret
+    };
+
+    // Clients unaware of server-generated documents could not open it.
+    assert_eq!(goto_map(caps::full()).await, Value::Null);
+
+    let mut caps = caps::full();
+    caps["experimental"] = json!({ "textDocumentContent": {} });
+    assert_eq!(
+        goto_map(caps).await[0]["uri"],
+        json!("nil:///builtins/map.md"),
+    );
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn selection_range() {
+    TestClient::run("#- /default.nix\n", |client| async move {
+        client.initialize(caps::minimal()).await;
+        client.did_open("/default.nix", "{ a = \"foo\"; b = [ 1 ]; }");
+        let resp = client
+            .request(
+                "textDocument/selectionRange",
+                json!({
+                    "textDocument": { "uri": client.workspace.uri("/default.nix") },
+                    "positions": [
+                        { "line": 0, "character": 8 },
+                        { "line": 0, "character": 19 },
+                    ],
+                }),
+            )
+            .await;
+
+        // One chain for each position, from the innermost.
+        let chains = resp["result"]
+            .as_array()
+            .expect("result")
+            .iter()
+            .map(|mut sel| {
+                let mut cols = Vec::new();
+                loop {
+                    let range = &sel["range"];
+                    cols.push((
+                        range["start"]["character"].as_u64().unwrap(),
+                        range["end"]["character"].as_u64().unwrap(),
+                    ));
+                    match sel.get("parent") {
+                        Some(parent) => sel = parent,
+                        None => break,
+                    }
+                }
+                cols
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            chains,
+            [
+                vec![(7, 10), (6, 11), (2, 12), (0, 25)],
+                vec![(19, 20), (17, 22), (13, 23), (0, 25)],
+            ],
+        );
+    })
+    .await;
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn document_highlight() {
+    TestClient::run("#- /default.nix\n", |client| async move {
+        client.initialize(caps::minimal()).await;
+        client.did_open("/default.nix", "let x = 1; in { inherit x; y = x; }");
+        let resp = client
+            .request(
+                "textDocument/documentHighlight",
+                json!({
+                    "textDocument": { "uri": client.workspace.uri("/default.nix") },
+                    "position": { "line": 0, "character": 24 },
+                }),
+            )
+            .await;
+
+        // Write for the definition, Read for references.
+        let mut hls = resp["result"]
+            .as_array()
+            .expect("result")
+            .iter()
+            .map(|hl| {
+                (
+                    hl["range"]["start"]["character"].as_u64().unwrap(),
+                    hl["kind"].as_u64().unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        hls.sort();
+        assert_eq!(hls, [(4, 3), (24, 2), (31, 2)]);
+    })
+    .await;
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn folder_completion_command() {
+    let fixture = "#- /default.nix\n./\n#- /sub/a.nix\n1\n";
+    for (client_name, folder_command) in [
+        ("Visual Studio Code", "editor.action.triggerSuggest"),
+        ("Neovim", "nil.recordCompletion"),
+    ] {
+        TestClient::run(fixture, |client| async move {
+            client
+                .initialize_with(json!({
+                    "processId": null,
+                    "rootUri": client.workspace.root_uri(),
+                    "capabilities": {},
+                    "clientInfo": { "name": client_name },
+                }))
+                .await;
+            client.did_open_on_disk("/default.nix");
+
+            let resp = client
+                .request(
+                    "textDocument/completion",
+                    client.position("/default.nix", 0, 2),
+                )
+                .await;
+            let items = resp["result"].as_array().expect("completions");
+            let command = |label: &str| {
+                items
+                    .iter()
+                    .find(|item| item["label"] == label)
+                    .unwrap_or_else(|| panic!("missing {label}: {resp}"))["command"]["command"]
+                    .clone()
+            };
+            assert_eq!(command("sub/"), folder_command, "{client_name}");
+            // Other items are always recorded.
+            assert_eq!(
+                command("default.nix"),
+                "nil.recordCompletion",
+                "{client_name}"
+            );
+        })
+        .await;
+    }
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn extract_rename_command() {
+    let fixture = "#- /default.nix\n{ pkgs }: pkgs.hello\n";
+    for (client_name, command) in [
+        ("Visual Studio Code", json!("editor.action.rename")),
+        ("Neovim", Value::Null),
+    ] {
+        TestClient::run(fixture, |client| async move {
+            client
+                .initialize_with(json!({
+                    "processId": null,
+                    "rootUri": client.workspace.root_uri(),
+                    "capabilities": {},
+                    "clientInfo": { "name": client_name },
+                }))
+                .await;
+            client.did_open_on_disk("/default.nix");
+
+            let uri = client.workspace.uri("/default.nix");
+            let resp = client
+                .request(
+                    "textDocument/codeAction",
+                    json!({
+                        "textDocument": { "uri": uri },
+                        "range": {
+                            "start": { "line": 0, "character": 10 },
+                            "end": { "line": 0, "character": 20 },
+                        },
+                        "context": { "diagnostics": [] },
+                    }),
+                )
+                .await;
+            let actions = resp["result"].as_array().expect("actions");
+            let extract = actions
+                .iter()
+                .find(|action| action["title"] == "Extract into let binding `hello`")
+                .unwrap_or_else(|| panic!("{resp}"));
+            assert_eq!(extract["command"]["command"], command, "{client_name}");
+        })
+        .await;
+    }
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn idle_shutdown() {
+    TestClient::run("", |client| async move {
+        // The client keeps silent after initialization without closing the pipe.
+        client
+            .initialize_with(json!({
+                "processId": null,
+                "rootUri": client.workspace.root_uri(),
+                "capabilities": {},
+                "initializationOptions": { "idleShutdownMs": 50 },
+            }))
+            .await;
+        client.wait_for_exit().await;
+    })
+    .await;
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn push_config_refreshes_diagnostics() {
+    TestClient::run("#- /default.nix\n", |client| async move {
+        client.initialize(caps::minimal()).await;
+        client.did_open("/default.nix", "let a = 1; in { b = 2; }");
+        let diags = client.wait_for_diagnostics(1, "/default.nix").await;
+        assert_eq!(codes(&diags), ["unused_binding"]);
+
+        // Without `workspace/configuration` support, settings are pushed.
+        client.notify(
+            "workspace/didChangeConfiguration",
+            json!({ "settings": { "nil": { "diagnostics": { "ignored": ["unused_binding"] } } } }),
+        );
+        let diags = client.wait_for_diagnostics(2, "/default.nix").await;
+        assert_eq!(diags, Vec::<Value>::new());
+    })
+    .await;
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn toggle_features() {
+    TestClient::run("#- /default.nix\n", |client| async move {
+        let client = &client;
+        let semantic_tokens_registrations = || {
+            client
+                .received()
+                .iter()
+                .filter_map(|msg| {
+                    let kind = match msg["method"].as_str()? {
+                        "client/registerCapability" => "register",
+                        "client/unregisterCapability" => "unregister",
+                        _ => return None,
+                    };
+                    msg["params"]
+                        .to_string()
+                        .contains(r#""method":"textDocument/semanticTokens""#)
+                        .then_some(kind)
+                })
+                .collect::<Vec<_>>()
+        };
+        let wait_for_registrations = |n: usize| async move {
+            client
+                .wait_for_nth(n, |msg| {
+                    msg["method"]
+                        .as_str()
+                        .is_some_and(|m| m.ends_with("registerCapability"))
+                        && msg["params"]
+                            .to_string()
+                            .contains(r#""method":"textDocument/semanticTokens""#)
+                })
+                .await
+        };
+        let set_features = |features: Value| {
+            client.notify(
+                "workspace/didChangeConfiguration",
+                json!({ "settings": { "nil": { "features": features } } }),
+            );
+        };
+        let uri = client.workspace.uri("/default.nix");
+        let range = json!({
+            "textDocument": { "uri": uri },
+            "range": { "start": { "line": 0, "character": 0 }, "end": { "line": 0, "character": 26 } },
+        });
+
+        let init = client
+            .initialize(json!({
+                "textDocument": {
+                    "semanticTokens": {
+                        "dynamicRegistration": true,
+                        "requests": {},
+                        "tokenTypes": [],
+                        "tokenModifiers": [],
+                        "formats": [],
+                    },
+                },
+            }))
+            .await;
+        let caps = &init["capabilities"];
+        assert_eq!(caps["semanticTokensProvider"], Value::Null, "{init}");
+        assert_ne!(caps["inlayHintProvider"], Value::Null, "{init}");
+
+        let msg = wait_for_registrations(1).await;
+        assert!(
+            msg["params"].to_string().contains("legend"),
+            "Missing options: {msg}"
+        );
+        client.did_open("/default.nix", "let b = 1; in { a ? b }: a");
+
+        // Dynamically unregistered.
+        set_features(json!({ "semanticTokens": false, "inlayHint": false }));
+        wait_for_registrations(2).await;
+        assert_eq!(semantic_tokens_registrations(), ["register", "unregister"]);
+        // Statically advertised, but returns nothing.
+        let resp = client
+            .request(
+                "textDocument/semanticTokens/full",
+                json!({ "textDocument": { "uri": uri } }),
+            )
+            .await;
+        assert_eq!(resp["result"], Value::Null, "{resp}");
+        let resp = client.request("textDocument/inlayHint", range.clone()).await;
+        assert_eq!(resp["result"], Value::Null, "{resp}");
+        let resp = client.request("nil/status", Value::Null).await;
+        let features = &resp["result"]["features"];
+        assert_eq!(features["semanticTokens"], false, "{resp}");
+        assert_eq!(features["inlayHint"], false, "{resp}");
+        assert_eq!(features["foldingRange"], true, "{resp}");
+
+        // Flip back.
+        set_features(json!({}));
+        wait_for_registrations(3).await;
+        let resp = client.request("textDocument/inlayHint", range).await;
+        assert_eq!(resp["result"].as_array().map(Vec::len), Some(1), "{resp}");
+
+        // Quick toggles are sent in order.
+        set_features(json!({ "semanticTokens": false }));
+        set_features(json!({ "semanticTokens": true }));
+        wait_for_registrations(5).await;
+        assert_eq!(
+            semantic_tokens_registrations(),
+            ["register", "unregister", "register", "unregister", "register"],
+        );
+    })
+    .await;
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn invalid_settings() {
+    TestClient::run("#- /default.nix\n42\n", |client| async move {
+        let registered = |msg: &Value, kind: &str| {
+            msg["method"] == format!("client/{kind}Capability")
+                && msg["params"]
+                    .to_string()
+                    .contains(r#""method":"textDocument/formatting""#)
+        };
+        let push_command = |command: Value| {
+            client.notify(
+                "workspace/didChangeConfiguration",
+                json!({ "settings": { "nil": { "formatting": { "command": command } } } }),
+            );
+        };
+
+        client
+            .initialize(json!({
+                "textDocument": { "formatting": { "dynamicRegistration": true } },
+            }))
+            .await;
+        client.did_open_on_disk("/default.nix");
+
+        push_command(json!(["cat"]));
+        client.wait_for(|msg| registered(msg, "register")).await;
+
+        // The invalid command is not kept, but reset to the default `null`.
+        push_command(json!([""]));
+        let msg = client
+            .wait_for(|msg| msg["method"] == "window/showMessage")
+            .await;
+        assert_eq!(msg["params"]["type"], 2, "{msg}");
+        let text = msg["params"]["message"].as_str().unwrap();
+        assert!(text.contains("`formatting.command`"), "{msg}");
+        client.wait_for(|msg| registered(msg, "unregister")).await;
+
+        let resp = client
+            .request(
+                "textDocument/formatting",
+                json!({
+                    "textDocument": { "uri": client.workspace.uri("/default.nix") },
+                    "options": { "tabSize": 2, "insertSpaces": true },
+                }),
+            )
+            .await;
+        assert_eq!(resp["result"], Value::Null, "{resp}");
+    })
+    .await;
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn recent_completions_first() {
+    TestClient::run("#- /default.nix\n", |client| async move {
+        let src = "let apple = 1; avocado = 2; in a";
+        // Labels sorted as the client would do.
+        let sorted_labels = |resp: &Value| {
+            let mut items = resp["result"]
+                .as_array()
+                .expect("completions")
+                .iter()
+                .map(|item| {
+                    (
+                        item["sortText"].as_str().unwrap().to_owned(),
+                        item["label"].as_str().unwrap().to_owned(),
+                    )
+                })
+                .filter(|(_, label)| label == "apple" || label == "avocado")
+                .collect::<Vec<_>>();
+            items.sort();
+            items
+                .into_iter()
+                .map(|(_, label)| label)
+                .collect::<Vec<_>>()
+        };
+        let position = client.position("/default.nix", 0, src.len() as u32);
+
+        client.initialize(caps::minimal()).await;
+        client.did_open("/default.nix", src);
+
+        let resp = client
+            .request("textDocument/completion", position.clone())
+            .await;
+        assert_eq!(sorted_labels(&resp), ["apple", "avocado"], "{resp}");
+        let command = resp["result"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|item| item["label"] == "avocado")
+            .unwrap()["command"]
+            .clone();
+        assert_eq!(command["command"], "nil.recordCompletion");
+
+        // The client executes the command after accepting the item.
+        let resp = client.request("workspace/executeCommand", command).await;
+        assert!(resp["error"].is_null(), "{resp}");
+
+        let resp = client.request("textDocument/completion", position).await;
+        assert_eq!(sorted_labels(&resp), ["avocado", "apple"], "{resp}");
+    })
+    .await;
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn semantic_tokens_range() {
+    TestClient::run("#- /default.nix\n", |client| async move {
+        let init = client.initialize(caps::minimal()).await;
+        let caps = &init["capabilities"]["semanticTokensProvider"];
+        assert_eq!(caps["range"], true, "{init}");
+        client.did_open("/default.nix", "[\n  # comment\n  42\n]");
+
+        // The comment straddles the start of the range.
+        let resp = client
+            .request(
+                "textDocument/semanticTokens/range",
+                json!({
+                    "textDocument": { "uri": client.workspace.uri("/default.nix") },
+                    "range": {
+                        "start": { "line": 1, "character": 5 },
+                        "end": { "line": 2, "character": 4 },
+                    },
+                }),
+            )
+            .await;
+        let data = resp["result"]["data"].as_array().expect("tokens");
+        let positions = data
+            .chunks(5)
+            .map(|tok| (tok[0].clone(), tok[1].clone(), tok[2].clone()))
+            .collect::<Vec<_>>();
+        // Line and column deltas are relative to the start of the file.
+        assert_eq!(
+            positions,
+            [(1, 5, 6), (1, 2, 2)].map(|(l, c, n)| (l.into(), c.into(), n.into())),
+            "{resp}",
+        );
+    })
+    .await;
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn semantic_tokens_delta() {
+    TestClient::run("#- /default.nix\n", |client| async move {
+        let doc = json!({ "uri": client.workspace.uri("/default.nix") });
+        let delta = |prev: &Value| {
+            client.request(
+                "textDocument/semanticTokens/full/delta",
+                json!({ "textDocument": doc, "previousResultId": prev }),
+            )
+        };
+
+        let init = client.initialize(caps::minimal()).await;
+        let caps = &init["capabilities"]["semanticTokensProvider"];
+        assert_eq!(caps["full"]["delta"], true, "{init}");
+        client.did_open("/default.nix", "let a = 1; in a");
+
+        let resp = client
+            .request(
+                "textDocument/semanticTokens/full",
+                json!({ "textDocument": doc }),
+            )
+            .await;
+        let full_id = resp["result"]["resultId"].clone();
+        assert!(full_id.is_string(), "{resp}");
+
+        client.did_change("/default.nix", 1, "let a = 1; in a + a");
+        let resp = delta(&full_id).await;
+        let edits = resp["result"]["edits"].as_array().expect("delta result");
+        assert_eq!(edits.len(), 1, "{resp}");
+        assert_ne!(resp["result"]["resultId"], full_id);
+        let delta_id = resp["result"]["resultId"].clone();
+
+        // Stale ids fall back to full results.
+        let resp = delta(&full_id).await;
+        assert!(resp["result"]["data"].is_array(), "{resp}");
+
+        // Ids are invalidated on close.
+        let last_id = resp["result"]["resultId"].clone();
+        assert_ne!(last_id, delta_id);
+        client.did_close("/default.nix");
+        let resp = delta(&last_id).await;
+        assert!(resp["result"]["data"].is_array(), "{resp}");
+    })
+    .await;
+}
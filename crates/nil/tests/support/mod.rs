@@ -0,0 +1,476 @@
+//! A fake LSP client driving the real server in-process, over in-memory pipes.
+//!
+//! Messages are raw JSON values, so tests assert on what is actually sent over the wire.
+#![allow(dead_code)]
+
+use futures::channel::mpsc;
+use futures::io::AsyncWrite;
+use futures::stream::IntoAsyncRead;
+use futures::{Future, StreamExt, TryStreamExt};
+use lsp_types::Url;
+use serde_json::{json, Value};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// The watchdog limit of a whole test, including the server shutdown.
+const TIMEOUT: Duration = Duration::from_secs(20);
+
+/// The error code of requests cancelled by changes, which should be retried.
+const CONTENT_MODIFIED: i32 = -32801;
+
+/// Client capabilities to initialize with.
+pub mod caps {
+    use serde_json::{json, Value};
+
+    /// No capabilities at all. The server should not send any request to the client.
+    pub fn minimal() -> Value {
+        json!({})
+    }
+
+    /// Common capabilities of full-featured editors, like VSCode.
+    pub fn full() -> Value {
+        json!({
+            "workspace": {
+                "configuration": true,
+                "didChangeConfiguration": { "dynamicRegistration": true },
+                "didChangeWatchedFiles": {
+                    "dynamicRegistration": true,
+                    "relativePatternSupport": true,
+                },
+                "workspaceFolders": true,
+            },
+            "textDocument": {
+                "synchronization": { "dynamicRegistration": true, "willSaveWaitUntil": true },
+                "publishDiagnostics": { "relatedInformation": true, "versionSupport": true },
+                "semanticTokens": {
+                    "dynamicRegistration": true,
+                    "requests": { "range": true, "full": { "delta": true } },
+                    "tokenTypes": [],
+                    "tokenModifiers": [],
+                    "formats": ["relative"],
+                },
+            },
+            "window": { "workDoneProgress": true },
+            "general": { "positionEncodings": ["utf-16"] },
+        })
+    }
+}
+
+/// A temporary workspace on disk, removed on drop.
+///
+/// It is created from a fixture of files each started with a `#- /path` header line, like:
+/// ```text
+/// #- /default.nix
+/// import ./foo.nix
+/// #- /foo.nix
+/// 42
+/// ```
+#[derive(Debug)]
+pub struct Workspace {
+    root: PathBuf,
+}
+
+impl Workspace {
+    pub fn new(fixture: &str) -> Self {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let root = std::env::temp_dir().join(format!(
+            "nil-lsp-test-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed),
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let this = Self { root };
+
+        let mut cur: Option<(&str, String)> = None;
+        for line in fixture.lines().chain(["#- "]) {
+            if let Some(path) = line.strip_prefix("#- ") {
+                if let Some((path, text)) = cur.take() {
+                    this.write(path, &text);
+                }
+                cur = Some((path.trim(), String::new()));
+            } else if let Some((_, text)) = &mut cur {
+                text.push_str(line);
+                text.push('\n');
+            } else {
+                assert!(line.trim().is_empty(), "Content before any file header");
+            }
+        }
+        this
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn root_uri(&self) -> Url {
+        Url::from_file_path(&self.root).unwrap()
+    }
+
+    /// The absolute path of `path` relative to the root, like `/foo.nix`.
+    pub fn path(&self, path: &str) -> PathBuf {
+        self.root.join(path.trim_start_matches('/'))
+    }
+
+    pub fn uri(&self, path: &str) -> Url {
+        Url::from_file_path(self.path(path)).unwrap()
+    }
+
+    pub fn read(&self, path: &str) -> String {
+        std::fs::read_to_string(self.path(path)).unwrap()
+    }
+
+    pub fn write(&self, path: &str, text: &str) {
+        let path = self.path(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, text).unwrap();
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.root);
+    }
+}
+
+/// Messages received from the server, shared with the dispatcher.
+#[derive(Debug, Default)]
+struct Received {
+    messages: Mutex<Vec<Value>>,
+    exited: Mutex<bool>,
+    notify: Notify,
+}
+
+/// The client side of a running server.
+pub struct TestClient {
+    input: mpsc::UnboundedSender<io::Result<Vec<u8>>>,
+    received: Arc<Received>,
+    settings: Arc<Mutex<Value>>,
+    next_id: AtomicU64,
+    pub workspace: Workspace,
+}
+
+impl TestClient {
+    /// Run a server on `fixture` until the client `f` returns, or panic on timeout.
+    ///
+    /// Requests from the server are answered automatically: `workspace/configuration` with
+    /// the current [`TestClient::set_settings`], and others with `null`.
+    pub async fn run<Fut: Future<Output = ()>>(fixture: &str, f: impl FnOnce(Self) -> Fut) {
+        Self::run_with(fixture, nil::run_server, f).await;
+    }
+
+    /// Same as [`TestClient::run`], but the server is run by `serve`, eg. with extra routes.
+    pub async fn run_with<S, Fut>(
+        fixture: &str,
+        serve: impl FnOnce(PipeReader, PipeWriter) -> S,
+        f: impl FnOnce(Self) -> Fut,
+    ) where
+        S: Future<Output = anyhow::Result<()>>,
+        Fut: Future<Output = ()>,
+    {
+        let (input_tx, input_rx) = mpsc::unbounded();
+        let (output_tx, output_rx) = mpsc::unbounded();
+        let received = Arc::new(Received::default());
+        let settings = Arc::new(Mutex::new(Value::Null));
+
+        let server = {
+            let received = received.clone();
+            async move {
+                let ret = serve(input_rx.into_async_read(), PipeWriter(output_tx)).await;
+                if let Err(err) = ret {
+                    panic!("Server failed: {err:#}");
+                }
+                *received.exited.lock().unwrap() = true;
+                received.notify.notify_waiters();
+            }
+        };
+        let dispatch = dispatch(
+            output_rx,
+            input_tx.clone(),
+            received.clone(),
+            settings.clone(),
+        );
+        let client = Self {
+            input: input_tx,
+            received,
+            settings,
+            next_id: AtomicU64::new(1),
+            workspace: Workspace::new(fixture),
+        };
+
+        let fut = async {
+            let client = f(client);
+            tokio::pin!(client);
+            tokio::select! {
+                // The server exited normally. Let the client finish.
+                _ = futures::future::join(server, dispatch) => client.await,
+                () = &mut client => {}
+            }
+        };
+        tokio::time::timeout(TIMEOUT, fut).await.expect("Timeout");
+    }
+
+    /// Replace settings returned for `workspace/configuration`, like `{ "nil": { ... } }`.
+    pub fn set_settings(&self, settings: Value) {
+        *self.settings.lock().unwrap() = settings;
+    }
+
+    pub fn send(&self, msg: Value) {
+        self.input.unbounded_send(Ok(encode(&msg))).unwrap();
+    }
+
+    pub fn notify(&self, method: &str, params: Value) {
+        self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }));
+    }
+
+    /// Send a request and return its id, without waiting for the response.
+    pub fn request_async(&self, method: &str, params: Value) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }));
+        id
+    }
+
+    /// Send a request and wait for the full response message, including `result` or `error`.
+    /// Requests failed with `ContentModified` are retried, as editors do.
+    pub async fn request(&self, method: &str, params: Value) -> Value {
+        loop {
+            let resp = self
+                .response(self.request_async(method, params.clone()))
+                .await;
+            if resp["error"]["code"] != CONTENT_MODIFIED {
+                return resp;
+            }
+        }
+    }
+
+    /// Wait for the response of the request `id`.
+    pub async fn response(&self, id: u64) -> Value {
+        self.wait_for(|msg| msg["id"] == id && msg["method"].is_null())
+            .await
+    }
+
+    /// Do the `initialize` handshake, then send `initialized`.
+    /// Returns the `InitializeResult`.
+    pub async fn initialize(&self, capabilities: Value) -> Value {
+        self.initialize_with(json!({
+            "processId": null,
+            "rootUri": self.workspace.root_uri(),
+            "capabilities": capabilities,
+        }))
+        .await
+    }
+
+    /// Same as [`TestClient::initialize`], but with full `InitializeParams`.
+    pub async fn initialize_with(&self, params: Value) -> Value {
+        let resp = self.request("initialize", params).await;
+        assert!(resp["error"].is_null(), "{resp}");
+        self.notify("initialized", json!({}));
+        resp["result"].clone()
+    }
+
+    /// Do the `shutdown` and `exit` sequence, and wait for the server to stop.
+    pub async fn shutdown(&self) {
+        let resp = self.request("shutdown", Value::Null).await;
+        assert!(resp["error"].is_null(), "{resp}");
+        self.notify("exit", Value::Null);
+        self.wait_for_exit().await;
+    }
+
+    /// Wait for the server to stop by itself.
+    pub async fn wait_for_exit(&self) {
+        loop {
+            let notified = self.received.notify.notified();
+            if *self.received.exited.lock().unwrap() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// Wait until all files of the workspace are loaded.
+    /// This requires the `window.workDoneProgress` capability, like [`caps::full`].
+    pub async fn wait_for_scan(&self) {
+        self.wait_for(|msg| {
+            msg["method"] == "$/progress"
+                && msg["params"]["token"] == "nil/scanWorkspaceProgress"
+                && msg["params"]["value"]["kind"] == "end"
+        })
+        .await;
+    }
+
+    pub fn did_open(&self, path: &str, text: &str) {
+        self.notify(
+            "textDocument/didOpen",
+            json!({
+                "textDocument": {
+                    "uri": self.workspace.uri(path),
+                    "languageId": "nix",
+                    "version": 0,
+                    "text": text,
+                },
+            }),
+        );
+    }
+
+    /// Open a file with its content on disk.
+    pub fn did_open_on_disk(&self, path: &str) {
+        self.did_open(path, &self.workspace.read(path));
+    }
+
+    /// Replace the whole content of an opened file.
+    pub fn did_change(&self, path: &str, version: i32, text: &str) {
+        self.notify(
+            "textDocument/didChange",
+            json!({
+                "textDocument": { "uri": self.workspace.uri(path), "version": version },
+                "contentChanges": [{ "text": text }],
+            }),
+        );
+    }
+
+    pub fn did_close(&self, path: &str) {
+        self.notify(
+            "textDocument/didClose",
+            json!({ "textDocument": { "uri": self.workspace.uri(path) } }),
+        );
+    }
+
+    /// Parameters of a `TextDocumentPositionParams`, like for hovers and goto definitions.
+    pub fn position(&self, path: &str, line: u32, character: u32) -> Value {
+        json!({
+            "textDocument": { "uri": self.workspace.uri(path) },
+            "position": { "line": line, "character": character },
+        })
+    }
+
+    /// All messages received from the server so far, in order.
+    pub fn received(&self) -> Vec<Value> {
+        self.received.messages.lock().unwrap().clone()
+    }
+
+    /// Wait for any message ever received from the server satisfying `pred`.
+    pub async fn wait_for(&self, pred: impl Fn(&Value) -> bool) -> Value {
+        self.wait_for_nth(1, pred).await
+    }
+
+    /// Wait for the `n`-th (1-based) message received from the server satisfying `pred`.
+    pub async fn wait_for_nth(&self, n: usize, pred: impl Fn(&Value) -> bool) -> Value {
+        loop {
+            // Register before checking, so that no message is missed in between.
+            let notified = self.received.notify.notified();
+            if let Some(msg) = self.received().into_iter().filter(&pred).nth(n - 1) {
+                return msg;
+            }
+            notified.await;
+        }
+    }
+
+    /// Wait for the latest diagnostics published for `path` after the `n`-th (1-based) one.
+    pub async fn wait_for_diagnostics(&self, n: usize, path: &str) -> Vec<Value> {
+        let uri = self.workspace.uri(path);
+        let msg = self
+            .wait_for_nth(n, |msg| {
+                msg["method"] == "textDocument/publishDiagnostics"
+                    && msg["params"]["uri"] == uri.as_str()
+            })
+            .await;
+        msg["params"]["diagnostics"].as_array().unwrap().clone()
+    }
+}
+
+fn encode(msg: &Value) -> Vec<u8> {
+    let msg = msg.to_string();
+    format!("Content-Length: {}\r\n\r\n{msg}", msg.len()).into_bytes()
+}
+
+/// Decode all complete frames at the beginning of `buf`, leaving the incomplete rest.
+fn decode(buf: &mut Vec<u8>) -> Vec<Value> {
+    let mut msgs = Vec::new();
+    while let Some(header_end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+        let header = std::str::from_utf8(&buf[..header_end]).unwrap();
+        let len = header
+            .lines()
+            .find_map(|line| line.strip_prefix("Content-Length: "))
+            .expect("Missing Content-Length")
+            .parse::<usize>()
+            .unwrap();
+        let body_start = header_end + 4;
+        if buf.len() < body_start + len {
+            break;
+        }
+        msgs.push(serde_json::from_slice(&buf[body_start..body_start + len]).unwrap());
+        buf.drain(..body_start + len);
+    }
+    msgs
+}
+
+/// Collect messages from the server, and answer its requests.
+async fn dispatch(
+    mut output: mpsc::UnboundedReceiver<Vec<u8>>,
+    input: mpsc::UnboundedSender<io::Result<Vec<u8>>>,
+    received: Arc<Received>,
+    settings: Arc<Mutex<Value>>,
+) {
+    let mut buf = Vec::new();
+    while let Some(chunk) = output.next().await {
+        buf.extend(chunk);
+        for msg in decode(&mut buf) {
+            // Requests have both `id` and `method`.
+            if !msg["id"].is_null() && msg["method"].is_string() {
+                let result = match msg["method"].as_str().unwrap() {
+                    "workspace/configuration" => {
+                        let settings = settings.lock().unwrap();
+                        let items = msg["params"]["items"].as_array().unwrap();
+                        items
+                            .iter()
+                            .map(|item| match item["section"].as_str() {
+                                Some(section) => settings[section].clone(),
+                                None => settings.clone(),
+                            })
+                            .collect()
+                    }
+                    _ => Value::Null,
+                };
+                let resp = json!({ "jsonrpc": "2.0", "id": msg["id"], "result": result });
+                // The server may have exited.
+                let _ = input.unbounded_send(Ok(encode(&resp)));
+            }
+            received.messages.lock().unwrap().push(msg);
+        }
+        received.notify.notify_waiters();
+    }
+}
+
+/// The reading end of the in-memory pipe to the server.
+pub type PipeReader = IntoAsyncRead<mpsc::UnboundedReceiver<io::Result<Vec<u8>>>>;
+
+/// The writing end of the in-memory pipe from the server.
+pub struct PipeWriter(mpsc::UnboundedSender<Vec<u8>>);
+
+impl AsyncWrite for PipeWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.0.unbounded_send(buf.to_vec()) {
+            Ok(()) => Poll::Ready(Ok(buf.len())),
+            Err(_) => Poll::Ready(Err(io::ErrorKind::BrokenPipe.into())),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.0.close_channel();
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,380 @@
+//! On-disk cache for results of expensive `nix` invocations.
+//!
+//! Entries are keyed by the workspace, the hash of its `flake.lock` and an entry name
+//! (typically the flake input name). The layout is
+//! `<dir>/nil-eval-v1/<workspace hash>/<lock hash>/<entry name>.json`.
+//!
+//! The directory `<dir>` is configured by the user and may contain anything else, so only
+//! files and directories matching the layout under the owned `nil-eval-v1` are ever removed.
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+use std::{env, fs, io, process};
+
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+const CACHE_DIR_NAME: &str = "nil";
+/// The subdirectory owned by the cache. The version is bumped on layout changes.
+const OWNED_DIR_NAME: &str = "nil-eval-v1";
+const ENTRY_EXT: &str = "json";
+const TEMP_EXT: &str = "tmp";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EvalCache {
+    dir: PathBuf,
+    workspace_key: String,
+    max_size: u64,
+}
+
+impl EvalCache {
+    /// Create a cache handle for the workspace at `root_path`, storing entries inside `dir`.
+    /// Nothing is touched on disk yet.
+    pub fn new(dir: PathBuf, root_path: &Path, max_size: u64) -> Self {
+        Self {
+            dir: dir.join(OWNED_DIR_NAME),
+            workspace_key: hash_hex(root_path.to_string_lossy().as_bytes()),
+            max_size,
+        }
+    }
+
+    /// The platform default cache directory, `$XDG_CACHE_HOME/nil` or `$HOME/.cache/nil`.
+    pub fn default_dir() -> Option<PathBuf> {
+        let base = env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .filter(|p| p.is_absolute())
+            .or_else(|| Some(PathBuf::from(env::var_os("HOME")?).join(".cache")))?;
+        Some(base.join(CACHE_DIR_NAME))
+    }
+
+    /// Calculate the cache key of a `flake.lock` content.
+    pub fn lock_hash(lock_src: &[u8]) -> String {
+        hash_hex(lock_src)
+    }
+
+    /// The directory owned by the cache, inside the configured one.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn workspace_dir(&self) -> PathBuf {
+        self.dir.join(&self.workspace_key)
+    }
+
+    fn entry_path(&self, lock_hash: &str, name: &str) -> PathBuf {
+        let mut file_name = escape_file_name(name);
+        file_name.push('.');
+        file_name.push_str(ENTRY_EXT);
+        self.workspace_dir().join(lock_hash).join(file_name)
+    }
+
+    /// Get a cached entry. Missing or corrupted entries are returned as `None`.
+    pub fn get<T: DeserializeOwned>(&self, lock_hash: &str, name: &str) -> Option<T> {
+        let bytes = fs::read(self.entry_path(lock_hash, name)).ok()?;
+        serde_json::from_slice(&bytes).ok()
+    }
+
+    /// Store an entry, replacing the previous one if any.
+    pub fn put<T: Serialize>(&self, lock_hash: &str, name: &str, value: &T) -> Result<()> {
+        let path = self.entry_path(lock_hash, name);
+        let parent = path.parent().expect("Entry path has parents");
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create cache directory {parent:?}"))?;
+        let bytes = serde_json::to_vec(value)?;
+        // Write to a temporary file first, so that concurrent readers never see partial entries.
+        // Each writer has its own one, in case of concurrent writers of the same entry.
+        static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+        let tmp_path = path.with_extension(format!(
+            "{ENTRY_EXT}.{}-{}.{TEMP_EXT}",
+            process::id(),
+            TEMP_COUNTER.fetch_add(1, Ordering::Relaxed),
+        ));
+        fs::write(&tmp_path, bytes)
+            .and_then(|()| fs::rename(&tmp_path, &path))
+            .with_context(|| format!("Failed to write cache entry {path:?}"))?;
+        Ok(())
+    }
+
+    /// Remove entries of this workspace whose lock hash is not in `live_lock_hashes`, then
+    /// remove the oldest entries of all workspaces until the total size is under the limit.
+    pub fn gc(&self, live_lock_hashes: &HashSet<String>) -> Result<()> {
+        let workspace_dir = self.workspace_dir();
+        let mut entries = Vec::new();
+        for path in layout_files(&self.dir, ENTRY_EXT)? {
+            let lock_dir = path.parent().expect("Has lock dir");
+            let is_stale = lock_dir.parent() == Some(&workspace_dir)
+                && !lock_dir
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| live_lock_hashes.contains(name));
+            if is_stale {
+                remove_layout_file(&path)?;
+                continue;
+            }
+            let meta = fs::metadata(&path)?;
+            let mtime = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            entries.push((mtime, meta.len(), path));
+        }
+
+        let mut total_size = entries.iter().map(|(_, size, _)| size).sum::<u64>();
+        entries.sort();
+        for (_, size, path) in entries {
+            if total_size <= self.max_size {
+                break;
+            }
+            remove_layout_file(&path)?;
+            total_size -= size;
+        }
+        Ok(())
+    }
+
+    /// Remove all entries of all workspaces.
+    pub fn clear(&self) -> Result<()> {
+        for ext in [ENTRY_EXT, TEMP_EXT] {
+            for path in layout_files(&self.dir, ext)? {
+                remove_layout_file(&path)?;
+            }
+        }
+        // Fails if there are unknown files, which are kept.
+        let _ = fs::remove_dir(&self.dir);
+        Ok(())
+    }
+
+    /// Remove all entries in the cache directory `dir`, as configured.
+    pub fn clear_dir(dir: &Path) -> Result<()> {
+        Self::new(dir.to_owned(), Path::new(""), 0).clear()
+    }
+}
+
+/// All files ending with `.{ext}` in `<dir>/<workspace hash>/<lock hash>/`.
+/// Files and directories of other names are skipped.
+fn layout_files(dir: &Path, ext: &str) -> Result<Vec<PathBuf>> {
+    let mut ret = Vec::new();
+    for ws_dir in read_dir_if_exists(dir)? {
+        let ws_dir = ws_dir?;
+        if !is_hash_name(&ws_dir) {
+            continue;
+        }
+        for lock_dir in read_dir_if_exists(&ws_dir.path())? {
+            let lock_dir = lock_dir?;
+            if !is_hash_name(&lock_dir) {
+                continue;
+            }
+            for file in read_dir_if_exists(&lock_dir.path())? {
+                let file = file?;
+                let path = file.path();
+                if file.file_type()?.is_file() && path.extension().is_some_and(|got| got == ext) {
+                    ret.push(path);
+                }
+            }
+        }
+    }
+    Ok(ret)
+}
+
+/// Directories named by `hash_hex`.
+fn is_hash_name(entry: &fs::DirEntry) -> bool {
+    let name = entry.file_name();
+    let Some(name) = name.to_str() else {
+        return false;
+    };
+    name.len() == 16
+        && name.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+        && entry.file_type().is_ok_and(|ty| ty.is_dir())
+}
+
+/// Remove a file returned by `layout_files`, and its lock and workspace directories if empty.
+fn remove_layout_file(path: &Path) -> Result<()> {
+    fs::remove_file(path).with_context(|| format!("Failed to remove {path:?}"))?;
+    // Failures are expected for non-empty ones.
+    let lock_dir = path.parent().expect("Has lock dir");
+    if fs::remove_dir(lock_dir).is_ok() {
+        let _ = fs::remove_dir(lock_dir.parent().expect("Has workspace dir"));
+    }
+    Ok(())
+}
+
+fn read_dir_if_exists(path: &Path) -> Result<impl Iterator<Item = io::Result<fs::DirEntry>>> {
+    let iter = match fs::read_dir(path) {
+        Ok(iter) => Some(iter),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+        Err(err) => return Err(err).with_context(|| format!("Failed to read {path:?}")),
+    };
+    Ok(iter.into_iter().flatten())
+}
+
+/// Escape characters which are not safe in file names.
+fn escape_file_name(name: &str) -> String {
+    let mut ret = String::with_capacity(name.len());
+    for b in name.bytes() {
+        if b.is_ascii_alphanumeric() || b == b'-' || b == b'_' {
+            ret.push(b as char);
+        } else {
+            ret.push_str(&format!("%{b:02X}"));
+        }
+    }
+    ret
+}
+
+/// A stable non-cryptographic hash (64-bit FNV-1a) in hex.
+/// We cannot use `std`'s `DefaultHasher` since it is not guaranteed to be stable across releases.
+fn hash_hex(bytes: &[u8]) -> String {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+    let hash = bytes.iter().fold(OFFSET_BASIS, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(PRIME)
+    });
+    format!("{hash:016x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flake_output::{eval_flake_output, FlakeOutput};
+    use crate::FlakeUrl;
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = env::temp_dir().join(format!("nil-test-{}-{name}", std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn get_put() {
+        let tmp = TempDir::new("get-put");
+        let cache = EvalCache::new(tmp.0.join("cache"), "/workspace".as_ref(), u64::MAX);
+        let lock_hash = EvalCache::lock_hash(b"{}");
+
+        assert_eq!(cache.get::<Vec<String>>(&lock_hash, "nixpkgs"), None);
+        let value = vec!["foo".to_owned(), "bar".to_owned()];
+        cache.put(&lock_hash, "nixpkgs", &value).unwrap();
+        assert_eq!(cache.get(&lock_hash, "nixpkgs"), Some(value));
+
+        // Different keys do not collide.
+        assert_eq!(cache.get::<Vec<String>>(&lock_hash, "nixpkgs/"), None);
+        assert_eq!(cache.get::<Vec<String>>("other", "nixpkgs"), None);
+        let other_ws = EvalCache::new(tmp.0.join("cache"), "/other".as_ref(), u64::MAX);
+        assert_eq!(other_ws.get::<Vec<String>>(&lock_hash, "nixpkgs"), None);
+
+        cache.clear().unwrap();
+        assert!(!cache.dir().exists());
+        assert_eq!(cache.get::<Vec<String>>(&lock_hash, "nixpkgs"), None);
+        // Clearing a non-existing cache is fine.
+        cache.clear().unwrap();
+    }
+
+    #[test]
+    fn gc_stale_lock() {
+        let tmp = TempDir::new("gc-stale");
+        let cache = EvalCache::new(tmp.0.clone(), "/workspace".as_ref(), u64::MAX);
+        let other_ws = EvalCache::new(tmp.0.clone(), "/other".as_ref(), u64::MAX);
+        let (old, new) = (EvalCache::lock_hash(b"old"), EvalCache::lock_hash(b"new"));
+        cache.put(&old, "a", &1).unwrap();
+        cache.put(&new, "a", &2).unwrap();
+        other_ws.put(&old, "a", &3).unwrap();
+
+        cache.gc(&HashSet::from_iter([new.clone()])).unwrap();
+        assert_eq!(cache.get::<i32>(&old, "a"), None);
+        assert_eq!(cache.get::<i32>(&new, "a"), Some(2));
+        // Other workspaces are untouched.
+        assert_eq!(other_ws.get::<i32>(&old, "a"), Some(3));
+    }
+
+    #[test]
+    fn gc_size_limit() {
+        let tmp = TempDir::new("gc-size");
+        let unlimited = EvalCache::new(tmp.0.clone(), "/workspace".as_ref(), u64::MAX);
+        let lock = EvalCache::lock_hash(b"lock");
+        let live = HashSet::from_iter([lock.clone()]);
+        let value = "x".repeat(100);
+        for (i, name) in ["a", "b", "c"].into_iter().enumerate() {
+            unlimited.put(&lock, name, &value).unwrap();
+            // Make modification times distinguishable.
+            let mtime = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(i as u64 + 1);
+            fs::File::options()
+                .write(true)
+                .open(unlimited.entry_path(&lock, name))
+                .and_then(|f| f.set_modified(mtime))
+                .unwrap();
+        }
+        unlimited.gc(&live).unwrap();
+
+        let limited = EvalCache::new(tmp.0.clone(), "/workspace".as_ref(), 250);
+        limited.gc(&live).unwrap();
+        assert_eq!(limited.get::<String>(&lock, "a"), None);
+        assert_eq!(limited.get::<String>(&lock, "b").as_ref(), Some(&value));
+        assert_eq!(limited.get::<String>(&lock, "c").as_ref(), Some(&value));
+    }
+
+    #[test]
+    fn keep_unknown_files() {
+        let tmp = TempDir::new("unknown");
+        let cache = EvalCache::new(tmp.0.clone(), "/workspace".as_ref(), 0);
+        let lock = EvalCache::lock_hash(b"lock");
+        cache.put(&lock, "a", &1).unwrap();
+        let unknown = [
+            tmp.0.join("user-data"),
+            tmp.0.join("a/b/c"),
+            cache.dir().join("notes"),
+            cache.dir().join("x/y/z.json"),
+            cache.entry_path(&lock, "a").with_extension("txt"),
+        ];
+        for path in &unknown {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "keep").unwrap();
+        }
+
+        cache.gc(&HashSet::new()).unwrap();
+        assert_eq!(cache.get::<i32>(&lock, "a"), None);
+        cache.put(&lock, "a", &1).unwrap();
+        EvalCache::clear_dir(&tmp.0).unwrap();
+        assert_eq!(cache.get::<i32>(&lock, "a"), None);
+        for path in &unknown {
+            assert_eq!(fs::read_to_string(path).unwrap(), "keep", "{path:?}");
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn cache_flake_output_with_fake_nix() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = TempDir::new("fake-nix");
+        let fake_nix = tmp.0.join("nix");
+        let write_fake_nix = |script: &str| {
+            fs::write(&fake_nix, format!("#!/bin/sh\n{script}\n")).unwrap();
+            fs::set_permissions(&fake_nix, fs::Permissions::from_mode(0o755)).unwrap();
+        };
+        write_fake_nix(
+            r#"echo '{"packages":{"x86_64-linux":{"hello":{"type":"derivation","name":"hello-1.0"}}}}'"#,
+        );
+
+        let cache = EvalCache::new(tmp.0.join("cache"), "/workspace".as_ref(), u64::MAX);
+        let lock_hash = EvalCache::lock_hash(b"lock");
+        let flake_url = FlakeUrl::new_path("/non-existing");
+        let output = eval_flake_output(&fake_nix, &flake_url, None, false, None)
+            .await
+            .unwrap();
+        cache.put(&lock_hash, "hello", &output).unwrap();
+
+        // Later evaluations would fail, but the cached result is still available.
+        write_fake_nix("exit 1");
+        eval_flake_output(&fake_nix, &flake_url, None, false, None)
+            .await
+            .unwrap_err();
+        assert_eq!(cache.get::<FlakeOutput>(&lock_hash, "hello"), Some(output));
+    }
+}
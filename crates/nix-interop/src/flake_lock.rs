@@ -92,6 +92,16 @@ pub async fn resolve_flake_locked_inputs(
     Ok(resolved)
 }
 
+/// Get names of all root inputs from a flake lock, sorted. This does not invoke `nix`.
+pub fn root_input_names(lock_src: &[u8]) -> Result<Vec<String>> {
+    let lock =
+        serde_json::from_slice::<FlakeLock>(lock_src).context("Failed to parse flake lock")?;
+    let root_node = lock.nodes.get(&lock.root).context("Missing root node")?;
+    let mut names = root_node.inputs.keys().cloned().collect::<Vec<_>>();
+    names.sort();
+    Ok(names)
+}
+
 #[derive(Debug)]
 struct Resolver<'a> {
     lock: &'a FlakeLock,
@@ -227,6 +237,13 @@ pub async fn archive(nix_command: &Path, flake_url: &FlakeUrl) -> Result<()> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn root_input_names() {
+        let lock_src = std::fs::read("./tests/test_flake/flake.lock").unwrap();
+        let got = super::root_input_names(&lock_src).unwrap();
+        assert_eq!(got, ["nix", "nixpkgs", "non-flake-file"]);
+    }
+
     #[tokio::test]
     #[ignore = "requires calling 'nix'"]
     async fn resolve_flake_lock_inputs() {
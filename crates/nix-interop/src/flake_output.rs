@@ -3,7 +3,7 @@ use std::path::Path;
 use std::process::Stdio;
 
 use anyhow::{ensure, Context, Result};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::process::Command;
 use tokio::sync::watch;
@@ -106,7 +106,7 @@ pub async fn eval_flake_output(
     Ok(val)
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum FlakeOutput {
     Leaf(Leaf),
@@ -129,7 +129,7 @@ impl FlakeOutput {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Leaf {
     #[serde(rename = "type")]
@@ -139,7 +139,7 @@ pub struct Leaf {
 }
 
 // https://github.com/NixOS/nix/blob/2.14.1/src/nix/flake.cc#L1105
-#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub enum Type {
     NixosModule,
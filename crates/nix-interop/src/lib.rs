@@ -4,6 +4,7 @@ use std::fmt;
 use std::path::{Path, PathBuf};
 
 pub mod eval;
+pub mod eval_cache;
 pub mod flake_lock;
 pub mod flake_output;
 pub mod info;
@@ -17,6 +17,13 @@ pub fn is_valid_ident(name: &str) -> bool {
         && KEYWORDS.iter().all(|&(kw, _)| name != kw)
 }
 
+/// Check if a comment is a doc comment `/** ... */`, as in RFC 145.
+/// The empty `/**/` and decorative ones like `/*****/` are not.
+pub fn is_doc_comment(text: &str) -> bool {
+    text.strip_prefix("/**")
+        .is_some_and(|rest| !rest.starts_with(['*', '/']))
+}
+
 /// Escape a literal Attr. Quote it if it's not a valid identifier.
 pub fn escape_literal_attr(name: &str) -> Cow<'_, str> {
     if is_valid_ident(name) {
@@ -366,6 +373,16 @@ mod tests {
         assert!(!is_valid_ident("in"));
     }
 
+    #[test]
+    fn doc_comment() {
+        assert!(is_doc_comment("/** Doc. */"));
+        assert!(is_doc_comment("/**\n * Doc.\n */"));
+        assert!(!is_doc_comment("/* Not doc. */"));
+        assert!(!is_doc_comment("/**/"));
+        assert!(!is_doc_comment("/*****/"));
+        assert!(!is_doc_comment("# Not doc."));
+    }
+
     #[test]
     fn escape_attr() {
         assert_eq!(escape_literal_attr("foo"), "foo");
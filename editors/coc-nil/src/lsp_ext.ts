@@ -1,3 +1,19 @@
 import * as lc from 'coc.nvim';
 
 export const reloadFlake = new lc.NotificationType0('nil/reloadFlake');
+
+export interface AttrpathSegment {
+  name: string;
+  range: lc.Range;
+}
+
+export interface AttrpathAtPositionResult {
+  path: string;
+  segments: AttrpathSegment[];
+}
+
+export const attrpathAtPosition = new lc.RequestType<
+  lc.TextDocumentPositionParams,
+  AttrpathAtPositionResult | null,
+  void
+>('nil/attrpathAtPosition');
@@ -19,8 +19,35 @@ export async function activate(context: ExtensionContext): Promise<void> {
   const client = new LanguageClient('nil', 'nil Language Server', serverOptions, clientOptions);
   context.subscriptions.push(services.registLanguageClient(client));
   context.subscriptions.push(commands.registerCommand('nil.reloadFlake', () => onReloadFlake(client)));
+
+  if (cfg.get('attrpath.enable', true)) {
+    context.subscriptions.push(
+      workspace.registerAutocmd({
+        event: 'CursorHold',
+        callback: () => updateAttrpath(client),
+      })
+    );
+  }
 }
 
 function onReloadFlake(client: LanguageClient) {
   client.sendNotification(lsp_ext.reloadFlake);
 }
+
+// Expose the attribute path at the cursor as `b:nil_attrpath`, for statuslines or winbars,
+// eg. `set winbar=%{get(b:,'nil_attrpath','')}`.
+async function updateAttrpath(client: LanguageClient) {
+  const { document, position } = await workspace.getCurrentState();
+  if (document.languageId !== 'nix' || !client.started) {
+    return;
+  }
+  const doc = workspace.getDocument(document.uri);
+  if (!doc) {
+    return;
+  }
+  const ret = await client.sendRequest(lsp_ext.attrpathAtPosition, {
+    textDocument: { uri: document.uri },
+    position,
+  });
+  await workspace.nvim.call('setbufvar', [doc.bufnr, 'nil_attrpath', ret?.path ?? '']);
+}